@@ -0,0 +1,107 @@
+//! BLE through `bluetooth-hci`.
+//!
+//! Starts the BLE stack, then drives it with the host side of `bluetooth-hci` over
+//! `tl_mbox::hci::HciController`: resets the controller, sets the advertising data and prints
+//! every event read with `bluetooth_hci::host::uart::Hci::read` over semihosting.
+//!
+//! Requires the BLE stack firmware on CPU2 and the `bluetooth-hci` feature.
+#![no_std]
+#![no_main]
+
+extern crate panic_halt;
+extern crate stm32wb_hal as hal;
+
+#[path = "board/nucleo_wb55.rs"]
+mod board;
+
+use bluetooth_hci::event::Event;
+use bluetooth_hci::host::uart::{Hci as UartHci, Packet};
+use bluetooth_hci::host::Hci;
+use cortex_m_rt::{entry, exception, ExceptionFrame};
+use cortex_m_semihosting::hprintln;
+use nb::block;
+use stm32wb55::Stm32Wb5xTypes;
+
+use hal::flash::FlashExt;
+use hal::pac;
+use hal::prelude::*;
+use hal::tl_mbox::hci::HciController;
+use hal::wireless::{self, Wireless, WirelessConfig};
+
+use board::SysTickTimer;
+
+type VendorEvent = <Stm32Wb5xTypes as bluetooth_hci::Vendor>::Event;
+
+/// Flags and complete local name AD structures (length, AD type, data)
+const ADV_DATA: &[u8] = b"\x02\x01\x06\x0a\x09WB55 HCI";
+
+#[entry]
+fn main() -> ! {
+    let cp = cortex_m::Peripherals::take().unwrap();
+    let dp = pac::Peripherals::take().unwrap();
+
+    // Board clocks with LSE for the RF wake-up clock. The IPCC interrupts stay masked, the
+    // controller polls the mailbox.
+    let clock_config = board::clock_config();
+    let mut timer = SysTickTimer::new(cp.SYST, clock_config.sysclk_frequency());
+    let Wireless { mut mbox, .. } = wireless::setup(
+        dp.RCC.constrain(),
+        clock_config,
+        &mut dp.FLASH.constrain().acr,
+        dp.IPCC,
+        WirelessConfig::default(),
+        &mut timer,
+        5_000_u32,
+    )
+    .unwrap();
+
+    let mut hci: HciController<Stm32Wb5xTypes> = HciController::new(&mut mbox);
+
+    block!(hci.reset()).unwrap();
+    wait_command_complete(&mut hci);
+
+    block!(hci.le_set_advertising_data(ADV_DATA)).unwrap();
+    wait_command_complete(&mut hci);
+
+    loop {
+        read_event(&mut hci);
+    }
+}
+
+/// Reads and prints one packet, polling the IPCC RX handler until an event is queued.
+fn read_event(hci: &mut HciController<Stm32Wb5xTypes>) -> Option<Packet<VendorEvent>> {
+    loop {
+        match hci.read() {
+            Ok(packet) => {
+                hprintln!("{:?}", packet).unwrap();
+                return Some(packet);
+            }
+            Err(nb::Error::WouldBlock) => hci.poll_rx(),
+            Err(nb::Error::Other(e)) => {
+                hprintln!("HCI read error: {:?}", e).unwrap();
+                return None;
+            }
+        }
+    }
+}
+
+/// Reads events until the Command Complete event of the last command.
+fn wait_command_complete(hci: &mut HciController<Stm32Wb5xTypes>) {
+    loop {
+        if let Some(Packet::Event(Event::CommandComplete(_))) = read_event(hci) {
+            return;
+        }
+    }
+}
+
+#[exception]
+#[allow(non_snake_case)]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("HardFault at {:#?}", ef);
+}
+
+#[exception]
+#[allow(non_snake_case)]
+fn DefaultHandler(irqn: i16) {
+    panic!("Unhandled exception (IRQn = {})", irqn);
+}
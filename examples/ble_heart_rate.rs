@@ -0,0 +1,238 @@
+//! BLE heart rate sensor.
+//!
+//! Exposes the Heart Rate service (0x180D) with the Heart Rate Measurement characteristic
+//! (0x2A37), notified once per second with a simulated value paced by the RTC. Advertising is
+//! restarted whenever the central disconnects. The green LED (PB0 on P-NUCLEO-WB55) is on while
+//! a central is connected.
+//!
+//! Requires the BLE stack firmware on CPU2. The mailbox is polled from the main loop and nothing
+//! is printed, so the example runs without a debugger attached.
+#![no_std]
+#![no_main]
+
+extern crate panic_halt;
+extern crate stm32wb_hal as hal;
+
+#[path = "board/nucleo_wb55.rs"]
+mod board;
+
+use cortex_m_rt::{entry, exception, ExceptionFrame};
+use embedded_hal::digital::v2::OutputPin;
+
+use hal::flash::FlashExt;
+use hal::pac;
+use hal::prelude::*;
+use hal::rtc::Rtc;
+use hal::tl_mbox::ble::connection::Connections;
+use hal::tl_mbox::ble::CmdResponse;
+use hal::tl_mbox::TlMbox;
+use hal::wireless::{self, Wireless, WirelessConfig};
+
+use board::SysTickTimer;
+
+const HCI_RESET: u16 = 0x0c03;
+const ACI_GAP_SET_DISCOVERABLE: u16 = 0xfc83;
+const ACI_GAP_INIT: u16 = 0xfc8a;
+const ACI_GATT_INIT: u16 = 0xfd01;
+const ACI_GATT_ADD_SERVICE: u16 = 0xfd02;
+const ACI_GATT_ADD_CHAR: u16 = 0xfd04;
+const ACI_GATT_UPDATE_CHAR_VALUE: u16 = 0xfd06;
+
+const HEART_RATE_SERVICE_UUID: u16 = 0x180d;
+const HEART_RATE_MEASUREMENT_UUID: u16 = 0x2a37;
+
+/// Complete local name AD structure data (AD type, then name)
+const LOCAL_NAME: &[u8] = b"\x09WB55 HRS";
+
+/// Advertising interval, 0.625 ms units
+const ADV_INTERVAL_MIN: u16 = 0x80;
+const ADV_INTERVAL_MAX: u16 = 0xa0;
+
+struct Ble {
+    mbox: TlMbox,
+    connections: Connections,
+    service_handle: u16,
+    measurement_handle: u16,
+}
+
+impl Ble {
+    /// Sends an ACI/HCI command and waits for its response.
+    fn cmd(&mut self, opcode: u16, params: &[u8]) -> CmdResponse {
+        let connections = &mut self.connections;
+
+        self.mbox
+            .blocking_ble_cmd(
+                opcode,
+                params,
+                || {},
+                |evt| {
+                    connections.process(&evt);
+                },
+            )
+            .unwrap()
+    }
+
+    /// Sends a command that must succeed.
+    fn cmd_ok(&mut self, opcode: u16, params: &[u8]) -> CmdResponse {
+        let rsp = self.cmd(opcode, params);
+        assert_eq!(rsp.status, 0);
+        rsp
+    }
+
+    /// Initializes GAP and GATT and adds the heart rate service.
+    fn init_gatt(&mut self) {
+        self.cmd_ok(HCI_RESET, &[]);
+        self.cmd_ok(ACI_GATT_INIT, &[]);
+
+        // Peripheral role, no privacy, 8 bytes device name
+        self.cmd_ok(ACI_GAP_INIT, &[0x01, 0x00, 0x08]);
+
+        // 16-bit UUID, primary service, 4 attribute records
+        let uuid = HEART_RATE_SERVICE_UUID.to_le_bytes();
+        let rsp = self.cmd_ok(ACI_GATT_ADD_SERVICE, &[0x01, uuid[0], uuid[1], 0x01, 4]);
+        let params = rsp.return_params();
+        self.service_handle = u16::from_le_bytes([params[0], params[1]]);
+
+        // 16-bit UUID, 2 bytes variable length value, notify, no security, no GATT events,
+        // 16 bytes encryption key
+        let service = self.service_handle.to_le_bytes();
+        let uuid = HEART_RATE_MEASUREMENT_UUID.to_le_bytes();
+        let rsp = self.cmd_ok(
+            ACI_GATT_ADD_CHAR,
+            &[
+                service[0], service[1], 0x01, uuid[0], uuid[1], 2, 0, 0x10, 0x00, 0x00, 16, 0x01,
+            ],
+        );
+        let params = rsp.return_params();
+        self.measurement_handle = u16::from_le_bytes([params[0], params[1]]);
+    }
+
+    /// Starts undirected connectable advertising.
+    fn start_advertising(&mut self) {
+        let mut params = [0; 32];
+        let mut len = 0;
+        let mut push = |bytes: &[u8]| {
+            params[len..len + bytes.len()].copy_from_slice(bytes);
+            len += bytes.len();
+        };
+
+        // ADV_IND, interval, public address, no filter
+        push(&[0x00]);
+        push(&ADV_INTERVAL_MIN.to_le_bytes());
+        push(&ADV_INTERVAL_MAX.to_le_bytes());
+        push(&[0x00, 0x00]);
+        push(&[LOCAL_NAME.len() as u8]);
+        push(LOCAL_NAME);
+        // No service UUIDs, no preferred connection interval
+        push(&[0x00, 0x00, 0x00, 0x00, 0x00]);
+
+        self.cmd_ok(ACI_GAP_SET_DISCOVERABLE, &params[..len]);
+    }
+
+    /// Notifies a heart rate measurement to subscribed centrals.
+    fn notify_heart_rate(&mut self, bpm: u8) {
+        let service = self.service_handle.to_le_bytes();
+        let measurement = self.measurement_handle.to_le_bytes();
+
+        // Offset 0, 2 bytes: flags (8-bit value), heart rate
+        let rsp = self.cmd(
+            ACI_GATT_UPDATE_CHAR_VALUE,
+            &[
+                service[0],
+                service[1],
+                measurement[0],
+                measurement[1],
+                0,
+                2,
+                0x00,
+                bpm,
+            ],
+        );
+        self.connections.on_tx_status(rsp.status);
+    }
+}
+
+#[entry]
+fn main() -> ! {
+    let cp = cortex_m::Peripherals::take().unwrap();
+    let dp = pac::Peripherals::take().unwrap();
+
+    // Board clocks with LSE for RTC and the RF wake-up clock. The IPCC interrupts stay masked,
+    // the mailbox is polled.
+    let clock_config = board::clock_config();
+    let mut timer = SysTickTimer::new(cp.SYST, clock_config.sysclk_frequency());
+    let Wireless { mut rcc, mbox, .. } = wireless::setup(
+        dp.RCC.constrain(),
+        clock_config,
+        &mut dp.FLASH.constrain().acr,
+        dp.IPCC,
+        WirelessConfig::default(),
+        &mut timer,
+        5_000_u32,
+    )
+    .unwrap();
+
+    let rtc = Rtc::rtc(dp.RTC, &mut rcc);
+
+    let mut gpiob = dp.GPIOB.split(&mut rcc);
+    let mut led = gpiob
+        .pb0
+        .into_push_pull_output(&mut gpiob.moder, &mut gpiob.otyper);
+
+    let mut ble = Ble {
+        mbox,
+        connections: Connections::new(),
+        service_handle: 0,
+        measurement_handle: 0,
+    };
+    ble.init_gatt();
+
+    let mut advertising = false;
+    let mut last_second = rtc.get_time().seconds;
+    let mut bpm: u8 = 60;
+
+    loop {
+        ble.mbox.poll();
+
+        while let Some(evt) = ble.mbox.dequeue_event() {
+            ble.connections.process(&evt);
+        }
+
+        // Connection events may also be consumed while waiting for a command response, so act
+        // on the connection state rather than on the events. The stack stops advertising when
+        // a central connects.
+        if ble.connections.is_connected() {
+            advertising = false;
+            let _ = led.set_high();
+        } else {
+            let _ = led.set_low();
+
+            if !advertising {
+                ble.start_advertising();
+                advertising = true;
+            }
+        }
+
+        let second = rtc.get_time().seconds;
+        if second != last_second {
+            last_second = second;
+            bpm = if bpm >= 100 { 60 } else { bpm + 1 };
+
+            if ble.connections.is_connected() && !ble.connections.tx_pool_full() {
+                ble.notify_heart_rate(bpm);
+            }
+        }
+    }
+}
+
+#[exception]
+#[allow(non_snake_case)]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("HardFault at {:#?}", ef);
+}
+
+#[exception]
+#[allow(non_snake_case)]
+fn DefaultHandler(irqn: i16) {
+    panic!("Unhandled exception (IRQn = {})", irqn);
+}
@@ -0,0 +1,123 @@
+//! Board definitions for the P-NUCLEO-WB55 (MB1355) board.
+
+// Each example uses only part of the board
+#![allow(dead_code)]
+
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m::peripheral::SYST;
+use embedded_hal::timer::CountDown;
+use void::Void;
+
+use hal::gpio::gpiob::{PB0, PB1, PB5};
+use hal::gpio::gpioc::PC4;
+use hal::gpio::{Input, Output, PullUp, PushPull};
+use hal::rcc::{
+    ApbDivider, Config, HDivider, HseDivider, PllConfig, PllSrc, RfWakeupClock, RtcClkSrc,
+    SysClkSrc, UsbClkSrc,
+};
+use hal::time::Hertz;
+
+pub type LedBlue = PB5<Output<PushPull>>;
+pub type LedGreen = PB0<Output<PushPull>>;
+pub type LedRed = PB1<Output<PushPull>>;
+
+/// User button B1, active low.
+pub type ButtonB1 = PC4<Input<PullUp>>;
+
+/// On-board LEDs.
+pub struct Leds {
+    pub blue: LedBlue,
+    pub green: LedGreen,
+    pub red: LedRed,
+}
+
+impl Leds {
+    pub fn new(gpiob: hal::gpio::gpiob::Parts) -> Self {
+        let mut gpiob = gpiob;
+
+        Leds {
+            blue: gpiob
+                .pb5
+                .into_push_pull_output(&mut gpiob.moder, &mut gpiob.otyper),
+            green: gpiob
+                .pb0
+                .into_push_pull_output(&mut gpiob.moder, &mut gpiob.otyper),
+            red: gpiob
+                .pb1
+                .into_push_pull_output(&mut gpiob.moder, &mut gpiob.otyper),
+        }
+    }
+}
+
+/// Clock configuration used by the board examples.
+///
+/// * 32 MHz HSE with PLL
+/// * 64 MHz CPU1, 32 MHz CPU2
+/// * 64 MHz for APB1, APB2
+/// * USB clock source from PLLQ (32 / 2 * 3 = 48)
+/// * LSE for RTC and the RF wake-up clock
+pub fn clock_config() -> Config {
+    Config::new(SysClkSrc::Pll(PllSrc::Hse(HseDivider::NotDivided)))
+        .cpu1_hdiv(HDivider::NotDivided)
+        .cpu2_hdiv(HDivider::Div2)
+        .apb1_div(ApbDivider::NotDivided)
+        .apb2_div(ApbDivider::NotDivided)
+        .pll_cfg(PllConfig {
+            m: 2,
+            n: 12,
+            r: 3,
+            q: Some(4),
+            p: Some(3),
+        })
+        .usb_src(UsbClkSrc::PllQ)
+        .with_lse()
+        .rtc_src(RtcClkSrc::Lse)
+        .rf_wkp_sel(RfWakeupClock::Lse)
+}
+
+/// SysTick as a millisecond `CountDown`, e.g. for the timeouts of `wireless::setup`.
+///
+/// Counts at `sysclk`, which must be the SYSCLK frequency while the timer runs. Create it with the
+/// frequency of the clock configuration `wireless::setup` applies.
+pub struct SysTickTimer {
+    syst: SYST,
+    ticks_per_ms: u32,
+    remaining_ms: u32,
+}
+
+impl SysTickTimer {
+    pub fn new(mut syst: SYST, sysclk: Hertz) -> Self {
+        syst.set_clock_source(SystClkSource::Core);
+
+        SysTickTimer {
+            syst,
+            ticks_per_ms: sysclk.0 / 1_000,
+            remaining_ms: 0,
+        }
+    }
+}
+
+impl CountDown for SysTickTimer {
+    type Time = u32;
+
+    fn start<T: Into<u32>>(&mut self, ms: T) {
+        self.remaining_ms = ms.into();
+
+        self.syst.set_reload(self.ticks_per_ms - 1);
+        self.syst.clear_current();
+        self.syst.enable_counter();
+    }
+
+    fn wait(&mut self) -> nb::Result<(), Void> {
+        if self.remaining_ms > 0 && self.syst.has_wrapped() {
+            self.remaining_ms -= 1;
+        }
+
+        if self.remaining_ms == 0 {
+            self.syst.disable_counter();
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
@@ -0,0 +1,52 @@
+//! Drives a spare IPCC channel by hand, as an own CPU1/CPU2 protocol would.
+//!
+//! CPU2 isn't booted, so CPU1 plays both sides: it sends on the CPU1 to CPU2 direction, takes
+//! the message on behalf of CPU2, then answers on the CPU2 to CPU1 direction. With custom CPU2
+//! firmware, the CPU2 side runs there and `TlMbox` can use the other channels meanwhile.
+#![no_main]
+#![no_std]
+
+extern crate panic_semihosting;
+extern crate stm32wb_hal as hal;
+
+use cortex_m_rt::entry;
+use cortex_m_semihosting::hprintln;
+
+use hal::ipcc::{IpccChannel, IpccExt};
+use hal::pac;
+use hal::prelude::*;
+use hal::tl_mbox::channels;
+
+/// Not used by the mailbox unless the `thread` feature is enabled.
+const CHANNEL: IpccChannel = IpccChannel::Channel5;
+
+#[entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+
+    assert!(!channels::is_mbox_channel(CHANNEL));
+
+    let mut rcc = dp.RCC.constrain();
+    let mut ipcc = dp.IPCC.constrain();
+    ipcc.init(&mut rcc);
+
+    // CPU1 sends a message
+    ipcc.c1_set_flag_channel(CHANNEL);
+    hprintln!("CPU1 -> CPU2 occupied: {}", ipcc.c1_is_active_flag(CHANNEL)).unwrap();
+
+    // CPU2 takes it, the channel is free again
+    ipcc.c2_clear_flag_channel(CHANNEL);
+    hprintln!("CPU1 -> CPU2 occupied: {}", ipcc.c1_is_active_flag(CHANNEL)).unwrap();
+
+    // CPU2 answers
+    ipcc.c2_set_flag_channel(CHANNEL);
+    hprintln!("CPU2 -> CPU1 occupied: {}", ipcc.c2_is_active_flag(CHANNEL)).unwrap();
+
+    // CPU1 takes the answer
+    ipcc.c1_clear_flag_channel(CHANNEL);
+    hprintln!("CPU2 -> CPU1 occupied: {}", ipcc.c2_is_active_flag(CHANNEL)).unwrap();
+
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
@@ -0,0 +1,118 @@
+//! Keeps CPU1 in Stop2 and lets CPU2 wake it up through the IPCC.
+//!
+//! Boots CPU2 and enters Stop2. The ready event of CPU2 wakes CPU1, which then starts the BLE
+//! stack and goes back to Stop2 until the response arrives. Events are reported through
+//! semihosting, Stop mode debugging is enabled to keep the debugger connected.
+#![no_std]
+#![no_main]
+
+extern crate panic_semihosting;
+extern crate stm32wb_hal as hal;
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::Mutex;
+use cortex_m::peripheral::NVIC;
+use cortex_m_rt::{entry, exception, ExceptionFrame};
+use cortex_m_semihosting::hprintln;
+
+use hal::bind_interrupt;
+use hal::flash::FlashExt;
+use hal::interrupt;
+use hal::ipcc::IpccExt;
+use hal::pac;
+use hal::prelude::*;
+use hal::rcc::{
+    ApbDivider, Config, HDivider, HseDivider, PllConfig, PllSrc, RfWakeupClock, StopWakeupClock,
+    SysClkSrc,
+};
+use hal::tl_mbox::shci::{shci_ble_init, ShciBleInitCmdParam, SHCI_SUB_EVT_CODE_READY};
+use hal::tl_mbox::TlMbox;
+use hal::wireless::low_power;
+
+static MBOX: Mutex<RefCell<Option<TlMbox>>> = Mutex::new(RefCell::new(None));
+
+#[entry]
+fn main() -> ! {
+    let mut cp = cortex_m::Peripherals::take().unwrap();
+    let dp = pac::Peripherals::take().unwrap();
+
+    cp.DCB.enable_trace();
+    cp.DWT.enable_cycle_counter();
+
+    let clock_config = Config::new(SysClkSrc::Pll(PllSrc::Hse(HseDivider::NotDivided)))
+        .cpu1_hdiv(HDivider::NotDivided)
+        .cpu2_hdiv(HDivider::Div2)
+        .apb1_div(ApbDivider::NotDivided)
+        .apb2_div(ApbDivider::NotDivided)
+        .pll_cfg(PllConfig {
+            m: 2,
+            n: 12,
+            r: 3,
+            q: Some(4),
+            p: Some(3),
+        })
+        .with_lse()
+        .rf_wkp_sel(RfWakeupClock::Lse);
+
+    let mut rcc = dp.RCC.constrain();
+    rcc.apply_clock_config(clock_config, &mut dp.FLASH.constrain().acr)
+        .unwrap();
+    rcc.set_stop_wakeup_clock(StopWakeupClock::HSI16);
+
+    hal::debug::stop_mode_debug(true);
+
+    let mut mbox = TlMbox::tl_init(&mut rcc, dp.IPCC.constrain());
+    mbox.ipcc().configure_wakeup(true);
+
+    cortex_m::interrupt::free(|cs| MBOX.borrow(cs).replace(Some(mbox)));
+    unsafe {
+        NVIC::unmask(interrupt::IPCC_C1_RX_IT);
+        NVIC::unmask(interrupt::IPCC_C1_TX_IT);
+    }
+
+    hal::pwr::set_cpu2(true);
+
+    loop {
+        cortex_m::interrupt::free(|cs| {
+            let mut mbox = MBOX.borrow(cs).borrow_mut();
+            let mbox = mbox.as_mut().unwrap();
+
+            while let Some(evt) = mbox.dequeue_event() {
+                low_power::event_dequeued();
+                hprintln!(
+                    "event {:#04x}, sub event {:?}",
+                    evt.view().evt_code(),
+                    evt.sub_event_code()
+                )
+                .unwrap();
+
+                if evt.sub_event_code() == Some(SHCI_SUB_EVT_CODE_READY) {
+                    shci_ble_init(mbox.ipcc(), &ShciBleInitCmdParam::default()).unwrap();
+                }
+            }
+
+            // The IPCC interrupt is handled once the critical section ends
+            if low_power::enter_stop2(&mut rcc, mbox, &mut cp.SCB).is_ok() {
+                if let Some(latency) = low_power::wakeup_latency() {
+                    hprintln!("woken up, max latency {} cycles", latency.max).unwrap();
+                }
+            }
+        });
+    }
+}
+
+bind_interrupt!(IPCC_C1_RX_IT => MBOX);
+bind_interrupt!(IPCC_C1_TX_IT => MBOX);
+
+#[exception]
+#[allow(non_snake_case)]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("HardFault at {:#?}", ef);
+}
+
+#[exception]
+#[allow(non_snake_case)]
+fn DefaultHandler(irqn: i16) {
+    panic!("Unhandled exception (IRQn = {})", irqn);
+}
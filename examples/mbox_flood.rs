@@ -0,0 +1,200 @@
+//! Event queue flood test.
+//!
+//! Enables passive BLE scanning without duplicate filtering, so every advertising packet around
+//! the board is reported as an event. The IPCC interrupts fill the event queue through
+//! `MboxIrq` while the main loop drains it through `MboxClient` outside of any critical section,
+//! slowed down on purpose so that the queue runs full and drains are deferred.
+//!
+//! Every event is checked for a BLE packet type and a length matching its header. Event and
+//! error counters and mailbox statistics are printed through semihosting periodically.
+//!
+//! Requires the BLE stack firmware on CPU2 and advertisers nearby.
+#![no_std]
+#![no_main]
+
+extern crate panic_semihosting;
+extern crate stm32wb_hal as hal;
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::Mutex;
+use cortex_m::peripheral::NVIC;
+use cortex_m_rt::{entry, exception, ExceptionFrame};
+use cortex_m_semihosting::hprintln;
+
+use hal::flash::FlashExt;
+use hal::interrupt;
+use hal::ipcc::IpccExt;
+use hal::pac;
+use hal::prelude::*;
+use hal::rcc::{
+    ApbDivider, Config, HDivider, HseDivider, PllConfig, PllSrc, RfWakeupClock, SysClkSrc,
+};
+use hal::tl_mbox::consts::TlPacketType;
+use hal::tl_mbox::evt::EvtBox;
+use hal::tl_mbox::shci::ShciBleInitCmdParam;
+use hal::tl_mbox::split::{MboxClient, MboxIrq};
+use hal::tl_mbox::TlMbox;
+
+const HCI_LE_SET_SCAN_PARAMETERS: u16 = 0x200b;
+const HCI_LE_SET_SCAN_ENABLE: u16 = 0x200c;
+const HCI_COMMAND_COMPLETE_EVT: u8 = 0x0e;
+
+/// Passive scan, 10 ms interval and window, public address, no filter
+const SCAN_PARAMETERS: [u8; 7] = [0x00, 0x10, 0x00, 0x10, 0x00, 0x00, 0x00];
+/// Scan enabled, duplicates reported
+const SCAN_ENABLE: [u8; 2] = [0x01, 0x00];
+
+/// Number of events between two reports
+const REPORT_INTERVAL: u32 = 1000;
+/// CPU cycles spent per dequeued event, slows down the consumer
+const CONSUMER_DELAY: u32 = 20_000;
+
+static MBOX_IRQ: Mutex<RefCell<Option<MboxIrq>>> = Mutex::new(RefCell::new(None));
+
+#[entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+
+    // * 32 MHz HSE with PLL
+    // * 64 MHz CPU1, 32 MHz CPU2
+    // * LSE for the RF wake-up clock
+    let clock_config = Config::new(SysClkSrc::Pll(PllSrc::Hse(HseDivider::NotDivided)))
+        .cpu1_hdiv(HDivider::NotDivided)
+        .cpu2_hdiv(HDivider::Div2)
+        .apb1_div(ApbDivider::NotDivided)
+        .apb2_div(ApbDivider::NotDivided)
+        .pll_cfg(PllConfig {
+            m: 2,
+            n: 12,
+            r: 3,
+            q: Some(4),
+            p: Some(3),
+        })
+        .with_lse()
+        .rf_wkp_sel(RfWakeupClock::Lse);
+
+    let mut rcc = dp.RCC.constrain();
+    rcc.apply_clock_config(clock_config, &mut dp.FLASH.constrain().acr)
+        .unwrap();
+
+    let mut mbox = TlMbox::tl_init(&mut rcc, dp.IPCC.constrain());
+
+    hal::pwr::set_cpu2(true);
+    mbox.wait_for_cpu2_ready(|| {});
+
+    let cc = mbox
+        .blocking_shci_ble_init(&ShciBleInitCmdParam::default(), || {})
+        .unwrap();
+    assert_eq!(cc.status(), 0);
+
+    let (irq, mut client) = mbox.split();
+    cortex_m::interrupt::free(|cs| MBOX_IRQ.borrow(cs).replace(Some(irq)));
+
+    unsafe {
+        NVIC::unmask(interrupt::IPCC_C1_RX_IT);
+        NVIC::unmask(interrupt::IPCC_C1_TX_IT);
+    }
+
+    client
+        .send_ble_cmd(HCI_LE_SET_SCAN_PARAMETERS, &SCAN_PARAMETERS)
+        .unwrap();
+    wait_command_complete(&mut client);
+
+    client
+        .send_ble_cmd(HCI_LE_SET_SCAN_ENABLE, &SCAN_ENABLE)
+        .unwrap();
+    wait_command_complete(&mut client);
+
+    hprintln!("Scanning").unwrap();
+
+    let mut events: u32 = 0;
+    let mut errors: u32 = 0;
+    loop {
+        let evt = match client.dequeue_event() {
+            Some(evt) => evt,
+            None => continue,
+        };
+
+        if !check_event(&evt) {
+            errors += 1;
+        }
+        drop(evt);
+
+        cortex_m::asm::delay(CONSUMER_DELAY);
+
+        events = events.wrapping_add(1);
+        if events % REPORT_INTERVAL == 0 {
+            let (stats, dropped) = cortex_m::interrupt::free(|cs| {
+                let irq = MBOX_IRQ.borrow(cs).borrow();
+                let irq = irq.as_ref().unwrap();
+                (irq.stats(), irq.dropped_events())
+            });
+
+            hprintln!(
+                "events {}: errors {}, dropped {}, deferred drains {}",
+                events,
+                errors,
+                dropped,
+                stats.deferred_drains
+            )
+            .unwrap();
+        }
+    }
+}
+
+/// Checks the packet type and that the event fits its header.
+fn check_event(evt: &EvtBox) -> bool {
+    let mut buf = [0u8; 258];
+    let len = match evt.write(&mut buf) {
+        Ok(len) => len,
+        Err(_) => return false,
+    };
+
+    // Event serial: kind, event code, payload length, payload
+    evt.kind() == TlPacketType::BleEvt as u8 && len == 3 + buf[2] as usize
+}
+
+/// Drops events until the Command Complete event of the last command.
+fn wait_command_complete(client: &mut MboxClient) {
+    loop {
+        if let Some(evt) = client.dequeue_event() {
+            let mut buf = [0u8; 258];
+            if let Ok(len) = evt.write(&mut buf) {
+                if len > 1 && buf[1] == HCI_COMMAND_COMPLETE_EVT {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[interrupt]
+fn IPCC_C1_RX_IT() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(irq) = MBOX_IRQ.borrow(cs).borrow_mut().as_mut() {
+            irq.interrupt_ipcc_rx_handler();
+        }
+    });
+}
+
+#[interrupt]
+fn IPCC_C1_TX_IT() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(irq) = MBOX_IRQ.borrow(cs).borrow_mut().as_mut() {
+            irq.interrupt_ipcc_tx_handler();
+        }
+    });
+}
+
+#[exception]
+#[allow(non_snake_case)]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("HardFault at {:#?}", ef);
+}
+
+#[exception]
+#[allow(non_snake_case)]
+fn DefaultHandler(irqn: i16) {
+    panic!("Unhandled exception (IRQn = {})", irqn);
+}
@@ -0,0 +1,155 @@
+//! Measures mailbox event latency from IPCC interrupt entry to application dequeue.
+//!
+//! Boots CPU2, initializes the BLE stack and sends a burst of `HCI_Read_Local_Version_Information`
+//! commands. Latency statistics of the command complete events are reported through
+//! semihosting.
+//!
+//! Requires the `mbox-latency` feature:
+//! `cargo run --example mbox_latency --features mbox-latency`
+#![no_std]
+#![no_main]
+
+extern crate panic_semihosting;
+extern crate stm32wb_hal as hal;
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::Mutex;
+use cortex_m::peripheral::NVIC;
+use cortex_m_rt::{entry, exception, ExceptionFrame};
+use cortex_m_semihosting::hprintln;
+
+use hal::bind_interrupt;
+use hal::flash::FlashExt;
+use hal::interrupt;
+use hal::ipcc::IpccExt;
+use hal::pac;
+use hal::prelude::*;
+use hal::rcc::{
+    ApbDivider, Config, HDivider, HseDivider, PllConfig, PllSrc, RfWakeupClock, SysClkSrc,
+};
+use hal::tl_mbox::ble::ble_send_cmd;
+use hal::tl_mbox::latency::NUM_SAMPLES;
+use hal::tl_mbox::shci::ShciBleInitCmdParam;
+use hal::tl_mbox::TlMbox;
+
+/// HCI_Read_Local_Version_Information: packet type, opcode 0x1001, no parameters
+const HCI_READ_LOCAL_VERSION: [u8; 4] = [0x01, 0x01, 0x10, 0x00];
+
+const BURST: usize = 32;
+
+static MBOX: Mutex<RefCell<Option<TlMbox>>> = Mutex::new(RefCell::new(None));
+
+#[entry]
+fn main() -> ! {
+    let mut cp = cortex_m::Peripherals::take().unwrap();
+    let dp = pac::Peripherals::take().unwrap();
+
+    cp.DCB.enable_trace();
+    cp.DWT.enable_cycle_counter();
+
+    let clock_config = Config::new(SysClkSrc::Pll(PllSrc::Hse(HseDivider::NotDivided)))
+        .cpu1_hdiv(HDivider::NotDivided)
+        .cpu2_hdiv(HDivider::Div2)
+        .apb1_div(ApbDivider::NotDivided)
+        .apb2_div(ApbDivider::NotDivided)
+        .pll_cfg(PllConfig {
+            m: 2,
+            n: 12,
+            r: 3,
+            q: Some(4),
+            p: Some(3),
+        })
+        .with_lse()
+        .rf_wkp_sel(RfWakeupClock::Lse);
+
+    let mut rcc = dp.RCC.constrain();
+    rcc.apply_clock_config(clock_config, &mut dp.FLASH.constrain().acr)
+        .unwrap();
+
+    let mut mbox = TlMbox::tl_init(&mut rcc, dp.IPCC.constrain());
+
+    hal::pwr::set_cpu2(true);
+
+    // Wait for CPU2 to become ready, then start the BLE stack
+    mbox.wait_for_cpu2_ready(|| {});
+
+    let cc = mbox
+        .blocking_shci_ble_init(&ShciBleInitCmdParam::default(), || {})
+        .unwrap();
+    hprintln!("BLE init status: {}", cc.payload[0]).unwrap();
+
+    mbox.clear_latency_samples();
+
+    cortex_m::interrupt::free(|cs| MBOX.borrow(cs).replace(Some(mbox)));
+    unsafe {
+        NVIC::unmask(interrupt::IPCC_C1_RX_IT);
+        NVIC::unmask(interrupt::IPCC_C1_TX_IT);
+    }
+
+    // Send commands one after another, events are collected by the IPCC RX interrupt
+    let mut received = 0;
+    cortex_m::interrupt::free(|cs| {
+        if let Some(mbox) = MBOX.borrow(cs).borrow_mut().as_mut() {
+            ble_send_cmd(mbox.ipcc(), &HCI_READ_LOCAL_VERSION);
+        }
+    });
+
+    while received < BURST {
+        cortex_m::interrupt::free(|cs| {
+            if let Some(mbox) = MBOX.borrow(cs).borrow_mut().as_mut() {
+                if mbox.dequeue_event().is_some() {
+                    received += 1;
+                    if received < BURST {
+                        ble_send_cmd(mbox.ipcc(), &HCI_READ_LOCAL_VERSION);
+                    }
+                }
+            }
+        });
+    }
+
+    let mut totals = [0u32; NUM_SAMPLES];
+    let count = cortex_m::interrupt::free(|cs| {
+        let mbox = MBOX.borrow(cs).borrow();
+        let mbox = mbox.as_ref().unwrap();
+
+        let samples = mbox.latency_samples();
+        for (total, sample) in totals.iter_mut().zip(samples) {
+            *total = sample.total();
+        }
+        samples.len()
+    });
+
+    let totals = &mut totals[..count];
+    totals.sort_unstable();
+
+    if count > 0 {
+        hprintln!(
+            "{} samples, IRQ to dequeue latency in cycles: min {}, median {}, max {}",
+            count,
+            totals[0],
+            totals[count / 2],
+            totals[count - 1]
+        )
+        .unwrap();
+    }
+
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
+
+bind_interrupt!(IPCC_C1_RX_IT => MBOX);
+bind_interrupt!(IPCC_C1_TX_IT => MBOX);
+
+#[exception]
+#[allow(non_snake_case)]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("HardFault at {:#?}", ef);
+}
+
+#[exception]
+#[allow(non_snake_case)]
+fn DefaultHandler(irqn: i16) {
+    panic!("Unhandled exception (IRQn = {})", irqn);
+}
@@ -0,0 +1,238 @@
+//! Mailbox stress test under USB load.
+//!
+//! Floods CPU2 with tagged `SHCI_C2_FUS_GetState` system commands while the USB CDC-ACM port
+//! echoes a data stream driven by the PC. The USB interrupt runs at a higher priority than the
+//! IPCC interrupts, so it preempts mailbox processing whenever no critical section is held.
+//!
+//! The PC is expected to send an incrementing byte sequence (0, 1, .., 255, 0, ..) and to check
+//! the echoed data. Sequence numbers are checked on both paths:
+//!
+//! * every command response must carry the tag of its command and the expected opcode,
+//! * every byte received over USB must follow the previous one.
+//!
+//! Failure counters and mailbox statistics are printed through semihosting periodically.
+#![no_std]
+#![no_main]
+
+extern crate panic_semihosting;
+extern crate stm32wb_hal as hal;
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use cortex_m::interrupt::Mutex;
+use cortex_m::peripheral::NVIC;
+use cortex_m_rt::{entry, exception, ExceptionFrame};
+use cortex_m_semihosting::hprintln;
+
+use hal::bind_interrupt;
+use hal::flash::FlashExt;
+use hal::interrupt;
+use hal::ipcc::IpccExt;
+use hal::pac;
+use hal::prelude::*;
+use hal::rcc::{
+    ApbDivider, Config, HDivider, HseDivider, PllConfig, PllSrc, RfWakeupClock, SysClkSrc,
+    UsbClkSrc,
+};
+use hal::serial_io::SerialIo;
+use hal::tl_mbox::shci::{shci_c2_fus_get_state, SHCI_OPCODE_C2_FUS_GET_STATE};
+use hal::tl_mbox::TlMbox;
+use hal::usb::{Peripheral, UsbBus, UsbBusType};
+
+use usb_device::bus::UsbBusAllocator;
+use usb_device::prelude::*;
+use usbd_serial::{SerialPort, USB_CLASS_CDC};
+
+/// Number of commands between two reports
+const REPORT_INTERVAL: u32 = 1000;
+
+static MBOX: Mutex<RefCell<Option<TlMbox>>> = Mutex::new(RefCell::new(None));
+static USB: Mutex<RefCell<Option<UsbState>>> = Mutex::new(RefCell::new(None));
+static mut USB_BUS: Option<UsbBusAllocator<UsbBusType>> = None;
+
+/// Bytes received over USB out of sequence
+static USB_SEQ_ERRORS: AtomicU32 = AtomicU32::new(0);
+/// Bytes received over USB
+static USB_BYTES: AtomicU32 = AtomicU32::new(0);
+
+struct UsbState {
+    device: UsbDevice<'static, UsbBusType>,
+    serial: SerialPort<'static, UsbBusType>,
+    next_byte: Option<u8>,
+}
+
+#[entry]
+fn main() -> ! {
+    let mut cp = cortex_m::Peripherals::take().unwrap();
+    let dp = pac::Peripherals::take().unwrap();
+
+    // * 32 MHz HSE with PLL
+    // * 64 MHz CPU1, 32 MHz CPU2
+    // * USB clock source from PLLQ (32 / 2 * 3 = 48)
+    let clock_config = Config::new(SysClkSrc::Pll(PllSrc::Hse(HseDivider::NotDivided)))
+        .cpu1_hdiv(HDivider::NotDivided)
+        .cpu2_hdiv(HDivider::Div2)
+        .apb1_div(ApbDivider::NotDivided)
+        .apb2_div(ApbDivider::NotDivided)
+        .pll_cfg(PllConfig {
+            m: 2,
+            n: 12,
+            r: 3,
+            q: Some(4),
+            p: Some(3),
+        })
+        .usb_src(UsbClkSrc::PllQ)
+        .with_lse()
+        .rf_wkp_sel(RfWakeupClock::Lse);
+
+    let mut rcc = dp.RCC.constrain();
+    rcc.apply_clock_config(clock_config, &mut dp.FLASH.constrain().acr)
+        .unwrap();
+
+    let mut mbox = TlMbox::tl_init(&mut rcc, dp.IPCC.constrain());
+
+    hal::pwr::set_cpu2(true);
+
+    // Wait for CPU2 to become ready
+    mbox.wait_for_cpu2_ready(|| {});
+
+    // USB CDC-ACM echo
+    hal::pwr::set_usb(true);
+
+    let mut gpioa = dp.GPIOA.split(&mut rcc);
+    let usb = Peripheral {
+        usb: dp.USB,
+        pin_dm: gpioa.pa11.into_af10(&mut gpioa.moder, &mut gpioa.afrh),
+        pin_dp: gpioa.pa12.into_af10(&mut gpioa.moder, &mut gpioa.afrh),
+    };
+
+    // NOTE(unsafe) written once before the USB interrupt is unmasked
+    let usb_bus = unsafe {
+        USB_BUS = Some(UsbBus::new(usb));
+        USB_BUS.as_ref().unwrap()
+    };
+
+    let serial = SerialPort::new(usb_bus);
+    let device = UsbDeviceBuilder::new(usb_bus, UsbVidPid(0x16c0, 0x27dd))
+        .manufacturer("Fake company")
+        .product("Mailbox stress test")
+        .serial_number("TEST")
+        .device_class(USB_CLASS_CDC)
+        .build();
+
+    mbox.clear_stats();
+
+    cortex_m::interrupt::free(|cs| {
+        MBOX.borrow(cs).replace(Some(mbox));
+        USB.borrow(cs).replace(Some(UsbState {
+            device,
+            serial,
+            next_byte: None,
+        }));
+    });
+
+    // USB preempts the mailbox handlers
+    unsafe {
+        cp.NVIC.set_priority(interrupt::USB_LP, 0x40);
+        cp.NVIC.set_priority(interrupt::IPCC_C1_RX_IT, 0x80);
+        cp.NVIC.set_priority(interrupt::IPCC_C1_TX_IT, 0x80);
+
+        NVIC::unmask(interrupt::USB_LP);
+        NVIC::unmask(interrupt::IPCC_C1_RX_IT);
+        NVIC::unmask(interrupt::IPCC_C1_TX_IT);
+    }
+
+    let mut tag: u32 = 0;
+    let mut sent = false;
+    let mut cmd_errors: u32 = 0;
+
+    loop {
+        // Send the next command as soon as the previous one has been answered
+        if !sent {
+            sent = with_mbox(|mbox| mbox.tagged_cmd(tag, shci_c2_fus_get_state).is_ok());
+            continue;
+        }
+
+        let response = with_mbox(|mbox| mbox.pop_tagged_cc_evt(tag));
+        let (resp_tag, cc) = match response {
+            Some(response) => response,
+            None => continue,
+        };
+
+        let cmd_code = cc.cmd_code;
+        if resp_tag != Some(tag) || cmd_code != SHCI_OPCODE_C2_FUS_GET_STATE {
+            cmd_errors += 1;
+        }
+
+        // Events are not used, but must not pile up in the queue
+        while with_mbox(|mbox| mbox.dequeue_event()).is_some() {}
+
+        tag = tag.wrapping_add(1);
+        sent = false;
+
+        if tag % REPORT_INTERVAL == 0 {
+            let stats = with_mbox(|mbox| mbox.stats());
+
+            hprintln!(
+                "cmds {}: cmd errors {}, tag mismatches {}, USB bytes {}, USB seq errors {}",
+                tag,
+                cmd_errors,
+                stats.tag_mismatches,
+                USB_BYTES.load(Ordering::Relaxed),
+                USB_SEQ_ERRORS.load(Ordering::Relaxed)
+            )
+            .unwrap();
+            hprintln!("{:?}", stats).unwrap();
+        }
+    }
+}
+
+fn with_mbox<R>(f: impl FnOnce(&mut TlMbox) -> R) -> R {
+    cortex_m::interrupt::free(|cs| f(MBOX.borrow(cs).borrow_mut().as_mut().unwrap()))
+}
+
+#[interrupt]
+fn USB_LP() {
+    cortex_m::interrupt::free(|cs| {
+        let mut usb = USB.borrow(cs).borrow_mut();
+        let usb = match usb.as_mut() {
+            Some(usb) => usb,
+            None => return,
+        };
+
+        if !usb.device.poll(&mut [&mut usb.serial]) {
+            return;
+        }
+
+        let mut buf = [0u8; 64];
+        if let Ok(count) = SerialIo::read(&mut usb.serial, &mut buf) {
+            for &byte in &buf[..count] {
+                if let Some(expected) = usb.next_byte {
+                    if byte != expected {
+                        USB_SEQ_ERRORS.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                usb.next_byte = Some(byte.wrapping_add(1));
+            }
+            USB_BYTES.fetch_add(count as u32, Ordering::Relaxed);
+
+            let _ = usb.serial.blocking_write_all(&buf[..count]);
+        }
+    });
+}
+
+bind_interrupt!(IPCC_C1_RX_IT => MBOX);
+bind_interrupt!(IPCC_C1_TX_IT => MBOX);
+
+#[exception]
+#[allow(non_snake_case)]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("HardFault at {:#?}", ef);
+}
+
+#[exception]
+#[allow(non_snake_case)]
+fn DefaultHandler(irqn: i16) {
+    panic!("Unhandled exception (IRQn = {})", irqn);
+}
@@ -0,0 +1,150 @@
+//! Board demo for the P-NUCLEO-WB55 exercising clocks, GPIO, EXTI, RTC and the mailbox together.
+//!
+//! * Clocks run at 64 MHz from HSE with PLL.
+//! * Green LED blinks, blue LED is toggled by the B1 button through the EXTI4 interrupt.
+//! * RTC time is reported once per second.
+//! * CPU2 wireless firmware version is reported once CPU2 signals ready.
+//!
+//! There is no USART or IWDG driver in the HAL yet, so reports go through semihosting instead of
+//! the ST-LINK VCP and the watchdog is not started.
+#![no_std]
+#![no_main]
+
+extern crate panic_semihosting;
+extern crate stm32wb_hal as hal;
+
+#[path = "board/nucleo_wb55.rs"]
+mod board;
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use cortex_m::interrupt::Mutex;
+use cortex_m::peripheral::NVIC;
+use cortex_m_rt::{entry, exception, ExceptionFrame};
+use cortex_m_semihosting::hprintln;
+use embedded_hal::digital::v2::OutputPin;
+
+use hal::delay::Delay;
+use hal::flash::FlashExt;
+use hal::gpio::{Edge, ExtiPin};
+use hal::interrupt;
+use hal::ipcc::IpccExt;
+use hal::pac;
+use hal::prelude::*;
+use hal::rtc::Rtc;
+use hal::tl_mbox::TlMbox;
+
+use board::{ButtonB1, Leds};
+
+static BUTTON: Mutex<RefCell<Option<ButtonB1>>> = Mutex::new(RefCell::new(None));
+static BUTTON_PRESSED: AtomicBool = AtomicBool::new(false);
+
+#[entry]
+fn main() -> ! {
+    let cp = cortex_m::Peripherals::take().unwrap();
+    let mut dp = pac::Peripherals::take().unwrap();
+
+    let mut rcc = dp.RCC.constrain();
+    rcc.apply_clock_config(board::clock_config(), &mut dp.FLASH.constrain().acr)
+        .unwrap();
+
+    // LEDs
+    let mut leds = Leds::new(dp.GPIOB.split(&mut rcc));
+
+    // B1 button on EXTI line 4
+    let mut gpioc = dp.GPIOC.split(&mut rcc);
+    let mut button = gpioc
+        .pc4
+        .into_pull_up_input(&mut gpioc.moder, &mut gpioc.pupdr);
+    button.make_interrupt_source(&mut dp.SYSCFG);
+    button.trigger_on_edge(&mut dp.EXTI, Edge::FALLING);
+    button.enable_interrupt(&mut dp.EXTI);
+    cortex_m::interrupt::free(|cs| BUTTON.borrow(cs).replace(Some(button)));
+
+    // RTC from LSE
+    let rtc = Rtc::rtc(dp.RTC, &mut rcc);
+
+    // Mailbox and CPU2
+    let mut mbox = TlMbox::tl_init(&mut rcc, dp.IPCC.constrain());
+    hal::pwr::set_cpu2(true);
+
+    unsafe {
+        NVIC::unmask(interrupt::EXTI4);
+    }
+
+    let mut delay = Delay::new(cp.SYST, rcc.clocks);
+
+    hprintln!("P-NUCLEO-WB55 demo, SYSCLK = {} Hz", rcc.clocks.sysclk().0).unwrap();
+
+    let mut blue_on = false;
+    let mut cpu2_ready = false;
+    let mut last_second = rtc.get_time().seconds;
+    loop {
+        if BUTTON_PRESSED.swap(false, Ordering::Relaxed) {
+            blue_on = !blue_on;
+            if blue_on {
+                let _ = leds.blue.set_high();
+            } else {
+                let _ = leds.blue.set_low();
+            }
+        }
+
+        if !cpu2_ready {
+            mbox.interrupt_ipcc_rx_handler();
+
+            while mbox.dequeue_event().is_some() {}
+
+            if let Some(ready) = mbox.cpu2_ready() {
+                cpu2_ready = true;
+
+                match ready.fw_info {
+                    Some(info) => hprintln!(
+                        "CPU2 firmware {}.{}.{}",
+                        info.version_major(),
+                        info.version_minor(),
+                        info.subversion()
+                    )
+                    .unwrap(),
+                    None => {
+                        hprintln!("CPU2 is ready ({:?}), no wireless firmware", ready.mode).unwrap()
+                    }
+                }
+            }
+        }
+
+        let time = rtc.get_time();
+        if time.seconds != last_second {
+            last_second = time.seconds;
+            hprintln!("{:02}:{:02}:{:02}", time.hours, time.minutes, time.seconds).unwrap();
+        }
+
+        let _ = leds.green.set_high();
+        delay.delay_ms(50_u32);
+        let _ = leds.green.set_low();
+        delay.delay_ms(50_u32);
+    }
+}
+
+#[interrupt]
+fn EXTI4() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(button) = BUTTON.borrow(cs).borrow_mut().as_mut() {
+            button.clear_interrupt_pending_bit();
+        }
+    });
+
+    BUTTON_PRESSED.store(true, Ordering::Relaxed);
+}
+
+#[exception]
+#[allow(non_snake_case)]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("HardFault at {:#?}", ef);
+}
+
+#[exception]
+#[allow(non_snake_case)]
+fn DefaultHandler(irqn: i16) {
+    panic!("Unhandled exception (IRQn = {})", irqn);
+}
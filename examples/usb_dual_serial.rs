@@ -0,0 +1,215 @@
+//! USB throughput test with two CDC-ACM ports.
+//!
+//! Both ports echo a data stream driven by the PC, so four bulk endpoints are active at the same
+//! time. The `USB_LP` handler polls the device until no endpoint has a pending transfer
+//! (`usb::ctr_pending`), and the SOF interrupt gives a 1 ms tick for the throughput report.
+//!
+//! The PC is expected to send an incrementing byte sequence (0, 1, .., 255, 0, ..) on each port
+//! and to check the echoed data. A byte that doesn't follow the previous one of its port is
+//! counted as lost. Byte rates and counters are printed through semihosting every second.
+#![no_std]
+#![no_main]
+
+extern crate panic_semihosting;
+extern crate stm32wb_hal as hal;
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use cortex_m::interrupt::Mutex;
+use cortex_m::peripheral::NVIC;
+use cortex_m_rt::{entry, exception, ExceptionFrame};
+use cortex_m_semihosting::hprintln;
+
+use hal::flash::FlashExt;
+use hal::interrupt;
+use hal::pac;
+use hal::prelude::*;
+use hal::rcc::{ApbDivider, Config, HDivider, HseDivider, PllConfig, PllSrc, SysClkSrc, UsbClkSrc};
+use hal::serial_io::SerialIo;
+use hal::usb::{self, Peripheral, UsbBus, UsbBusType};
+
+use usb_device::bus::UsbBusAllocator;
+use usb_device::prelude::*;
+
+use usbd_serial::SerialPort;
+
+/// Upper bound of polls per interrupt, a class that leaves a transfer unread must not hang the
+/// handler
+const MAX_POLLS: u32 = 16;
+
+static USB: Mutex<RefCell<Option<UsbState>>> = Mutex::new(RefCell::new(None));
+static mut USB_BUS: Option<UsbBusAllocator<UsbBusType>> = None;
+
+static BYTES: [AtomicU32; 2] = [AtomicU32::new(0), AtomicU32::new(0)];
+static LOST: [AtomicU32; 2] = [AtomicU32::new(0), AtomicU32::new(0)];
+/// Interrupts that needed more than one poll
+static DRAINED: AtomicU32 = AtomicU32::new(0);
+
+struct Port {
+    serial: SerialPort<'static, UsbBusType>,
+    next_byte: Option<u8>,
+}
+
+struct UsbState {
+    device: UsbDevice<'static, UsbBusType>,
+    ports: [Port; 2],
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+
+    // * 32 MHz HSE with PLL
+    // * 64 MHz CPU1, 32 MHz CPU2
+    // * USB clock source from PLLQ (32 / 2 * 3 = 48)
+    let clock_config = Config::new(SysClkSrc::Pll(PllSrc::Hse(HseDivider::NotDivided)))
+        .cpu1_hdiv(HDivider::NotDivided)
+        .cpu2_hdiv(HDivider::Div2)
+        .apb1_div(ApbDivider::NotDivided)
+        .apb2_div(ApbDivider::NotDivided)
+        .pll_cfg(PllConfig {
+            m: 2,
+            n: 12,
+            r: 3,
+            q: Some(4),
+            p: Some(3),
+        })
+        .usb_src(UsbClkSrc::PllQ);
+
+    let mut rcc = dp.RCC.constrain();
+    rcc.apply_clock_config(clock_config, &mut dp.FLASH.constrain().acr)
+        .unwrap();
+
+    hal::pwr::set_usb(true);
+
+    let mut gpioa = dp.GPIOA.split(&mut rcc);
+    let usb = Peripheral {
+        usb: dp.USB,
+        pin_dm: gpioa.pa11.into_af10(&mut gpioa.moder, &mut gpioa.afrh),
+        pin_dp: gpioa.pa12.into_af10(&mut gpioa.moder, &mut gpioa.afrh),
+    };
+
+    // NOTE(unsafe) written once before the USB interrupt is unmasked
+    let usb_bus = unsafe {
+        USB_BUS = Some(UsbBus::new(usb));
+        USB_BUS.as_ref().unwrap()
+    };
+
+    let ports = [
+        Port {
+            serial: SerialPort::new(usb_bus),
+            next_byte: None,
+        },
+        Port {
+            serial: SerialPort::new(usb_bus),
+            next_byte: None,
+        },
+    ];
+    let device = UsbDeviceBuilder::new(usb_bus, UsbVidPid(0x16c0, 0x27dd))
+        .manufacturer("Fake company")
+        .product("Dual serial port")
+        .serial_number("TEST")
+        .composite_with_iads()
+        .build();
+
+    usb::enable_sof_interrupt(true);
+
+    cortex_m::interrupt::free(|cs| USB.borrow(cs).replace(Some(UsbState { device, ports })));
+    unsafe {
+        NVIC::unmask(interrupt::USB_LP);
+    }
+
+    let mut last_sof = usb::sof_count();
+    let mut last_bytes = [0u32; 2];
+    loop {
+        // One SOF per millisecond while the bus is active
+        let sof = usb::sof_count();
+        if sof.wrapping_sub(last_sof) < 1000 {
+            continue;
+        }
+        last_sof = sof;
+
+        for (i, last) in last_bytes.iter_mut().enumerate() {
+            let bytes = BYTES[i].load(Ordering::Relaxed);
+            hprintln!(
+                "port {}: {} B/s, lost {}",
+                i,
+                bytes.wrapping_sub(*last),
+                LOST[i].load(Ordering::Relaxed)
+            )
+            .unwrap();
+            *last = bytes;
+        }
+        hprintln!(
+            "frame {}, drained interrupts {}",
+            usb::frame_number(),
+            DRAINED.load(Ordering::Relaxed)
+        )
+        .unwrap();
+    }
+}
+
+/// Echoes the received bytes and checks their sequence.
+fn echo(port: &mut Port, i: usize) {
+    let mut buf = [0u8; 64];
+    if let Ok(count) = SerialIo::read(&mut port.serial, &mut buf) {
+        for &byte in &buf[..count] {
+            if let Some(expected) = port.next_byte {
+                if byte != expected {
+                    LOST[i].fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            port.next_byte = Some(byte.wrapping_add(1));
+        }
+        BYTES[i].fetch_add(count as u32, Ordering::Relaxed);
+
+        let _ = port.serial.blocking_write_all(&buf[..count]);
+    }
+}
+
+#[interrupt]
+fn USB_LP() {
+    usb::on_frame_interrupt();
+
+    cortex_m::interrupt::free(|cs| {
+        let mut state = USB.borrow(cs).borrow_mut();
+        let state = match state.as_mut() {
+            Some(state) => state,
+            None => return,
+        };
+
+        let mut polls = 0;
+        loop {
+            let [port0, port1] = &mut state.ports;
+            if state
+                .device
+                .poll(&mut [&mut port0.serial, &mut port1.serial])
+            {
+                echo(port0, 0);
+                echo(port1, 1);
+            }
+
+            polls += 1;
+            if !usb::ctr_pending() || polls == MAX_POLLS {
+                break;
+            }
+        }
+
+        if polls > 1 {
+            DRAINED.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+}
+
+#[exception]
+#[allow(non_snake_case)]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("HardFault at {:#?}", ef);
+}
+
+#[exception]
+#[allow(non_snake_case)]
+fn DefaultHandler(irqn: i16) {
+    panic!("Unhandled exception (IRQn = {})", irqn);
+}
@@ -11,6 +11,7 @@ use hal::flash::FlashExt;
 use hal::pac;
 use hal::prelude::*;
 use hal::rcc::{ApbDivider, Config, HDivider, HseDivider, PllConfig, PllSrc, SysClkSrc, UsbClkSrc};
+use hal::serial_io::SerialIo;
 use hal::usb::{Peripheral, UsbBus};
 
 use usb_device::prelude::*;
@@ -20,7 +21,7 @@ use usbd_serial::{SerialPort, USB_CLASS_CDC};
 fn main() -> ! {
     let dp = pac::Peripherals::take().unwrap();
 
-    let rcc = dp.RCC.constrain();
+    let mut rcc = dp.RCC.constrain();
 
     // Fastest clock configuration.
     // * 32 MHz HSE with PLL
@@ -41,7 +42,8 @@ fn main() -> ! {
         })
         .usb_src(UsbClkSrc::PllQ);
 
-    let mut rcc = rcc.apply_clock_config(clock_config, &mut dp.FLASH.constrain().acr);
+    rcc.apply_clock_config(clock_config, &mut dp.FLASH.constrain().acr)
+        .unwrap();
 
     // Enable USB power supply
     hal::pwr::set_usb(true);
@@ -71,26 +73,15 @@ fn main() -> ! {
 
         let mut buf = [0u8; 64];
 
-        match serial.read(&mut buf) {
-            Ok(count) if count > 0 => {
-                // Echo back in upper case
-                for c in buf[0..count].iter_mut() {
-                    if 0x61 <= *c && *c <= 0x7a {
-                        *c &= !0x20;
-                    }
-                }
-
-                let mut write_offset = 0;
-                while write_offset < count {
-                    match serial.write(&buf[write_offset..count]) {
-                        Ok(len) if len > 0 => {
-                            write_offset += len;
-                        }
-                        _ => {}
-                    }
+        if let Ok(count) = SerialIo::read(&mut serial, &mut buf) {
+            // Echo back in upper case
+            for c in buf[0..count].iter_mut() {
+                if 0x61 <= *c && *c <= 0x7a {
+                    *c &= !0x20;
                 }
             }
-            _ => {}
+
+            let _ = serial.blocking_write_all(&buf[0..count]);
         }
     }
 }
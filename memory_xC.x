@@ -37,5 +37,17 @@ SECTIONS {
     EVT_QUEUE               0x20030b10 (NOLOAD) : { *(EVT_QUEUE) } >RAM_SHARED
     CS_BUFFER               0x20030b18 (NOLOAD) : { *(CS_BUFFER) } >RAM_SHARED
     TRACES_EVT_QUEUE        0x20030094 (NOLOAD) : { *(TRACES_EVT_QUEUE) } >RAM_SHARED
+    TRACES_EVT_POOL         0x20031070 (NOLOAD) : { *(TRACES_EVT_POOL) } >RAM_SHARED
     FREE_BUF_QUEUE          0x2003008c (NOLOAD) : { *(FREE_BUF_QUEUE) } >RAM_SHARED
+
+    OT_CMD_BUFFER           0x20030b30 (NOLOAD) : { *(OT_CMD_BUFFER) } >RAM_SHARED
+    CLI_CMD_BUFFER          0x20030c3c (NOLOAD) : { *(CLI_CMD_BUFFER) } >RAM_SHARED
+    OT_NOTIF_BUFFER         0x20030d48 (NOLOAD) : { *(OT_NOTIF_BUFFER) } >RAM_SHARED
+
+    MAC_802_15_4_CMD_BUFFER   0x20030e54 (NOLOAD) : { *(MAC_802_15_4_CMD_BUFFER) } >RAM_SHARED
+    MAC_802_15_4_NOTIF_BUFFER 0x20030f60 (NOLOAD) : { *(MAC_802_15_4_NOTIF_BUFFER) } >RAM_SHARED
 }
+
+/* CPU2 expects the reference table alone at the start of the shared RAM */
+ASSERT(ADDR(TL_REF_TABLE) == ORIGIN(RAM_SHARED), "TL_REF_TABLE must be at the start of RAM_SHARED")
+ASSERT(SIZEOF(TL_REF_TABLE) == 28, "TL_REF_TABLE section must only contain the reference table")
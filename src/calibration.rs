@@ -0,0 +1,345 @@
+//! Production calibration record
+//!
+//! A fixed-size, versioned record protected by a CRC, meant to be stored in a dedicated flash
+//! page. Layout (little endian):
+//!
+//! | Offset | Size | Field                        |
+//! |--------|------|------------------------------|
+//! | 0      | 4    | magic `CALB`                 |
+//! | 4      | 2    | record version               |
+//! | 6      | 1    | HSE trim (HSETUNE)           |
+//! | 7      | 1    | TX power backoff, 0.5 dB     |
+//! | 8      | 2    | RTC smooth calibration CALR  |
+//! | 10     | 2    | ADC offset                   |
+//! | 12     | 16   | reserved, zero               |
+//! | 28     | 4    | CRC-32/MPEG-2 of bytes 0..28 |
+//!
+//! CRC-32/MPEG-2 is what the CRC peripheral computes in its reset configuration, so the
+//! record can be checked in hardware as well.
+//!
+//! `save` programs the record through a `flash::WriteQueue`, into a page that must have been
+//! erased before, e.g. by the production programmer.
+
+use crate::flash::write_queue::EnqueueError;
+use crate::flash::{WriteQueue, WriteToken, PAGE_SIZE};
+use crate::rcc::Rcc;
+use crate::rtc::Rtc;
+
+/// Size of the record in bytes.
+pub const RECORD_SIZE: usize = 32;
+
+/// Current record version.
+pub const RECORD_VERSION: u16 = 1;
+
+const MAGIC: u32 = 0x424c_4143; // "CALB"
+const FLASH_BASE: u32 = 0x0800_0000;
+const CRC_OFFSET: usize = RECORD_SIZE - 4;
+
+// RTC_CALR bits
+const CALR_CALP: u16 = 1 << 15;
+const CALR_CALM_MASK: u16 = 0x1ff;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CalibrationError {
+    /// No record was found, e.g. the page is erased.
+    Missing,
+    /// Record was written by an unsupported version.
+    UnsupportedVersion(u16),
+    /// Record is corrupt.
+    CrcMismatch,
+    /// Record passed the CRC check, but holds values out of range.
+    InvalidValue,
+    /// Record area isn't erased, a record can only be saved once per erase.
+    NotErased,
+    /// Record couldn't be enqueued for programming.
+    Enqueue(EnqueueError),
+}
+
+/// Calibration values.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Calibration {
+    hse_tune: u8,
+    tx_power_backoff: u8,
+    rtc_calr: u16,
+    adc_offset: i16,
+}
+
+impl Calibration {
+    pub fn new() -> Self {
+        Calibration::default()
+    }
+
+    /// Sets HSE load capacitance trimming (0..=63).
+    pub fn with_hse_tune(mut self, tune: u8) -> Self {
+        assert!(tune <= 63);
+        self.hse_tune = tune;
+        self
+    }
+
+    /// Sets RTC smooth calibration, see `Rtc::calibrate`.
+    pub fn with_rtc_calibration(mut self, calp: bool, calm: u16) -> Self {
+        assert!(calm <= CALR_CALM_MASK);
+        self.rtc_calr = if calp { CALR_CALP } else { 0 } | calm;
+        self
+    }
+
+    /// Sets ADC offset.
+    pub fn with_adc_offset(mut self, offset: i16) -> Self {
+        self.adc_offset = offset;
+        self
+    }
+
+    /// Sets TX power backoff in 0.5 dB steps.
+    pub fn with_tx_power_backoff(mut self, backoff: u8) -> Self {
+        self.tx_power_backoff = backoff;
+        self
+    }
+
+    /// Returns HSE load capacitance trimming.
+    pub fn hse_tune(&self) -> u8 {
+        self.hse_tune
+    }
+
+    /// Returns RTC smooth calibration as (CALP, CALM).
+    pub fn rtc_calibration(&self) -> (bool, u16) {
+        (
+            self.rtc_calr & CALR_CALP != 0,
+            self.rtc_calr & CALR_CALM_MASK,
+        )
+    }
+
+    /// Returns ADC offset.
+    pub fn adc_offset(&self) -> i16 {
+        self.adc_offset
+    }
+
+    /// Returns TX power backoff in 0.5 dB steps.
+    pub fn tx_power_backoff(&self) -> u8 {
+        self.tx_power_backoff
+    }
+
+    /// Serializes the record, including the CRC.
+    pub fn to_bytes(&self) -> [u8; RECORD_SIZE] {
+        let mut buf = [0; RECORD_SIZE];
+
+        buf[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        buf[4..6].copy_from_slice(&RECORD_VERSION.to_le_bytes());
+        buf[6] = self.hse_tune;
+        buf[7] = self.tx_power_backoff;
+        buf[8..10].copy_from_slice(&self.rtc_calr.to_le_bytes());
+        buf[10..12].copy_from_slice(&self.adc_offset.to_le_bytes());
+
+        let crc = crc32_mpeg2(&buf[..CRC_OFFSET]);
+        buf[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+
+        buf
+    }
+
+    /// Parses and validates a serialized record.
+    pub fn from_bytes(buf: &[u8; RECORD_SIZE]) -> Result<Self, CalibrationError> {
+        let u16_at = |i: usize| u16::from_le_bytes([buf[i], buf[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+
+        if u32_at(0) != MAGIC {
+            return Err(CalibrationError::Missing);
+        }
+
+        let version = u16_at(4);
+        if version != RECORD_VERSION {
+            return Err(CalibrationError::UnsupportedVersion(version));
+        }
+
+        if u32_at(CRC_OFFSET) != crc32_mpeg2(&buf[..CRC_OFFSET]) {
+            return Err(CalibrationError::CrcMismatch);
+        }
+
+        let calibration = Calibration {
+            hse_tune: buf[6],
+            tx_power_backoff: buf[7],
+            rtc_calr: u16_at(8),
+            adc_offset: u16_at(10) as i16,
+        };
+
+        if calibration.hse_tune > 63 || calibration.rtc_calr & !(CALR_CALP | CALR_CALM_MASK) != 0 {
+            return Err(CalibrationError::InvalidValue);
+        }
+
+        Ok(calibration)
+    }
+
+    /// Applies HSE trimming.
+    pub fn apply_hse_tune(&self, rcc: &mut Rcc) {
+        rcc.set_hse_tune(self.hse_tune);
+    }
+
+    /// Applies RTC smooth calibration.
+    pub fn apply_rtc_calibration(&self, rtc: &Rtc) {
+        let (calp, calm) = self.rtc_calibration();
+        rtc.calibrate(calp, calm);
+    }
+}
+
+/// Loads and validates the record stored at the start of flash page `page`.
+pub fn load(page: u8) -> Result<Calibration, CalibrationError> {
+    Calibration::from_bytes(&read_record(page))
+}
+
+/// Enqueues `calibration` to be programmed at the start of flash page `page`.
+///
+/// The record area must be erased. The result of programming is reported by `queue` for the
+/// returned token, `load` reads the record back once it completed.
+pub fn save(
+    queue: &mut WriteQueue,
+    page: u8,
+    calibration: &Calibration,
+) -> Result<WriteToken, CalibrationError> {
+    if !is_erased(&read_record(page)) {
+        return Err(CalibrationError::NotErased);
+    }
+
+    queue
+        .enqueue(page_address(page), &calibration.to_bytes())
+        .map_err(CalibrationError::Enqueue)
+}
+
+fn page_address(page: u8) -> u32 {
+    FLASH_BASE + page as u32 * PAGE_SIZE
+}
+
+fn read_record(page: u8) -> [u8; RECORD_SIZE] {
+    let addr = page_address(page);
+
+    let mut buf = [0; RECORD_SIZE];
+    for (i, byte) in buf.iter_mut().enumerate() {
+        // NOTE(unsafe) flash is always readable
+        *byte = unsafe { core::ptr::read_volatile((addr as usize + i) as *const u8) };
+    }
+
+    buf
+}
+
+/// Returns `true` if `buf` holds erased flash.
+fn is_erased(buf: &[u8]) -> bool {
+    buf.iter().all(|&byte| byte == 0xff)
+}
+
+/// Bitwise CRC-32/MPEG-2: polynomial 0x04C11DB7, initial value 0xFFFFFFFF, no reflection.
+fn crc32_mpeg2(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calibration() -> Calibration {
+        Calibration::new()
+            .with_hse_tune(42)
+            .with_rtc_calibration(true, 0x123)
+            .with_adc_offset(-17)
+            .with_tx_power_backoff(6)
+    }
+
+    /// Recomputes the CRC of a modified record.
+    fn seal(buf: &mut [u8; RECORD_SIZE]) {
+        let crc = crc32_mpeg2(&buf[..CRC_OFFSET]);
+        buf[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    #[test]
+    fn crc32_mpeg2_check_value() {
+        assert_eq!(crc32_mpeg2(b"123456789"), 0x0376_e6e7);
+    }
+
+    #[test]
+    fn round_trip() {
+        let buf = calibration().to_bytes();
+        assert_eq!(&buf[0..4], b"CALB");
+        assert!(buf[12..CRC_OFFSET].iter().all(|&byte| byte == 0));
+
+        let parsed = Calibration::from_bytes(&buf).unwrap();
+        assert_eq!(parsed, calibration());
+        assert_eq!(parsed.rtc_calibration(), (true, 0x123));
+        assert_eq!(parsed.adc_offset(), -17);
+    }
+
+    #[test]
+    fn bad_crc() {
+        let mut buf = calibration().to_bytes();
+        buf[7] ^= 1;
+        assert_eq!(
+            Calibration::from_bytes(&buf),
+            Err(CalibrationError::CrcMismatch)
+        );
+
+        let mut buf = calibration().to_bytes();
+        buf[CRC_OFFSET] ^= 0x80;
+        assert_eq!(
+            Calibration::from_bytes(&buf),
+            Err(CalibrationError::CrcMismatch)
+        );
+    }
+
+    #[test]
+    fn missing_magic() {
+        let erased = [0xff; RECORD_SIZE];
+        assert!(is_erased(&erased));
+        assert_eq!(
+            Calibration::from_bytes(&erased),
+            Err(CalibrationError::Missing)
+        );
+
+        let mut buf = calibration().to_bytes();
+        buf[0] = b'X';
+        seal(&mut buf);
+        assert_eq!(
+            Calibration::from_bytes(&buf),
+            Err(CalibrationError::Missing)
+        );
+        assert!(!is_erased(&buf));
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut buf = calibration().to_bytes();
+        buf[4..6].copy_from_slice(&2u16.to_le_bytes());
+        seal(&mut buf);
+        assert_eq!(
+            Calibration::from_bytes(&buf),
+            Err(CalibrationError::UnsupportedVersion(2))
+        );
+    }
+
+    #[test]
+    fn out_of_range_values() {
+        let mut buf = calibration().to_bytes();
+        buf[6] = 64;
+        seal(&mut buf);
+        assert_eq!(
+            Calibration::from_bytes(&buf),
+            Err(CalibrationError::InvalidValue)
+        );
+
+        // Reserved RTC_CALR bits
+        let mut buf = calibration().to_bytes();
+        buf[9] |= 0x20;
+        seal(&mut buf);
+        assert_eq!(
+            Calibration::from_bytes(&buf),
+            Err(CalibrationError::InvalidValue)
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//! Debug support (DBGMCU)
+//!
+//! Freezes peripheral counters while the core is halted by a debugger and keeps the debug
+//! connection alive in low-power modes.
+
+use core::ops::BitOr;
+
+const DBGMCU_BASE: usize = 0xe004_2000;
+
+const DBGMCU_CR: usize = DBGMCU_BASE + 0x04;
+const DBGMCU_APB1FZR1: usize = DBGMCU_BASE + 0x3c;
+const DBGMCU_C2APB1FZR1: usize = DBGMCU_BASE + 0x40;
+const DBGMCU_APB1FZR2: usize = DBGMCU_BASE + 0x44;
+const DBGMCU_C2APB1FZR2: usize = DBGMCU_BASE + 0x48;
+const DBGMCU_APB2FZR: usize = DBGMCU_BASE + 0x4c;
+const DBGMCU_C2APB2FZR: usize = DBGMCU_BASE + 0x50;
+
+// DBGMCU_CR bits
+const CR_DBG_STOP: u32 = 1 << 1;
+
+/// Set of peripherals that can be stopped while a core is halted.
+///
+/// Combine with `|`: `Peripheral::IWDG | Peripheral::TIM1`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Peripheral {
+    apb1_1: u32,
+    apb1_2: u32,
+    apb2: u32,
+}
+
+impl Peripheral {
+    pub const NONE: Peripheral = Peripheral::apb1_1(0);
+
+    pub const TIM2: Peripheral = Peripheral::apb1_1(1 << 0);
+    pub const RTC: Peripheral = Peripheral::apb1_1(1 << 10);
+    pub const WWDG: Peripheral = Peripheral::apb1_1(1 << 11);
+    pub const IWDG: Peripheral = Peripheral::apb1_1(1 << 12);
+    pub const I2C1: Peripheral = Peripheral::apb1_1(1 << 21);
+    pub const I2C3: Peripheral = Peripheral::apb1_1(1 << 23);
+    pub const LPTIM1: Peripheral = Peripheral::apb1_1(1 << 31);
+
+    pub const LPTIM2: Peripheral = Peripheral::apb1_2(1 << 5);
+
+    pub const TIM1: Peripheral = Peripheral::apb2(1 << 11);
+    pub const TIM16: Peripheral = Peripheral::apb2(1 << 17);
+    pub const TIM17: Peripheral = Peripheral::apb2(1 << 18);
+
+    const fn apb1_1(bits: u32) -> Self {
+        Peripheral {
+            apb1_1: bits,
+            apb1_2: 0,
+            apb2: 0,
+        }
+    }
+
+    const fn apb1_2(bits: u32) -> Self {
+        Peripheral {
+            apb1_1: 0,
+            apb1_2: bits,
+            apb2: 0,
+        }
+    }
+
+    const fn apb2(bits: u32) -> Self {
+        Peripheral {
+            apb1_1: 0,
+            apb1_2: 0,
+            apb2: bits,
+        }
+    }
+}
+
+impl BitOr for Peripheral {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Peripheral {
+            apb1_1: self.apb1_1 | rhs.apb1_1,
+            apb1_2: self.apb1_2 | rhs.apb1_2,
+            apb2: self.apb2 | rhs.apb2,
+        }
+    }
+}
+
+fn modify(addr: usize, set: u32, clear: u32) {
+    // NOTE(unsafe) DBGMCU registers only affect debug behavior
+    unsafe {
+        let reg = addr as *mut u32;
+        reg.write_volatile((reg.read_volatile() & !clear) | set);
+    }
+}
+
+/// Stops `peripherals` while CPU1 is halted by the debugger.
+///
+/// Peripherals not in the set are left unchanged.
+pub fn freeze_on_halt(peripherals: Peripheral) {
+    modify(DBGMCU_APB1FZR1, peripherals.apb1_1, 0);
+    modify(DBGMCU_APB1FZR2, peripherals.apb1_2, 0);
+    modify(DBGMCU_APB2FZR, peripherals.apb2, 0);
+}
+
+/// Lets `peripherals` run while CPU1 is halted by the debugger.
+pub fn unfreeze_on_halt(peripherals: Peripheral) {
+    modify(DBGMCU_APB1FZR1, 0, peripherals.apb1_1);
+    modify(DBGMCU_APB1FZR2, 0, peripherals.apb1_2);
+    modify(DBGMCU_APB2FZR, 0, peripherals.apb2);
+}
+
+/// Stops `peripherals` while CPU2 is halted by the debugger.
+///
+/// Peripherals that CPU2 can't freeze (TIM2, WWDG and IWDG) are ignored.
+pub fn c2_freeze_on_halt(peripherals: Peripheral) {
+    let c2_apb1_1 = !(Peripheral::TIM2 | Peripheral::WWDG | Peripheral::IWDG).apb1_1;
+
+    modify(DBGMCU_C2APB1FZR1, peripherals.apb1_1 & c2_apb1_1, 0);
+    modify(DBGMCU_C2APB1FZR2, peripherals.apb1_2, 0);
+    modify(DBGMCU_C2APB2FZR, peripherals.apb2, 0);
+}
+
+/// Keeps the debug connection alive in Stop modes.
+///
+/// Increases Stop mode consumption, should be disabled in production.
+pub fn stop_mode_debug(enabled: bool) {
+    if enabled {
+        modify(DBGMCU_CR, CR_DBG_STOP, 0);
+    } else {
+        modify(DBGMCU_CR, 0, CR_DBG_STOP);
+    }
+}
@@ -0,0 +1,114 @@
+//! EXTI line handler registry
+//!
+//! Requires the `exti-registry` feature.
+//!
+//! Handlers are registered per EXTI line (0..=15) and invoked by `dispatch`, which should be
+//! called from the corresponding `EXTIx` interrupt handlers:
+//!
+//! ```ignore
+//! #[interrupt]
+//! fn EXTI15_10() {
+//!     exti::dispatch(IrqRange::Exti15_10);
+//! }
+//! ```
+
+#![cfg(feature = "exti-registry")]
+
+use crate::stm32::EXTI;
+
+/// Number of GPIO EXTI lines.
+pub const NUM_LINES: usize = 16;
+
+/// Line handler. Receives the number of the line that triggered.
+pub type Handler = fn(u8);
+
+/// Registry error
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// Line number is out of the 0..=15 range.
+    InvalidLine,
+    /// A handler is already registered for the line.
+    AlreadyRegistered,
+}
+
+/// EXTI lines served by a single interrupt vector.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum IrqRange {
+    Exti0,
+    Exti1,
+    Exti2,
+    Exti3,
+    Exti4,
+    Exti9_5,
+    Exti15_10,
+}
+
+impl IrqRange {
+    /// Returns a mask of EXTI lines served by this interrupt vector.
+    pub fn mask(&self) -> u32 {
+        match self {
+            IrqRange::Exti0 => 1 << 0,
+            IrqRange::Exti1 => 1 << 1,
+            IrqRange::Exti2 => 1 << 2,
+            IrqRange::Exti3 => 1 << 3,
+            IrqRange::Exti4 => 1 << 4,
+            IrqRange::Exti9_5 => 0b11111 << 5,
+            IrqRange::Exti15_10 => 0b111111 << 10,
+        }
+    }
+}
+
+static mut HANDLERS: [Option<Handler>; NUM_LINES] = [None; NUM_LINES];
+
+/// Registers `handler` for the EXTI `line`.
+///
+/// Fails if the line number is invalid or the line already has a handler.
+pub fn register(line: u8, handler: Handler) -> Result<(), Error> {
+    if line as usize >= NUM_LINES {
+        return Err(Error::InvalidLine);
+    }
+
+    cortex_m::interrupt::free(|_| unsafe {
+        let slot = &mut HANDLERS[line as usize];
+        if slot.is_some() {
+            return Err(Error::AlreadyRegistered);
+        }
+
+        *slot = Some(handler);
+        Ok(())
+    })
+}
+
+/// Removes the handler registered for the EXTI `line`, if any.
+pub fn unregister(line: u8) -> Result<Option<Handler>, Error> {
+    if line as usize >= NUM_LINES {
+        return Err(Error::InvalidLine);
+    }
+
+    Ok(cortex_m::interrupt::free(|_| unsafe {
+        HANDLERS[line as usize].take()
+    }))
+}
+
+/// Clears pending EXTI lines of the `range` and calls their handlers.
+///
+/// Pending lines without a registered handler are cleared as well.
+pub fn dispatch(range: IrqRange) {
+    // NOTE(unsafe) pending register is only cleared for the lines of this interrupt vector
+    let exti = unsafe { &*EXTI::ptr() };
+
+    let pending = exti.pr1.read().bits() & range.mask();
+    exti.pr1.write(|w| unsafe { w.bits(pending) });
+
+    for line in 0..NUM_LINES {
+        if pending & (1 << line) == 0 {
+            continue;
+        }
+
+        // Handlers are called outside of the critical section
+        let handler = cortex_m::interrupt::free(|_| unsafe { HANDLERS[line] });
+        if let Some(handler) = handler {
+            handler(line as u8);
+        }
+    }
+}
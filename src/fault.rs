@@ -0,0 +1,86 @@
+//! Fault policy
+//!
+//! Protocol violations detected by the transport layer (full event queue, malformed event,
+//! missing shared table, message on a channel the driver doesn't handle) are reported as a
+//! `FaultKind` to the `FaultHandler` selected in `TlMboxConfig`. Every fault is counted in
+//! `MboxStats::faults` before the handler is called.
+//!
+//! The default handler, `PanicOnFault`, panics, which is what you want during development. A
+//! shipped device can use `CountAndContinue` instead: the offending event is dropped (its buffer
+//! is still returned to CPU2) and the mailbox keeps running.
+
+/// Fault detected by the transport layer. Each call site reports a distinct kind.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FaultKind {
+    /// System event was dropped, the event queue was full.
+    SysEvtQueueFull,
+    /// BLE event was dropped, the event queue was full.
+    BleEvtQueueFull,
+    /// System command response is shorter than a Command Complete header.
+    InvalidCmdRspLength,
+    /// Memory manager table isn't set up, released buffers are kept until it is.
+    NullMemManagerTable,
+    /// Message received on an IPCC channel without handler, it was acknowledged and dropped.
+    UnhandledRxChannel(u8),
+    /// IPCC channel without handler was freed, the TX interrupt was disabled.
+    UnhandledTxChannel(u8),
+}
+
+/// Reaction to transport layer faults.
+pub trait FaultHandler: Sync {
+    /// Called after `kind` has been counted. Returning continues with the fault recovery of
+    /// the call site.
+    fn on_fault(&self, kind: FaultKind);
+}
+
+/// Panics on every fault.
+pub struct PanicOnFault;
+
+impl FaultHandler for PanicOnFault {
+    fn on_fault(&self, kind: FaultKind) {
+        panic!("mailbox fault: {:?}", kind);
+    }
+}
+
+/// Only counts faults and continues.
+pub struct CountAndContinue;
+
+impl FaultHandler for CountAndContinue {
+    fn on_fault(&self, _kind: FaultKind) {}
+}
+
+/// Number of faults of each kind.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FaultCounters {
+    pub sys_evt_queue_full: u32,
+    pub ble_evt_queue_full: u32,
+    pub invalid_cmd_rsp_length: u32,
+    pub null_mem_manager_table: u32,
+    pub unhandled_rx_channel: u32,
+    pub unhandled_tx_channel: u32,
+}
+
+impl FaultCounters {
+    /// Returns total number of faults.
+    pub fn total(&self) -> u32 {
+        self.sys_evt_queue_full
+            .wrapping_add(self.ble_evt_queue_full)
+            .wrapping_add(self.invalid_cmd_rsp_length)
+            .wrapping_add(self.null_mem_manager_table)
+            .wrapping_add(self.unhandled_rx_channel)
+            .wrapping_add(self.unhandled_tx_channel)
+    }
+
+    pub(crate) fn record(&mut self, kind: FaultKind) {
+        let counter = match kind {
+            FaultKind::SysEvtQueueFull => &mut self.sys_evt_queue_full,
+            FaultKind::BleEvtQueueFull => &mut self.ble_evt_queue_full,
+            FaultKind::InvalidCmdRspLength => &mut self.invalid_cmd_rsp_length,
+            FaultKind::NullMemManagerTable => &mut self.null_mem_manager_table,
+            FaultKind::UnhandledRxChannel(_) => &mut self.unhandled_rx_channel,
+            FaultKind::UnhandledTxChannel(_) => &mut self.unhandled_tx_channel,
+        };
+
+        *counter = counter.wrapping_add(1);
+    }
+}
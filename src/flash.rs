@@ -2,6 +2,12 @@
 
 use crate::stm32::{flash, FLASH};
 
+pub mod write_queue;
+
+use write_queue::{CR_LOCK, KEY1, KEY2, SR_BSY, SR_ERRORS};
+
+pub use write_queue::{WriteError, WriteQueue, WriteToken};
+
 /// Extension trait to constrain the FLASH peripheral
 pub trait FlashExt {
     /// Constrains the FLASH peripheral to play nicely with the other abstractions
@@ -12,6 +18,7 @@ impl FlashExt for FLASH {
     fn constrain(self) -> Parts {
         Parts {
             acr: ACR { _0: () },
+            cr: CR { _0: () },
         }
     }
 }
@@ -20,6 +27,8 @@ impl FlashExt for FLASH {
 pub struct Parts {
     /// Opaque ACR register
     pub acr: ACR,
+    /// Opaque CR register, needed to program flash
+    pub cr: CR,
 }
 
 /// Opaque ACR register
@@ -33,3 +42,275 @@ impl ACR {
         unsafe { &(*FLASH::ptr()).acr }
     }
 }
+
+/// Opaque CR register
+///
+/// Grants exclusive access to the program/erase control registers (KEYR, SR and CR).
+pub struct CR {
+    _0: (),
+}
+
+impl CR {
+    pub(crate) fn rb(&mut self) -> &flash::RegisterBlock {
+        // NOTE(unsafe) this proxy grants exclusive access to the program/erase registers
+        unsafe { &*FLASH::ptr() }
+    }
+}
+
+/// HSEM semaphore held by CPU1 while it programs or erases flash and CPU2 runs (AN5289).
+pub const FLASH_SEMAPHORE: u8 = 2;
+
+/// HSEM semaphore CPU2 holds while flash must not be accessed, e.g. during radio activity.
+///
+/// CPU2 only uses it after `tl_mbox::shci::shci_c2_set_flash_activity_control` selected it.
+pub const CPU2_BLOCK_FLASH_SEMAPHORE: u8 = 7;
+
+/// Flash page size in bytes
+pub const PAGE_SIZE: u32 = 4096;
+
+/// Address of the flash size in KiB (FLASHSIZE)
+const FLASHSIZE_BASE: u32 = 0x1fff_75e0;
+
+/// Returns flash size in bytes.
+pub fn flash_size() -> u32 {
+    // NOTE(unsafe) read-only engineering data
+    let kib = unsafe { core::ptr::read_volatile(FLASHSIZE_BASE as *const u16) };
+    kib as u32 * 1024
+}
+
+/// Write-protected area, inclusive range of flash pages
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WrpArea {
+    pub start_page: u8,
+    pub end_page: u8,
+}
+
+impl WrpArea {
+    fn from_bits(bits: u32) -> Option<Self> {
+        let start_page = (bits & 0xff) as u8;
+        let end_page = ((bits >> 16) & 0xff) as u8;
+
+        // Area is disabled when its start is past its end
+        if start_page <= end_page {
+            Some(WrpArea {
+                start_page,
+                end_page,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the register value of `area`, a disabled area has its start past its end.
+    fn to_bits(area: Option<Self>) -> u32 {
+        match area {
+            Some(area) => area.start_page as u32 | (area.end_page as u32) << 16,
+            None => WRP_DISABLED,
+        }
+    }
+
+    /// Returns `true` if any page of `first..=last` is protected by this area.
+    pub fn overlaps(&self, first: u8, last: u8) -> bool {
+        first <= self.end_page && last >= self.start_page
+    }
+}
+
+/// Currently active write protection of the CPU1 flash
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WriteProtection {
+    /// Area A (`FLASH_WRP1AR`)
+    pub area_a: Option<WrpArea>,
+    /// Area B (`FLASH_WRP1BR`)
+    pub area_b: Option<WrpArea>,
+    /// First page of the secure area owned by FUS and the wireless stack, if flash security is
+    /// enabled. The area extends to the end of flash.
+    pub secure_start_page: Option<u8>,
+}
+
+impl WriteProtection {
+    /// Returns `true` if any page of `first..=last` can't be erased or programmed by CPU1.
+    pub fn is_protected(&self, first: u8, last: u8) -> bool {
+        self.area_a.map_or(false, |a| a.overlaps(first, last))
+            || self.area_b.map_or(false, |a| a.overlaps(first, last))
+            || self.secure_start_page.map_or(false, |s| last >= s)
+    }
+}
+
+// FLASH_SFR bits
+const SFR_SFSA_MASK: u32 = 0xff;
+const SFR_FSD: u32 = 1 << 8;
+
+// FLASH_WRP1xR fields
+const WRP_STRT_END_MASK: u32 = 0x00ff_00ff;
+const WRP_DISABLED: u32 = 0x0000_00ff;
+
+// FLASH_CR bits
+const CR_OPTSTRT: u32 = 1 << 17;
+const CR_OBL_LAUNCH: u32 = 1 << 27;
+const CR_OPTLOCK: u32 = 1 << 30;
+
+// FLASH_SR bits
+const SR_OPTVERR: u32 = 1 << 15;
+
+const OPT_KEY1: u32 = 0x0819_2a3b;
+const OPT_KEY2: u32 = 0x4c5d_6e7f;
+
+/// Write protection configuration error.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WrpConfigError {
+    /// Area has its start page past its end page, use `None` to disable an area.
+    InvalidArea,
+    /// Area extends past the end of flash or into the secure area.
+    OutOfRange,
+    /// CPU2 currently holds `FLASH_SEMAPHORE`.
+    Cpu2Busy,
+    /// Option byte programming failed with the given FLASH_SR error bits.
+    Program(u32),
+}
+
+/// Reads the active write protection settings from the loaded option bytes.
+pub fn write_protection() -> WriteProtection {
+    // NOTE(unsafe) read-only access to option byte registers
+    let flash = unsafe { &*FLASH::ptr() };
+
+    let sfr = flash.sfr.read().bits();
+    let secure_start_page = if sfr & SFR_FSD == 0 {
+        Some((sfr & SFR_SFSA_MASK) as u8)
+    } else {
+        None
+    };
+
+    WriteProtection {
+        area_a: WrpArea::from_bits(flash.wrp1ar.read().bits()),
+        area_b: WrpArea::from_bits(flash.wrp1br.read().bits()),
+        secure_start_page,
+    }
+}
+
+/// Checks that `area` only covers pages CPU1 owns.
+fn check_area(area: Option<WrpArea>, protection: &WriteProtection) -> Result<(), WrpConfigError> {
+    let area = match area {
+        Some(area) => area,
+        None => return Ok(()),
+    };
+
+    if area.start_page > area.end_page {
+        return Err(WrpConfigError::InvalidArea);
+    }
+    let page_count = flash_size() / PAGE_SIZE;
+    if area.end_page as u32 >= page_count
+        || protection
+            .secure_start_page
+            .map_or(false, |s| area.end_page >= s)
+    {
+        return Err(WrpConfigError::OutOfRange);
+    }
+    Ok(())
+}
+
+/// Programs the write protection areas A and B into the option bytes, `None` disables an area.
+///
+/// The new settings are only loaded by `launch_option_bytes` or a power-on reset, until then
+/// `write_protection` and program/erase checks keep using the active ones. Must not be called
+/// while a `WriteQueue` is programming.
+pub fn set_write_protection(
+    cr: &mut CR,
+    area_a: Option<WrpArea>,
+    area_b: Option<WrpArea>,
+) -> Result<(), WrpConfigError> {
+    let protection = write_protection();
+    check_area(area_a, &protection)?;
+    check_area(area_b, &protection)?;
+
+    let cpu2_running = crate::pwr::cpu2_booted();
+    if cpu2_running && !crate::rcc::hsem_try_lock(FLASH_SEMAPHORE) {
+        return Err(WrpConfigError::Cpu2Busy);
+    }
+
+    let rb = cr.rb();
+    if rb.cr.read().bits() & CR_LOCK != 0 {
+        rb.keyr.write(|w| unsafe { w.bits(KEY1) });
+        rb.keyr.write(|w| unsafe { w.bits(KEY2) });
+    }
+    if rb.cr.read().bits() & CR_OPTLOCK != 0 {
+        rb.optkeyr.write(|w| unsafe { w.bits(OPT_KEY1) });
+        rb.optkeyr.write(|w| unsafe { w.bits(OPT_KEY2) });
+    }
+    while rb.sr.read().bits() & SR_BSY != 0 {}
+    rb.sr.write(|w| unsafe { w.bits(SR_ERRORS | SR_OPTVERR) });
+
+    // Reserved bits must keep their value
+    rb.wrp1ar.modify(|r, w| unsafe {
+        w.bits((r.bits() & !WRP_STRT_END_MASK) | WrpArea::to_bits(area_a))
+    });
+    rb.wrp1br.modify(|r, w| unsafe {
+        w.bits((r.bits() & !WRP_STRT_END_MASK) | WrpArea::to_bits(area_b))
+    });
+
+    rb.cr
+        .modify(|r, w| unsafe { w.bits(r.bits() | CR_OPTSTRT) });
+    while rb.sr.read().bits() & SR_BSY != 0 {}
+    let errors = rb.sr.read().bits() & (SR_ERRORS | SR_OPTVERR);
+
+    rb.cr
+        .modify(|r, w| unsafe { w.bits(r.bits() | CR_OPTLOCK | CR_LOCK) });
+    if cpu2_running {
+        crate::rcc::hsem_unlock(FLASH_SEMAPHORE);
+    }
+
+    if errors != 0 {
+        rb.sr.write(|w| unsafe { w.bits(errors) });
+        return Err(WrpConfigError::Program(errors));
+    }
+    Ok(())
+}
+
+/// Loads the programmed option bytes, this resets the device.
+pub fn launch_option_bytes(cr: &mut CR) -> ! {
+    let rb = cr.rb();
+    if rb.cr.read().bits() & CR_LOCK != 0 {
+        rb.keyr.write(|w| unsafe { w.bits(KEY1) });
+        rb.keyr.write(|w| unsafe { w.bits(KEY2) });
+    }
+    if rb.cr.read().bits() & CR_OPTLOCK != 0 {
+        rb.optkeyr.write(|w| unsafe { w.bits(OPT_KEY1) });
+        rb.optkeyr.write(|w| unsafe { w.bits(OPT_KEY2) });
+    }
+    rb.cr
+        .modify(|r, w| unsafe { w.bits(r.bits() | CR_OBL_LAUNCH) });
+
+    loop {
+        cortex_m::asm::nop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrp_area_bits_roundtrip() {
+        let area = WrpArea {
+            start_page: 3,
+            end_page: 17,
+        };
+        assert_eq!(WrpArea::to_bits(Some(area)), 0x0011_0003);
+        assert_eq!(WrpArea::from_bits(WrpArea::to_bits(Some(area))), Some(area));
+        assert_eq!(WrpArea::from_bits(WrpArea::to_bits(None)), None);
+    }
+
+    #[test]
+    fn protection_covers_secure_area() {
+        let protection = WriteProtection {
+            area_a: Some(WrpArea {
+                start_page: 0,
+                end_page: 1,
+            }),
+            area_b: None,
+            secure_start_page: Some(200),
+        };
+        assert!(protection.is_protected(1, 4));
+        assert!(!protection.is_protected(2, 199));
+        assert!(protection.is_protected(150, 200));
+    }
+}
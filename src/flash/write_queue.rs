@@ -0,0 +1,420 @@
+//! Interrupt-driven flash programming queue
+//!
+//! `WriteQueue::enqueue` copies a record into the queue and returns right away. Records are
+//! programmed one double word at a time: the `FLASH` interrupt (end of operation or error)
+//! checks the double word just programmed and starts the next one, so the caller never waits
+//! for flash.
+//!
+//! The queue must be bound to the `FLASH` interrupt, and to the `HSEM` interrupt when CPU2 runs:
+//!
+//! ```ignore
+//! static FLASH_QUEUE: Mutex<RefCell<Option<WriteQueue>>> = Mutex::new(RefCell::new(None));
+//!
+//! bind_interrupt!(FLASH => FLASH_QUEUE);
+//! bind_interrupt!(HSEM => FLASH_QUEUE);
+//! ```
+//!
+//! While CPU2 runs, programming follows AN5289: CPU1 holds `FLASH_SEMAPHORE` while the queue
+//! isn't empty, and only starts a double word while CPU2 doesn't hold
+//! `CPU2_BLOCK_FLASH_SEMAPHORE`. Programming resumes from the `HSEM` interrupt once the
+//! semaphore CPU2 held is released. If CPU2 suspends flash operations with PESD instead,
+//! `retry` must be called periodically.
+//!
+//! A record that fails (write protection, programming error, data read back differs) is
+//! completed with an error and the queue continues with the next record.
+
+use heapless::consts::U8;
+use heapless::spsc;
+
+use super::PAGE_SIZE;
+use super::{flash_size, write_protection, CPU2_BLOCK_FLASH_SEMAPHORE, CR, FLASH_SEMAPHORE};
+use crate::interrupts::{irq, InterruptHandler};
+use crate::rcc::{hsem_is_locked_by_other, hsem_set_free_irq, hsem_take_free_irq};
+use crate::rcc::{hsem_try_lock, hsem_unlock};
+
+/// Maximum length of a record in bytes.
+pub const MAX_RECORD_LEN: usize = 64;
+
+const FLASH_BASE: u32 = 0x0800_0000;
+
+pub(super) const KEY1: u32 = 0x4567_0123;
+pub(super) const KEY2: u32 = 0xcdef_89ab;
+
+// FLASH_SR bits
+const SR_EOP: u32 = 1 << 0;
+const SR_OPERR: u32 = 1 << 1;
+const SR_PROGERR: u32 = 1 << 3;
+const SR_WRPERR: u32 = 1 << 4;
+const SR_PGAERR: u32 = 1 << 5;
+const SR_SIZERR: u32 = 1 << 6;
+const SR_PGSERR: u32 = 1 << 7;
+const SR_MISSERR: u32 = 1 << 8;
+const SR_FASTERR: u32 = 1 << 9;
+pub(super) const SR_BSY: u32 = 1 << 16;
+const SR_PESD: u32 = 1 << 19;
+pub(super) const SR_ERRORS: u32 =
+    SR_OPERR | SR_PROGERR | SR_WRPERR | SR_PGAERR | SR_SIZERR | SR_PGSERR | SR_MISSERR | SR_FASTERR;
+
+// FLASH_CR bits
+const CR_PG: u32 = 1 << 0;
+const CR_EOPIE: u32 = 1 << 24;
+const CR_ERRIE: u32 = 1 << 25;
+pub(super) const CR_LOCK: u32 = 1 << 31;
+
+/// Identifies an enqueued record in its completion.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct WriteToken(u32);
+
+/// Completion of a record.
+pub type WriteResult = Result<(), WriteError>;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WriteError {
+    /// Record targets a write protected page (WRPERR).
+    WriteProtected,
+    /// Programming failed with the given FLASH_SR error bits, e.g. PROGERR if the target wasn't
+    /// erased.
+    Program(u32),
+    /// Double word at `address` differs from the record after programming.
+    VerifyMismatch { address: u32 },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EnqueueError {
+    /// Queue is full.
+    Full,
+    /// Address is not aligned to a double word (8 bytes).
+    Unaligned,
+    /// Record is empty or longer than `MAX_RECORD_LEN`.
+    InvalidLength,
+    /// Record doesn't fit into flash.
+    OutOfRange,
+    /// Record targets a page protected by the active WRP areas or the secure area.
+    WriteProtected,
+}
+
+struct Record {
+    token: WriteToken,
+    address: u32,
+    len: usize,
+    data: [u8; MAX_RECORD_LEN],
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum State {
+    Idle,
+    Programming,
+    /// Waiting for CPU2 to allow flash accesses
+    Blocked,
+}
+
+/// Background flash programming queue, see module documentation.
+pub struct WriteQueue {
+    cr: CR,
+    pending: spsc::Queue<Record, U8>,
+    /// Record being programmed and offset of its next double word
+    current: Option<(Record, usize)>,
+    completions: spsc::Queue<(WriteToken, WriteResult), U8>,
+    callback: Option<fn(WriteToken, WriteResult)>,
+    lost_completions: u32,
+    next_token: u32,
+    state: State,
+    flash_sem_held: bool,
+}
+
+impl WriteQueue {
+    /// Creates a queue delivering completions through `poll_completion`.
+    pub fn new(cr: CR) -> Self {
+        WriteQueue {
+            cr,
+            pending: spsc::Queue::new(),
+            current: None,
+            completions: spsc::Queue::new(),
+            callback: None,
+            lost_completions: 0,
+            next_token: 0,
+            state: State::Idle,
+            flash_sem_held: false,
+        }
+    }
+
+    /// Delivers completions to `callback` instead, called from the `FLASH` interrupt.
+    pub fn with_callback(mut self, callback: fn(WriteToken, WriteResult)) -> Self {
+        self.callback = Some(callback);
+        self
+    }
+
+    /// Enqueues `data` to be programmed at `address`.
+    ///
+    /// The last double word is padded with `0xff`. Records targeting a write protected page are
+    /// rejected with `EnqueueError::WriteProtected`. Starts programming if the queue was idle.
+    pub fn enqueue(&mut self, address: u32, data: &[u8]) -> Result<WriteToken, EnqueueError> {
+        if address % 8 != 0 {
+            return Err(EnqueueError::Unaligned);
+        }
+        if data.is_empty() || data.len() > MAX_RECORD_LEN {
+            return Err(EnqueueError::InvalidLength);
+        }
+        let end = address as u64 + padded_len(data.len()) as u64;
+        if address < FLASH_BASE || end > FLASH_BASE as u64 + flash_size() as u64 {
+            return Err(EnqueueError::OutOfRange);
+        }
+        let (first, last) = page_range(address, padded_len(data.len()));
+        if write_protection().is_protected(first, last) {
+            return Err(EnqueueError::WriteProtected);
+        }
+
+        let token = WriteToken(self.next_token);
+        let mut record = Record {
+            token,
+            address,
+            len: data.len(),
+            data: [0xff; MAX_RECORD_LEN],
+        };
+        record.data[..data.len()].copy_from_slice(data);
+
+        self.pending
+            .enqueue(record)
+            .map_err(|_| EnqueueError::Full)?;
+        self.next_token = self.next_token.wrapping_add(1);
+
+        if self.state == State::Idle {
+            self.program_next();
+        }
+
+        Ok(token)
+    }
+
+    /// Returns the next completion, unless completions are delivered to a callback.
+    pub fn poll_completion(&mut self) -> Option<(WriteToken, WriteResult)> {
+        self.completions.dequeue()
+    }
+
+    /// Returns the number of completions dropped because `poll_completion` wasn't called often
+    /// enough.
+    pub fn lost_completions(&self) -> u32 {
+        self.lost_completions
+    }
+
+    /// Returns `true` if all records have been completed.
+    pub fn is_idle(&self) -> bool {
+        self.state == State::Idle
+    }
+
+    /// Resumes programming if it was held off by CPU2.
+    pub fn retry(&mut self) {
+        if self.state == State::Blocked {
+            self.program_next();
+        }
+    }
+
+    /// Starts programming the next double word, or ends the session if there is none.
+    fn program_next(&mut self) {
+        loop {
+            if self.current.is_none() {
+                self.current = self.pending.dequeue().map(|record| (record, 0));
+            }
+
+            let (address, words) = match &self.current {
+                None => {
+                    self.finish();
+                    return;
+                }
+                Some((record, offset)) if *offset >= padded_len(record.len) => {
+                    self.complete(Ok(()));
+                    continue;
+                }
+                Some((record, offset)) => {
+                    let dword = &record.data[*offset..*offset + 8];
+                    (
+                        record.address + *offset as u32,
+                        [
+                            u32::from_le_bytes([dword[0], dword[1], dword[2], dword[3]]),
+                            u32::from_le_bytes([dword[4], dword[5], dword[6], dword[7]]),
+                        ],
+                    )
+                }
+            };
+
+            self.state = if self.start_dword(address, words) {
+                State::Programming
+            } else {
+                State::Blocked
+            };
+            return;
+        }
+    }
+
+    /// Starts programming of a double word, returns `false` if CPU2 doesn't allow it now.
+    fn start_dword(&mut self, address: u32, words: [u32; 2]) -> bool {
+        let radio_safe = crate::pwr::cpu2_booted();
+
+        if radio_safe && !self.flash_sem_held {
+            if !hsem_try_lock(FLASH_SEMAPHORE) {
+                hsem_set_free_irq(FLASH_SEMAPHORE, true);
+                return false;
+            }
+            self.flash_sem_held = true;
+        }
+
+        let rb = self.cr.rb();
+        if rb.cr.read().bits() & CR_LOCK != 0 {
+            rb.keyr.write(|w| unsafe { w.bits(KEY1) });
+            rb.keyr.write(|w| unsafe { w.bits(KEY2) });
+            // Flags left over by other flash users would fail the first double word
+            rb.sr.write(|w| unsafe { w.bits(SR_ERRORS | SR_EOP) });
+        }
+        rb.cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | CR_PG | CR_EOPIE | CR_ERRIE) });
+
+        // CPU2 must not take the semaphore between the check and the start of programming
+        cortex_m::interrupt::free(|_| {
+            if radio_safe && hsem_is_locked_by_other(CPU2_BLOCK_FLASH_SEMAPHORE) {
+                hsem_set_free_irq(CPU2_BLOCK_FLASH_SEMAPHORE, true);
+                return false;
+            }
+            if rb.sr.read().bits() & SR_PESD != 0 {
+                return false;
+            }
+
+            // NOTE(unsafe) address was checked by `enqueue`, programming starts with the
+            // second word
+            unsafe {
+                core::ptr::write_volatile(address as *mut u32, words[0]);
+                core::ptr::write_volatile((address + 4) as *mut u32, words[1]);
+            }
+            true
+        })
+    }
+
+    /// Checks the double word that has just been programmed.
+    fn on_flash_interrupt(&mut self) {
+        if self.state != State::Programming {
+            return;
+        }
+
+        let rb = self.cr.rb();
+        let sr = rb.sr.read().bits();
+        if sr & SR_BSY != 0 {
+            return;
+        }
+        rb.sr
+            .write(|w| unsafe { w.bits(sr & (SR_ERRORS | SR_EOP)) });
+
+        let result = match &self.current {
+            Some((record, offset)) => {
+                let address = record.address + *offset as u32;
+                let errors = sr & SR_ERRORS;
+
+                if errors & SR_WRPERR != 0 {
+                    Err(WriteError::WriteProtected)
+                } else if errors != 0 {
+                    Err(WriteError::Program(errors))
+                } else if !verify(address, &record.data[*offset..*offset + 8]) {
+                    Err(WriteError::VerifyMismatch { address })
+                } else {
+                    Ok(())
+                }
+            }
+            None => return,
+        };
+
+        match result {
+            Ok(()) => {
+                if let Some((_, offset)) = &mut self.current {
+                    *offset += 8;
+                }
+            }
+            Err(e) => self.complete(Err(e)),
+        }
+
+        self.program_next();
+    }
+
+    /// Completes the current record with `result`.
+    fn complete(&mut self, result: WriteResult) {
+        let token = match self.current.take() {
+            Some((record, _)) => record.token,
+            None => return,
+        };
+
+        match self.callback {
+            Some(callback) => callback(token, result),
+            None => {
+                if self.completions.enqueue((token, result)).is_err() {
+                    self.lost_completions = self.lost_completions.wrapping_add(1);
+                }
+            }
+        }
+    }
+
+    /// Locks flash and releases the flash semaphore once the queue is empty.
+    fn finish(&mut self) {
+        let rb = self.cr.rb();
+        rb.cr.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(CR_PG | CR_EOPIE | CR_ERRIE)) | CR_LOCK)
+        });
+
+        if self.flash_sem_held {
+            hsem_unlock(FLASH_SEMAPHORE);
+            self.flash_sem_held = false;
+        }
+
+        self.state = State::Idle;
+    }
+}
+
+impl InterruptHandler<irq::FLASH> for WriteQueue {
+    fn on_interrupt(&mut self) {
+        self.on_flash_interrupt();
+    }
+}
+
+impl InterruptHandler<irq::HSEM> for WriteQueue {
+    fn on_interrupt(&mut self) {
+        let mut released = false;
+        for &sem in &[FLASH_SEMAPHORE, CPU2_BLOCK_FLASH_SEMAPHORE] {
+            if hsem_take_free_irq(sem) {
+                hsem_set_free_irq(sem, false);
+                released = true;
+            }
+        }
+
+        if released {
+            self.retry();
+        }
+    }
+}
+
+/// Returns `true` if flash at `address` holds `expected`.
+fn verify(address: u32, expected: &[u8]) -> bool {
+    expected.iter().enumerate().all(|(i, &byte)| {
+        // NOTE(unsafe) flash is always readable
+        unsafe { core::ptr::read_volatile((address as usize + i) as *const u8) == byte }
+    })
+}
+
+/// Returns `len` rounded up to double words.
+fn padded_len(len: usize) -> usize {
+    (len + 7) & !7
+}
+
+/// Returns the first and last page of `len` bytes at `address`, which must be in flash.
+fn page_range(address: u32, len: usize) -> (u8, u8) {
+    let offset = address - FLASH_BASE;
+    let first = offset / PAGE_SIZE;
+    let last = (offset + len as u32 - 1) / PAGE_SIZE;
+    (first as u8, last as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_range_spans_pages() {
+        assert_eq!(page_range(FLASH_BASE, 8), (0, 0));
+        assert_eq!(page_range(FLASH_BASE + PAGE_SIZE - 8, 16), (0, 1));
+        assert_eq!(page_range(FLASH_BASE + 255 * PAGE_SIZE, 64), (255, 255));
+    }
+}
@@ -2,6 +2,7 @@
 
 use core::marker::PhantomData;
 
+use crate::pwr::{WakeupPolarity, WakeupSource};
 use crate::rcc::Rcc;
 use crate::stm32::{EXTI, SYSCFG};
 
@@ -103,6 +104,91 @@ pub enum Edge {
     RISING_FALLING,
 }
 
+/// GPIO ports walked by `audit`, in `AuditReport::floating` order.
+pub const AUDIT_PORTS: [char; 6] = ['A', 'B', 'C', 'D', 'E', 'H'];
+
+/// Result of `audit`.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct AuditReport {
+    /// Bit `i` is set if pin `i` of the port is floating. Ports are in `AUDIT_PORTS` order.
+    pub floating: [u16; 6],
+}
+
+impl AuditReport {
+    /// Returns `true` if no floating pin was found.
+    pub fn is_clean(&self) -> bool {
+        self.floating.iter().all(|&pins| pins == 0)
+    }
+
+    /// Returns floating pins as (port, pin) pairs, e.g. `('A', 3)`.
+    pub fn floating_pins(&self) -> impl Iterator<Item = (char, u8)> + '_ {
+        AUDIT_PORTS
+            .iter()
+            .zip(self.floating.iter())
+            .flat_map(|(&port, &pins)| {
+                (0..16)
+                    .filter(move |i| pins & (1 << i) != 0)
+                    .map(move |i| (port, i))
+            })
+    }
+}
+
+/// Debug helper: walks all GPIO ports and reports digital inputs without pull resistor.
+///
+/// A pin counts as floating if it's in input mode, has no GPIO pull-up/pull-down and no PWR
+/// Standby pull is applied to it. Such pins are the usual source of leakage current in low power
+/// modes. Ports with disabled clock are skipped.
+pub fn audit() -> AuditReport {
+    // RCC_AHB2ENR bits of ports A, B, C, D, E, H
+    const PORT_EN: [u32; 6] = [0, 1, 2, 3, 4, 7];
+
+    let ports: [*const u32; 6] = [
+        crate::stm32::GPIOA::ptr() as *const u32,
+        crate::stm32::GPIOB::ptr() as *const u32,
+        crate::stm32::GPIOC::ptr() as *const u32,
+        crate::stm32::GPIOD::ptr() as *const u32,
+        crate::stm32::GPIOE::ptr() as *const u32,
+        crate::stm32::GPIOH::ptr() as *const u32,
+    ];
+
+    // NOTE(unsafe) atomic reads with no side effects
+    let ahb2enr = unsafe { (*crate::stm32::RCC::ptr()).ahb2enr.read().bits() };
+
+    let mut report = AuditReport::default();
+
+    for (port, &base) in ports.iter().enumerate() {
+        if ahb2enr & (1 << PORT_EN[port]) == 0 {
+            continue;
+        }
+
+        // MODER is at offset 0x00, PUPDR at 0x0C
+        let (moder, pupdr) = unsafe {
+            (
+                core::ptr::read_volatile(base),
+                core::ptr::read_volatile(base.add(3)),
+            )
+        };
+
+        let retained = if crate::pwr::pull_config_enabled() {
+            let (pucr, pdcr) = crate::pwr::standby_pull_bits(port as u8);
+            pucr | pdcr
+        } else {
+            0
+        };
+
+        for i in 0..16 {
+            let input = (moder >> (2 * i)) & 0b11 == 0b00;
+            let no_pull = (pupdr >> (2 * i)) & 0b11 == 0b00;
+
+            if input && no_pull && retained & (1 << i) == 0 {
+                report.floating[port] |= 1 << i;
+            }
+        }
+    }
+
+    report
+}
+
 /// External Interrupt Pin
 pub trait ExtiPin {
     fn make_interrupt_source(&mut self, syscfg: &mut SYSCFG);
@@ -160,6 +246,7 @@ macro_rules! gpio {
             use crate::hal::digital::v2::{OutputPin, InputPin};
             use crate::stm32::{$gpioy, $GPIOX, EXTI, SYSCFG};
 
+            use crate::pwr::{Pwr, StandbyPull};
             use crate::rcc::Rcc;
             use super::{
                 Alternate,
@@ -361,6 +448,27 @@ macro_rules! gpio {
                 }
 
                 impl<MODE> $PXi<MODE> {
+                    /// Pulls the pin up in Standby and Shutdown through PWR_PUCRx.
+                    ///
+                    /// Enables the PWR pull configuration (APC).
+                    pub fn retain_pull_up_in_standby(&mut self, pwr: &mut Pwr) {
+                        crate::pwr::set_standby_pull($extigpionr, $i, StandbyPull::Up);
+                        pwr.set_pull_config(true);
+                    }
+
+                    /// Pulls the pin down in Standby and Shutdown through PWR_PDCRx.
+                    ///
+                    /// Enables the PWR pull configuration (APC).
+                    pub fn retain_pull_down_in_standby(&mut self, pwr: &mut Pwr) {
+                        crate::pwr::set_standby_pull($extigpionr, $i, StandbyPull::Down);
+                        pwr.set_pull_config(true);
+                    }
+
+                    /// Removes the Standby pull of the pin.
+                    pub fn release_pull_in_standby(&mut self, _pwr: &mut Pwr) {
+                        crate::pwr::set_standby_pull($extigpionr, $i, StandbyPull::None);
+                    }
+
                     /// Configures the pin to operate as a floating input pin
                     pub fn into_floating_input(
                         self,
@@ -734,3 +842,36 @@ gpio!(GPIOH, gpioh, gpioh, gpiohen, gpiohrst, PHx, 5, [
     PH1: (ph1, 1, Input<Floating>, AFRL, exticr1),
     PH3: (ph3, 3, Input<Floating>, AFRL, exticr1),
 ]);
+
+macro_rules! wakeup_pins {
+    ($($gpiox:ident::$PXi:ident: ($i:expr, $port:expr, $wkup:expr),)+) => {
+        $(
+            impl<MODE> $gpiox::$PXi<MODE> {
+                /// Configures the pin as a Standby wake-up pin.
+                ///
+                /// The pull resistor is applied by PWR, so it's retained in Standby. Returned
+                /// `WakeupSource` is accepted by `pwr::standby`.
+                pub fn into_wakeup_input(
+                    self,
+                    moder: &mut $gpiox::MODER,
+                    pupdr: &mut $gpiox::PUPDR,
+                    polarity: WakeupPolarity,
+                ) -> ($gpiox::$PXi<Input<Floating>>, WakeupSource) {
+                    let pin = self.into_floating_input(moder, pupdr);
+                    let source = crate::pwr::enable_wakeup_pin($wkup, $port, $i, polarity);
+
+                    (pin, source)
+                }
+            }
+        )+
+    }
+}
+
+// WKUP1..5 pin mapping, RM0434 PWR chapter
+wakeup_pins! {
+    gpioa::PA0: (0, 0, 1),
+    gpioc::PC13: (13, 2, 2),
+    gpioc::PC12: (12, 2, 3),
+    gpioa::PA2: (2, 0, 4),
+    gpioc::PC5: (5, 2, 5),
+}
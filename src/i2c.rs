@@ -2,6 +2,7 @@
 
 use crate::stm32::{I2C1, I2C3};
 use cast::u8;
+use cortex_m::peripheral::DWT;
 
 use crate::gpio::gpioa::{PA10, PA7, PA9};
 use crate::gpio::gpiob::{PB10, PB11, PB13, PB14, PB4, PB6, PB7, PB8, PB9};
@@ -9,7 +10,7 @@ use crate::gpio::gpioc::{PC0, PC1};
 use crate::gpio::{Alternate, OpenDrain, Output, AF4};
 use crate::hal::blocking::i2c::{Read, Write, WriteRead};
 use crate::rcc::Rcc;
-use crate::time::Hertz;
+use crate::time::{Hertz, MicroSecond};
 
 /// I2C error
 #[derive(Debug)]
@@ -21,9 +22,10 @@ pub enum Error {
     Arbitration,
     /// NACK
     Nack,
+    /// Transfer didn't progress within the configured timeout
+    Timeout,
     // Overrun, // slave mode only
     // Pec, // SMBUS mode only
-    // Timeout, // SMBUS mode only
     // Alert, // SMBUS mode only
 }
 
@@ -55,14 +57,20 @@ unsafe impl SdaPin<I2C3> for PB14<Alternate<AF4, Output<OpenDrain>>> {}
 unsafe impl SclPin<I2C3> for PC0<Alternate<AF4, Output<OpenDrain>>> {}
 unsafe impl SdaPin<I2C3> for PC1<Alternate<AF4, Output<OpenDrain>>> {}
 
+const DWT_CTRL_CYCCNTENA: u32 = 1 << 0;
+
 /// I2C peripheral operating in master mode
 pub struct I2c<I2C, PINS> {
     i2c: I2C,
     pins: PINS,
+    hclk1: Hertz,
+    /// Maximum duration of a wait in DWT cycles
+    timeout: Option<u32>,
 }
 
 macro_rules! busy_wait {
-    ($i2c:expr, $flag:ident) => {
+    ($i2c:expr, $flag:ident, $timeout:expr) => {
+        let start = DWT::get_cycle_count();
         loop {
             let isr = $i2c.isr.read();
 
@@ -74,6 +82,15 @@ macro_rules! busy_wait {
                 return Err(Error::Nack);
             } else if isr.$flag().bit_is_set() {
                 break;
+            } else if let Some(timeout) = $timeout {
+                if DWT::get_cycle_count().wrapping_sub(start) >= timeout {
+                    // Software reset releases the lines and resets the state machine
+                    $i2c.cr1.modify(|_, w| w.pe().clear_bit());
+                    while $i2c.cr1.read().pe().bit_is_set() {}
+                    $i2c.cr1.modify(|_, w| w.pe().set_bit());
+
+                    return Err(Error::Timeout);
+                }
             } else {
                 // try again
             }
@@ -178,7 +195,39 @@ macro_rules! hal {
                     // Enable the peripheral
                     i2c.cr1.write(|w| w.pe().set_bit());
 
-                    I2c { i2c, pins }
+                    I2c {
+                        i2c,
+                        pins,
+                        hclk1: rcc.clocks.hclk1(),
+                        timeout: None,
+                    }
+                }
+
+                /// Bounds every wait for the bus by `timeout`.
+                ///
+                /// A wait that exceeds the timeout resets the peripheral and returns
+                /// `Error::Timeout`, so a slave holding SCL low can't hang the caller forever.
+                /// A zero `timeout` removes the timeout, like `clear_timeout`.
+                ///
+                /// Time is measured with the DWT cycle counter at the HCLK1 frequency the
+                /// `Rcc` had when the peripheral was created. The counter must be enabled by
+                /// the application (`DCB::enable_trace` and `DWT::enable_cycle_counter`),
+                /// timeouts beyond one counter wrap (about 67 s at 64 MHz) are never reached.
+                pub fn set_timeout<T: Into<MicroSecond>>(&mut self, timeout: T) {
+                    let timeout = timeout.into();
+                    self.timeout = if timeout.0 == 0 {
+                        None
+                    } else {
+                        // NOTE(unsafe) read-only access to DWT_CTRL
+                        let ctrl = unsafe { (*DWT::ptr()).ctrl.read() };
+                        debug_assert!(ctrl & DWT_CTRL_CYCCNTENA != 0, "DWT cycle counter off");
+                        Some(timeout.cycles(self.hclk1).max(1))
+                    };
+                }
+
+                /// Removes the timeout, waits for the bus are unbounded.
+                pub fn clear_timeout(&mut self) {
+                    self.timeout = None;
                 }
 
                 /// Releases the I2C peripheral and associated pins
@@ -211,7 +260,7 @@ macro_rules! hal {
                     for byte in bytes {
                         // Wait until we are allowed to send data (START has been ACKed or last byte
                         // when through)
-                        busy_wait!(self.i2c, txis);
+                        busy_wait!(self.i2c, txis, self.timeout);
 
                         // put byte on the wire
                         self.i2c.txdr.write(unsafe { |w| { w.txdata().bits(*byte) } });
@@ -244,7 +293,7 @@ macro_rules! hal {
 
                     for byte in buffer {
                         // Wait until we have received something
-                        busy_wait!(self.i2c, rxne);
+                        busy_wait!(self.i2c, rxne, self.timeout);
 
                         *byte = self.i2c.rxdr.read().rxdata().bits();
                     }
@@ -286,14 +335,14 @@ macro_rules! hal {
                     for byte in bytes {
                         // Wait until we are allowed to send data (START has been ACKed or last byte
                         // when through)
-                        busy_wait!(self.i2c, txis);
+                        busy_wait!(self.i2c, txis, self.timeout);
 
                         // put byte on the wire
                         self.i2c.txdr.write(|w| unsafe { w.txdata().bits(*byte) });
                     }
 
                     // Wait until the last transmission is finished
-                    busy_wait!(self.i2c, tc);
+                    busy_wait!(self.i2c, tc, self.timeout);
 
                     // reSTART and prepare to receive bytes into `buffer`
                     self.i2c.cr2.write(|w| unsafe {
@@ -311,7 +360,7 @@ macro_rules! hal {
 
                     for byte in buffer {
                         // Wait until we have received something
-                        busy_wait!(self.i2c, rxne);
+                        busy_wait!(self.i2c, rxne, self.timeout);
 
                         *byte = self.i2c.rxdr.read().rxdata().bits();
                     }
@@ -0,0 +1,138 @@
+//! Typed interrupt handler bindings
+//!
+//! Drivers that must be serviced from an interrupt implement `InterruptHandler<I>`, where `I` is
+//! the marker type of the interrupt vector from the `irq` module. A driver can only be bound to
+//! the vectors it implements the handler for, so wiring it to the wrong interrupt fails to
+//! compile.
+//!
+//! Bare-metal applications generate the vectors with `bind_interrupt!` (requires the `rt`
+//! feature), RTIC applications call `InterruptHandler::on_interrupt` from their tasks:
+//!
+//! ```ignore
+//! static MBOX: Mutex<RefCell<Option<TlMbox>>> = Mutex::new(RefCell::new(None));
+//!
+//! bind_interrupt!(IPCC_C1_RX_IT => MBOX);
+//! bind_interrupt!(IPCC_C1_TX_IT => MBOX);
+//! bind_interrupt!(EXTI15_10 => &mut ExtiRegistry); // `exti-registry` feature
+//! ```
+
+use crate::pac::Interrupt;
+
+/// Interrupt vector marker type.
+pub trait Irq {
+    /// Interrupt number of the vector.
+    const INTERRUPT: Interrupt;
+}
+
+/// Driver serviced from interrupt `I`.
+pub trait InterruptHandler<I: Irq> {
+    /// Services the interrupt. Must be called from interrupt `I` (or the task bound to it).
+    fn on_interrupt(&mut self);
+}
+
+/// Interrupt vector marker types, named after the vectors.
+pub mod irq {
+    use super::Irq;
+    use crate::pac::Interrupt;
+
+    macro_rules! irqs {
+        ($($name:ident,)+) => {
+            $(
+                #[allow(non_camel_case_types)]
+                pub struct $name;
+
+                impl Irq for $name {
+                    const INTERRUPT: Interrupt = Interrupt::$name;
+                }
+            )+
+        };
+    }
+
+    irqs! {
+        FLASH,
+        HSEM,
+        IPCC_C1_RX_IT,
+        IPCC_C1_TX_IT,
+        EXTI0,
+        EXTI1,
+        EXTI2,
+        EXTI3,
+        EXTI4,
+        EXTI9_5,
+        EXTI15_10,
+    }
+}
+
+/// Unmasks interrupt `I` in the NVIC.
+///
+/// # Safety
+///
+/// See `cortex_m::peripheral::NVIC::unmask`.
+pub unsafe fn unmask<I: Irq>() {
+    cortex_m::peripheral::NVIC::unmask(I::INTERRUPT);
+}
+
+/// Handler of the EXTI line handler registry, see `exti` module.
+#[cfg(feature = "exti-registry")]
+pub struct ExtiRegistry;
+
+#[cfg(feature = "exti-registry")]
+macro_rules! exti_registry_handlers {
+    ($($irq:ident: $range:ident,)+) => {
+        $(
+            impl InterruptHandler<irq::$irq> for ExtiRegistry {
+                fn on_interrupt(&mut self) {
+                    crate::exti::dispatch(crate::exti::IrqRange::$range);
+                }
+            }
+        )+
+    };
+}
+
+#[cfg(feature = "exti-registry")]
+exti_registry_handlers! {
+    EXTI0: Exti0,
+    EXTI1: Exti1,
+    EXTI2: Exti2,
+    EXTI3: Exti3,
+    EXTI4: Exti4,
+    EXTI9_5: Exti9_5,
+    EXTI15_10: Exti15_10,
+}
+
+/// Generates the interrupt vector `$irq` calling the `InterruptHandler` of a driver.
+///
+/// `$irq => STATE` takes the driver from a `Mutex<RefCell<Option<T>>>` static; the vector does
+/// nothing while it's `None`. `$irq => &mut expr` calls the handler of a stateless driver.
+#[cfg(feature = "rt")]
+#[macro_export]
+macro_rules! bind_interrupt {
+    ($irq:ident => &mut $handler:expr) => {
+        const _: () = {
+            use $crate::interrupt;
+
+            #[interrupt]
+            fn $irq() {
+                $crate::interrupts::InterruptHandler::<$crate::interrupts::irq::$irq>::on_interrupt(
+                    &mut $handler,
+                );
+            }
+        };
+    };
+    ($irq:ident => $state:ident) => {
+        const _: () = {
+            use $crate::interrupt;
+
+            #[interrupt]
+            fn $irq() {
+                $crate::cortex_m::interrupt::free(|cs| {
+                    if let Some(state) = $state.borrow(cs).borrow_mut().as_mut() {
+                        $crate::interrupts::InterruptHandler::<$crate::interrupts::irq::$irq>::on_interrupt(
+                            state,
+                        );
+                    }
+                });
+            }
+        };
+    };
+}
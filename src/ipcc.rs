@@ -1,7 +1,14 @@
 use crate::rcc::Rcc;
-use stm32wb_pac::IPCC;
+use stm32wb_pac::{EXTI, IPCC};
 
-#[derive(Debug, Copy, Clone)]
+pub mod c2;
+pub mod split;
+pub mod token;
+
+/// EXTI direct line of the IPCC CPU1 interrupts (line 36).
+const EXTI_IMR2_IPCC: u32 = 1 << 4;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 #[repr(C)]
 pub enum IpccChannel {
     Channel1 = 0x00000001,
@@ -16,6 +23,22 @@ impl IpccChannel {
     pub fn iterator() -> IpccChannelIterator {
         IpccChannelIterator { channel_number: 0 }
     }
+
+    /// Bit of the channel in the status (`CxTOCySR`) registers and the occupied masks of the mask
+    /// (`CxMR`) registers.
+    pub fn bit(self) -> u32 {
+        self as u32
+    }
+
+    /// Bit of the channel free mask in the mask (`CxMR`) registers.
+    pub fn free_mask_bit(self) -> u32 {
+        (self as u32) << 16
+    }
+
+    /// Returns `true` if the channel's bit is set in status register value `sr`.
+    pub fn is_set_in(self, sr: u32) -> bool {
+        sr & self.bit() != 0
+    }
 }
 
 pub struct IpccChannelIterator {
@@ -43,14 +66,46 @@ impl Iterator for IpccChannelIterator {
     }
 }
 
+/// Step of the `Ipcc::loopback_test` that failed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LoopbackStep {
+    /// CPU1 to CPU2 channel wasn't occupied after setting the CPU1 flag.
+    C1FlagSet,
+    /// CPU1 to CPU2 channel wasn't free after clearing the CPU1 flag.
+    C1FlagClear,
+    /// CPU2 to CPU1 channel wasn't occupied after setting the CPU2 flag.
+    C2FlagSet,
+    /// CPU2 to CPU1 channel wasn't free after clearing the CPU2 flag.
+    C2FlagClear,
+    /// Receive (occupied) interrupt mask didn't read back as written.
+    RxMask,
+    /// Transmit (free) interrupt mask didn't read back as written.
+    TxMask,
+}
+
+/// IPCC loopback test failure
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LoopbackError {
+    /// CPU2 is booted and may be using the channels.
+    Cpu2Running,
+    /// Register read back didn't match the reference manual behavior.
+    Failed {
+        channel: IpccChannel,
+        step: LoopbackStep,
+    },
+}
+
 pub struct Ipcc {
     pub rb: IPCC,
 }
 
 impl Ipcc {
     /// Enables clocking of IPCC and unmasks two associated interrupts: `IPCC_C1_RX` and `IPCC_C1_TX`.
+    ///
+    /// HSEM is clocked as well, the semaphores shared with CPU2 are needed once it runs.
     pub fn init(&mut self, rcc: &mut Rcc) {
         rcc.set_ipcc(true);
+        rcc.set_hsem(true);
 
         // Enable IPCC interrupts
         self.rb
@@ -62,18 +117,92 @@ impl Ipcc {
         }
     }
 
+    /// Disables the IPCC interrupts enabled by `init`, for polling the mailbox with
+    /// `TlMbox::poll`.
+    pub fn disable_interrupts(&mut self) {
+        cortex_m::peripheral::NVIC::mask(stm32wb_pac::interrupt::IPCC_C1_RX_IT);
+        cortex_m::peripheral::NVIC::mask(stm32wb_pac::interrupt::IPCC_C1_TX_IT);
+        self.rb
+            .c1cr
+            .modify(|_, w| w.rxoie().clear_bit().txfie().clear_bit());
+    }
+
+    /// Returns `true` if the IPCC RX or TX interrupt can fire.
+    pub fn interrupts_enabled(&self) -> bool {
+        use cortex_m::peripheral::NVIC;
+        use stm32wb_pac::interrupt::{IPCC_C1_RX_IT, IPCC_C1_TX_IT};
+
+        let c1cr = self.rb.c1cr.read();
+        (c1cr.rxoie().bit_is_set() && NVIC::is_enabled(IPCC_C1_RX_IT))
+            || (c1cr.txfie().bit_is_set() && NVIC::is_enabled(IPCC_C1_TX_IT))
+    }
+
+    /// Disables the IPCC clock of CPU1 and returns the peripheral.
+    ///
+    /// CPU2 has its own clock enable, but its messages go unnoticed until the IPCC is initialized
+    /// again, so release it only once the mailbox is no longer used.
+    pub fn release(mut self, rcc: &mut Rcc) -> IPCC {
+        self.disable_interrupts();
+        set_exti_wakeup(false);
+        rcc.set_ipcc(false);
+        self.rb
+    }
+
+    /// Lets the IPCC interrupts wake up CPU1 from Stop 0, 1 and 2 (`enable`), or only from Sleep.
+    ///
+    /// Enabling sets `C1CR.RXOIE`/`TXFIE` and unmasks the IPCC wake-up (EXTI direct line 36,
+    /// `C1IMR2`). The NVIC lines are unmasked by `init`. After wake-up from Stop, SYSCLK runs from
+    /// the Stop wake-up clock, see `Rcc::restore_after_stop`.
+    pub fn configure_wakeup(&mut self, enable: bool) {
+        if enable {
+            self.rb
+                .c1cr
+                .modify(|_, w| w.rxoie().set_bit().txfie().set_bit());
+        }
+        set_exti_wakeup(enable);
+    }
+
+    /// Enables the `IPCC_C1_RX` interrupt (`C1CR.RXOIE`), raised while an unmasked channel is
+    /// occupied by CPU2.
+    pub fn enable_rx_irq(&mut self) {
+        self.rb.c1cr.modify(|_, w| w.rxoie().set_bit());
+    }
+
+    /// Disables the `IPCC_C1_RX` interrupt. Channel flags and masks keep their state, so a
+    /// channel occupied meanwhile raises the interrupt once it's enabled again.
+    pub fn disable_rx_irq(&mut self) {
+        self.rb.c1cr.modify(|_, w| w.rxoie().clear_bit());
+    }
+
+    /// Enables the `IPCC_C1_TX` interrupt (`C1CR.TXFIE`), raised while an unmasked channel is
+    /// free.
+    pub fn enable_tx_irq(&mut self) {
+        self.rb.c1cr.modify(|_, w| w.txfie().set_bit());
+    }
+
+    /// Disables the `IPCC_C1_TX` interrupt, see `disable_rx_irq`.
+    pub fn disable_tx_irq(&mut self) {
+        self.rb.c1cr.modify(|_, w| w.txfie().clear_bit());
+    }
+
     /// Resets IPCC to the default state.
     pub fn reset(&mut self) {
         for channel in IpccChannel::iterator() {
-            self.c1_clear_flag_channel(channel);
-            self.c2_clear_flag_channel(channel);
+            self.reset_channel(channel);
+        }
+    }
 
-            self.c1_set_rx_channel(channel, false);
-            self.c2_set_rx_channel(channel, false);
+    /// Resets one channel to the default state: flags of both directions cleared, all its
+    /// interrupts masked.
+    pub fn reset_channel(&mut self, channel: IpccChannel) {
+        self.c1_clear_flag_channel(channel);
+        self.c2_clear_flag_channel(channel);
 
-            self.c1_set_tx_channel(channel, false);
-            self.c2_set_tx_channel(channel, false);
-        }
+        self.c1_set_rx_channel(channel, false);
+        self.c2_set_rx_channel(channel, false);
+
+        self.c1_set_tx_channel(channel, false);
+        self.c2_set_tx_channel(channel, false);
     }
 
     pub fn c1_set_rx_channel(&mut self, channel: IpccChannel, enabled: bool) {
@@ -194,6 +323,8 @@ impl Ipcc {
         }
     }
 
+    /// Returns `true` while the CPU1 to CPU2 channel is occupied, i.e. CPU1 set the flag and CPU2
+    /// didn't clear it yet.
     pub fn c1_is_active_flag(&self, channel: IpccChannel) -> bool {
         match channel {
             IpccChannel::Channel1 => self.rb.c1to2sr.read().ch1f().bit(),
@@ -205,6 +336,8 @@ impl Ipcc {
         }
     }
 
+    /// Returns `true` while the CPU2 to CPU1 channel is occupied, i.e. CPU2 set the flag and CPU1
+    /// didn't clear it yet.
     pub fn c2_is_active_flag(&self, channel: IpccChannel) -> bool {
         match channel {
             IpccChannel::Channel1 => self.rb.c2toc1sr.read().ch1f().bit(),
@@ -216,6 +349,78 @@ impl Ipcc {
         }
     }
 
+    /// Exercises the flag, clear and mask register paths of every channel without CPU2.
+    ///
+    /// Both sides of each channel are driven from CPU1, so CPU2 must not be booted. IPCC must be
+    /// initialized with `init`. Interrupt masks are restored and all flags are left cleared.
+    pub fn loopback_test(&mut self) -> Result<(), LoopbackError> {
+        if crate::pwr::cpu2_booted() {
+            return Err(LoopbackError::Cpu2Running);
+        }
+
+        let c1mr = self.rb.c1mr.read().bits();
+
+        // Avoid spurious interrupts while flags are toggled
+        self.rb.c1mr.write(|w| unsafe { w.bits(!0) });
+
+        let result = IpccChannel::iterator().try_for_each(|channel| {
+            let check = |ok: bool, step| {
+                if ok {
+                    Ok(())
+                } else {
+                    Err(LoopbackError::Failed { channel, step })
+                }
+            };
+
+            self.c1_set_flag_channel(channel);
+            check(self.c1_is_active_flag(channel), LoopbackStep::C1FlagSet)?;
+            self.c2_clear_flag_channel(channel);
+            check(!self.c1_is_active_flag(channel), LoopbackStep::C1FlagClear)?;
+
+            self.c2_set_flag_channel(channel);
+            check(self.c2_is_active_flag(channel), LoopbackStep::C2FlagSet)?;
+            self.c1_clear_flag_channel(channel);
+            check(!self.c2_is_active_flag(channel), LoopbackStep::C2FlagClear)?;
+
+            for &enabled in &[true, false] {
+                self.c1_set_rx_channel(channel, enabled);
+                check(
+                    self.c1_get_rx_channel(channel) == enabled,
+                    LoopbackStep::RxMask,
+                )?;
+
+                self.c1_set_tx_channel(channel, enabled);
+                check(
+                    self.c1_get_tx_channel(channel) == enabled,
+                    LoopbackStep::TxMask,
+                )?;
+            }
+
+            Ok(())
+        });
+
+        // Leave no flag set behind a failed step
+        for channel in IpccChannel::iterator() {
+            self.c1_clear_flag_channel(channel);
+            self.c2_clear_flag_channel(channel);
+        }
+        self.rb.c1mr.write(|w| unsafe { w.bits(c1mr) });
+
+        result
+    }
+
+    /// Returns `true` if CPU2 occupies `channel`, i.e. sent a message CPU1 didn't acknowledge
+    /// yet. Same as `c2_is_active_flag`.
+    pub fn c2_is_channel_occupied(&self, channel: IpccChannel) -> bool {
+        channel.is_set_in(self.rb.c2toc1sr.read().bits())
+    }
+
+    /// Returns `true` if CPU1 may write the next message to `channel`, i.e. CPU2 has
+    /// acknowledged the previous one.
+    pub fn c1_is_channel_free(&self, channel: IpccChannel) -> bool {
+        !channel.is_set_in(self.rb.c1to2sr.read().bits())
+    }
+
     pub fn is_tx_pending(&self, channel: IpccChannel) -> bool {
         !self.c1_is_active_flag(channel) && self.c1_get_tx_channel(channel)
     }
@@ -223,6 +428,34 @@ impl Ipcc {
     pub fn is_rx_pending(&self, channel: IpccChannel) -> bool {
         self.c2_is_active_flag(channel) && self.c1_get_rx_channel(channel)
     }
+
+    /// Splits the IPCC into per-channel handles, see `split` module. `init` must be called
+    /// before.
+    pub fn split(self) -> split::IpccChannels {
+        split::IpccChannels::new(self.rb)
+    }
+
+    /// Returns the CPU2 side of the IPCC. Requires the `ipcc-c2` feature, see `c2` module.
+    #[cfg(feature = "ipcc-c2")]
+    pub fn c2(&mut self) -> c2::C2 {
+        c2::C2::new(self)
+    }
+}
+
+/// Unmasks or masks the IPCC wake-up of CPU1 from Stop modes.
+pub(crate) fn set_exti_wakeup(enable: bool) {
+    // The register holds the masks of other EXTI lines as well
+    cortex_m::interrupt::free(|_| {
+        // NOTE(unsafe) only the IPCC bit changes, within a critical section
+        let exti = unsafe { &*EXTI::ptr() };
+        exti.c1imr2.modify(|r, w| unsafe {
+            w.bits(if enable {
+                r.bits() | EXTI_IMR2_IPCC
+            } else {
+                r.bits() & !EXTI_IMR2_IPCC
+            })
+        });
+    });
 }
 
 /// Extension trait that constrains the `IPCC` peripheral
@@ -0,0 +1,89 @@
+//! CPU2 side of the IPCC
+//!
+//! Requires the `ipcc-c2` feature. Meant for developing custom CPU2 firmware, where both sides
+//! of the mailbox are driven from CPU1 (e.g. with the debugger halting CPU2). Don't use it
+//! together with ST's wireless stack: the CPU2 registers belong to the stack running on CPU2.
+//!
+//! The accessors mirror the `c1_*` methods of `Ipcc` with the roles of the CPUs swapped.
+
+#![cfg(feature = "ipcc-c2")]
+
+use super::{Ipcc, IpccChannel};
+
+/// CPU2 view of the IPCC, obtained with `Ipcc::c2`.
+pub struct C2<'a> {
+    ipcc: &'a mut Ipcc,
+}
+
+impl<'a> C2<'a> {
+    pub(super) fn new(ipcc: &'a mut Ipcc) -> Self {
+        C2 { ipcc }
+    }
+
+    /// Enables or disables the `IPCC_C2_RX` and `IPCC_C2_TX` interrupts (C2CR).
+    pub fn set_interrupts(&mut self, rx: bool, tx: bool) {
+        self.ipcc
+            .rb
+            .c2cr
+            .modify(|_, w| w.rxoie().bit(rx).txfie().bit(tx));
+    }
+
+    pub fn set_rx_channel(&mut self, channel: IpccChannel, enabled: bool) {
+        self.ipcc.c2_set_rx_channel(channel, enabled);
+    }
+
+    pub fn get_rx_channel(&self, channel: IpccChannel) -> bool {
+        let c2mr = self.ipcc.rb.c2mr.read();
+
+        // If bit is set to 1 then interrupt is disabled
+        !match channel {
+            IpccChannel::Channel1 => c2mr.ch1om().bit(),
+            IpccChannel::Channel2 => c2mr.ch2om().bit(),
+            IpccChannel::Channel3 => c2mr.ch3om().bit(),
+            IpccChannel::Channel4 => c2mr.ch4om().bit(),
+            IpccChannel::Channel5 => c2mr.ch5om().bit(),
+            IpccChannel::Channel6 => c2mr.ch6om().bit(),
+        }
+    }
+
+    pub fn set_tx_channel(&mut self, channel: IpccChannel, enabled: bool) {
+        self.ipcc.c2_set_tx_channel(channel, enabled);
+    }
+
+    pub fn get_tx_channel(&self, channel: IpccChannel) -> bool {
+        let c2mr = self.ipcc.rb.c2mr.read();
+
+        // If bit is set to 1 then interrupt is disabled
+        !match channel {
+            IpccChannel::Channel1 => c2mr.ch1fm().bit(),
+            IpccChannel::Channel2 => c2mr.ch2fm().bit(),
+            IpccChannel::Channel3 => c2mr.ch3fm().bit(),
+            IpccChannel::Channel4 => c2mr.ch4fm().bit(),
+            IpccChannel::Channel5 => c2mr.ch5fm().bit(),
+            IpccChannel::Channel6 => c2mr.ch6fm().bit(),
+        }
+    }
+
+    /// Clears IPCC receive channel status for CPU2.
+    pub fn clear_flag_channel(&mut self, channel: IpccChannel) {
+        self.ipcc.c2_clear_flag_channel(channel);
+    }
+
+    /// Sets IPCC transmit channel status for CPU2.
+    pub fn set_flag_channel(&mut self, channel: IpccChannel) {
+        self.ipcc.c2_set_flag_channel(channel);
+    }
+
+    /// Returns `true` if the CPU2 to CPU1 channel is occupied.
+    pub fn is_active_flag(&self, channel: IpccChannel) -> bool {
+        self.ipcc.c2_is_active_flag(channel)
+    }
+
+    pub fn is_tx_pending(&self, channel: IpccChannel) -> bool {
+        !self.ipcc.c2_is_active_flag(channel) && self.get_tx_channel(channel)
+    }
+
+    pub fn is_rx_pending(&self, channel: IpccChannel) -> bool {
+        self.ipcc.c1_is_active_flag(channel) && self.get_rx_channel(channel)
+    }
+}
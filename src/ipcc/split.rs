@@ -0,0 +1,124 @@
+//! Per-channel IPCC handles
+//!
+//! `Ipcc::split` turns the IPCC into one `IpccChannelHandle` per channel, so that e.g. the
+//! system command path and the BLE event handler don't need to share a `&mut Ipcc`.
+//!
+//! Flags are set and cleared through `C1SCR`, which has a bit per channel and operation, so no
+//! read-modify-write is needed. The channel masks share `C1MR`, which is modified within a
+//! critical section.
+
+use stm32wb_pac::IPCC;
+
+use super::{Ipcc, IpccChannel};
+
+/// The six IPCC channels, see `Ipcc::split`.
+pub struct IpccChannels {
+    pub ch1: IpccChannelHandle,
+    pub ch2: IpccChannelHandle,
+    pub ch3: IpccChannelHandle,
+    pub ch4: IpccChannelHandle,
+    pub ch5: IpccChannelHandle,
+    pub ch6: IpccChannelHandle,
+    rb: IPCC,
+}
+
+impl IpccChannels {
+    pub(super) fn new(rb: IPCC) -> Self {
+        IpccChannels {
+            ch1: IpccChannelHandle::new(IpccChannel::Channel1),
+            ch2: IpccChannelHandle::new(IpccChannel::Channel2),
+            ch3: IpccChannelHandle::new(IpccChannel::Channel3),
+            ch4: IpccChannelHandle::new(IpccChannel::Channel4),
+            ch5: IpccChannelHandle::new(IpccChannel::Channel5),
+            ch6: IpccChannelHandle::new(IpccChannel::Channel6),
+            rb,
+        }
+    }
+
+    /// Returns the IPCC, once all handles are given back.
+    pub fn join(self) -> Ipcc {
+        Ipcc { rb: self.rb }
+    }
+}
+
+/// CPU1 side of one IPCC channel. Only accesses the bits of its channel.
+pub struct IpccChannelHandle {
+    channel: IpccChannel,
+}
+
+impl IpccChannelHandle {
+    fn new(channel: IpccChannel) -> Self {
+        IpccChannelHandle { channel }
+    }
+
+    pub fn channel(&self) -> IpccChannel {
+        self.channel
+    }
+
+    /// Signals a message to CPU2: sets the CPU1 to CPU2 flag.
+    pub fn set_flag(&mut self) {
+        // NOTE(unsafe) write-only register, other channels' bits are written as 0, no effect
+        unsafe { rb() }
+            .c1scr
+            .write(|w| unsafe { w.bits(self.channel.bit() << 16) });
+    }
+
+    /// Acknowledges a message from CPU2: clears the CPU2 to CPU1 flag.
+    pub fn clear_flag(&mut self) {
+        // NOTE(unsafe) see `set_flag`
+        unsafe { rb() }
+            .c1scr
+            .write(|w| unsafe { w.bits(self.channel.bit()) });
+    }
+
+    /// Returns `true` if CPU2 occupies the channel and the occupied interrupt is enabled, see
+    /// `Ipcc::is_rx_pending`.
+    pub fn is_rx_pending(&self) -> bool {
+        // NOTE(unsafe) read-only access
+        let rb = unsafe { rb() };
+        self.channel.is_set_in(rb.c2toc1sr.read().bits())
+            && !self.channel.is_set_in(rb.c1mr.read().bits())
+    }
+
+    /// Returns `true` if the channel is free and the free interrupt is enabled, see
+    /// `Ipcc::is_tx_pending`.
+    pub fn is_tx_pending(&self) -> bool {
+        // NOTE(unsafe) read-only access
+        let rb = unsafe { rb() };
+        !self.channel.is_set_in(rb.c1to2sr.read().bits())
+            && rb.c1mr.read().bits() & self.channel.free_mask_bit() == 0
+    }
+
+    /// Returns `true` if CPU1 may write the next message, see `Ipcc::c1_is_channel_free`.
+    pub fn is_free(&self) -> bool {
+        // NOTE(unsafe) read-only access
+        !self
+            .channel
+            .is_set_in(unsafe { rb() }.c1to2sr.read().bits())
+    }
+
+    /// Enables or disables the occupied (RX) interrupt of the channel.
+    pub fn enable_rx(&mut self, enabled: bool) {
+        // Masked if set
+        self.modify_mask(self.channel.bit(), !enabled);
+    }
+
+    /// Enables or disables the free (TX) interrupt of the channel.
+    pub fn enable_tx(&mut self, enabled: bool) {
+        self.modify_mask(self.channel.free_mask_bit(), !enabled);
+    }
+
+    fn modify_mask(&mut self, bit: u32, set: bool) {
+        // The masks of all channels share the register
+        cortex_m::interrupt::free(|_| {
+            // NOTE(unsafe) only this channel's bit changes, within a critical section
+            unsafe { rb() }.c1mr.modify(|r, w| unsafe {
+                w.bits(if set { r.bits() | bit } else { r.bits() & !bit })
+            });
+        });
+    }
+}
+
+unsafe fn rb() -> &'static stm32wb_pac::ipcc::RegisterBlock {
+    &*IPCC::ptr()
+}
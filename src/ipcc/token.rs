@@ -0,0 +1,120 @@
+//! Half-duplex channel handshakes
+//!
+//! ST's transport uses every channel direction in one of two ways:
+//!
+//! * send with acknowledge, CPU1 to CPU2: CPU1 writes a message into shared memory and sets the
+//!   flag. CPU2 clears the flag once it's done with the message, e.g. wrote the response into the
+//!   same buffer, which raises the TX (free) interrupt. Without the interrupt, it's simplex: CPU1
+//!   only checks that the channel is free before the next message.
+//! * receive with release, CPU2 to CPU1: CPU2 sets the flag, which raises the RX (occupied)
+//!   interrupt. CPU1 reads the message and clears the flag, so that CPU2 can send the next one.
+//!
+//! `IpccTxToken` and `IpccRxToken` implement the CPU1 side of both once. They are plain channel
+//! numbers, so the transport keeps them in constants.
+
+use super::{Ipcc, IpccChannel};
+
+/// CPU1 side of a CPU1 to CPU2 channel, see module documentation.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct IpccTxToken {
+    channel: IpccChannel,
+}
+
+impl IpccTxToken {
+    pub const fn new(channel: IpccChannel) -> Self {
+        IpccTxToken { channel }
+    }
+
+    pub fn channel(&self) -> IpccChannel {
+        self.channel
+    }
+
+    /// Returns `true` while CPU2 hasn't taken the last message.
+    pub fn is_busy(&self, ipcc: &Ipcc) -> bool {
+        ipcc.c1_is_active_flag(self.channel)
+    }
+
+    /// Sends a message written by `write` and waits for the acknowledge in the TX interrupt.
+    ///
+    /// Returns what `write` returns.
+    pub fn send<R, F: FnOnce() -> R>(&self, ipcc: &mut Ipcc, write: F) -> R {
+        let r = write();
+        self.notify(ipcc);
+        r
+    }
+
+    /// Signals a message already in shared memory, with acknowledge interrupt.
+    pub fn notify(&self, ipcc: &mut Ipcc) {
+        ipcc.c1_set_flag_channel(self.channel);
+        self.enable_ack_irq(ipcc);
+    }
+
+    /// Signals a message already in shared memory, without acknowledge interrupt.
+    pub fn notify_simplex(&self, ipcc: &mut Ipcc) {
+        ipcc.c1_set_flag_channel(self.channel);
+    }
+
+    /// Enables the TX interrupt, raised once CPU2 takes the message.
+    pub fn enable_ack_irq(&self, ipcc: &mut Ipcc) {
+        ipcc.c1_set_tx_channel(self.channel, true);
+    }
+
+    /// Disables the TX interrupt, call it when the acknowledge is handled.
+    pub fn disable_ack_irq(&self, ipcc: &mut Ipcc) {
+        ipcc.c1_set_tx_channel(self.channel, false);
+    }
+
+    /// Handles the acknowledge from the TX interrupt handler.
+    ///
+    /// If CPU2 took the message and the interrupt is enabled, disables it and returns what
+    /// `complete` returns.
+    pub fn on_ack<R, F: FnOnce() -> R>(&self, ipcc: &mut Ipcc, complete: F) -> Option<R> {
+        if !ipcc.is_tx_pending(self.channel) {
+            return None;
+        }
+
+        self.disable_ack_irq(ipcc);
+        Some(complete())
+    }
+}
+
+/// CPU1 side of a CPU2 to CPU1 channel, see module documentation.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct IpccRxToken {
+    channel: IpccChannel,
+}
+
+impl IpccRxToken {
+    pub const fn new(channel: IpccChannel) -> Self {
+        IpccRxToken { channel }
+    }
+
+    pub fn channel(&self) -> IpccChannel {
+        self.channel
+    }
+
+    /// Enables the RX interrupt, raised when CPU2 sends a message.
+    pub fn enable(&self, ipcc: &mut Ipcc) {
+        ipcc.c1_set_rx_channel(self.channel, true);
+    }
+
+    /// Returns `true` if CPU2 sent a message and the RX interrupt is enabled.
+    pub fn is_pending(&self, ipcc: &Ipcc) -> bool {
+        ipcc.is_rx_pending(self.channel)
+    }
+
+    /// Receives a message with `read` and releases the channel if it returns `true`.
+    ///
+    /// If `read` leaves part of the message, it returns `false`: the flag then stays set, so CPU2
+    /// holds back further messages, and the RX interrupt is disabled until `enable`. Returns what
+    /// `read` returns.
+    pub fn receive<F: FnOnce() -> bool>(&self, ipcc: &mut Ipcc, read: F) -> bool {
+        if read() {
+            ipcc.c1_clear_flag_channel(self.channel);
+            true
+        } else {
+            ipcc.c1_set_rx_channel(self.channel, false);
+            false
+        }
+    }
+}
@@ -3,8 +3,9 @@
 //! NOTE: This HAL implementation is under active development (as is the underlying
 //! `embedded_hal` itself, together with its traits, some of which are unproven).
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
+pub use cortex_m;
 pub use embedded_hal as hal;
 pub use stm32wb_pac as pac;
 
@@ -14,17 +15,40 @@ pub use self::pac::interrupt;
 pub use crate::pac as device;
 pub use crate::pac as stm32;
 
+/// Trace-level message through the `log` facade, compiled out unless the `log` feature is
+/// enabled.
+#[cfg(feature = "log")]
+macro_rules! trace {
+    ($($arg:tt)+) => {
+        log::trace!($($arg)+)
+    };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! trace {
+    ($($arg:tt)+) => {};
+}
+
+pub mod calibration;
 pub mod datetime;
+pub mod debug;
 pub mod delay;
 
+pub mod exti;
+pub mod fault;
 pub mod flash;
 pub mod gpio;
 pub mod i2c;
+pub mod interrupts;
 pub mod ipcc;
+pub mod logger;
+pub mod power_savings;
 pub mod prelude;
 pub mod pwr;
 pub mod rcc;
 pub mod rtc;
+pub mod serial_io;
 pub mod time;
 pub mod tl_mbox;
 pub mod usb;
+pub mod wireless;
@@ -0,0 +1,187 @@
+//! Buffered `log` backend for a serial console
+//!
+//! Requires the `logger` feature.
+//!
+//! Records are formatted into a fixed size ring buffer from any context, including interrupts,
+//! and are written out by `drain`, called from the idle loop or the TX-empty interrupt with any
+//! `SerialIo` implementation:
+//!
+//! ```ignore
+//! logger::init(LevelFilter::Info);
+//! log::info!("started");
+//!
+//! loop {
+//!     logger::drain(&mut serial);
+//! }
+//! ```
+//!
+//! Records longer than `MAX_RECORD_SIZE` bytes are truncated and end with `~`. Records that
+//! don't fit into the free space of the buffer are dropped whole and counted, see `dropped`.
+
+#![cfg(feature = "logger")]
+
+use core::fmt::Write;
+
+use cortex_m::interrupt;
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::serial_io::SerialIo;
+
+/// Maximum length of a single record including the line ending.
+pub const MAX_RECORD_SIZE: usize = 128;
+
+/// Size of the ring buffer.
+pub const BUFFER_SIZE: usize = 1024;
+
+struct Ring {
+    buf: [u8; BUFFER_SIZE],
+    /// Read position
+    head: usize,
+    /// Number of stored bytes
+    len: usize,
+    dropped: u32,
+}
+
+static mut RING: Ring = Ring {
+    buf: [0; BUFFER_SIZE],
+    head: 0,
+    len: 0,
+    dropped: 0,
+};
+
+/// Record formatting buffer that truncates instead of failing.
+struct RecordBuf {
+    buf: [u8; MAX_RECORD_SIZE],
+    len: usize,
+    truncated: bool,
+}
+
+impl Write for RecordBuf {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        // Keep room for the truncation mark and line ending
+        let room = MAX_RECORD_SIZE - 3 - self.len;
+        let n = core::cmp::min(room, s.len());
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+
+        if n < s.len() {
+            self.truncated = true;
+        }
+
+        Ok(())
+    }
+}
+
+/// `log` backend writing into the ring buffer.
+pub struct SerialLogger;
+
+static LOGGER: SerialLogger = SerialLogger;
+
+impl Log for SerialLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut buf = RecordBuf {
+            buf: [0; MAX_RECORD_SIZE],
+            len: 0,
+            truncated: false,
+        };
+        let _ = write!(
+            buf,
+            "{} {}: {}",
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        if buf.truncated {
+            buf.buf[buf.len] = b'~';
+            buf.len += 1;
+        }
+        buf.buf[buf.len..buf.len + 2].copy_from_slice(b"\r\n");
+        buf.len += 2;
+
+        let record = &buf.buf[..buf.len];
+        interrupt::free(|_| {
+            // NOTE(unsafe) ring buffer is only accessed inside critical sections
+            let ring = unsafe { &mut RING };
+
+            if BUFFER_SIZE - ring.len < record.len() {
+                ring.dropped = ring.dropped.wrapping_add(1);
+                return;
+            }
+
+            for &byte in record {
+                let i = (ring.head + ring.len) % BUFFER_SIZE;
+                ring.buf[i] = byte;
+                ring.len += 1;
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the logger as the global `log` backend with `level` as the maximum level.
+///
+/// Fails if another logger is already installed.
+pub fn init(level: LevelFilter) -> Result<(), log::SetLoggerError> {
+    log::set_logger(&LOGGER)?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+/// Changes the maximum enabled level.
+pub fn set_level(level: LevelFilter) {
+    log::set_max_level(level);
+}
+
+/// Writes buffered records to `serial` until the buffer is empty or `serial` would block.
+///
+/// Returns the number of bytes written.
+pub fn drain<S: SerialIo>(serial: &mut S) -> Result<usize, S::Error> {
+    let mut total = 0;
+
+    loop {
+        // Copy a chunk out of the ring, so the critical section doesn't cover the write
+        let mut chunk = [0u8; 32];
+        let len = interrupt::free(|_| {
+            // NOTE(unsafe) ring buffer is only accessed inside critical sections
+            let ring = unsafe { &RING };
+
+            let len = core::cmp::min(chunk.len(), ring.len);
+            for (i, byte) in chunk[..len].iter_mut().enumerate() {
+                *byte = ring.buf[(ring.head + i) % BUFFER_SIZE];
+            }
+            len
+        });
+
+        if len == 0 {
+            return Ok(total);
+        }
+
+        let written = match serial.write(&chunk[..len]) {
+            Ok(written) => written,
+            Err(nb::Error::WouldBlock) => return Ok(total),
+            Err(nb::Error::Other(e)) => return Err(e),
+        };
+
+        interrupt::free(|_| {
+            // NOTE(unsafe) ring buffer is only accessed inside critical sections
+            let ring = unsafe { &mut RING };
+            ring.head = (ring.head + written) % BUFFER_SIZE;
+            ring.len -= written;
+        });
+        total += written;
+    }
+}
+
+/// Returns the number of records dropped because the buffer was full.
+pub fn dropped() -> u32 {
+    interrupt::free(|_| unsafe { RING.dropped })
+}
@@ -0,0 +1,305 @@
+//! Bulk power saving helpers
+//!
+//! Helpers to switch off unused peripheral clocks and put unused pins into analog mode before
+//! entering low-power modes, and to find out which peripheral clocks are still running.
+//!
+//! The HAL doesn't track which peripheral wrappers are alive, so the caller must list every
+//! peripheral that is still in use. FLASH clock is never disabled, nor are the IPCC and HSEM
+//! clocks while CPU2 runs: the mailbox and the semaphores shared with CPU2 depend on them. RCC
+//! and PWR have no clock enable bit and are never affected.
+
+use crate::rcc::Rcc;
+use crate::stm32::{GPIOA, GPIOB, GPIOC, GPIOD, GPIOE, GPIOH};
+
+/// RCC clock enable register that controls a peripheral.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Bus {
+    Ahb1,
+    Ahb2,
+    Ahb3,
+    Apb1_1,
+    Apb1_2,
+    Apb2,
+}
+
+macro_rules! periph_ids {
+    ($($ID:ident: ($bus:ident, $bit:expr),)+) => {
+        /// Peripheral with a clock enable bit in RCC.
+        #[derive(Debug, Copy, Clone, PartialEq)]
+        pub enum PeriphId {
+            $($ID,)+
+        }
+
+        impl PeriphId {
+            /// All peripherals with a clock enable bit.
+            pub const ALL: &'static [PeriphId] = &[$(PeriphId::$ID,)+];
+
+            /// Returns clock enable register of the peripheral.
+            pub fn bus(&self) -> Bus {
+                match self {
+                    $(PeriphId::$ID => Bus::$bus,)+
+                }
+            }
+
+            /// Returns clock enable bit position of the peripheral.
+            pub fn bit(&self) -> u8 {
+                match self {
+                    $(PeriphId::$ID => $bit,)+
+                }
+            }
+        }
+    }
+}
+
+// RM0434, section 6.4
+periph_ids! {
+    Dma1: (Ahb1, 0),
+    Dma2: (Ahb1, 1),
+    Dmamux1: (Ahb1, 2),
+    Crc: (Ahb1, 12),
+    Tsc: (Ahb1, 16),
+
+    GpioA: (Ahb2, 0),
+    GpioB: (Ahb2, 1),
+    GpioC: (Ahb2, 2),
+    GpioD: (Ahb2, 3),
+    GpioE: (Ahb2, 4),
+    GpioH: (Ahb2, 7),
+    Adc: (Ahb2, 13),
+    Aes1: (Ahb2, 16),
+
+    Qspi: (Ahb3, 8),
+    Pka: (Ahb3, 16),
+    Aes2: (Ahb3, 17),
+    Rng: (Ahb3, 18),
+    Hsem: (Ahb3, 19),
+    Ipcc: (Ahb3, 20),
+    Flash: (Ahb3, 25),
+
+    Tim2: (Apb1_1, 0),
+    Lcd: (Apb1_1, 9),
+    RtcApb: (Apb1_1, 10),
+    Wwdg: (Apb1_1, 11),
+    Spi2: (Apb1_1, 14),
+    I2c1: (Apb1_1, 21),
+    I2c3: (Apb1_1, 23),
+    Crs: (Apb1_1, 24),
+    Usb: (Apb1_1, 26),
+    Lptim1: (Apb1_1, 31),
+
+    Lpuart1: (Apb1_2, 0),
+    Lptim2: (Apb1_2, 5),
+
+    Tim1: (Apb2, 11),
+    Spi1: (Apb2, 12),
+    Usart1: (Apb2, 14),
+    Tim16: (Apb2, 17),
+    Tim17: (Apb2, 18),
+    Sai1: (Apb2, 21),
+}
+
+/// Snapshot of the CPU1 peripheral clock enable registers.
+#[derive(Debug, Copy, Clone)]
+pub struct ClockReport {
+    pub ahb1: u32,
+    pub ahb2: u32,
+    pub ahb3: u32,
+    pub apb1_1: u32,
+    pub apb1_2: u32,
+    pub apb2: u32,
+}
+
+impl ClockReport {
+    /// Returns `true` if the peripheral clock is enabled.
+    pub fn is_enabled(&self, id: PeriphId) -> bool {
+        let reg = match id.bus() {
+            Bus::Ahb1 => self.ahb1,
+            Bus::Ahb2 => self.ahb2,
+            Bus::Ahb3 => self.ahb3,
+            Bus::Apb1_1 => self.apb1_1,
+            Bus::Apb1_2 => self.apb1_2,
+            Bus::Apb2 => self.apb2,
+        };
+
+        reg & (1 << id.bit()) != 0
+    }
+
+    /// Iterates over peripherals that have their clock enabled.
+    pub fn enabled(&self) -> impl Iterator<Item = PeriphId> + '_ {
+        PeriphId::ALL
+            .iter()
+            .copied()
+            .filter(move |id| self.is_enabled(*id))
+    }
+}
+
+/// Returns which peripheral clocks are currently enabled for CPU1.
+pub fn enabled_peripheral_clocks(rcc: &Rcc) -> ClockReport {
+    ClockReport {
+        ahb1: rcc.rb.ahb1enr.read().bits(),
+        ahb2: rcc.rb.ahb2enr.read().bits(),
+        ahb3: rcc.rb.ahb3enr.read().bits(),
+        apb1_1: rcc.rb.apb1enr1.read().bits(),
+        apb1_2: rcc.rb.apb1enr2.read().bits(),
+        apb2: rcc.rb.apb2enr.read().bits(),
+    }
+}
+
+/// Disables clocks of all peripherals except the ones listed in `keep`.
+///
+/// FLASH clock is always kept enabled, IPCC and HSEM clocks too once CPU2 is booted
+/// (`pwr::cpu2_booted`).
+pub fn disable_all_peripheral_clocks_except(rcc: &mut Rcc, keep: &[PeriphId]) {
+    let mask = disable_mask(keep, crate::pwr::cpu2_booted());
+    apply_disable_mask(rcc, &mask);
+}
+
+/// Returns the clock enable bits to clear for `disable_all_peripheral_clocks_except`.
+fn disable_mask(keep: &[PeriphId], cpu2_running: bool) -> ClockReport {
+    let mut mask = ClockReport {
+        ahb1: 0,
+        ahb2: 0,
+        ahb3: 0,
+        apb1_1: 0,
+        apb1_2: 0,
+        apb2: 0,
+    };
+
+    for id in PeriphId::ALL.iter().filter(|id| !keep.contains(id)) {
+        if always_kept(*id, cpu2_running) {
+            continue;
+        }
+
+        let reg = match id.bus() {
+            Bus::Ahb1 => &mut mask.ahb1,
+            Bus::Ahb2 => &mut mask.ahb2,
+            Bus::Ahb3 => &mut mask.ahb3,
+            Bus::Apb1_1 => &mut mask.apb1_1,
+            Bus::Apb1_2 => &mut mask.apb1_2,
+            Bus::Apb2 => &mut mask.apb2,
+        };
+        *reg |= 1 << id.bit();
+    }
+
+    mask
+}
+
+/// Returns `true` if the clock of `id` must stay enabled whatever the caller keeps.
+fn always_kept(id: PeriphId, cpu2_running: bool) -> bool {
+    match id {
+        PeriphId::Flash => true,
+        // CPU2 relies on the mailbox and the shared semaphores
+        PeriphId::Ipcc | PeriphId::Hsem => cpu2_running,
+        _ => false,
+    }
+}
+
+fn apply_disable_mask(rcc: &mut Rcc, mask: &ClockReport) {
+    rcc.rb
+        .ahb1enr
+        .modify(|r, w| unsafe { w.bits(r.bits() & !mask.ahb1) });
+    rcc.rb
+        .ahb2enr
+        .modify(|r, w| unsafe { w.bits(r.bits() & !mask.ahb2) });
+    rcc.rb
+        .ahb3enr
+        .modify(|r, w| unsafe { w.bits(r.bits() & !mask.ahb3) });
+    rcc.rb
+        .apb1enr1
+        .modify(|r, w| unsafe { w.bits(r.bits() & !mask.apb1_1) });
+    rcc.rb
+        .apb1enr2
+        .modify(|r, w| unsafe { w.bits(r.bits() & !mask.apb1_2) });
+    rcc.rb
+        .apb2enr
+        .modify(|r, w| unsafe { w.bits(r.bits() & !mask.apb2) });
+}
+
+/// GPIO port.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Port {
+    A,
+    B,
+    C,
+    D,
+    E,
+    H,
+}
+
+/// Puts pins of the `port` selected by `pin_mask` (bit N selects pin N) into analog mode and
+/// removes their pull-up/pull-down resistors.
+///
+/// Analog mode has the lowest leakage. Typed pin wrappers of the affected pins must not be used
+/// afterwards. Port clock is enabled if needed and left enabled.
+pub fn set_unused_pins_analog(rcc: &mut Rcc, port: Port, pin_mask: u16) {
+    let mut moder_mask = 0u32;
+    let mut pupdr_mask = 0u32;
+    for i in 0..16 {
+        if pin_mask & (1 << i) != 0 {
+            moder_mask |= 0b11 << (2 * i);
+            pupdr_mask |= 0b11 << (2 * i);
+        }
+    }
+
+    macro_rules! analog {
+        ($GPIOX:ident, $gpioxen:ident) => {{
+            rcc.rb.ahb2enr.modify(|_, w| w.$gpioxen().set_bit());
+
+            // NOTE(unsafe) the caller gives up the selected pins
+            let gpio = unsafe { &*$GPIOX::ptr() };
+            gpio.moder
+                .modify(|r, w| unsafe { w.bits(r.bits() | moder_mask) });
+            gpio.pupdr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !pupdr_mask) });
+        }};
+    }
+
+    match port {
+        Port::A => analog!(GPIOA, gpioaen),
+        Port::B => analog!(GPIOB, gpioben),
+        Port::C => analog!(GPIOC, gpiocen),
+        Port::D => analog!(GPIOD, gpioden),
+        Port::E => analog!(GPIOE, gpioeen),
+        Port::H => analog!(GPIOH, gpiohen),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Bits set in the mask are the clocks to disable
+
+    #[test]
+    fn keeps_listed_peripherals() {
+        let keep = [PeriphId::GpioA, PeriphId::Usart1, PeriphId::Lptim1];
+        let mask = disable_mask(&keep, false);
+
+        for id in PeriphId::ALL {
+            let kept = keep.contains(id) || *id == PeriphId::Flash;
+            assert_eq!(mask.is_enabled(*id), !kept, "{:?}", id);
+        }
+    }
+
+    #[test]
+    fn never_disables_flash() {
+        assert!(!disable_mask(&[], false).is_enabled(PeriphId::Flash));
+        assert!(!disable_mask(&[], true).is_enabled(PeriphId::Flash));
+    }
+
+    #[test]
+    fn keeps_ipcc_and_hsem_while_cpu2_runs() {
+        let mask = disable_mask(&[], true);
+        assert!(!mask.is_enabled(PeriphId::Ipcc));
+        assert!(!mask.is_enabled(PeriphId::Hsem));
+        assert_eq!(mask.enabled().count(), PeriphId::ALL.len() - 3);
+    }
+
+    #[test]
+    fn disables_ipcc_and_hsem_without_cpu2() {
+        let mask = disable_mask(&[], false);
+        assert!(mask.is_enabled(PeriphId::Ipcc));
+        assert!(mask.is_enabled(PeriphId::Hsem));
+        assert_eq!(mask.enabled().count(), PeriphId::ALL.len() - 1);
+    }
+}
@@ -10,6 +10,12 @@ pub fn set_cpu2(enabled: bool) {
     pwr.cr4.modify(|_, w| w.c2boot().bit(enabled))
 }
 
+/// Returns `true` if CPU2 boot was requested by CPU1.
+pub fn cpu2_booted() -> bool {
+    let pwr = unsafe { &*stm32wb_pac::PWR::ptr() };
+    pwr.cr4.read().c2boot().bit_is_set()
+}
+
 /// Enables or disables access to the backup domain.
 pub fn set_backup_access(enabled: bool) {
     let pwr = unsafe { &*stm32wb_pac::PWR::ptr() };
@@ -18,3 +24,240 @@ pub fn set_backup_access(enabled: bool) {
     pwr.cr1.modify(|_, w| w.dbp().bit(enabled));
     pwr.cr1.modify(|_, w| w.dbp().bit(enabled));
 }
+
+/// Extension trait that constrains the `PWR` peripheral
+pub trait PwrExt {
+    /// Constrains the `PWR` peripheral so it plays nicely with the other abstractions
+    fn constrain(self) -> Pwr;
+}
+
+impl PwrExt for stm32wb_pac::PWR {
+    fn constrain(self) -> Pwr {
+        Pwr { _0: () }
+    }
+}
+
+/// Constrained PWR peripheral.
+///
+/// Owning it is required to change the Standby pull configuration of the pins.
+pub struct Pwr {
+    _0: (),
+}
+
+impl Pwr {
+    /// Enables or disables applying the PWR_PUCRx/PWR_PDCRx pull configuration (APC).
+    ///
+    /// Enabled by the `retain_*_in_standby` pin methods and by wake-up pins.
+    pub fn set_pull_config(&mut self, enabled: bool) {
+        let pwr = unsafe { &*stm32wb_pac::PWR::ptr() };
+        pwr.cr3.modify(|r, w| unsafe {
+            w.bits(if enabled {
+                r.bits() | CR3_APC
+            } else {
+                r.bits() & !CR3_APC
+            })
+        });
+    }
+
+    /// Returns `true` if the PWR_PUCRx/PWR_PDCRx pull configuration is applied.
+    pub fn pull_config(&self) -> bool {
+        pull_config_enabled()
+    }
+}
+
+pub(crate) fn pull_config_enabled() -> bool {
+    let pwr = unsafe { &*stm32wb_pac::PWR::ptr() };
+    pwr.cr3.read().bits() & CR3_APC != 0
+}
+
+/// Pull resistor applied by PWR in Standby and Shutdown.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StandbyPull {
+    None,
+    Up,
+    Down,
+}
+
+/// Active level of a Standby wake-up pin.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WakeupPolarity {
+    /// Wake up on the rising edge, the pin is pulled down.
+    High,
+    /// Wake up on the falling edge, the pin is pulled up.
+    Low,
+}
+
+/// Proof that a wake-up pin (WKUP1..5) is enabled.
+///
+/// Obtained with `into_wakeup_input` on one of the wake-up capable pins.
+#[derive(Debug)]
+pub struct WakeupSource {
+    wkup: u8,
+}
+
+impl WakeupSource {
+    /// Returns the wake-up pin number (1..=5).
+    pub fn wkup(&self) -> u8 {
+        self.wkup
+    }
+
+    /// Disables the wake-up pin.
+    pub fn disable(self) {
+        let pwr = unsafe { &*stm32wb_pac::PWR::ptr() };
+        pwr.cr3
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << (self.wkup - 1))) });
+    }
+}
+
+/// Standby entry error
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StandbyError {
+    /// No wake-up source was given, the device would never wake up.
+    NoWakeupSource,
+}
+
+// PWR_CR1 bits
+const CR1_LPMS_MASK: u32 = 0b111;
+const CR1_LPMS_STOP2: u32 = 0b010;
+const CR1_LPMS_STANDBY: u32 = 0b011;
+
+// PWR_CR3 bits
+const CR3_APC: u32 = 1 << 10;
+
+// PWR_SCR bits
+const SCR_CWUF_MASK: u32 = 0b11111;
+
+/// Returns PWR_PUCRx and PWR_PDCRx bits of GPIO port `port` (0 = A .. 4 = E, 5 = H).
+pub(crate) fn standby_pull_bits(port: u8) -> (u32, u32) {
+    let pwr = unsafe { &*stm32wb_pac::PWR::ptr() };
+
+    match port {
+        0 => (pwr.pucra.read().bits(), pwr.pdcra.read().bits()),
+        1 => (pwr.pucrb.read().bits(), pwr.pdcrb.read().bits()),
+        2 => (pwr.pucrc.read().bits(), pwr.pdcrc.read().bits()),
+        3 => (pwr.pucrd.read().bits(), pwr.pdcrd.read().bits()),
+        4 => (pwr.pucre.read().bits(), pwr.pdcre.read().bits()),
+        5 => (pwr.pucrh.read().bits(), pwr.pdcrh.read().bits()),
+        _ => unreachable!(),
+    }
+}
+
+/// Sets Standby pull of `pin` of GPIO port `port` (0 = A .. 4 = E, 5 = H).
+///
+/// Takes effect once APC is enabled.
+pub(crate) fn set_standby_pull(port: u8, pin: u8, pull: StandbyPull) {
+    let pwr = unsafe { &*stm32wb_pac::PWR::ptr() };
+
+    macro_rules! set_pull {
+        ($pucr:ident, $pdcr:ident) => {{
+            let (up, down) = match pull {
+                StandbyPull::None => (false, false),
+                StandbyPull::Up => (true, false),
+                StandbyPull::Down => (false, true),
+            };
+
+            pwr.$pucr
+                .modify(|r, w| unsafe { w.bits((r.bits() & !(1 << pin)) | ((up as u32) << pin)) });
+            pwr.$pdcr.modify(|r, w| unsafe {
+                w.bits((r.bits() & !(1 << pin)) | ((down as u32) << pin))
+            });
+        }};
+    }
+
+    match port {
+        0 => set_pull!(pucra, pdcra),
+        1 => set_pull!(pucrb, pdcrb),
+        2 => set_pull!(pucrc, pdcrc),
+        3 => set_pull!(pucrd, pdcrd),
+        4 => set_pull!(pucre, pdcre),
+        5 => set_pull!(pucrh, pdcrh),
+        _ => unreachable!(),
+    }
+}
+
+/// Enables wake-up pin `wkup` (1..=5) mapped to `pin` of GPIO port `port` (0 = A, 2 = C).
+///
+/// Pull resistor opposite to the active level is applied through PWR_PUCRx/PWR_PDCRx so it's
+/// retained in Standby.
+pub(crate) fn enable_wakeup_pin(
+    wkup: u8,
+    port: u8,
+    pin: u8,
+    polarity: WakeupPolarity,
+) -> WakeupSource {
+    let pwr = unsafe { &*stm32wb_pac::PWR::ptr() };
+
+    set_standby_pull(
+        port,
+        pin,
+        match polarity {
+            WakeupPolarity::High => StandbyPull::Down,
+            WakeupPolarity::Low => StandbyPull::Up,
+        },
+    );
+
+    let bit = 1 << (wkup - 1);
+    pwr.cr4.modify(|r, w| unsafe {
+        w.bits(match polarity {
+            WakeupPolarity::High => r.bits() & !bit,
+            WakeupPolarity::Low => r.bits() | bit,
+        })
+    });
+    pwr.cr3
+        .modify(|r, w| unsafe { w.bits(r.bits() | CR3_APC | bit) });
+
+    WakeupSource { wkup }
+}
+
+/// Enters Standby mode.
+///
+/// Refuses to enter Standby if `sources` is empty, use `standby_unchecked` when the device is
+/// woken up by other means (RTC, reset). Standby is only reached when CPU2 allows it as well.
+pub fn standby(
+    scb: &mut cortex_m::peripheral::SCB,
+    sources: &[WakeupSource],
+) -> Result<core::convert::Infallible, StandbyError> {
+    if sources.is_empty() {
+        return Err(StandbyError::NoWakeupSource);
+    }
+
+    standby_unchecked(scb)
+}
+
+/// Enters Standby mode without checking for wake-up sources.
+pub fn standby_unchecked(scb: &mut cortex_m::peripheral::SCB) -> ! {
+    let pwr = unsafe { &*stm32wb_pac::PWR::ptr() };
+
+    // Pending wake-up flags would prevent entering Standby
+    pwr.scr.write(|w| unsafe { w.bits(SCR_CWUF_MASK) });
+
+    pwr.cr1
+        .modify(|r, w| unsafe { w.bits((r.bits() & !CR1_LPMS_MASK) | CR1_LPMS_STANDBY) });
+
+    scb.set_sleepdeep();
+
+    loop {
+        cortex_m::asm::dsb();
+        cortex_m::asm::wfi();
+    }
+}
+
+/// Enters Stop2 mode and returns after wake-up.
+///
+/// Any enabled EXTI wake-up line (including direct lines like IPCC) ends Stop2. SYSCLK then runs
+/// from the clock selected with `Rcc::set_stop_wakeup_clock`, call `Rcc::restore_after_stop`
+/// before relying on the configured clocks. Stop2 is only reached when CPU2 allows it as well,
+/// otherwise the core just sleeps.
+pub fn stop2(scb: &mut cortex_m::peripheral::SCB) {
+    let pwr = unsafe { &*stm32wb_pac::PWR::ptr() };
+
+    pwr.scr.write(|w| unsafe { w.bits(SCR_CWUF_MASK) });
+
+    pwr.cr1
+        .modify(|r, w| unsafe { w.bits((r.bits() & !CR1_LPMS_MASK) | CR1_LPMS_STOP2) });
+
+    scb.set_sleepdeep();
+    cortex_m::asm::dsb();
+    cortex_m::asm::wfi();
+    scb.clear_sleepdeep();
+}
@@ -1,9 +1,14 @@
 use super::mux::*;
+use super::{ClockConfigError, HSE_FREQ, HSI_FREQ};
+use crate::time::{Hertz, U32Ext};
 
 #[derive(Debug, Clone)]
 pub struct Config {
-    pub(crate) lse: bool,
+    pub(crate) lse: Option<LseConfig>,
     pub(crate) lsi1: bool,
+    pub(crate) lsi2: bool,
+    pub(crate) msi_pll: bool,
+    pub(crate) rf: bool,
 
     pub(crate) sysclk_src: SysClkSrc,
 
@@ -26,8 +31,11 @@ impl Default for Config {
     /// SYSCLK = 4 MHz, HCLK = 4MHz, CPU1 = CPU2 = 4MHz, APB1 = APB2 = 4MHz
     fn default() -> Self {
         Config {
-            lse: false,
+            lse: None,
             lsi1: false,
+            lsi2: false,
+            msi_pll: false,
+            rf: false,
             sysclk_src: SysClkSrc::Hsi,
             pll_cfg: PllConfig::default(),
             apb1_div: ApbDivider::NotDivided,
@@ -96,16 +104,42 @@ impl Config {
         self
     }
 
-    pub fn with_lse(mut self) -> Self {
-        self.lse = true;
+    /// Enables the LSE crystal oscillator with the default `LseConfig`.
+    pub fn with_lse(self) -> Self {
+        self.lse(LseConfig::default())
+    }
+
+    /// Enables LSE, the 32.768 kHz clock the radio needs for its sleep timer when selected with
+    /// `rf_wkp_sel(RfWakeupClock::Lse)`.
+    pub fn lse(mut self, cfg: LseConfig) -> Self {
+        self.lse = Some(cfg);
         self
     }
 
+    /// Enables LSI1, the low power internal 32 kHz RC oscillator.
+    ///
+    /// Select it with `rtc_src(RtcClkSrc::Lsi)` and `rf_wkp_sel(RfWakeupClock::Lsi)` on boards
+    /// without LSE crystal. These start LSI1 on their own if neither LSI is enabled.
     pub fn with_lsi1(mut self) -> Self {
         self.lsi1 = true;
         self
     }
 
+    /// Enables LSI2, the internal 32 kHz RC oscillator with better accuracy than LSI1, see
+    /// `Rcc::enable_lsi2`.
+    pub fn with_lsi2(mut self) -> Self {
+        self.lsi2 = true;
+        self
+    }
+
+    /// Enables MSI hardware auto-calibration against LSE (MSIPLLEN).
+    ///
+    /// Requires LSE to be enabled with `with_lse`.
+    pub fn msi_pll_mode(mut self, enabled: bool) -> Self {
+        self.msi_pll = enabled;
+        self
+    }
+
     pub fn rtc_src(mut self, src: RtcClkSrc) -> Self {
         self.rtc_src = src;
         self
@@ -115,9 +149,85 @@ impl Config {
         self.rf_wkp_src = sel;
         self
     }
+
+    /// Marks the configuration as intended for running the radio on CPU2.
+    ///
+    /// `apply_clock_config` then rejects it with `ClockConfigError::RfClockInvalid` unless
+    /// `check_rf_clocks` passes.
+    pub fn with_rf(mut self) -> Self {
+        self.rf = true;
+        self
+    }
+
+    /// Returns HSE prescaler (HSEPRE) if SYSCLK is derived from HSE.
+    pub fn hse_prescaler(&self) -> Option<HseDivider> {
+        match &self.sysclk_src {
+            SysClkSrc::HseSys(div) | SysClkSrc::Pll(PllSrc::Hse(div)) => Some(div.clone()),
+            _ => None,
+        }
+    }
+
+    /// Returns SYSCLK frequency the configuration results in.
+    pub fn sysclk_frequency(&self) -> Hertz {
+        match &self.sysclk_src {
+            SysClkSrc::Msi(range) => range.frequency(),
+            SysClkSrc::Hsi => HSI_FREQ.into(),
+            SysClkSrc::HseSys(div) => div.frequency(),
+            SysClkSrc::Pll(src) => {
+                let f_input = match src {
+                    PllSrc::Msi(range) => range.frequency(),
+                    PllSrc::Hsi => HSI_FREQ.into(),
+                    PllSrc::Hse(div) => div.frequency(),
+                };
+
+                self.pll_cfg.r_frequency(f_input)
+            }
+        }
+    }
+
+    /// Returns `true` if the RTC or the RF wake-up runs from LSI.
+    pub(crate) fn selects_lsi(&self) -> bool {
+        let rtc = match self.rtc_src {
+            RtcClkSrc::Lsi => true,
+            _ => false,
+        };
+        let rf_wkp = match self.rf_wkp_src {
+            RfWakeupClock::Lsi => true,
+            _ => false,
+        };
+
+        rtc || rf_wkp
+    }
+
+    /// Returns CPU2 clock (HCLK2) frequency the configuration results in.
+    pub fn cpu2_frequency(&self) -> Hertz {
+        Hertz(self.sysclk_frequency().0 / self.cpu2_hdiv.divisor())
+    }
+
+    /// Checks the clock constraints of the radio:
+    ///
+    /// * SYSCLK is derived from HSE, so the 32 MHz HSE the radio runs from is enabled. The HSE
+    ///   prescaler only affects SYSCLK, the radio always sees the undivided HSE.
+    /// * CPU2 clock doesn't exceed 32 MHz.
+    /// * RF wake-up clock source is selected, and enabled if it's LSE. LSI is started as needed.
+    pub fn check_rf_clocks(&self) -> Result<(), ClockConfigError> {
+        let hse_on = self.hse_prescaler().is_some();
+        let cpu2_ok = self.cpu2_frequency() <= Hertz::from(32.mhz());
+        let rf_wkp_ok = match self.rf_wkp_src {
+            RfWakeupClock::None => false,
+            RfWakeupClock::Lse => self.lse.is_some(),
+            _ => true,
+        };
+
+        if hse_on && cpu2_ok && rf_wkp_ok {
+            Ok(())
+        } else {
+            Err(ClockConfigError::RfClockInvalid)
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Copy, Clone)]
 pub enum MsiRange {
     #[doc = "range 0 around 100 kHz"]
     RANGE100K = 0,
@@ -145,6 +255,26 @@ pub enum MsiRange {
     RANGE48M = 11,
 }
 
+impl MsiRange {
+    /// Returns nominal frequency of the range.
+    pub fn frequency(&self) -> Hertz {
+        match self {
+            MsiRange::RANGE100K => 100.khz().into(),
+            MsiRange::RANGE200K => 200.khz().into(),
+            MsiRange::RANGE400K => 400.khz().into(),
+            MsiRange::RANGE800K => 800.khz().into(),
+            MsiRange::RANGE1M => 1.mhz().into(),
+            MsiRange::RANGE2M => 2.mhz().into(),
+            MsiRange::RANGE4M => 4.mhz().into(),
+            MsiRange::RANGE8M => 8.mhz().into(),
+            MsiRange::RANGE16M => 16.mhz().into(),
+            MsiRange::RANGE24M => 24.mhz().into(),
+            MsiRange::RANGE32M => 32.mhz().into(),
+            MsiRange::RANGE48M => 48.mhz().into(),
+        }
+    }
+}
+
 impl Default for MsiRange {
     fn default() -> Self {
         MsiRange::RANGE4M
@@ -158,6 +288,17 @@ pub enum HseDivider {
     Div2,
 }
 
+impl HseDivider {
+    /// Returns the divided HSE frequency.
+    pub fn frequency(&self) -> Hertz {
+        let hse = Hertz::from(HSE_FREQ);
+        match self {
+            HseDivider::NotDivided => hse,
+            HseDivider::Div2 => Hertz(hse.0 / 2),
+        }
+    }
+}
+
 /// PLL configuration.
 #[derive(Debug, Clone)]
 pub struct PllConfig {
@@ -168,6 +309,36 @@ pub struct PllConfig {
     pub p: Option<u8>,
 }
 
+impl PllConfig {
+    /// Returns the VCO frequency for the PLL input frequency `input`.
+    ///
+    /// # Panics
+    ///
+    /// If the frequency doesn't fit in `Hertz`.
+    pub fn vco_frequency<F: Into<Hertz>>(&self, input: F) -> Hertz {
+        Hertz(input.into().0 / self.m as u32)
+            .checked_mul(self.n as u32)
+            .expect("frequency overflow")
+    }
+
+    /// Returns the PLLR output (PLLCLK) frequency for the PLL input frequency `input`.
+    pub fn r_frequency<F: Into<Hertz>>(&self, input: F) -> Hertz {
+        Hertz(self.vco_frequency(input).0 / self.r as u32)
+    }
+
+    /// Returns the PLLP output frequency for the PLL input frequency `input`, if enabled.
+    pub fn p_frequency<F: Into<Hertz>>(&self, input: F) -> Option<Hertz> {
+        let vco = self.vco_frequency(input);
+        self.p.map(|p| Hertz(vco.0 / p as u32))
+    }
+
+    /// Returns the PLLQ output frequency for the PLL input frequency `input`, if enabled.
+    pub fn q_frequency<F: Into<Hertz>>(&self, input: F) -> Option<Hertz> {
+        let vco = self.vco_frequency(input);
+        self.q.map(|q| Hertz(vco.0 / q as u32))
+    }
+}
+
 impl Default for PllConfig {
     fn default() -> Self {
         PllConfig {
@@ -243,6 +414,36 @@ impl HDivider {
     }
 }
 
+/// LSE oscillator drive capability (LSEDRV), from lowest to highest power consumption.
+///
+/// Higher drive helps crystals with a high ESR or load capacitance to start.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LseDrive {
+    Low = 0b00,
+    MediumLow = 0b01,
+    MediumHigh = 0b10,
+    High = 0b11,
+}
+
+/// LSE configuration, see `Config::lse`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LseConfig {
+    /// External clock on OSC32_IN instead of a crystal (LSEBYP).
+    pub bypass: bool,
+    /// Ignored with `bypass`.
+    pub drive: LseDrive,
+}
+
+impl Default for LseConfig {
+    /// Crystal with the lowest drive, the reset value.
+    fn default() -> Self {
+        LseConfig {
+            bypass: false,
+            drive: LseDrive::Low,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum StopWakeupClock {
     MSI = 0,
@@ -270,3 +471,193 @@ impl Default for RtcClkSrc {
         RtcClkSrc::None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pll(src: PllSrc, m: u8, n: u8, r: u8) -> Config {
+        Config::new(SysClkSrc::Pll(src)).pll_cfg(PllConfig {
+            m,
+            n,
+            r,
+            q: None,
+            p: None,
+        })
+    }
+
+    #[test]
+    fn sysclk_frequency_table() {
+        let table = [
+            (Config::default(), 16_000_000),
+            (Config::hsi(), 16_000_000),
+            (Config::new(SysClkSrc::Msi(MsiRange::RANGE100K)), 100_000),
+            (Config::new(SysClkSrc::Msi(MsiRange::RANGE4M)), 4_000_000),
+            (Config::new(SysClkSrc::Msi(MsiRange::RANGE48M)), 48_000_000),
+            (Config::hse_sys(HseDivider::NotDivided), 32_000_000),
+            (Config::hse_sys(HseDivider::Div2), 16_000_000),
+            (Config::pll(), 16_000_000),
+            (
+                pll(PllSrc::Hse(HseDivider::NotDivided), 2, 8, 2),
+                64_000_000,
+            ),
+            (
+                pll(PllSrc::Hse(HseDivider::NotDivided), 2, 12, 3),
+                64_000_000,
+            ),
+            (
+                pll(PllSrc::Hse(HseDivider::NotDivided), 4, 16, 4),
+                32_000_000,
+            ),
+            (pll(PllSrc::Hse(HseDivider::Div2), 1, 8, 2), 64_000_000),
+            (pll(PllSrc::Hsi, 1, 8, 2), 64_000_000),
+            (pll(PllSrc::Hsi, 4, 32, 2), 64_000_000),
+            (pll(PllSrc::Msi(MsiRange::RANGE32M), 2, 6, 3), 32_000_000),
+            (pll(PllSrc::Msi(MsiRange::RANGE48M), 3, 8, 2), 64_000_000),
+        ];
+
+        for (config, sysclk) in table.iter() {
+            assert_eq!(config.sysclk_frequency(), Hertz(*sysclk), "{:?}", config);
+        }
+    }
+
+    #[test]
+    fn pll_output_frequencies() {
+        let cfg = PllConfig {
+            m: 2,
+            n: 12,
+            r: 3,
+            q: Some(4),
+            p: Some(8),
+        };
+
+        assert_eq!(cfg.vco_frequency(32.mhz()), Hertz(192_000_000));
+        assert_eq!(cfg.r_frequency(32.mhz()), Hertz(64_000_000));
+        assert_eq!(cfg.q_frequency(32.mhz()), Some(Hertz(48_000_000)));
+        assert_eq!(cfg.p_frequency(32.mhz()), Some(Hertz(24_000_000)));
+        assert_eq!(PllConfig::default().p_frequency(16.mhz()), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn pll_vco_overflow_panics() {
+        let cfg = PllConfig {
+            n: 86,
+            ..PllConfig::default()
+        };
+
+        cfg.vco_frequency(Hertz(u32::MAX));
+    }
+
+    #[test]
+    fn cpu2_frequency_divides_sysclk() {
+        let config = pll(PllSrc::Hse(HseDivider::NotDivided), 2, 8, 2);
+
+        let table = [
+            (HDivider::NotDivided, 64_000_000),
+            (HDivider::Div2, 32_000_000),
+            (HDivider::Div3, 21_333_333),
+            (HDivider::Div10, 6_400_000),
+            (HDivider::Div512, 125_000),
+        ];
+
+        for (div, hclk2) in table.iter() {
+            let config = config.clone().cpu2_hdiv(*div);
+            assert_eq!(config.cpu2_frequency(), Hertz(*hclk2), "{:?}", div);
+        }
+    }
+
+    #[test]
+    fn hse_prescaler() {
+        assert!(Config::hsi().hse_prescaler().is_none());
+        assert!(Config::pll().hse_prescaler().is_none());
+        assert!(pll(PllSrc::Hsi, 1, 8, 2).hse_prescaler().is_none());
+
+        match Config::hse_sys(HseDivider::Div2).hse_prescaler() {
+            Some(HseDivider::Div2) => {}
+            other => panic!("{:?}", other),
+        }
+        match pll(PllSrc::Hse(HseDivider::NotDivided), 2, 8, 2).hse_prescaler() {
+            Some(HseDivider::NotDivided) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn rf_clocks_valid() {
+        let hse_64m = pll(PllSrc::Hse(HseDivider::NotDivided), 2, 8, 2);
+
+        let table = [
+            Config::hse_sys(HseDivider::NotDivided)
+                .with_lse()
+                .rf_wkp_sel(RfWakeupClock::Lse),
+            Config::hse_sys(HseDivider::Div2).rf_wkp_sel(RfWakeupClock::Lsi),
+            Config::hse_sys(HseDivider::NotDivided).rf_wkp_sel(RfWakeupClock::HsiDiv1024),
+            hse_64m
+                .clone()
+                .cpu2_hdiv(HDivider::Div2)
+                .with_lse()
+                .rf_wkp_sel(RfWakeupClock::Lse),
+            hse_64m
+                .clone()
+                .cpu2_hdiv(HDivider::Div3)
+                .rf_wkp_sel(RfWakeupClock::Lsi),
+            pll(PllSrc::Hse(HseDivider::Div2), 1, 8, 2)
+                .cpu2_hdiv(HDivider::Div2)
+                .rf_wkp_sel(RfWakeupClock::Lsi),
+            // The HSE prescaler doesn't matter as long as CPU2 stays within 32 MHz
+            pll(PllSrc::Hse(HseDivider::Div2), 1, 4, 2).rf_wkp_sel(RfWakeupClock::Lsi),
+        ];
+
+        for config in table.iter() {
+            assert_eq!(config.check_rf_clocks(), Ok(()), "{:?}", config);
+        }
+    }
+
+    #[test]
+    fn rf_clocks_invalid() {
+        let hse_64m = pll(PllSrc::Hse(HseDivider::NotDivided), 2, 8, 2);
+
+        let table = [
+            // SYSCLK not from HSE
+            Config::hsi().rf_wkp_sel(RfWakeupClock::Lsi),
+            Config::pll().rf_wkp_sel(RfWakeupClock::Lsi),
+            pll(PllSrc::Hsi, 1, 4, 2).rf_wkp_sel(RfWakeupClock::Lsi),
+            Config::new(SysClkSrc::Msi(MsiRange::RANGE32M)).rf_wkp_sel(RfWakeupClock::Lsi),
+            // CPU2 above 32 MHz
+            hse_64m.clone().rf_wkp_sel(RfWakeupClock::Lsi),
+            pll(PllSrc::Hse(HseDivider::NotDivided), 1, 6, 4).rf_wkp_sel(RfWakeupClock::Lsi),
+            // No RF wake-up clock
+            Config::hse_sys(HseDivider::NotDivided),
+            Config::hse_sys(HseDivider::NotDivided).rf_wkp_sel(RfWakeupClock::None),
+            // LSE selected, but not enabled
+            Config::hse_sys(HseDivider::NotDivided).rf_wkp_sel(RfWakeupClock::Lse),
+            hse_64m
+                .clone()
+                .cpu2_hdiv(HDivider::Div2)
+                .rf_wkp_sel(RfWakeupClock::Lse),
+        ];
+
+        for config in table.iter() {
+            assert_eq!(
+                config.check_rf_clocks(),
+                Err(ClockConfigError::RfClockInvalid),
+                "{:?}",
+                config
+            );
+        }
+    }
+
+    #[test]
+    fn selects_lsi() {
+        assert!(!Config::default().selects_lsi());
+        assert!(Config::default().rtc_src(RtcClkSrc::Lsi).selects_lsi());
+        assert!(Config::default()
+            .rf_wkp_sel(RfWakeupClock::Lsi)
+            .selects_lsi());
+        assert!(!Config::default()
+            .rtc_src(RtcClkSrc::Lse)
+            .rf_wkp_sel(RfWakeupClock::HsiDiv1024)
+            .selects_lsi());
+    }
+}
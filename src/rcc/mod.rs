@@ -9,13 +9,51 @@ pub use mux::*;
 use crate::stm32::RCC;
 
 use crate::flash::ACR;
-use crate::time::{Hertz, U32Ext};
+use crate::time::{Hertz, KiloHertz, MegaHertz, U32Ext};
 
 /// HSI frequency.
-pub const HSI_FREQ: u32 = 16_000_000;
+pub const HSI_FREQ: MegaHertz = MegaHertz(16);
 
 /// On WB55 HSE frequency is fixed with 32 MHz.
-pub const HSE_FREQ: u32 = 32_000_000;
+pub const HSE_FREQ: MegaHertz = MegaHertz(32);
+
+/// Nominal LSI1 and LSI2 frequency.
+pub const LSI_FREQ: KiloHertz = KiloHertz(32);
+
+#[derive(Copy, Clone)]
+enum Lsi {
+    Lsi1,
+    Lsi2,
+}
+
+/// HSEM semaphore that guards RCC configuration shared with CPU2 (AN5289).
+pub const RCC_SEMAPHORE: u8 = 3;
+
+/// HSEM semaphore that guards CLK48 configuration shared with CPU2 (AN5289).
+pub const CLK48_SEMAPHORE: u8 = 5;
+
+const HSEM_BASE: usize = 0x5800_1400;
+const HSEM_COREID_CPU1: u32 = 0x4;
+const HSEM_LOCK: u32 = 1 << 31;
+
+// HSEM register offsets
+const HSEM_R: usize = 0x000;
+const HSEM_RLR: usize = 0x080;
+const HSEM_C1IER: usize = 0x100;
+const HSEM_C1ICR: usize = 0x104;
+const HSEM_C1MISR: usize = 0x10c;
+
+// RCC_CSR bits
+const CSR_RFRSTS: u32 = 1 << 14;
+const CSR_RFRST: u32 = 1 << 15;
+
+// RCC_EXTCFGR bits
+const EXTCFGR_RFCSS: u32 = 1 << 20;
+
+// RCC_HSECR
+const HSECR_UNLOCK_KEY: u32 = 0xCAFE_CAFE;
+const HSECR_HSETUNE_SHIFT: u32 = 8;
+const HSECR_HSETUNE_MASK: u32 = 0x3f;
 
 pub struct Rcc {
     pub clocks: Clocks,
@@ -23,45 +61,131 @@ pub struct Rcc {
     pub(crate) rb: RCC,
 }
 
+/// Clock configuration error.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ClockConfigError {
+    /// MSI PLL mode requires LSE to be enabled.
+    MsiPllWithoutLse,
+    /// CPU2 currently owns the shared clock configuration.
+    Cpu2Busy,
+    /// Configuration marked with `Config::with_rf` doesn't meet the radio clock constraints,
+    /// see `Config::check_rf_clocks`.
+    RfClockInvalid,
+    /// LSE didn't become ready within `LSE_STARTUP_TIMEOUT_MS`, LSE is left disabled.
+    LseTimeout,
+}
+
+/// Time LSE may take to start, the datasheet gives 2 s typical for crystals.
+pub const LSE_STARTUP_TIMEOUT_MS: u32 = 5000;
+
 impl Rcc {
-    pub fn apply_clock_config(mut self, config: config::Config, acr: &mut ACR) -> Self {
-        self.config = config.clone();
+    /// Applies `config`.
+    ///
+    /// On error the previous configuration stays in place and the call can be retried, e.g.
+    /// after `ClockConfigError::Cpu2Busy`.
+    pub fn apply_clock_config(
+        &mut self,
+        config: config::Config,
+        acr: &mut ACR,
+    ) -> Result<(), ClockConfigError> {
+        self.apply_clock_config_with_feed(config, acr, || {})
+    }
+
+    /// Same as `apply_clock_config`, but calls `feed` from every busy-wait loop (oscillator ready
+    /// flags, PLL lock, SYSCLK switch and prescaler updates).
+    ///
+    /// Use it to keep a watchdog fed while waiting for slow oscillators to start. `feed` is never
+    /// called from within a critical section.
+    pub fn apply_clock_config_with_feed<F: FnMut()>(
+        &mut self,
+        config: config::Config,
+        acr: &mut ACR,
+        mut feed: F,
+    ) -> Result<(), ClockConfigError> {
+        // MSI auto-calibration needs LSE to be ready before MSIPLLEN is set
+        if config.msi_pll && config.lse.is_none() {
+            return Err(ClockConfigError::MsiPllWithoutLse);
+        }
+
+        if config.rf {
+            config.check_rf_clocks()?;
+        }
 
         // Enable backup domain access to access LSE/RTC registers
         crate::pwr::set_backup_access(true);
 
-        // Configure LSE if needed
-        if config.lse {
-            self.rb.bdcr.modify(|_, w| w.lseon().set_bit());
-            while !self.rb.bdcr.read().lserdy().bit_is_set() {}
-
+        // LSE may take seconds to start, don't keep CPU2 out of the clock settings meanwhile
+        if let Some(lse) = config.lse {
+            self.enable_lse(lse, &mut feed)?;
             self.clocks.lse = Some(32768.hz());
         }
 
-        // Configure LSI1 if needed
-        if config.lsi1 {
-            self.rb.csr.modify(|_, w| w.lsi1on().clear_bit());
-        } else {
-            self.rb.csr.modify(|_, w| w.lsi1on().set_bit());
-            while !self.rb.csr.read().lsi1rdy().bit_is_set() {}
+        // Clock settings are shared with a running CPU2, CLK48 has its own semaphore
+        let cpu2_running = crate::pwr::cpu2_booted();
+        let clk48_locked = cpu2_running && config.usb_src.is_some();
+        if cpu2_running {
+            self.set_hsem(true);
+            if !self.is_safe_to_reconfigure_clocks() || !hsem_try_lock(RCC_SEMAPHORE) {
+                return Err(ClockConfigError::Cpu2Busy);
+            }
+            if clk48_locked && !hsem_try_lock(CLK48_SEMAPHORE) {
+                hsem_unlock(RCC_SEMAPHORE);
+                return Err(ClockConfigError::Cpu2Busy);
+            }
+        }
+
+        let result = self.apply_clock_config_locked(config, acr, &mut feed);
+
+        if clk48_locked {
+            hsem_unlock(CLK48_SEMAPHORE);
+        }
+        if cpu2_running {
+            hsem_unlock(RCC_SEMAPHORE);
+        }
+
+        result
+    }
+
+    fn apply_clock_config_locked<F: FnMut()>(
+        &mut self,
+        config: config::Config,
+        acr: &mut ACR,
+        mut feed: F,
+    ) -> Result<(), ClockConfigError> {
+        // Configure LSI1 and LSI2 if needed, LSI1 also if the RTC or RF wake-up needs an LSI.
+        // They're left running otherwise, CPU2 or IWDG may use them.
+        if config.lsi1 || (config.selects_lsi() && !config.lsi2) {
+            self.enable_lsi(Lsi::Lsi1, &mut feed);
+        }
+        if config.lsi2 {
+            self.enable_lsi(Lsi::Lsi2, &mut feed);
         }
 
+        // Configure MSI hardware auto-calibration against LSE
+        self.rb.cr.modify(|_, w| w.msipllen().bit(config.msi_pll));
+        self.clocks.msi_pll = config.msi_pll;
+
         // Select system clock source
         let sysclk_bits = match &config.sysclk_src {
-            SysClkSrc::Msi(_msi_range) => todo!(),
+            SysClkSrc::Msi(msi_range) => {
+                // Range change takes effect immediately if MSI already drives SYSCLK,
+                // so make sure FLASH is slow enough for any range first.
+                acr.acr().modify(|_, w| unsafe { w.latency().bits(3) });
+
+                self.configure_and_wait_for_msi(msi_range, &mut feed);
+                self.clocks.sysclk = msi_range.frequency();
+
+                0b00
+            }
             SysClkSrc::Hsi => todo!(),
             SysClkSrc::HseSys(hse_div) => {
-                self.clocks.hse = Some(HSE_FREQ.hz());
-
-                self.clocks.sysclk = match hse_div {
-                    HseDivider::NotDivided => HSE_FREQ.hz(),
-                    HseDivider::Div2 => (HSE_FREQ / 2).hz(),
-                };
+                self.clocks.hse = Some(HSE_FREQ.into());
+                self.clocks.sysclk = hse_div.frequency();
 
                 0b10
             }
             SysClkSrc::Pll(src) => {
-                self.configure_and_wait_for_pll(&config.pll_cfg, src);
+                self.configure_and_wait_for_pll(&config.pll_cfg, src, &mut feed);
                 if let Some(pllclk) = self.clocks.pllclk {
                     self.clocks.sysclk = pllclk;
                 }
@@ -89,7 +213,9 @@ impl Rcc {
             .modify(|_r, w| unsafe { w.sw().bits(sysclk_bits) });
 
         // Wait for SYSCLK to switch
-        while self.rb.cfgr.read().sw() != sysclk_bits {}
+        while self.rb.cfgr.read().sw() != sysclk_bits {
+            feed();
+        }
 
         // Configure CPU1 and CPU2 dividers
         self.clocks.hclk1 = (self.clocks.sysclk.0 / config.cpu1_hdiv.divisor()).hz();
@@ -107,8 +233,12 @@ impl Rcc {
         });
 
         // Wait for prescaler values to apply
-        while !self.rb.cfgr.read().hpref().bit_is_set() {}
-        while !self.rb.extcfgr.read().shdhpref().bit_is_set() {}
+        while !self.rb.cfgr.read().hpref().bit_is_set() {
+            feed();
+        }
+        while !self.rb.extcfgr.read().shdhpref().bit_is_set() {
+            feed();
+        }
 
         // Apply PCLK1(APB1) / PCLK2(APB2) values
         self.rb.cfgr.modify(|_r, w| unsafe {
@@ -118,8 +248,12 @@ impl Rcc {
                 .bits(config.apb2_div as u8)
         });
 
-        while !self.rb.cfgr.read().ppre1f().bit_is_set() {}
-        while !self.rb.cfgr.read().ppre2f().bit_is_set() {}
+        while !self.rb.cfgr.read().ppre1f().bit_is_set() {
+            feed();
+        }
+        while !self.rb.cfgr.read().ppre2f().bit_is_set() {
+            feed();
+        }
 
         self.clocks.pclk1 = (self.clocks.hclk1.0 / config.apb1_div.divisor()).hz();
         self.clocks.pclk2 = (self.clocks.hclk1.0 / config.apb2_div.divisor()).hz();
@@ -134,7 +268,7 @@ impl Rcc {
                 UsbClkSrc::Hsi48 => todo!(),
                 UsbClkSrc::PllSai1Q => todo!(),
                 UsbClkSrc::PllQ => self.clocks.pllq,
-                UsbClkSrc::Msi => todo!(),
+                UsbClkSrc::Msi => self.clocks.msi,
             };
         }
 
@@ -143,26 +277,81 @@ impl Rcc {
             .csr
             .modify(|_, w| unsafe { w.rfwkpsel().bits(config.rf_wkp_src as u8) });
 
-        self
+        self.config = config;
+        Ok(())
+    }
+
+    /// Starts LSE, waiting at most `LSE_STARTUP_TIMEOUT_MS`. Backup domain access must be enabled.
+    ///
+    /// The timeout is a busy-wait of `clocks.sysclk / 1000` cycles per millisecond, so it assumes
+    /// `clocks.sysclk` is the SYSCLK currently running. This holds because LSE is started before
+    /// the new configuration is applied, while `clocks` still describes the previous one. If
+    /// SYSCLK was changed behind the back of `Rcc`, the timeout scales accordingly.
+    fn enable_lse<F: FnMut()>(
+        &mut self,
+        lse: LseConfig,
+        feed: &mut F,
+    ) -> Result<(), ClockConfigError> {
+        let bdcr = &self.rb.bdcr;
+
+        // LSEBYP can only be changed while LSE is off, the drive at any time
+        if bdcr.read().lseon().bit_is_clear() {
+            bdcr.modify(|_, w| w.lsebyp().bit(lse.bypass));
+        }
+        bdcr.modify(|_, w| unsafe { w.lsedrv().bits(lse.drive as u8) });
+        bdcr.modify(|_, w| w.lseon().set_bit());
+
+        // Polled every millisecond at the SYSCLK that is still running, see above
+        let cycles_per_ms = self.clocks.sysclk.0 / 1000;
+        for _ in 0..LSE_STARTUP_TIMEOUT_MS {
+            if bdcr.read().lserdy().bit_is_set() {
+                return Ok(());
+            }
+
+            feed();
+            cortex_m::asm::delay(cycles_per_ms);
+        }
+
+        bdcr.modify(|_, w| w.lseon().clear_bit());
+        Err(ClockConfigError::LseTimeout)
     }
 
-    #[allow(unreachable_code)] // TODO: remove
-    fn configure_and_wait_for_pll(&mut self, config: &PllConfig, src: &PllSrc) {
+    fn configure_and_wait_for_msi<F: FnMut()>(&mut self, range: &MsiRange, feed: &mut F) {
+        // MSI range can only be changed while MSI is off or ready
+        while self.rb.cr.read().msion().bit_is_set() && !self.rb.cr.read().msirdy().bit_is_set() {
+            feed();
+        }
+
+        self.rb
+            .cr
+            .modify(|_, w| unsafe { w.msirange().bits(*range as u8).msion().set_bit() });
+        while !self.rb.cr.read().msirdy().bit_is_set() {
+            feed();
+        }
+
+        self.clocks.msi = Some(range.frequency());
+    }
+
+    fn configure_and_wait_for_pll<F: FnMut()>(
+        &mut self,
+        config: &PllConfig,
+        src: &PllSrc,
+        feed: &mut F,
+    ) {
         // Select PLL and PLLSAI1 clock source [RM0434, p. 233]
         let (f_input, src_bits) = match src {
-            PllSrc::Msi(_range) => {
-                todo!();
+            PllSrc::Msi(range) => {
+                self.configure_and_wait_for_msi(range, feed);
 
-                let f_input = 0;
-                (f_input, 0b01)
+                (range.frequency(), 0b01)
             }
-            PllSrc::Hsi => (HSI_FREQ, 0b10),
+            PllSrc::Hsi => (HSI_FREQ.into(), 0b10),
             PllSrc::Hse(div) => {
-                self.clocks.hse = Some(HSE_FREQ.hz());
+                self.clocks.hse = Some(HSE_FREQ.into());
 
-                let (divided, f_input) = match div {
-                    HseDivider::NotDivided => (false, HSE_FREQ),
-                    HseDivider::Div2 => (true, HSE_FREQ / 2),
+                let divided = match div {
+                    HseDivider::NotDivided => false,
+                    HseDivider::Div2 => true,
                 };
 
                 // Configure HSE divider and enable it
@@ -170,9 +359,11 @@ impl Rcc {
                     .cr
                     .modify(|_, w| w.hsepre().bit(divided).hseon().set_bit());
                 // Wait for HSE startup
-                while !self.rb.cr.read().hserdy().bit_is_set() {}
+                while !self.rb.cr.read().hserdy().bit_is_set() {
+                    feed();
+                }
 
-                (f_input, 0b11)
+                (div.frequency(), 0b11)
             }
         };
 
@@ -203,25 +394,23 @@ impl Rcc {
         assert!(config.m <= 8);
         let pllm = (config.m - 1) & 0b111;
 
-        let vco = f_input / config.m as u32 * config.n as u32;
-        let f_pllr = vco / config.r as u32;
+        let max = Hertz::from(64.mhz());
 
-        assert!(f_pllr <= 64_000_000);
+        let f_pllr = config.r_frequency(f_input);
+        assert!(f_pllr <= max);
 
-        self.clocks.pllclk = Some(f_pllr.hz());
+        self.clocks.pllclk = Some(f_pllr);
 
-        if let Some(pllp) = pllp {
-            let f_pllp = vco / (pllp + 1) as u32;
-            assert!(f_pllp <= 64_000_000);
+        if let Some(f_pllp) = config.p_frequency(f_input) {
+            assert!(f_pllp <= max);
 
-            self.clocks.pllp = Some(f_pllp.hz());
+            self.clocks.pllp = Some(f_pllp);
         }
 
-        if let Some(pllq) = pllq {
-            let f_pllq = vco / (pllq + 1) as u32;
-            assert!(f_pllq <= 64_000_000);
+        if let Some(f_pllq) = config.q_frequency(f_input) {
+            assert!(f_pllq <= max);
 
-            self.clocks.pllq = Some(f_pllq.hz());
+            self.clocks.pllq = Some(f_pllq);
         }
 
         // Set PLL coefficients
@@ -236,7 +425,41 @@ impl Rcc {
 
         // Enable PLL and wait for setup
         self.rb.cr.modify(|_, w| w.pllon().set_bit());
-        while !self.rb.cr.read().pllrdy().bit_is_set() {}
+        while !self.rb.cr.read().pllrdy().bit_is_set() {
+            feed();
+        }
+    }
+
+    /// Enables LSI1 and waits until it's ready.
+    pub fn enable_lsi1(&mut self) {
+        self.enable_lsi(Lsi::Lsi1, &mut || {});
+    }
+
+    /// Enables LSI2 and waits until it's ready.
+    ///
+    /// LSI2 keeps the trimming loaded from factory calibration at reset (`CSR.LSI2TRIM`).
+    pub fn enable_lsi2(&mut self) {
+        self.enable_lsi(Lsi::Lsi2, &mut || {});
+    }
+
+    fn enable_lsi<F: FnMut()>(&mut self, lsi: Lsi, feed: &mut F) {
+        let csr = &self.rb.csr;
+        match lsi {
+            Lsi::Lsi1 => {
+                csr.modify(|_, w| w.lsi1on().set_bit());
+                while csr.read().lsi1rdy().bit_is_clear() {
+                    feed();
+                }
+                self.clocks.lsi1 = Some(LSI_FREQ.into());
+            }
+            Lsi::Lsi2 => {
+                csr.modify(|_, w| w.lsi2on().set_bit());
+                while csr.read().lsi2rdy().bit_is_clear() {
+                    feed();
+                }
+                self.clocks.lsi2 = Some(LSI_FREQ.into());
+            }
+        }
     }
 
     /// Enables or disables IPCC peripheral clock.
@@ -248,6 +471,71 @@ impl Rcc {
         let _ = self.rb.ahb3enr.read().ipccen();
     }
 
+    /// Enables or disables HSEM peripheral clock, needed to take the semaphores shared with CPU2.
+    pub fn set_hsem(&mut self, enabled: bool) {
+        self.rb.ahb3enr.modify(|_, w| w.hsemen().bit(enabled));
+
+        // Single memory access delay after peripheral is enabled
+        let _ = self.rb.ahb3enr.read().hsemen();
+    }
+
+    /// Returns `true` if the radio system (BLE and 802.15.4) is held in reset (RFRSTS).
+    pub fn rf_reset_status(&self) -> bool {
+        self.rb.csr.read().bits() & CSR_RFRSTS != 0
+    }
+
+    /// Asserts or releases the radio system reset (RFRST).
+    pub fn set_rf_reset(&mut self, reset: bool) {
+        self.rb.csr.modify(|r, w| unsafe {
+            w.bits(if reset {
+                r.bits() | CSR_RFRST
+            } else {
+                r.bits() & !CSR_RFRST
+            })
+        });
+    }
+
+    /// Sets HSE load capacitance trimming (HSETUNE, 0..=63).
+    ///
+    /// Used to tune the HSE frequency during production calibration.
+    pub fn set_hse_tune(&mut self, tune: u8) {
+        assert!(tune as u32 <= HSECR_HSETUNE_MASK);
+
+        // HSECR is unlocked for a single write
+        self.rb.hsecr.write(|w| unsafe { w.bits(HSECR_UNLOCK_KEY) });
+        self.rb.hsecr.modify(|r, w| unsafe {
+            w.bits(
+                (r.bits() & !(HSECR_HSETUNE_MASK << HSECR_HSETUNE_SHIFT))
+                    | ((tune as u32) << HSECR_HSETUNE_SHIFT),
+            )
+        });
+    }
+
+    /// Returns HSE load capacitance trimming (HSETUNE).
+    pub fn hse_tune(&self) -> u8 {
+        ((self.rb.hsecr.read().bits() >> HSECR_HSETUNE_SHIFT) & HSECR_HSETUNE_MASK) as u8
+    }
+
+    /// Returns `true` if the radio system clock (HCLK5) runs from HSE/2, `false` if from HSI16.
+    pub fn rf_clock_is_hse(&self) -> bool {
+        self.rb.extcfgr.read().bits() & EXTCFGR_RFCSS != 0
+    }
+
+    /// Returns `true` if shared clock settings can be changed without disturbing CPU2.
+    ///
+    /// It is always safe while CPU2 isn't booted or the radio is held in reset. Otherwise, ST
+    /// requires CPU1 to hold the RCC semaphore, and the CLK48 semaphore to change the 48 MHz
+    /// clock source, so this checks that CPU2 doesn't hold either of them.
+    /// `apply_clock_config` performs this check automatically and takes `RCC_SEMAPHORE`, plus
+    /// `CLK48_SEMAPHORE` if the configuration selects a USB clock source.
+    pub fn is_safe_to_reconfigure_clocks(&self) -> bool {
+        if !crate::pwr::cpu2_booted() || self.rf_reset_status() {
+            return true;
+        }
+
+        !hsem_is_locked_by_other(RCC_SEMAPHORE) && !hsem_is_locked_by_other(CLK48_SEMAPHORE)
+    }
+
     /// Sets default clock source after exit from STOP modes.
     pub fn set_stop_wakeup_clock(&mut self, stop_wakeup_clock: StopWakeupClock) {
         let bit = match stop_wakeup_clock {
@@ -257,6 +545,133 @@ impl Rcc {
 
         self.rb.cfgr.modify(|_, w| w.stopwuck().bit(bit));
     }
+
+    /// Switches SYSCLK back to the configured source after wake-up from Stop modes.
+    ///
+    /// Stop modes turn off HSE and the PLL and SYSCLK restarts from the Stop wake-up clock. PLL
+    /// and prescaler settings are retained, so only the oscillators are restarted. Does nothing
+    /// if SYSCLK already runs from the configured source (e.g. CPU2 restored it first), so it can
+    /// be called at the start of every interrupt handler that may end a Stop mode.
+    pub fn restore_after_stop(&mut self) {
+        let sysclk_bits = match &self.config.sysclk_src {
+            SysClkSrc::Msi(_) => 0b00,
+            SysClkSrc::Hsi => 0b01,
+            SysClkSrc::HseSys(_) => 0b10,
+            SysClkSrc::Pll(_) => 0b11,
+        };
+
+        if self.rb.cfgr.read().sws().bits() == sysclk_bits {
+            return;
+        }
+
+        // Clock settings are shared with a running CPU2
+        let cpu2_running = crate::pwr::cpu2_booted();
+        if cpu2_running {
+            self.set_hsem(true);
+            while !hsem_try_lock(RCC_SEMAPHORE) {}
+        }
+
+        if self.rb.cfgr.read().sws().bits() != sysclk_bits {
+            match &self.config.sysclk_src {
+                SysClkSrc::Msi(_) => self.start_msi(),
+                SysClkSrc::Hsi => self.start_hsi(),
+                SysClkSrc::HseSys(_) => self.start_hse(),
+                SysClkSrc::Pll(src) => {
+                    match src {
+                        PllSrc::Msi(_) => self.start_msi(),
+                        PllSrc::Hsi => self.start_hsi(),
+                        PllSrc::Hse(_) => self.start_hse(),
+                    }
+
+                    self.rb.cr.modify(|_, w| w.pllon().set_bit());
+                    while !self.rb.cr.read().pllrdy().bit_is_set() {}
+                }
+            }
+
+            self.rb
+                .cfgr
+                .modify(|_r, w| unsafe { w.sw().bits(sysclk_bits) });
+            while self.rb.cfgr.read().sws().bits() != sysclk_bits {}
+        }
+
+        if cpu2_running {
+            hsem_unlock(RCC_SEMAPHORE);
+        }
+    }
+
+    fn start_msi(&mut self) {
+        self.rb.cr.modify(|_, w| w.msion().set_bit());
+        while !self.rb.cr.read().msirdy().bit_is_set() {}
+    }
+
+    fn start_hsi(&mut self) {
+        self.rb.cr.modify(|_, w| w.hsion().set_bit());
+        while !self.rb.cr.read().hsirdy().bit_is_set() {}
+    }
+
+    fn start_hse(&mut self) {
+        self.rb.cr.modify(|_, w| w.hseon().set_bit());
+        while !self.rb.cr.read().hserdy().bit_is_set() {}
+    }
+}
+
+/// Returns the HSEM register at `offset`, the only access path to the HSEM registers.
+fn hsem_reg(offset: usize) -> *mut u32 {
+    (HSEM_BASE + offset) as *mut u32
+}
+
+/// Takes HSEM semaphore with the 1-step (read lock) procedure.
+///
+/// The HSEM clock must be enabled, see `Rcc::set_hsem`.
+pub(crate) fn hsem_try_lock(sem: u8) -> bool {
+    // NOTE(unsafe) reading RLR atomically takes the semaphore if it is free
+    let r = unsafe { core::ptr::read_volatile(hsem_reg(HSEM_RLR + 4 * sem as usize)) };
+    r == (HSEM_LOCK | HSEM_COREID_CPU1 << 8)
+}
+
+pub(crate) fn hsem_unlock(sem: u8) {
+    // NOTE(unsafe) write only releases the semaphore if it is held by CPU1 with process ID 0
+    unsafe {
+        core::ptr::write_volatile(hsem_reg(HSEM_R + 4 * sem as usize), HSEM_COREID_CPU1 << 8)
+    };
+}
+
+pub(crate) fn hsem_is_locked_by_other(sem: u8) -> bool {
+    // NOTE(unsafe) atomic read with no side effects
+    let r = unsafe { core::ptr::read_volatile(hsem_reg(HSEM_R + 4 * sem as usize)) };
+    r & HSEM_LOCK != 0 && (r >> 8) & 0xf != HSEM_COREID_CPU1
+}
+
+/// Enables or disables the CPU1 interrupt signalling that semaphore `sem` was released.
+pub(crate) fn hsem_set_free_irq(sem: u8, enabled: bool) {
+    let ier = hsem_reg(HSEM_C1IER);
+
+    cortex_m::interrupt::free(|_| {
+        // NOTE(unsafe) read-modify-write in a critical section
+        unsafe {
+            let bits = core::ptr::read_volatile(ier);
+            let bits = if enabled {
+                bits | 1 << sem
+            } else {
+                bits & !(1 << sem)
+            };
+            core::ptr::write_volatile(ier, bits);
+        }
+    });
+}
+
+/// Returns `true` and clears the flag if the release of semaphore `sem` raised the CPU1
+/// interrupt.
+pub(crate) fn hsem_take_free_irq(sem: u8) -> bool {
+    // NOTE(unsafe) MISR is read-only, writing ICR only clears the flag of `sem`
+    unsafe {
+        let misr = core::ptr::read_volatile(hsem_reg(HSEM_C1MISR));
+        if misr & 1 << sem == 0 {
+            return false;
+        }
+        core::ptr::write_volatile(hsem_reg(HSEM_C1ICR), 1 << sem);
+    }
+    true
 }
 
 /// Extension trait that constrains the `RCC` peripheral
@@ -290,6 +705,8 @@ pub struct Clocks {
 
     pub(crate) lse: Option<Hertz>,
     pub(crate) hse: Option<Hertz>, // Must be exactly 32 MHz
+    msi: Option<Hertz>,
+    msi_pll: bool,
 
     pclk1: Hertz,
     tim_pclk1: Hertz,
@@ -297,7 +714,8 @@ pub struct Clocks {
     pclk2: Hertz,
     tim_pclk2: Hertz,
 
-    pub(crate) lsi: Hertz,
+    lsi1: Option<Hertz>,
+    lsi2: Option<Hertz>,
 
     pub(crate) rtcclk: Hertz,
 
@@ -324,29 +742,32 @@ impl Default for Clocks {
     /// Default clock frequencies right after power-on reset.
     fn default() -> Self {
         Clocks {
-            sysclk: 4.mhz(),
-            hclk1: 4.mhz(),
-            hclk2: 4.mhz(),
-            hclk4: 4.mhz(),
-            systick: 4.mhz(),
+            sysclk: 4.mhz().into(),
+            hclk1: 4.mhz().into(),
+            hclk2: 4.mhz().into(),
+            hclk4: 4.mhz().into(),
+            systick: 4.mhz().into(),
             lse: None,
             hse: None,
-            pclk1: 4.mhz(),
-            tim_pclk1: 4.mhz(),
-            pclk2: 4.mhz(),
-            tim_pclk2: 4.mhz(),
-            lsi: 32.khz(),
-            rtcclk: 32.khz(),
+            msi: Some(4.mhz().into()),
+            msi_pll: false,
+            pclk1: 4.mhz().into(),
+            tim_pclk1: 4.mhz().into(),
+            pclk2: 4.mhz().into(),
+            tim_pclk2: 4.mhz().into(),
+            lsi1: None,
+            lsi2: None,
+            rtcclk: 32.khz().into(),
             rng: None,
             adc: None,
             clk48: None,
             sai1: None,
-            i2c1: 4.mhz(),
-            i2c3: 4.mhz(),
-            usart1: 4.mhz(),
-            lpuart1: 4.mhz(),
-            lptim1: 4.mhz(),
-            lptim2: 4.mhz(),
+            i2c1: 4.mhz().into(),
+            i2c3: 4.mhz().into(),
+            usart1: 4.mhz().into(),
+            lpuart1: 4.mhz().into(),
+            lptim1: 4.mhz().into(),
+            lptim2: 4.mhz().into(),
             pllclk: None,
             pllq: None,
             pllp: None,
@@ -360,6 +781,11 @@ impl Clocks {
         self.sysclk
     }
 
+    /// Returns CPU1 (HCLK1) frequency, which clocks the DWT cycle counter.
+    pub fn hclk1(&self) -> Hertz {
+        self.hclk1
+    }
+
     pub fn pclk1(&self) -> Hertz {
         self.pclk1
     }
@@ -368,7 +794,36 @@ impl Clocks {
         self.pclk2
     }
 
-    pub fn lsi(&self) -> Hertz {
-        self.lsi
+    /// Returns LSI frequency, if LSI1 or LSI2 was started by `Rcc`.
+    pub fn lsi(&self) -> Option<Hertz> {
+        self.lsi1.or(self.lsi2)
+    }
+
+    /// Returns LSI1 frequency, if LSI1 was started by `Rcc`.
+    pub fn lsi1(&self) -> Option<Hertz> {
+        self.lsi1
+    }
+
+    /// Returns LSI2 frequency, if LSI2 was started by `Rcc`.
+    pub fn lsi2(&self) -> Option<Hertz> {
+        self.lsi2
+    }
+
+    /// Returns LSE frequency, if LSE was started by `apply_clock_config`.
+    pub fn lse(&self) -> Option<Hertz> {
+        self.lse
+    }
+
+    /// Returns MSI frequency, if MSI is running.
+    pub fn msi(&self) -> Option<Hertz> {
+        self.msi
+    }
+
+    /// Returns `true` if MSI is auto-calibrated against LSE (MSI PLL mode).
+    ///
+    /// In this mode MSI inherits the LSE accuracy, so MSI-derived clocks are precise enough
+    /// for USB and UART baud rate generation.
+    pub fn msi_pll_mode(&self) -> bool {
+        self.msi_pll
     }
 }
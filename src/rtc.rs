@@ -1,9 +1,9 @@
 //! RTC peripheral abstraction
 
 use crate::datetime::*;
-use crate::rcc::{Rcc, RtcClkSrc};
+use crate::rcc::{Rcc, RtcClkSrc, LSI_FREQ};
 use crate::stm32::RTC;
-use crate::time::U32Ext;
+use crate::time::{MicroSecond, U32Ext};
 
 /// RTC Abstraction
 pub struct Rtc {
@@ -14,6 +14,19 @@ const RTC_CLK_DIV: u8 = 16;
 const ASYNCH_PREDIV: u8 = RTC_CLK_DIV - 1;
 const SYNCH_PREDIV: u16 = 0x7FFF;
 
+// RTC_ISR bits
+const ISR_SHPF: u32 = 1 << 3;
+const ISR_RSF: u32 = 1 << 5;
+const ISR_RECALPF: u32 = 1 << 16;
+
+// RTC_CALR bits
+const CALR_CALP: u32 = 1 << 15;
+const CALR_CALM_MASK: u32 = 0x1ff;
+
+// RTC_SHIFTR bits
+const SHIFTR_ADD1S: u32 = 1 << 31;
+const SHIFTR_SUBFS_MASK: u32 = 0x7fff;
+
 impl Rtc {
     #[inline(never)] // TODO: remove
     pub fn rtc(rtc: RTC, rcc: &mut Rcc) -> Self {
@@ -21,6 +34,13 @@ impl Rtc {
 
         rcc.rb.apb1enr1.modify(|_, w| w.rtcapben().set_bit());
 
+        // LSI is started by `apply_clock_config`, unless the source was selected afterwards
+        if let RtcClkSrc::Lsi = rcc.config.rtc_src {
+            if rcc.clocks.lsi().is_none() {
+                rcc.enable_lsi1();
+            }
+        }
+
         // select RTC clock source and enable RTC
         rcc.rb
             .bdcr
@@ -30,7 +50,7 @@ impl Rtc {
         rcc.clocks.rtcclk = match rcc.config.rtc_src {
             RtcClkSrc::None => 0.hz(),
             RtcClkSrc::Lse => rcc.clocks.lse.unwrap(),
-            RtcClkSrc::Lsi => rcc.clocks.lsi,
+            RtcClkSrc::Lsi => rcc.clocks.lsi().unwrap_or(LSI_FREQ.into()),
             RtcClkSrc::HseDiv32 => (rcc.clocks.hse.unwrap().0 / 32).hz(),
         };
 
@@ -123,6 +143,87 @@ impl Rtc {
         time
     }
 
+    /// Returns current date and time together with the fraction of the current second.
+    ///
+    /// The fraction resolution is `1 / (PREDIV_S + 1)` seconds. When the clock was shifted
+    /// backwards past a second boundary, the calendar is one second ahead of the real time;
+    /// it is corrected here, except at midnight where subseconds saturate to zero.
+    pub fn now_with_subseconds(&self) -> (Date, Time, MicroSecond) {
+        // Wait for calendar shadow registers to be synchronized
+        while self.rtc.isr.read().bits() & ISR_RSF == 0 {}
+
+        // Reading SSR locks TR and DR until DR is read, so the three values are coherent
+        let ss = self.rtc.ssr.read().bits() & 0xffff;
+        let timer = self.rtc.tr.read();
+        let dater = self.rtc.dr.read();
+
+        let prediv_s = self.rtc.prer.read().prediv_s().bits() as u32;
+
+        let mut time = Time::new(
+            bcd2_to_byte((timer.ht().bits(), timer.hu().bits())).into(),
+            bcd2_to_byte((timer.mnt().bits(), timer.mnu().bits())).into(),
+            bcd2_to_byte((timer.st().bits(), timer.su().bits())).into(),
+            self.rtc.cr.read().fmt().bit(),
+        );
+        let date = Date::new(
+            dater.wdu().bits().into(),
+            bcd2_to_byte((dater.dt().bits(), dater.du().bits())).into(),
+            bcd2_to_byte((dater.mt().bit() as u8, dater.mu().bits())).into(),
+            (bcd2_to_byte((dater.yt().bits(), dater.yu().bits())) as u16 + 1970_u16).into(),
+        );
+
+        let ticks = elapsed_ticks(&mut time, ss, prediv_s);
+
+        (date, time, ticks_to_micros(ticks, prediv_s).us())
+    }
+
+    /// Shifts the clock by `milliseconds` (-999..=999) using the RTC shift control.
+    ///
+    /// Positive values advance the clock, negative values delay it. The shift is rounded to
+    /// `1 / (PREDIV_S + 1)` seconds, shifts that round to zero are skipped. Blocks until the
+    /// shift is applied.
+    pub fn shift(&self, milliseconds: i16) {
+        assert!(milliseconds > -1000 && milliseconds < 1000);
+
+        let prediv_s = self.rtc.prer.read().prediv_s().bits() as u32;
+        let shiftr = match shiftr_bits(milliseconds, prediv_s) {
+            Some(shiftr) => shiftr,
+            None => return,
+        };
+
+        // Previous shift must be complete
+        while self.rtc.isr.read().bits() & ISR_SHPF != 0 {}
+
+        write_protection(&self.rtc, false);
+        self.rtc.shiftr.write(|w| unsafe { w.bits(shiftr) });
+        write_protection(&self.rtc, true);
+
+        while self.rtc.isr.read().bits() & ISR_SHPF != 0 {}
+    }
+
+    /// Applies smooth digital calibration.
+    ///
+    /// `calm` (0..=511) RTCCLK pulses are masked and, if `calp` is set, 512 pulses are added
+    /// every 2^20 RTCCLK cycles (32 s with a 32768 Hz clock).
+    pub fn calibrate(&self, calp: bool, calm: u16) {
+        assert!(calm as u32 <= CALR_CALM_MASK);
+
+        // Previous calibration must be applied
+        while self.rtc.isr.read().bits() & ISR_RECALPF != 0 {}
+
+        let calr = if calp { CALR_CALP } else { 0 } | calm as u32;
+
+        write_protection(&self.rtc, false);
+        self.rtc.calr.write(|w| unsafe { w.bits(calr) });
+        write_protection(&self.rtc, true);
+    }
+
+    /// Returns smooth digital calibration as (CALP, CALM).
+    pub fn calibration(&self) -> (bool, u16) {
+        let calr = self.rtc.calr.read().bits();
+        (calr & CALR_CALP != 0, (calr & CALR_CALM_MASK) as u16)
+    }
+
     pub fn set_date(&self, date: &Date) {
         write_protection(&self.rtc, false);
         {
@@ -212,3 +313,182 @@ fn bcd2_to_byte(bcd: (u8, u8)) -> u8 {
 
     (tmp + (value & 0x0F))
 }
+
+/// Returns the `RTC_SHIFTR` value that shifts the clock by `milliseconds` (-999..=999), or `None`
+/// if the shift rounds to zero.
+///
+/// SUBFS delays the clock by `SUBFS / (PREDIV_S + 1)` seconds, so a positive shift adds one
+/// second and subtracts the complement. SUBFS never exceeds PREDIV_S.
+fn shiftr_bits(milliseconds: i16, prediv_s: u32) -> Option<u32> {
+    let fraction = |ms: u32| ms * (prediv_s + 1) / 1000;
+
+    let shiftr = if milliseconds > 0 {
+        SHIFTR_ADD1S | fraction(1000 - milliseconds as u32)
+    } else {
+        let subfs = fraction(-(milliseconds as i32) as u32);
+        if subfs == 0 {
+            return None;
+        }
+        subfs
+    };
+
+    let subfs = shiftr & !SHIFTR_ADD1S;
+    debug_assert!(subfs <= prediv_s && subfs <= SHIFTR_SUBFS_MASK);
+
+    Some(shiftr)
+}
+
+/// Returns the ticks elapsed in the current second for sub second register value `ss`.
+///
+/// SS > PREDIV_S only happens after a shift delayed the clock: the calendar `time` is then one
+/// second ahead and is moved back. At midnight the date would have to follow, so the ticks
+/// saturate to zero instead.
+fn elapsed_ticks(time: &mut Time, ss: u32, prediv_s: u32) -> u32 {
+    if ss <= prediv_s {
+        return prediv_s - ss;
+    }
+
+    if time.hours == 0 && time.minutes == 0 && time.seconds == 0 {
+        return 0;
+    }
+
+    if time.seconds > 0 {
+        time.seconds -= 1;
+    } else {
+        time.seconds = 59;
+        if time.minutes > 0 {
+            time.minutes -= 1;
+        } else {
+            time.minutes = 59;
+            time.hours -= 1;
+        }
+    }
+
+    (prediv_s + 1) - (ss - prediv_s)
+}
+
+fn ticks_to_micros(ticks: u32, prediv_s: u32) -> u32 {
+    (ticks as u64 * 1_000_000 / (prediv_s as u64 + 1)) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREDIV_S: [u32; 4] = [SYNCH_PREDIV as u32, 0xff, 0x7f, 0];
+
+    fn time(hours: u32, minutes: u32, seconds: u32) -> Time {
+        Time {
+            hours,
+            minutes,
+            seconds,
+            daylight_savings: false,
+        }
+    }
+
+    /// Shift applied by `shiftr` in microseconds.
+    fn applied_shift_us(shiftr: u32, prediv_s: u32) -> i64 {
+        let add = if shiftr & SHIFTR_ADD1S != 0 {
+            1_000_000
+        } else {
+            0
+        };
+        let subfs = (shiftr & !SHIFTR_ADD1S) as i64;
+        add - subfs * 1_000_000 / (prediv_s as i64 + 1)
+    }
+
+    #[test]
+    fn shift_zero_is_skipped() {
+        for &prediv_s in &PREDIV_S {
+            assert_eq!(shiftr_bits(0, prediv_s), None);
+        }
+    }
+
+    #[test]
+    fn shift_bounds() {
+        let p = SYNCH_PREDIV as u32;
+        assert_eq!(shiftr_bits(1, p), Some(SHIFTR_ADD1S | 32735));
+        assert_eq!(shiftr_bits(999, p), Some(SHIFTR_ADD1S | 32));
+        assert_eq!(shiftr_bits(-1, p), Some(32));
+        assert_eq!(shiftr_bits(-999, p), Some(32735));
+    }
+
+    #[test]
+    fn shift_subfs_within_prediv_s() {
+        for &prediv_s in &PREDIV_S {
+            for ms in -999..=999 {
+                if let Some(shiftr) = shiftr_bits(ms, prediv_s) {
+                    let subfs = shiftr & !SHIFTR_ADD1S;
+                    assert!(subfs <= prediv_s, "ms {} prediv_s {}", ms, prediv_s);
+                    assert!(subfs <= SHIFTR_SUBFS_MASK);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn shift_precision() {
+        for &prediv_s in &PREDIV_S {
+            // Rounding error is below one tick, plus truncation of `applied_shift_us`
+            let tick_us = 1_000_000 / (prediv_s as i64 + 1);
+            for ms in -999..=999 {
+                let applied =
+                    shiftr_bits(ms, prediv_s).map_or(0, |s| applied_shift_us(s, prediv_s));
+                let error = (applied - ms as i64 * 1000).abs();
+                assert!(
+                    error <= tick_us + 1,
+                    "ms {} prediv_s {} error {} us",
+                    ms,
+                    prediv_s,
+                    error
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn elapsed_ticks_without_shift() {
+        let p = SYNCH_PREDIV as u32;
+        let mut t = time(12, 30, 15);
+
+        assert_eq!(elapsed_ticks(&mut t, p, p), 0);
+        assert_eq!(elapsed_ticks(&mut t, 0, p), p);
+        assert_eq!(elapsed_ticks(&mut t, p / 2 + 1, p), p / 2);
+        assert_eq!(t, time(12, 30, 15));
+    }
+
+    #[test]
+    fn elapsed_ticks_rolls_back_after_shift() {
+        let p = SYNCH_PREDIV as u32;
+
+        let mut t = time(12, 30, 15);
+        assert_eq!(elapsed_ticks(&mut t, p + 1, p), p);
+        assert_eq!(t, time(12, 30, 14));
+
+        let mut t = time(12, 30, 0);
+        assert_eq!(elapsed_ticks(&mut t, p + 0x4000, p), p + 1 - 0x4000);
+        assert_eq!(t, time(12, 29, 59));
+
+        let mut t = time(12, 0, 0);
+        elapsed_ticks(&mut t, 2 * p, p);
+        assert_eq!(t, time(11, 59, 59));
+    }
+
+    #[test]
+    fn elapsed_ticks_saturates_at_midnight() {
+        let p = SYNCH_PREDIV as u32;
+        let mut t = time(0, 0, 0);
+
+        assert_eq!(elapsed_ticks(&mut t, p + 1, p), 0);
+        assert_eq!(t, time(0, 0, 0));
+    }
+
+    #[test]
+    fn ticks_to_micros_resolution() {
+        let p = SYNCH_PREDIV as u32;
+        assert_eq!(ticks_to_micros(0, p), 0);
+        assert_eq!(ticks_to_micros(16384, p), 500_000);
+        assert_eq!(ticks_to_micros(p, p), 999_969);
+        assert_eq!(ticks_to_micros(128, 0xff), 500_000);
+    }
+}
@@ -0,0 +1,83 @@
+//! Console abstraction over byte streams
+//!
+//! `SerialIo` is implemented for every type that implements the `embedded_hal::serial` byte
+//! traits with a common error type. This covers `usbd_serial::SerialPort` and any future
+//! USART/LPUART driver, so console code can be written once for all of them.
+
+use crate::hal::serial;
+
+/// Non-blocking byte slice I/O.
+pub trait SerialIo {
+    type Error;
+
+    /// Reads available bytes into `buf`, returns the number of bytes read.
+    ///
+    /// Returns `WouldBlock` if no byte is available.
+    fn read(&mut self, buf: &mut [u8]) -> nb::Result<usize, Self::Error>;
+
+    /// Writes as many bytes of `buf` as can be accepted, returns the number of bytes written.
+    ///
+    /// Returns `WouldBlock` if no byte could be written.
+    fn write(&mut self, buf: &[u8]) -> nb::Result<usize, Self::Error>;
+
+    /// Flushes buffered output.
+    fn flush(&mut self) -> nb::Result<(), Self::Error>;
+
+    /// Writes all of `buf`, retrying while the output is busy.
+    fn blocking_write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        let mut offset = 0;
+        while offset < buf.len() {
+            offset += nb::block!(self.write(&buf[offset..]))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<T, E> SerialIo for T
+where
+    T: serial::Read<u8, Error = E> + serial::Write<u8, Error = E>,
+{
+    type Error = E;
+
+    fn read(&mut self, buf: &mut [u8]) -> nb::Result<usize, E> {
+        let mut count = 0;
+        while count < buf.len() {
+            match serial::Read::read(self) {
+                Ok(byte) => {
+                    buf[count] = byte;
+                    count += 1;
+                }
+                Err(nb::Error::WouldBlock) => break,
+                Err(nb::Error::Other(e)) => return Err(nb::Error::Other(e)),
+            }
+        }
+
+        if count == 0 && !buf.is_empty() {
+            Err(nb::Error::WouldBlock)
+        } else {
+            Ok(count)
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> nb::Result<usize, E> {
+        let mut count = 0;
+        for byte in buf {
+            match serial::Write::write(self, *byte) {
+                Ok(()) => count += 1,
+                Err(nb::Error::WouldBlock) => break,
+                Err(nb::Error::Other(e)) => return Err(nb::Error::Other(e)),
+            }
+        }
+
+        if count == 0 && !buf.is_empty() {
+            Err(nb::Error::WouldBlock)
+        } else {
+            Ok(count)
+        }
+    }
+
+    fn flush(&mut self) -> nb::Result<(), E> {
+        serial::Write::flush(self)
+    }
+}
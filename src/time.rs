@@ -8,14 +8,29 @@ pub struct Instant(pub u32);
 #[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
 pub struct Bps(pub u32);
 
+/// Baud-rate, same as `Bps`
+pub type Baud = Bps;
+
 /// Hertz
 #[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
 pub struct Hertz(pub u32);
 
+/// Kilohertz
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
+pub struct KiloHertz(pub u32);
+
+/// Megahertz
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
+pub struct MegaHertz(pub u32);
+
 /// Microseconds
 #[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
 pub struct MicroSecond(pub u32);
 
+/// Milliseconds
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
+pub struct MilliSecond(pub u32);
+
 /// Seconds
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Second(pub u32);
@@ -91,17 +106,17 @@ pub trait U32Ext {
     /// Wrap in `Hertz`
     fn hz(self) -> Hertz;
 
-    /// Wrap in `Hertz`
-    fn khz(self) -> Hertz;
+    /// Wrap in `KiloHertz`
+    fn khz(self) -> KiloHertz;
 
-    /// Wrap in `Hertz`
-    fn mhz(self) -> Hertz;
+    /// Wrap in `MegaHertz`
+    fn mhz(self) -> MegaHertz;
 
     /// Wrap in `MicroSecond`
     fn us(self) -> MicroSecond;
 
-    /// Wrap in `MicroSecond`
-    fn ms(self) -> MicroSecond;
+    /// Wrap in `MilliSecond`
+    fn ms(self) -> MilliSecond;
 
     /// Seconds
     fn seconds(self) -> Second;
@@ -133,16 +148,18 @@ impl U32Ext for u32 {
         Hertz(self)
     }
 
-    fn khz(self) -> Hertz {
-        Hertz(self.saturating_mul(1_000))
+    fn khz(self) -> KiloHertz {
+        assert!(self > 0);
+        KiloHertz(self)
     }
 
-    fn mhz(self) -> Hertz {
-        Hertz(self.saturating_mul(1_000_000))
+    fn mhz(self) -> MegaHertz {
+        assert!(self > 0);
+        MegaHertz(self)
     }
 
-    fn ms(self) -> MicroSecond {
-        MicroSecond(self.saturating_mul(1_000))
+    fn ms(self) -> MilliSecond {
+        MilliSecond(self)
     }
 
     fn us(self) -> MicroSecond {
@@ -175,11 +192,22 @@ impl U32Ext for u32 {
 }
 
 impl Hertz {
+    /// Adds two frequencies, returns `None` on overflow.
+    pub fn checked_add(self, other: Hertz) -> Option<Hertz> {
+        self.0.checked_add(other.0).map(Hertz)
+    }
+
+    /// Multiplies the frequency, returns `None` on overflow.
+    pub fn checked_mul(self, factor: u32) -> Option<Hertz> {
+        self.0.checked_mul(factor).map(Hertz)
+    }
+
+    /// Returns the duration of `cycles` periods, saturating at `u32::MAX` microseconds.
     pub fn duration(self, cycles: u32) -> MicroSecond {
         let cycles = cycles as u64;
         let clk = self.0 as u64;
         let us = cycles.saturating_mul(1_000_000_u64) / clk;
-        MicroSecond(us as u32)
+        MicroSecond(us.min(u32::MAX as u64) as u32)
     }
 }
 
@@ -200,12 +228,18 @@ impl Div for Hertz {
 }
 
 impl MicroSecond {
+    /// Adds two durations, returns `None` on overflow.
+    pub fn checked_add(self, other: MicroSecond) -> Option<MicroSecond> {
+        self.0.checked_add(other.0).map(MicroSecond)
+    }
+
+    /// Returns the number of `clk` cycles in the duration, saturating at `u32::MAX`.
     pub fn cycles(self, clk: Hertz) -> u32 {
         assert!(self.0 > 0);
         let clk = clk.0 as u64;
         let period = self.0 as u64;
         let cycles = clk.saturating_mul(period) / 1_000_000_u64;
-        cycles as u32
+        cycles.min(u32::MAX as u64) as u32
     }
 }
 
@@ -217,35 +251,241 @@ impl Add for MicroSecond {
     }
 }
 
-impl Into<MicroSecond> for Hertz {
-    fn into(self) -> MicroSecond {
-        assert!(self.0 <= 1_000_000);
-        MicroSecond(1_000_000 / self.0)
+impl KiloHertz {
+    /// Converts to `Hertz`, returns `None` on overflow.
+    pub fn checked_hz(self) -> Option<Hertz> {
+        self.0.checked_mul(1_000).map(Hertz)
+    }
+}
+
+impl MegaHertz {
+    /// Converts to `Hertz`, returns `None` on overflow.
+    pub fn checked_hz(self) -> Option<Hertz> {
+        self.0.checked_mul(1_000_000).map(Hertz)
+    }
+}
+
+impl MilliSecond {
+    /// Converts to `MicroSecond`, returns `None` on overflow.
+    pub fn checked_us(self) -> Option<MicroSecond> {
+        self.0.checked_mul(1_000).map(MicroSecond)
     }
 }
 
-impl Into<Hertz> for MicroSecond {
-    fn into(self) -> Hertz {
-        let period = self.0;
+impl From<KiloHertz> for Hertz {
+    fn from(khz: KiloHertz) -> Self {
+        khz.checked_hz().expect("frequency overflow")
+    }
+}
+
+impl From<MegaHertz> for Hertz {
+    fn from(mhz: MegaHertz) -> Self {
+        mhz.checked_hz().expect("frequency overflow")
+    }
+}
+
+impl From<MegaHertz> for KiloHertz {
+    fn from(mhz: MegaHertz) -> Self {
+        KiloHertz(mhz.0.checked_mul(1_000).expect("frequency overflow"))
+    }
+}
+
+impl From<MilliSecond> for MicroSecond {
+    fn from(ms: MilliSecond) -> Self {
+        ms.checked_us().expect("duration overflow")
+    }
+}
+
+impl From<Hertz> for MicroSecond {
+    fn from(freq: Hertz) -> Self {
+        assert!(freq.0 <= 1_000_000);
+        MicroSecond(1_000_000 / freq.0)
+    }
+}
+
+impl From<MicroSecond> for Hertz {
+    fn from(period: MicroSecond) -> Self {
+        let period = period.0;
         assert!(period > 0 && period <= 1_000_000);
         Hertz(1_000_000 / period)
     }
 }
 
-impl Into<MicroSecond> for Second {
-    fn into(self) -> MicroSecond {
-        MicroSecond(self.0 * 1000)
+impl From<Second> for MicroSecond {
+    fn from(seconds: Second) -> Self {
+        MicroSecond(seconds.0.checked_mul(1_000_000).expect("duration overflow"))
     }
 }
 
-impl Into<Second> for Minute {
-    fn into(self) -> Second {
-        Second(self.0 * 60)
+impl From<Minute> for Second {
+    fn from(minutes: Minute) -> Self {
+        Second(minutes.0 * 60)
     }
 }
 
-impl Into<Second> for Hour {
-    fn into(self) -> Second {
-        Second(self.0 * 3600)
+impl From<Hour> for Second {
+    fn from(hours: Hour) -> Self {
+        Second(hours.0 * 3600)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn u32_ext_typed() {
+        assert_eq!(32.khz(), KiloHertz(32));
+        assert_eq!(64.mhz(), MegaHertz(64));
+        assert_eq!(10.ms(), MilliSecond(10));
+        assert_eq!(Hertz::from(4_294_967.khz()), Hertz(4_294_967_000));
+        assert_eq!(Hertz::from(4_294.mhz()), Hertz(4_294_000_000));
+        assert_eq!(
+            MicroSecond::from(4_294_967.ms()),
+            MicroSecond(4_294_967_000)
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_hz_panics() {
+        0_u32.hz();
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_khz_panics() {
+        0_u32.khz();
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_mhz_panics() {
+        0_u32.mhz();
+    }
+
+    #[test]
+    fn checked_conversions() {
+        assert_eq!(
+            KiloHertz(4_294_967).checked_hz(),
+            Some(Hertz(4_294_967_000))
+        );
+        assert_eq!(KiloHertz(4_294_968).checked_hz(), None);
+        assert_eq!(MegaHertz(4_294).checked_hz(), Some(Hertz(4_294_000_000)));
+        assert_eq!(MegaHertz(4_295).checked_hz(), None);
+        assert_eq!(
+            MilliSecond(4_294_967).checked_us(),
+            Some(MicroSecond(4_294_967_000))
+        );
+        assert_eq!(MilliSecond(4_294_968).checked_us(), None);
+
+        assert_eq!(
+            Hertz(u32::MAX - 1).checked_add(Hertz(1)),
+            Some(Hertz(u32::MAX))
+        );
+        assert_eq!(Hertz(u32::MAX).checked_add(Hertz(1)), None);
+        assert_eq!(Hertz(0x8000_0000).checked_mul(1), Some(Hertz(0x8000_0000)));
+        assert_eq!(Hertz(0x8000_0000).checked_mul(2), None);
+        assert_eq!(
+            MicroSecond(u32::MAX).checked_add(MicroSecond(0)),
+            Some(MicroSecond(u32::MAX))
+        );
+        assert_eq!(MicroSecond(u32::MAX).checked_add(MicroSecond(1)), None);
+    }
+
+    #[test]
+    fn from_conversions() {
+        assert_eq!(Hertz::from(KiloHertz(32)), Hertz(32_000));
+        assert_eq!(Hertz::from(MegaHertz(64)), Hertz(64_000_000));
+        assert_eq!(
+            KiloHertz::from(MegaHertz(4_294_967)),
+            KiloHertz(4_294_967_000)
+        );
+        assert_eq!(MicroSecond::from(MilliSecond(5)), MicroSecond(5_000));
+        assert_eq!(MicroSecond::from(Second(4_294)), MicroSecond(4_294_000_000));
+        assert_eq!(Second::from(Minute(2)), Second(120));
+        assert_eq!(Second::from(Hour(2)), Second(7_200));
+    }
+
+    #[test]
+    #[should_panic]
+    fn from_khz_overflow_panics() {
+        Hertz::from(KiloHertz(4_294_968));
+    }
+
+    #[test]
+    #[should_panic]
+    fn from_mhz_overflow_panics() {
+        Hertz::from(4_295.mhz());
+    }
+
+    #[test]
+    #[should_panic]
+    fn from_ms_overflow_panics() {
+        MicroSecond::from(4_294_968.ms());
+    }
+
+    #[test]
+    #[should_panic]
+    fn from_mhz_to_khz_overflow_panics() {
+        KiloHertz::from(MegaHertz(4_294_968));
+    }
+
+    #[test]
+    #[should_panic]
+    fn from_seconds_overflow_panics() {
+        MicroSecond::from(Second(4_295));
+    }
+
+    #[test]
+    fn period_conversions() {
+        assert_eq!(MicroSecond::from(Hertz(1)), MicroSecond(1_000_000));
+        assert_eq!(MicroSecond::from(Hertz(1_000_000)), MicroSecond(1));
+        assert_eq!(MicroSecond::from(Hertz(3)), MicroSecond(333_333));
+        assert_eq!(Hertz::from(MicroSecond(1)), Hertz(1_000_000));
+        assert_eq!(Hertz::from(MicroSecond(1_000_000)), Hertz(1));
+    }
+
+    #[test]
+    #[should_panic]
+    fn period_above_1mhz_panics() {
+        MicroSecond::from(Hertz(1_000_001));
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_period_panics() {
+        Hertz::from(MicroSecond(0));
+    }
+
+    #[test]
+    fn duration() {
+        assert_eq!(Hertz(64_000_000).duration(64), MicroSecond(1));
+        assert_eq!(Hertz(64_000_000).duration(63), MicroSecond(0));
+        assert_eq!(Hertz(32_768).duration(32_768), MicroSecond(1_000_000));
+        assert_eq!(Hertz(u32::MAX).duration(u32::MAX), MicroSecond(1_000_000));
+
+        // Longest representable duration and beyond
+        assert_eq!(Hertz(1_000_000).duration(u32::MAX), MicroSecond(u32::MAX));
+        assert_eq!(Hertz(1).duration(u32::MAX), MicroSecond(u32::MAX));
+        assert_eq!(Hertz(999_999).duration(u32::MAX), MicroSecond(u32::MAX));
+    }
+
+    #[test]
+    fn cycles() {
+        assert_eq!(MicroSecond(1).cycles(Hertz(64_000_000)), 64);
+        assert_eq!(MicroSecond(1).cycles(Hertz(999_999)), 0);
+        assert_eq!(MicroSecond(1_000_000).cycles(Hertz(32_768)), 32_768);
+        assert_eq!(MicroSecond(1_000_000).cycles(Hertz(u32::MAX)), u32::MAX);
+
+        // Beyond `u32::MAX` cycles
+        assert_eq!(MicroSecond(1_000_001).cycles(Hertz(u32::MAX)), u32::MAX);
+        assert_eq!(MicroSecond(u32::MAX).cycles(Hertz(u32::MAX)), u32::MAX);
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_cycles_duration_panics() {
+        MicroSecond(0).cycles(Hertz(1));
     }
 }
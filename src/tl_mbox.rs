@@ -1,29 +1,58 @@
 use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU32, Ordering};
 
 use bit_field::BitField;
 use heapless::spsc;
+use void::Void;
 
+pub mod asynch;
 pub mod ble;
-mod channels;
+pub mod channels;
 pub mod cmd;
 pub mod consts;
 pub mod evt;
+pub mod fanout;
+pub mod hci;
+pub mod latency;
+mod layout;
 pub mod lhci;
+pub mod mac_802_15_4;
 pub mod mm;
+pub mod pool;
 pub mod shci;
+pub mod split;
 pub mod sys;
+pub mod thread;
+pub mod traces;
 mod unsafe_linked_list;
 
+use crate::fault::{FaultCounters, FaultHandler, FaultKind, PanicOnFault};
+use crate::interrupts::{irq, InterruptHandler};
 use crate::tl_mbox::cmd::{AclDataPacket, CmdPacket};
-use crate::tl_mbox::evt::EvtBox;
+use crate::tl_mbox::evt::{EvtBox, ShciEvent};
 use unsafe_linked_list::LinkedListNode;
 
+// Shared tables and packets are `packed`: multi-byte fields are only ever read and written by
+// value (`let v = table.field;`), never borrowed, since the reference could be unaligned.
+
+/// Safe boot firmware information, see `TlMbox::safe_boot_info`.
 #[derive(Debug, Copy, Clone)]
 #[repr(C, packed)]
 pub struct SafeBootInfoTable {
     version: u32,
 }
 
+impl SafeBootInfoTable {
+    /// Returns safe boot version.
+    pub fn version(&self) -> VersionInfo {
+        let version = self.version;
+        VersionInfo::from(version)
+    }
+}
+
+/// RSS (FUS) firmware information, see `TlMbox::rss_info`.
+///
+/// Same layout as `WirelessFwInfoTable`.
 #[derive(Debug, Copy, Clone)]
 #[repr(C, packed)]
 pub struct RssInfoTable {
@@ -32,6 +61,72 @@ pub struct RssInfoTable {
     rss_info: u32,
 }
 
+impl RssInfoTable {
+    /// Returns RSS version.
+    pub fn version(&self) -> VersionInfo {
+        let version = self.version;
+        VersionInfo::from(version)
+    }
+
+    /// Size of FLASH, expressed in number of 4K sectors.
+    pub fn flash_size(&self) -> u8 {
+        let memory_size = self.memory_size;
+        (memory_size.get_bits(0..8) & 0xff) as u8
+    }
+
+    /// Size of SRAM2a, expressed in number of 1K sectors.
+    pub fn sram2a_size(&self) -> u8 {
+        let memory_size = self.memory_size;
+        (memory_size.get_bits(24..32) & 0xff) as u8
+    }
+
+    /// Size of SRAM2b, expressed in number of 1K sectors.
+    pub fn sram2b_size(&self) -> u8 {
+        let memory_size = self.memory_size;
+        (memory_size.get_bits(16..24) & 0xff) as u8
+    }
+
+    /// Returns the raw RSS information flags, undocumented by ST.
+    pub fn info(&self) -> u32 {
+        self.rss_info
+    }
+}
+
+/// Version word of the device information tables, see `WirelessFwInfoTable` for the layout.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VersionInfo {
+    pub major: u8,
+    pub minor: u8,
+    pub sub: u8,
+    /// 0: mass market
+    pub branch: u8,
+    /// 0: untracked, 15: released, other: tracked
+    pub build: u8,
+}
+
+impl VersionInfo {
+    /// Returns (major, minor, sub), ignoring branch and build.
+    pub fn fw_version(&self) -> FwVersion {
+        FwVersion {
+            major: self.major,
+            minor: self.minor,
+            sub: self.sub,
+        }
+    }
+}
+
+impl From<u32> for VersionInfo {
+    fn from(version: u32) -> Self {
+        VersionInfo {
+            major: version.get_bits(24..32) as u8,
+            minor: version.get_bits(16..24) as u8,
+            sub: version.get_bits(8..16) as u8,
+            branch: version.get_bits(4..8) as u8,
+            build: version.get_bits(0..4) as u8,
+        }
+    }
+}
+
 /**
  * Version
  * [0:3]   = Build - 0: Untracked - 15:Released - x: Tracked version
@@ -57,37 +152,133 @@ pub struct WirelessFwInfoTable {
 
 impl WirelessFwInfoTable {
     pub fn version_major(&self) -> u8 {
-        let version = self.clone().version;
-        (version.get_bits(24..31) & 0xff) as u8
+        let version = self.version;
+        (version.get_bits(24..32) & 0xff) as u8
     }
 
     pub fn version_minor(&self) -> u8 {
-        let version = self.clone().version;
-        (version.clone().get_bits(16..23) & 0xff) as u8
+        let version = self.version;
+        (version.get_bits(16..24) & 0xff) as u8
     }
 
     pub fn subversion(&self) -> u8 {
-        let version = self.clone().version;
-        (version.clone().get_bits(8..15) & 0xff) as u8
+        let version = self.version;
+        (version.get_bits(8..16) & 0xff) as u8
     }
 
     /// Size of FLASH, expressed in number of 4K sectors.
     pub fn flash_size(&self) -> u8 {
-        let memory_size = self.clone().memory_size;
-        (memory_size.clone().get_bits(0..7) & 0xff) as u8
+        let memory_size = self.memory_size;
+        (memory_size.get_bits(0..8) & 0xff) as u8
     }
 
     /// Size of SRAM2a, expressed in number of 1K sectors.
     pub fn sram2a_size(&self) -> u8 {
-        let memory_size = self.clone().memory_size;
-        (memory_size.clone().get_bits(24..31) & 0xff) as u8
+        let memory_size = self.memory_size;
+        (memory_size.get_bits(24..32) & 0xff) as u8
     }
 
     /// Size of SRAM2b, expressed in number of 1K sectors.
     pub fn sram2b_size(&self) -> u8 {
-        let memory_size = self.clone().memory_size;
-        (memory_size.clone().get_bits(16..23) & 0xff) as u8
+        let memory_size = self.memory_size;
+        (memory_size.get_bits(16..24) & 0xff) as u8
     }
+
+    /// Returns firmware version.
+    pub fn version(&self) -> FwVersion {
+        FwVersion {
+            major: self.version_major(),
+            minor: self.version_minor(),
+            sub: self.subversion(),
+        }
+    }
+
+    /// Returns `true` if the firmware version is at least `min` (major, minor, sub).
+    pub fn meets(&self, min: (u8, u8, u8)) -> bool {
+        self.version() >= FwVersion::from(min)
+    }
+
+    /// Returns type of the wireless stack.
+    pub fn stack_type(&self) -> StackType {
+        // Third word is InfoStack in ST's headers, stack type is its lowest byte
+        let info_stack = self.thread_info;
+        StackType::from((info_stack & 0xff) as u8)
+    }
+}
+
+impl PartialEq<FwVersion> for WirelessFwInfoTable {
+    fn eq(&self, other: &FwVersion) -> bool {
+        self.version() == *other
+    }
+}
+
+impl PartialOrd<FwVersion> for WirelessFwInfoTable {
+    fn partial_cmp(&self, other: &FwVersion) -> Option<core::cmp::Ordering> {
+        Some(self.version().cmp(other))
+    }
+}
+
+/// Wireless firmware version, ordered by (major, minor, sub).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FwVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub sub: u8,
+}
+
+impl From<(u8, u8, u8)> for FwVersion {
+    fn from((major, minor, sub): (u8, u8, u8)) -> Self {
+        FwVersion { major, minor, sub }
+    }
+}
+
+/// Type of the wireless stack running on CPU2.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StackType {
+    BleFull,
+    BleHci,
+    BleLight,
+    BleBeacon,
+    ThreadFtd,
+    ThreadMtd,
+    Mac802154,
+    BleThreadFtdStatic,
+    Other(u8),
+}
+
+impl From<u8> for StackType {
+    fn from(value: u8) -> Self {
+        match value {
+            0x01 => StackType::BleFull,
+            0x02 => StackType::BleHci,
+            0x03 => StackType::BleLight,
+            0x04 => StackType::BleBeacon,
+            0x10 => StackType::ThreadFtd,
+            0x11 => StackType::ThreadMtd,
+            0x40 => StackType::Mac802154,
+            0x50 => StackType::BleThreadFtdStatic,
+            other => StackType::Other(other),
+        }
+    }
+}
+
+/// Wireless firmware doesn't meet the application requirements.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FwRequirementError {
+    /// CPU2 didn't fill the wireless firmware information table.
+    NoFirmware,
+    /// Firmware is older than required.
+    TooOld {
+        found: FwVersion,
+        required: FwVersion,
+        stack: StackType,
+    },
+    /// Firmware runs a different stack than required, e.g. HCI-only instead of the full stack.
+    WrongStack {
+        found: StackType,
+        required: StackType,
+        version: FwVersion,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -152,6 +343,9 @@ struct Mac802154Table {
 }
 
 /// Reference table. Contains pointers to all other tables.
+///
+/// CPU2 expects all the pointer slots, tables of transports disabled with the `thread`,
+/// `mac-802-15-4` and `traces` features are null.
 #[derive(Debug, Copy, Clone)]
 #[repr(C)]
 pub struct RefTable {
@@ -173,6 +367,7 @@ static mut TL_DEVICE_INFO_TABLE: MaybeUninit<DeviceInfoTable> = MaybeUninit::uni
 #[link_section = "TL_BLE_TABLE"]
 static mut TL_BLE_TABLE: MaybeUninit<BleTable> = MaybeUninit::uninit();
 
+#[cfg(feature = "thread")]
 #[link_section = "TL_THREAD_TABLE"]
 static mut TL_THREAD_TABLE: MaybeUninit<ThreadTable> = MaybeUninit::uninit();
 
@@ -182,9 +377,11 @@ static mut TL_SYS_TABLE: MaybeUninit<SysTable> = MaybeUninit::uninit();
 #[link_section = "TL_MEM_MANAGER_TABLE"]
 static mut TL_MEM_MANAGER_TABLE: MaybeUninit<MemManagerTable> = MaybeUninit::uninit();
 
+#[cfg(feature = "traces")]
 #[link_section = "TL_TRACES_TABLE"]
 static mut TL_TRACES_TABLE: MaybeUninit<TracesTable> = MaybeUninit::uninit();
 
+#[cfg(feature = "mac-802-15-4")]
 #[link_section = "TL_MAC_802_15_4_TABLE"]
 static mut TL_MAC_802_15_4_TABLE: MaybeUninit<Mac802154Table> = MaybeUninit::uninit();
 
@@ -194,10 +391,6 @@ static mut FREE_BUF_QUEUE: MaybeUninit<LinkedListNode> = MaybeUninit::uninit();
 // Not in shared RAM
 static mut LOCAL_FREE_BUF_QUEUE: MaybeUninit<LinkedListNode> = MaybeUninit::uninit();
 
-#[allow(dead_code)] // Not used currently but reserved
-#[link_section = "TRACES_EVT_QUEUE"]
-static mut TRACES_EVT_QUEUE: MaybeUninit<LinkedListNode> = MaybeUninit::uninit();
-
 type PacketHeader = unsafe_linked_list::LinkedListNode;
 
 const TL_PACKET_HEADER_SIZE: usize = core::mem::size_of::<PacketHeader>();
@@ -218,32 +411,15 @@ static mut SYSTEM_EVT_QUEUE: MaybeUninit<LinkedListNode> = MaybeUninit::uninit()
 #[link_section = "SYS_CMD_BUF"]
 pub static mut SYS_CMD_BUF: MaybeUninit<CmdPacket> = MaybeUninit::uninit();
 
-/**
- * Queue length of BLE Event
- * This parameter defines the number of asynchronous events that can be stored in the HCI layer before
- * being reported to the application. When a command is sent to the BLE core coprocessor, the HCI layer
- * is waiting for the event with the Num_HCI_Command_Packets set to 1. The receive queue shall be large
- * enough to store all asynchronous events received in between.
- * When CFG_TLBLE_MOST_EVENT_PAYLOAD_SIZE is set to 27, this allow to store three 255 bytes long asynchronous events
- * between the HCI command and its event.
- * This parameter depends on the value given to CFG_TLBLE_MOST_EVENT_PAYLOAD_SIZE. When the queue size is to small,
- * the system may hang if the queue is full with asynchronous events and the HCI layer is still waiting
- * for a CC/CS event, In that case, the notification TL_BLE_HCI_ToNot() is called to indicate
- * to the application a HCI command did not receive its command event within 30s (Default HCI Timeout).
- */
-const CFG_TLBLE_EVT_QUEUE_LENGTH: usize = 5;
-const CFG_TLBLE_MOST_EVENT_PAYLOAD_SIZE: usize = 255;
-const TL_BLE_EVENT_FRAME_SIZE: usize = TL_EVT_HEADER_SIZE + CFG_TLBLE_MOST_EVENT_PAYLOAD_SIZE;
+/// Largest BLE event, independent of the event pool configuration.
+const TL_BLE_EVENT_FRAME_SIZE: usize = TL_EVT_HEADER_SIZE + 255;
 
 const fn divc(x: usize, y: usize) -> usize {
     ((x) + (y) - 1) / (y)
 }
 
-const POOL_SIZE: usize =
-    CFG_TLBLE_EVT_QUEUE_LENGTH * 4 * divc(TL_PACKET_HEADER_SIZE + TL_BLE_EVENT_FRAME_SIZE, 4);
-
 #[link_section = "EVT_POOL"]
-static mut EVT_POOL: MaybeUninit<[u8; POOL_SIZE]> = MaybeUninit::uninit();
+static mut EVT_POOL: MaybeUninit<[u8; pool::POOL_SIZE]> = MaybeUninit::uninit();
 
 #[link_section = "SYS_SPARE_EVT_BUF"]
 static mut SYS_SPARE_EVT_BUF: MaybeUninit<[u8; TL_PACKET_HEADER_SIZE + TL_EVT_HEADER_SIZE + 255]> =
@@ -261,112 +437,1655 @@ static mut BLE_CMD_BUFFER: MaybeUninit<CmdPacket> = MaybeUninit::uninit();
 static mut HCI_ACL_DATA_BUFFER: MaybeUninit<[u8; TL_PACKET_HEADER_SIZE + 5 + 251]> =
     MaybeUninit::uninit();
 
-pub type HeaplessEvtQueue = spsc::Queue<EvtBox, heapless::consts::U32, u8, spsc::SingleCore>;
+/// Shared memory table checked by `TlMbox::selftest`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SharedTable {
+    Ref,
+    DeviceInfo,
+    Ble,
+    Thread,
+    Sys,
+    MemManager,
+    Traces,
+    Mac802154,
+}
+
+/// Shared memory layout problem found by `TlMbox::selftest`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SelfTestError {
+    /// Table pointer is null.
+    NullPointer(SharedTable),
+    /// Table is placed outside of SRAM2, where CPU2 can't reach it.
+    OutsideSram2(SharedTable),
+    /// Table address is not 4-byte aligned.
+    Misaligned(SharedTable),
+    /// Reference table is not at the start of SRAM2a, where CPU2 looks for it.
+    RefTableMisplaced,
+}
+
+const SRAM2_START: usize = 0x2003_0000;
+const SRAM2_END: usize = 0x2004_0000;
+
+/// Mailbox traffic counters.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct MboxStats {
+    /// IPCC RX handler calls
+    pub rx_irqs: u32,
+    /// IPCC TX handler calls
+    pub tx_irqs: u32,
+    /// Events put into the event queue
+    pub events_enqueued: u32,
+    /// Events taken from the event queue
+    pub events_dequeued: u32,
+    /// System command responses received
+    pub cc_events: u32,
+    /// Responses popped with `pop_tagged_cc_evt` that didn't carry the expected tag
+    pub tag_mismatches: u32,
+    /// Channel drains stopped by a full event queue, see `EvtQueueOverflow::Defer`
+    pub deferred_drains: u32,
+    /// System events received from CPU2
+    pub sys_events: u32,
+    /// BLE events received from CPU2
+    pub ble_events: u32,
+    /// Events dropped because the event queue was full
+    pub events_dropped: u32,
+    /// Event buffers given back to CPU2
+    pub buffers_released: u32,
+    /// Commands sent on the system, BLE, Thread and MAC 802.15.4 channels
+    pub cmds_sent: u32,
+    /// Highest number of events seen in the event queue
+    pub max_queue_depth: u32,
+    /// Transport layer faults, see `fault` module
+    pub faults: FaultCounters,
+}
+
+impl MboxStats {
+    /// Adds the counters updated outside of the IPCC handlers.
+    fn with_shared_counters(mut self) -> Self {
+        self.cmds_sent = CMDS_SENT.load(Ordering::Relaxed);
+        self.buffers_released = mm::released_buffers();
+        self
+    }
+}
+
+/// Commands are sent by free functions, without access to the mailbox.
+static CMDS_SENT: AtomicU32 = AtomicU32::new(0);
+
+/// Counts a command sent to CPU2 in `MboxStats::cmds_sent`.
+fn count_cmd() {
+    CMDS_SENT.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Firmware running on CPU2, as reported by the ready event.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CoprocessorMode {
+    /// No ready event was received yet.
+    Unknown,
+    /// The wireless stack is running.
+    WirelessStack,
+    /// The firmware upgrade service (FUS) is running, e.g. on devices fresh from production.
+    Fus,
+    /// Unknown value reported by CPU2.
+    Other(u8),
+}
+
+/// CPU2 ready event, see `TlMbox::cpu2_ready`.
+#[derive(Debug, Copy, Clone)]
+pub struct Cpu2Ready {
+    /// Firmware that reported ready, `WirelessStack` or `Fus` (or `Other`).
+    pub mode: CoprocessorMode,
+    /// Wireless firmware information, `None` if no wireless stack is installed.
+    pub fw_info: Option<WirelessFwInfoTable>,
+}
+
+/// CPU2 firmware, see `TlMbox::check_cpu2_presence`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Cpu2Firmware {
+    /// A wireless stack is installed. Its version is known once CPU2 reported ready.
+    WirelessStack(Option<FwVersion>),
+    /// Only FUS is installed, CPU2 boots into FUS. Its version is known once CPU2 reported ready.
+    FusOnly(Option<FwVersion>),
+    /// No secure area: CPU2 has no firmware to run and never reports ready.
+    None,
+}
+
+/// CPU2 didn't report ready in time, see `TlMbox::wait_for_cpu2_ready_timeout`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Cpu2Timeout {
+    /// Firmware found by `TlMbox::check_cpu2_presence`.
+    pub firmware: Cpu2Firmware,
+}
+
+/// Secure area of FUS alone at the end of flash, in pages. A larger secure area holds a
+/// wireless stack as well.
+const FUS_SECURE_PAGES: u32 = 12;
+
+/// Error reported by CPU2 at run time, see `TlMbox::cpu2_error`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Cpu2Error {
+    /// System error notification, e.g. a failed BLE stack initialization.
+    Shci(evt::ShciErrorCode),
+    /// HCI Hardware Error event with its hardware code, the BLE controller stopped.
+    HardwareError(u8),
+}
+
+/// Error of `TlMbox::ensure_wireless_stack_running`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StartWirelessStackError {
+    /// FUS rejected `SHCI_FUS_START_WS` with status.
+    FusRejected(u8),
+    /// CPU2 still runs FUS after `attempts` start requests. Usually no wireless stack is
+    /// installed.
+    StillInFus { attempts: u8 },
+    /// CPU2 reported an unknown firmware in the ready event.
+    UnknownMode(u8),
+    /// CPU2 didn't report ready in time.
+    Timeout(Cpu2Timeout),
+}
+
+/// Delivery mode of BLE channel events.
+#[derive(Copy, Clone)]
+pub enum BleEvtDelivery {
+    /// Events are put into the event queue and taken with `dequeue_event`.
+    Queue,
+    /// Events are passed to the callback directly from `interrupt_ipcc_rx_handler`, bypassing the
+    /// event queue.
+    ///
+    /// The callback runs at IPCC RX interrupt priority and must not block. The shared memory
+    /// buffer is released when the callback drops the `EvtBox`.
+    Callback(fn(EvtBox)),
+}
+
+/// Reaction to an event that doesn't fit into the full event queue.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EvtQueueOverflow {
+    /// Events are left in shared memory until `dequeue_event` makes room (default).
+    ///
+    /// The channel stays occupied meanwhile, which holds back further events on CPU2, so no
+    /// event is lost. Counted in `MboxStats::deferred_drains`.
+    Defer,
+    /// The new event is dropped.
+    DropNewest,
+    /// The oldest queued event is dropped to make room for the new one.
+    DropOldest,
+}
+
+/// Mailbox configuration.
+///
+/// The event queue holds `EVT_QUEUE_LEN` events, selected at build time with the
+/// `evt-queue-8`, `evt-queue-16` or `evt-queue-64` feature (32 by default).
+#[derive(Copy, Clone)]
+pub struct TlMboxConfig {
+    pub(crate) ble_evt_delivery: BleEvtDelivery,
+    pub(crate) fault_handler: &'static dyn FaultHandler,
+    pub(crate) evt_queue_overflow: EvtQueueOverflow,
+    pub(crate) ble_evt_pool_len: usize,
+}
+
+impl Default for TlMboxConfig {
+    /// All events are delivered through the event queue, events that don't fit stay in shared
+    /// memory until it has room again, faults panic.
+    fn default() -> Self {
+        TlMboxConfig {
+            ble_evt_delivery: BleEvtDelivery::Queue,
+            fault_handler: &PanicOnFault,
+            evt_queue_overflow: EvtQueueOverflow::Defer,
+            ble_evt_pool_len: pool::BLE_EVT_POOL_LEN,
+        }
+    }
+}
+
+impl TlMboxConfig {
+    pub fn new() -> Self {
+        TlMboxConfig::default()
+    }
+
+    /// Delivers BLE channel events to `callback` in interrupt context.
+    ///
+    /// System channel events keep going through the event queue. See `BleEvtDelivery::Callback`.
+    pub fn ble_evt_callback(mut self, callback: fn(EvtBox)) -> Self {
+        self.ble_evt_delivery = BleEvtDelivery::Callback(callback);
+        self
+    }
+
+    /// Selects the reaction to transport layer faults, e.g. `fault::CountAndContinue`.
+    pub fn fault_handler(mut self, handler: &'static dyn FaultHandler) -> Self {
+        self.fault_handler = handler;
+        self
+    }
+
+    /// Selects the reaction to a full event queue.
+    ///
+    /// A dropped event is counted in `TlMbox::dropped_events` and reported as a fault.
+    pub fn evt_queue_overflow(mut self, overflow: EvtQueueOverflow) -> Self {
+        self.evt_queue_overflow = overflow;
+        self
+    }
+
+    /// Gives only `len` events of the BLE event pool to CPU2, at most `pool::BLE_EVT_POOL_LEN`
+    /// (the default).
+    pub fn ble_evt_pool_len(mut self, len: usize) -> Self {
+        self.ble_evt_pool_len = len;
+        self
+    }
 
-pub struct TlMbox {
+    /// Returns the BLE event pool size given to CPU2 in the memory manager table, in bytes.
+    pub fn ble_evt_pool_size(&self) -> u32 {
+        pool::pool_size(self.ble_evt_pool_len)
+    }
+}
+
+#[cfg(any(
+    all(feature = "evt-queue-8", feature = "evt-queue-16"),
+    all(feature = "evt-queue-8", feature = "evt-queue-64"),
+    all(feature = "evt-queue-16", feature = "evt-queue-64"),
+))]
+compile_error!(
+    "Select at most one of the `evt-queue-8`, `evt-queue-16` and `evt-queue-64` features"
+);
+
+#[cfg(feature = "evt-queue-8")]
+type EvtQueueLen = heapless::consts::U8;
+#[cfg(feature = "evt-queue-16")]
+type EvtQueueLen = heapless::consts::U16;
+#[cfg(feature = "evt-queue-64")]
+type EvtQueueLen = heapless::consts::U64;
+#[cfg(not(any(
+    feature = "evt-queue-8",
+    feature = "evt-queue-16",
+    feature = "evt-queue-64"
+)))]
+type EvtQueueLen = heapless::consts::U32;
+
+/// Number of events the event queue holds.
+#[cfg(feature = "evt-queue-8")]
+pub const EVT_QUEUE_LEN: usize = 8;
+#[cfg(feature = "evt-queue-16")]
+pub const EVT_QUEUE_LEN: usize = 16;
+#[cfg(feature = "evt-queue-64")]
+pub const EVT_QUEUE_LEN: usize = 64;
+#[cfg(not(any(
+    feature = "evt-queue-8",
+    feature = "evt-queue-16",
+    feature = "evt-queue-64"
+)))]
+pub const EVT_QUEUE_LEN: usize = 32;
+
+/// Event queue between the IPCC RX handler (producer) and `dequeue_event` (consumer).
+///
+/// `MultiCore` orders the index updates with atomics, so producer and consumer may preempt each
+/// other once the queue is split, see `split` module.
+pub type HeaplessEvtQueue = spsc::Queue<EvtBox, EvtQueueLen, u8, spsc::MultiCore>;
+
+/// Event queue end filled by the IPCC RX handler: the whole queue, or its producer once the
+/// mailbox is split.
+pub(crate) trait EvtSink<T> {
+    fn len(&self) -> usize;
+    fn capacity(&self) -> usize;
+    fn enqueue(&mut self, item: T) -> Result<(), T>;
+    /// Takes the oldest item out of the queue, `None` if this end can't dequeue.
+    fn dequeue_oldest(&mut self) -> Option<T>;
+}
+
+impl<T, N> EvtSink<T> for spsc::Queue<T, N, u8, spsc::MultiCore>
+where
+    N: heapless::ArrayLength<T>,
+{
+    fn len(&self) -> usize {
+        spsc::Queue::len(self)
+    }
+
+    fn capacity(&self) -> usize {
+        spsc::Queue::capacity(self) as usize
+    }
+
+    fn enqueue(&mut self, item: T) -> Result<(), T> {
+        spsc::Queue::enqueue(self, item)
+    }
+
+    fn dequeue_oldest(&mut self) -> Option<T> {
+        self.dequeue()
+    }
+}
+
+/// Mailbox transport, see `tl_init`.
+///
+/// `Q` is the event queue end the IPCC handlers fill, it's only changed by `split`.
+pub struct TlMbox<Q = HeaplessEvtQueue> {
+    /// Channels of the mailbox are only accessed through it, see `channels`
+    ipcc: crate::ipcc::Ipcc,
     sys: sys::Sys,
     ble: ble::Ble,
     _mm: mm::MemoryManager,
 
     /// Current event that is produced during IPCC IRQ handler execution on SYS channel
-    evt_queue: HeaplessEvtQueue,
+    evt_queue: Q,
 
     /// Last received Command Complete event.
     last_cc_evt: Option<evt::CcEvt>,
+
+    /// Last BLE Command Status event
+    last_cs_evt: Option<evt::CsEvt>,
+    /// First error reported by CPU2
+    cpu2_error: Option<Cpu2Error>,
+    /// Last system command response, kept until the next one arrives
+    last_sys_response: Option<sys::SysCmdResponse>,
+
+    /// Tag of the system command in flight, see `tagged_cmd`
+    pending_tag: Option<u32>,
+    /// Tag of the command `last_cc_evt` answers
+    last_cc_tag: Option<u32>,
+
+    stats: MboxStats,
+    /// Events dropped because the event queue was full, saturating
+    dropped_events: u32,
+
+    /// System event channel drain stopped by a full event queue
+    sys_evt_deferred: bool,
+
+    /// BLE event channel drain stopped by a full event queue
+    ble_evt_deferred: bool,
+
+    config: TlMboxConfig,
+
+    coprocessor_mode: CoprocessorMode,
+
+    /// Serviced with `poll` instead of the IPCC interrupts
+    polled: bool,
+
+    #[cfg(feature = "mbox-latency")]
+    latency: latency::LatencyTracker,
+
+    #[cfg(feature = "evt-fanout")]
+    fanout: fanout::EvtFanout,
+
+    #[cfg(feature = "thread")]
+    thread: thread::Thread,
+
+    #[cfg(feature = "mac-802-15-4")]
+    mac_802_15_4: mac_802_15_4::Mac802154,
+
+    #[cfg(feature = "traces")]
+    traces: traces::Traces,
 }
 
 impl TlMbox {
     /// Initializes low-level transport between CPU1 and BLE stack on CPU2.
-    pub fn tl_init(rcc: &mut crate::rcc::Rcc, ipcc: &mut crate::ipcc::Ipcc) -> TlMbox {
-        // Populate reference table with pointers in the shared memory
-        unsafe {
-            TL_REF_TABLE = MaybeUninit::new(RefTable {
-                device_info_table: TL_DEVICE_INFO_TABLE.as_ptr(),
-                ble_table: TL_BLE_TABLE.as_ptr(),
-                thread_table: TL_THREAD_TABLE.as_ptr(),
-                sys_table: TL_SYS_TABLE.as_ptr(),
-                mem_manager_table: TL_MEM_MANAGER_TABLE.as_ptr(),
-                traces_table: TL_TRACES_TABLE.as_ptr(),
-                mac_802_15_4_table: TL_MAC_802_15_4_TABLE.as_ptr(),
-            });
+    ///
+    /// The mailbox takes over the IPCC, see `ipcc` for the channels it doesn't use.
+    pub fn tl_init(rcc: &mut crate::rcc::Rcc, ipcc: crate::ipcc::Ipcc) -> TlMbox {
+        TlMbox::tl_init_with_config(rcc, ipcc, TlMboxConfig::default())
+    }
 
-            TL_SYS_TABLE = MaybeUninit::zeroed();
-            TL_DEVICE_INFO_TABLE = MaybeUninit::zeroed();
-            TL_BLE_TABLE = MaybeUninit::zeroed();
-            TL_THREAD_TABLE = MaybeUninit::zeroed();
-            TL_MEM_MANAGER_TABLE = MaybeUninit::zeroed();
-            TL_TRACES_TABLE = MaybeUninit::zeroed();
-            TL_MAC_802_15_4_TABLE = MaybeUninit::zeroed();
+    /// Initializes low-level transport between CPU1 and BLE stack on CPU2 with `config`.
+    pub fn tl_init_with_config(
+        rcc: &mut crate::rcc::Rcc,
+        mut ipcc: crate::ipcc::Ipcc,
+        config: TlMboxConfig,
+    ) -> TlMbox {
+        init_shared_tables();
 
-            EVT_POOL = MaybeUninit::zeroed();
-            SYS_SPARE_EVT_BUF = MaybeUninit::zeroed();
-            BLE_SPARE_EVT_BUF = MaybeUninit::zeroed();
+        ipcc.init(rcc);
+        trace!("tl_init: shared tables initialized");
 
-            CS_BUFFER = MaybeUninit::zeroed();
-            BLE_CMD_BUFFER = MaybeUninit::zeroed();
-            HCI_ACL_DATA_BUFFER = MaybeUninit::zeroed();
-        }
+        TlMbox::new(ipcc, config)
+    }
 
-        ipcc.init(rcc);
+    /// Gives access to the IPCC, e.g. for the channels the mailbox doesn't use (see `channels`),
+    /// for the free `shci` functions or to mask the IPCC interrupts.
+    pub fn ipcc(&mut self) -> &mut crate::ipcc::Ipcc {
+        &mut self.ipcc
+    }
 
-        let sys = sys::Sys::new(ipcc);
-        let ble = ble::Ble::new(ipcc);
-        let mm = mm::MemoryManager::new();
+    /// Re-initializes the mailbox for a new CPU2 boot, e.g. after a CPU2 reset.
+    ///
+    /// Shared tables, linked lists and buffers are initialized again, the event queue is
+    /// emptied, IPCC flags of both directions of the mailbox channels are cleared and the receive
+    /// channels are enabled. Other channels are left alone, see `channels`.
+    /// Configuration and traffic counters are kept.
+    ///
+    /// Must be called while CPU2 doesn't use the mailbox, i.e. before `pwr::set_cpu2(true)`:
+    /// CPU2 reads the tables when it boots, and a message it sends before the reset is lost.
+    /// Events and traces taken from the previous session must be dropped before, their buffers
+    /// would otherwise be released into the new free buffer queue.
+    pub fn reset(&mut self) {
+        cortex_m::interrupt::free(|_| {
+            init_shared_tables();
+            for channel in crate::ipcc::IpccChannel::iterator() {
+                if channels::is_mbox_channel(channel) {
+                    self.ipcc.reset_channel(channel);
+                }
+            }
+
+            // NOTE(unsafe) the IPCC moves to the new mailbox, the old one is forgotten below
+            let ipcc = unsafe { core::ptr::read(&self.ipcc) };
+            let mut mbox = TlMbox::new(ipcc, self.config);
+            mbox.stats = self.stats;
+            mbox.dropped_events = self.dropped_events;
+            mbox.polled = self.polled;
+
+            // Queued events point to buffers of the previous session, which must not be released
+            core::mem::forget(core::mem::replace(self, mbox));
+        });
+
+        trace!("reset: shared tables initialized");
+    }
 
-        let evt_queue = unsafe { heapless::spsc::Queue::u8_sc() };
+    /// Creates the transport state and enables the receive channels. Shared tables must be
+    /// initialized.
+    fn new(mut ipcc: crate::ipcc::Ipcc, config: TlMboxConfig) -> TlMbox {
+        let sys = sys::Sys::new(&mut ipcc);
+        let ble = ble::Ble::new(&mut ipcc);
+        #[cfg(feature = "thread")]
+        let thread = thread::Thread::new(&mut ipcc);
+        #[cfg(feature = "mac-802-15-4")]
+        let mac_802_15_4 = mac_802_15_4::Mac802154::new(&mut ipcc);
+        #[cfg(feature = "traces")]
+        let traces = traces::Traces::new(&mut ipcc);
+        let mm = mm::MemoryManager::new(config.ble_evt_pool_size());
+
+        let evt_queue = heapless::spsc::Queue::u8();
 
         TlMbox {
+            ipcc,
             sys,
             ble,
             _mm: mm,
             evt_queue,
             last_cc_evt: None,
+            last_cs_evt: None,
+            cpu2_error: None,
+            last_sys_response: None,
+            pending_tag: None,
+            last_cc_tag: None,
+            stats: MboxStats::default(),
+            dropped_events: 0,
+            sys_evt_deferred: false,
+            ble_evt_deferred: false,
+            config,
+            coprocessor_mode: CoprocessorMode::Unknown,
+            polled: false,
+
+            #[cfg(feature = "mbox-latency")]
+            latency: latency::LatencyTracker::new(),
+
+            #[cfg(feature = "evt-fanout")]
+            fanout: fanout::EvtFanout::new(),
+
+            #[cfg(feature = "thread")]
+            thread,
+
+            #[cfg(feature = "mac-802-15-4")]
+            mac_802_15_4,
+
+            #[cfg(feature = "traces")]
+            traces,
+        }
+    }
+
+    /// IPCC RX interrupt handler: moves events received from CPU2 into the event queue.
+    pub fn interrupt_ipcc_rx_handler(&mut self) {
+        debug_assert!(!self.polled, "IPCC interrupt handled in polling mode");
+        self.rx_handler();
+    }
+
+    /// IPCC TX interrupt handler: completes system commands and buffer releases.
+    pub fn interrupt_ipcc_tx_handler(&mut self) {
+        debug_assert!(!self.polled, "IPCC interrupt handled in polling mode");
+        self.tx_handler();
+    }
+
+    /// Services the mailbox from a main loop, for firmwares that don't enable the IPCC
+    /// interrupts, e.g. bootloaders.
+    ///
+    /// Does the same as `interrupt_ipcc_rx_handler` and `interrupt_ipcc_tx_handler` together,
+    /// which it replaces. Channels are checked with their flags and masks (`C1MR`), which work
+    /// without interrupts, while the IPCC interrupts must stay off: `tl_init` enables
+    /// `RXOIE`/`TXFIE` and unmasks `IPCC_C1_RX_IT`/`IPCC_C1_TX_IT` in the NVIC, call
+    /// `Ipcc::disable_interrupts` through `ipcc` after it. Otherwise the IRQ handlers and `poll`
+    /// race for the same channels.
+    ///
+    /// The two modes must not be mixed: in debug builds, `poll` panics if the IPCC interrupts are
+    /// enabled, and the interrupt handlers panic once `poll` was called. The blocking helpers
+    /// (e.g. `blocking_read_cc_evt`, `blocking_sys_cmd_timeout`) work in both modes.
+    pub fn poll(&mut self) {
+        debug_assert!(
+            !self.ipcc.interrupts_enabled(),
+            "IPCC interrupts enabled in polling mode"
+        );
+        self.polled = true;
+
+        self.rx_handler();
+        self.tx_handler();
+    }
+}
+
+/// IPCC handlers, shared with `split::MboxIrq`.
+impl<Q: EvtSink<EvtBox>> TlMbox<Q> {
+    pub(crate) fn rx_handler(&mut self) {
+        #[cfg(feature = "mbox-latency")]
+        let irq_entry = latency::LatencyTracker::timestamp();
+
+        self.stats.rx_irqs = self.stats.rx_irqs.wrapping_add(1);
+        let overflow = self.config.evt_queue_overflow;
+        let mut enqueued = 0;
+
+        // Channels are serviced until none is left pending: CPU2 often signals several at once,
+        // e.g. system and BLE events right after it boots, and a channel whose flag is still set
+        // doesn't raise the interrupt again.
+        loop {
+            let mut serviced = false;
+
+            if self
+                .ipcc
+                .is_rx_pending(channels::cpu2::IPCC_SYSTEM_EVENT_CHANNEL)
+            {
+                trace!("IPCC RX: system event");
+                self.sys_evt_rx(overflow, &mut enqueued);
+                serviced = true;
+            }
+
+            if self
+                .ipcc
+                .is_rx_pending(channels::cpu2::IPCC_BLE_EVENT_CHANNEL)
+            {
+                trace!("IPCC RX: BLE event");
+                self.ble_evt_rx(overflow, &mut enqueued);
+                serviced = true;
+            }
+
+            #[cfg(feature = "thread")]
+            {
+                if self
+                    .ipcc
+                    .is_rx_pending(channels::cpu2::IPCC_THREAD_NOTIFICATION_ACK_CHANNEL)
+                {
+                    trace!("IPCC RX: Thread notification");
+                    self.thread.notification_handler(&mut self.ipcc);
+                    serviced = true;
+                }
+
+                let cli_channel = channels::cpu2::IPCC_THREAD_CLI_NOTIFICATION_ACK_CHANNEL;
+                if self.ipcc.is_rx_pending(cli_channel) {
+                    self.unhandled_rx(cli_channel);
+                    serviced = true;
+                }
+            }
+
+            #[cfg(feature = "mac-802-15-4")]
+            {
+                if self
+                    .ipcc
+                    .is_rx_pending(channels::cpu2::IPCC_MAC_802_15_4_NOTIFICATION_ACK_CHANNEL)
+                {
+                    trace!("IPCC RX: MAC 802.15.4 notification");
+                    self.mac_802_15_4.notification_handler(&mut self.ipcc);
+                    serviced = true;
+                }
+            }
+
+            #[cfg(feature = "traces")]
+            {
+                if self.ipcc.is_rx_pending(channels::cpu2::IPCC_TRACES_CHANNEL) {
+                    trace!("IPCC RX: traces");
+                    self.traces.evt_handler(&mut self.ipcc);
+                    serviced = true;
+                }
+            }
+
+            if !serviced {
+                break;
+            }
+        }
+
+        self.stats.events_enqueued = self.stats.events_enqueued.wrapping_add(enqueued);
+        self.stats.max_queue_depth = self.stats.max_queue_depth.max(self.evt_queue.len() as u32);
+
+        #[cfg(feature = "async")]
+        {
+            if enqueued > 0 {
+                asynch::wake_evt();
+            }
+        }
+
+        #[cfg(feature = "mbox-latency")]
+        self.latency.on_enqueue(irq_entry, enqueued as usize);
+
+        #[cfg(feature = "evt-fanout")]
+        {
+            if self.fanout.is_active() {
+                // Shared memory buffers are released as soon as the events are copied
+                while let Some(evt) = self.evt_queue.dequeue_oldest() {
+                    self.fanout.dispatch(&evt);
+                }
+            }
         }
     }
 
-    pub fn interrupt_ipcc_rx_handler(&mut self, ipcc: &mut crate::ipcc::Ipcc) {
-        if ipcc.is_rx_pending(channels::cpu2::IPCC_SYSTEM_EVENT_CHANNEL) {
-            self.sys.evt_handler(ipcc, &mut self.evt_queue);
-        } else if ipcc.is_rx_pending(channels::cpu2::IPCC_THREAD_NOTIFICATION_ACK_CHANNEL) {
-            todo!()
-        } else if ipcc.is_rx_pending(channels::cpu2::IPCC_BLE_EVENT_CHANNEL) {
-            self.ble.evt_handler(ipcc, &mut self.evt_queue);
-        } else if ipcc.is_rx_pending(channels::cpu2::IPCC_TRACES_CHANNEL) {
-            todo!()
-        } else if ipcc.is_rx_pending(channels::cpu2::IPCC_THREAD_CLI_NOTIFICATION_ACK_CHANNEL) {
-            todo!()
+    /// System event channel occupied: moves the events into the event queue.
+    fn sys_evt_rx(&mut self, overflow: EvtQueueOverflow, enqueued: &mut u32) {
+        let limit = drain_limit(&self.evt_queue, overflow);
+        let queue = &mut self.evt_queue;
+        let mode = &mut self.coprocessor_mode;
+        let cpu2_error = &mut self.cpu2_error;
+        let mut received = 0;
+        let mut dropped = 0;
+        let drained = self.sys.evt_handler(&mut self.ipcc, limit, |evt| {
+            received += 1;
+            if let Some(ready) = sys::ready_evt_mode(&evt) {
+                *mode = ready;
+            }
+            if let Ok(ShciEvent::Error(code)) = evt.try_into_shci_event() {
+                trace!("CPU2 error: {:?}", code);
+                cpu2_error.get_or_insert(Cpu2Error::Shci(code));
+            }
+            enqueue_evt(queue, evt, overflow, enqueued, &mut dropped);
+        });
+
+        self.stats.sys_events = self.stats.sys_events.wrapping_add(received);
+        if !drained {
+            self.sys_evt_deferred = true;
+            self.stats.deferred_drains = self.stats.deferred_drains.wrapping_add(1);
         }
+        self.on_dropped(dropped, overflow, FaultKind::SysEvtQueueFull);
     }
 
-    pub fn interrupt_ipcc_tx_handler(&mut self, ipcc: &mut crate::ipcc::Ipcc) {
-        if ipcc.is_tx_pending(channels::cpu1::IPCC_SYSTEM_CMD_RSP_CHANNEL) {
-            self.last_cc_evt = Some(self.sys.cmd_evt_handler(ipcc));
-        } else if ipcc.is_tx_pending(channels::cpu1::IPCC_THREAD_OT_CMD_RSP_CHANNEL) {
-            todo!()
-        } else if ipcc.is_tx_pending(channels::cpu1::IPCC_MM_RELEASE_BUFFER_CHANNEL) {
-            mm::free_buf_handler(ipcc);
-        } else if ipcc.is_tx_pending(channels::cpu1::IPCC_HCI_ACL_DATA_CHANNEL) {
-            self.ble.acl_data_handler(ipcc);
+    /// BLE event channel occupied: moves the events into the event queue or passes them to the
+    /// callback.
+    fn ble_evt_rx(&mut self, overflow: EvtQueueOverflow, enqueued: &mut u32) {
+        let last_cs_evt = &mut self.last_cs_evt;
+        let cpu2_error = &mut self.cpu2_error;
+        let mut received = 0;
+        match self.config.ble_evt_delivery {
+            BleEvtDelivery::Queue => {
+                let limit = drain_limit(&self.evt_queue, overflow);
+                let queue = &mut self.evt_queue;
+                let mut dropped = 0;
+                let drained = self.ble.evt_handler(&mut self.ipcc, limit, |evt| {
+                    received += 1;
+                    if let Some(cs) = ble::cs_evt(&evt) {
+                        *last_cs_evt = Some(cs);
+                    }
+                    if let Some(code) = ble::hardware_error(&evt) {
+                        cpu2_error.get_or_insert(Cpu2Error::HardwareError(code));
+                    }
+                    enqueue_evt(queue, evt, overflow, enqueued, &mut dropped);
+                });
+
+                if !drained {
+                    self.ble_evt_deferred = true;
+                    self.stats.deferred_drains = self.stats.deferred_drains.wrapping_add(1);
+                }
+                self.on_dropped(dropped, overflow, FaultKind::BleEvtQueueFull);
+            }
+            BleEvtDelivery::Callback(callback) => {
+                self.ble.evt_handler(&mut self.ipcc, usize::MAX, |evt| {
+                    received += 1;
+                    if let Some(cs) = ble::cs_evt(&evt) {
+                        *last_cs_evt = Some(cs);
+                    }
+                    if let Some(code) = ble::hardware_error(&evt) {
+                        cpu2_error.get_or_insert(Cpu2Error::HardwareError(code));
+                    }
+                    callback(evt)
+                });
+            }
         }
+
+        self.stats.ble_events = self.stats.ble_events.wrapping_add(received);
+    }
+
+    /// Records `dropped` events lost to a full event queue.
+    fn on_dropped(&mut self, dropped: u32, overflow: EvtQueueOverflow, kind: FaultKind) {
+        if dropped == 0 {
+            return;
+        }
+
+        self.dropped_events = self.dropped_events.saturating_add(dropped);
+        self.stats.events_dropped = self.stats.events_dropped.wrapping_add(dropped);
+
+        // Dropped oldest events leave the queue without being dequeued
+        #[cfg(feature = "mbox-latency")]
+        {
+            if let EvtQueueOverflow::DropOldest = overflow {
+                for _ in 0..dropped {
+                    self.latency.on_drop();
+                }
+            }
+        }
+        #[cfg(not(feature = "mbox-latency"))]
+        let _ = overflow;
+
+        for _ in 0..dropped {
+            self.fault(kind);
+        }
+    }
+
+    /// Counts `kind` and passes it to the fault handler.
+    fn fault(&mut self, kind: FaultKind) {
+        self.stats.faults.record(kind);
+        self.config.fault_handler.on_fault(kind);
+    }
+
+    /// Acknowledges and drops a message on a channel without handler.
+    #[cfg(feature = "thread")]
+    fn unhandled_rx(&mut self, channel: crate::ipcc::IpccChannel) {
+        self.ipcc.c1_clear_flag_channel(channel);
+        self.fault(FaultKind::UnhandledRxChannel(channel_number(channel)));
+    }
+
+    pub(crate) fn tx_handler(&mut self) {
+        self.stats.tx_irqs = self.stats.tx_irqs.wrapping_add(1);
+
+        // Same as RX: every freed channel is handled within one interrupt
+        loop {
+            let mut serviced = false;
+
+            if self
+                .ipcc
+                .is_tx_pending(channels::cpu1::IPCC_SYSTEM_CMD_RSP_CHANNEL)
+            {
+                trace!("IPCC TX: system command response");
+                self.last_cc_evt = Some(self.sys.cmd_evt_handler(&mut self.ipcc));
+                self.last_sys_response = self.sys.cmd_response();
+                if self.last_sys_response.is_none() {
+                    self.fault(FaultKind::InvalidCmdRspLength);
+                }
+                self.last_cc_tag = self.pending_tag.take();
+                self.stats.cc_events = self.stats.cc_events.wrapping_add(1);
+                serviced = true;
+
+                #[cfg(feature = "async")]
+                asynch::wake_cc();
+            }
+
+            if self
+                .ipcc
+                .is_tx_pending(channels::cpu1::IPCC_MM_RELEASE_BUFFER_CHANNEL)
+            {
+                trace!("IPCC TX: release buffer");
+                if mm::free_buf_handler(&mut self.ipcc).is_err() {
+                    self.fault(FaultKind::NullMemManagerTable);
+                }
+                serviced = true;
+            }
+
+            if self
+                .ipcc
+                .is_tx_pending(channels::cpu1::IPCC_HCI_ACL_DATA_CHANNEL)
+            {
+                self.ble.acl_data_handler(&mut self.ipcc);
+                serviced = true;
+            }
+
+            #[cfg(feature = "thread")]
+            {
+                if self
+                    .ipcc
+                    .is_tx_pending(channels::cpu1::IPCC_THREAD_OT_CMD_RSP_CHANNEL)
+                {
+                    trace!("IPCC TX: Thread command response");
+                    self.thread.cmd_rsp_handler(&mut self.ipcc);
+                    serviced = true;
+                }
+            }
+
+            #[cfg(feature = "mac-802-15-4")]
+            {
+                if self
+                    .ipcc
+                    .is_tx_pending(channels::cpu1::IPCC_MAC_802_15_4_CMD_RSP_CHANNEL)
+                {
+                    trace!("IPCC TX: MAC 802.15.4 command response");
+                    self.mac_802_15_4.cmd_rsp_handler(&mut self.ipcc);
+                    serviced = true;
+                }
+            }
+
+            if !serviced {
+                break;
+            }
+        }
+    }
+}
+
+impl TlMbox {
+    /// Validates the reference table and the pointers it holds.
+    ///
+    /// Tables of transports disabled by cargo features may be null, all others must be non-null,
+    /// 4-byte aligned and placed in SRAM2.
+    pub fn selftest(&self) -> Result<(), SelfTestError> {
+        let check = |table, ptr: usize, required: bool| {
+            if ptr == 0 {
+                return if required {
+                    Err(SelfTestError::NullPointer(table))
+                } else {
+                    Ok(())
+                };
+            }
+
+            if ptr < SRAM2_START || ptr >= SRAM2_END {
+                Err(SelfTestError::OutsideSram2(table))
+            } else if ptr % 4 != 0 {
+                Err(SelfTestError::Misaligned(table))
+            } else {
+                Ok(())
+            }
+        };
+
+        let ref_table_ptr = unsafe { TL_REF_TABLE.as_ptr() } as usize;
+        check(SharedTable::Ref, ref_table_ptr, true)?;
+        if ref_table_ptr != SRAM2_START {
+            return Err(SelfTestError::RefTableMisplaced);
+        }
+
+        let ref_table = unsafe { TL_REF_TABLE.assume_init() };
+        check(
+            SharedTable::DeviceInfo,
+            ref_table.device_info_table as usize,
+            true,
+        )?;
+        check(SharedTable::Ble, ref_table.ble_table as usize, true)?;
+        check(
+            SharedTable::Thread,
+            ref_table.thread_table as usize,
+            cfg!(feature = "thread"),
+        )?;
+        check(SharedTable::Sys, ref_table.sys_table as usize, true)?;
+        check(
+            SharedTable::MemManager,
+            ref_table.mem_manager_table as usize,
+            true,
+        )?;
+        check(
+            SharedTable::Traces,
+            ref_table.traces_table as usize,
+            cfg!(feature = "traces"),
+        )?;
+        check(
+            SharedTable::Mac802154,
+            ref_table.mac_802_15_4_table as usize,
+            cfg!(feature = "mac-802-15-4"),
+        )?;
+
+        Ok(())
     }
 
     /// Returns CPU2 wireless firmware information (if present).
     pub fn wireless_fw_info(&self) -> Option<WirelessFwInfoTable> {
-        let info = unsafe { &(*(*TL_REF_TABLE.as_ptr()).device_info_table).wireless_fw_info_table };
+        wireless_fw_info()
+    }
+
+    /// Returns RSS (FUS) firmware information (if present).
+    ///
+    /// Also valid while FUS runs instead of the wireless stack.
+    pub fn rss_info(&self) -> Option<RssInfoTable> {
+        let info = unsafe { (*(*TL_REF_TABLE.as_ptr()).device_info_table).rss_info_table };
+
+        // Zero version indicates that CPU2 didn't fill the information table
+        let version = info.version;
+        if version != 0 {
+            Some(info)
+        } else {
+            None
+        }
+    }
 
-        // Zero version indicates that CPU2 wasn't active and didn't fill the information table
-        if info.version != 0 {
-            Some(info.clone())
+    /// Returns safe boot firmware information (if present).
+    pub fn safe_boot_info(&self) -> Option<SafeBootInfoTable> {
+        let info = unsafe { (*(*TL_REF_TABLE.as_ptr()).device_info_table).safe_boot_info_table };
+
+        // Zero version indicates that CPU2 didn't fill the information table
+        let version = info.version;
+        if version != 0 {
+            Some(info)
         } else {
             None
         }
     }
 
+    /// Checks that CPU2 runs firmware of at least `min` (major, minor, sub) version and, if
+    /// given, the `stack` type.
+    pub fn require_fw_version(
+        &self,
+        min: (u8, u8, u8),
+        stack: Option<StackType>,
+    ) -> Result<WirelessFwInfoTable, FwRequirementError> {
+        let info = self
+            .wireless_fw_info()
+            .ok_or(FwRequirementError::NoFirmware)?;
+
+        if let Some(required) = stack {
+            if info.stack_type() != required {
+                return Err(FwRequirementError::WrongStack {
+                    found: info.stack_type(),
+                    required,
+                    version: info.version(),
+                });
+            }
+        }
+
+        if !info.meets(min) {
+            return Err(FwRequirementError::TooOld {
+                found: info.version(),
+                required: min.into(),
+                stack: info.stack_type(),
+            });
+        }
+
+        Ok(info)
+    }
+
     /// Picks single `EvtBox` from internal event queue.
     ///
-    /// Internal event queue is populated in IPCC RX IRQ handler.
+    /// Internal event queue is populated in IPCC RX IRQ handler. Both take `&mut self`, so a
+    /// mailbox shared with the IPCC interrupts must be accessed within a critical section, e.g.
+    /// a `Mutex<RefCell<_>>` as with `bind_interrupt!`. To dequeue events without one, split the
+    /// mailbox with `split`.
     pub fn dequeue_event(&mut self) -> Option<EvtBox> {
-        self.evt_queue.dequeue()
+        let evt = self.evt_queue.dequeue();
+        if evt.is_some() {
+            self.stats.events_dequeued = self.stats.events_dequeued.wrapping_add(1);
+        }
+
+        #[cfg(feature = "mbox-latency")]
+        {
+            if evt.is_some() {
+                self.latency.on_dequeue();
+            }
+        }
+
+        if evt.is_some() {
+            self.resume_deferred();
+        }
+
+        evt
+    }
+
+    /// Unmasks the event channels whose drain was deferred, now that the queue has room.
+    ///
+    /// Their flags are still set, so the IPCC RX interrupt fires again right away.
+    fn resume_deferred(&mut self) {
+        let room = self.evt_queue.capacity() as usize - self.evt_queue.len();
+
+        let sys = RxChannelAction::resume(self.sys_evt_deferred, room);
+        sys.apply(&mut self.ipcc, channels::cpu2::IPCC_SYSTEM_EVENT_CHANNEL);
+        self.sys_evt_deferred &= sys != RxChannelAction::Unmask;
+
+        let ble = RxChannelAction::resume(self.ble_evt_deferred, room);
+        ble.apply(&mut self.ipcc, channels::cpu2::IPCC_BLE_EVENT_CHANNEL);
+        self.ble_evt_deferred &= ble != RxChannelAction::Unmask;
+    }
+
+    /// Returns the number of received events not dequeued yet, including those left in shared
+    /// memory by a deferred drain.
+    pub fn pending_events(&self) -> usize {
+        let mut pending = self.evt_queue.len();
+        if self.sys_evt_deferred {
+            pending += self.sys.pending_events();
+        }
+        if self.ble_evt_deferred {
+            pending += self.ble.pending_events();
+        }
+
+        pending
+    }
+
+    /// Returns recorded event latency samples in no particular order.
+    ///
+    /// Requires the `mbox-latency` feature.
+    #[cfg(feature = "mbox-latency")]
+    pub fn latency_samples(&self) -> &[latency::LatencySample] {
+        self.latency.samples()
+    }
+
+    /// Discards recorded event latency samples.
+    #[cfg(feature = "mbox-latency")]
+    pub fn clear_latency_samples(&mut self) {
+        self.latency.clear()
+    }
+
+    /// Sends ACL data to the BLE connection `handle` with packet boundary flag `pb_flag`.
+    ///
+    /// Returns `WouldBlock` if the previous packet wasn't taken by CPU2 yet or if the controller
+    /// has no free ACL buffers; retry after the next IPCC interrupt.
+    pub fn send_acl_data(
+        &mut self,
+        handle: u16,
+        pb_flag: ble::AclPbFlag,
+        data: &[u8],
+    ) -> nb::Result<(), ble::AclError> {
+        self.ble
+            .send_acl_data(&mut self.ipcc, handle, pb_flag, data)
+    }
+
+    /// Sends ACL data, retrying until the controller accepts it.
+    ///
+    /// The IPCC TX and RX handlers are polled while waiting, so credits returned by `Number Of
+    /// Completed Packets` events are seen; the events are still queued for the application. With
+    /// `EvtQueueOverflow::Defer`, a full event queue holds credits back until `dequeue_event` makes
+    /// room, use `blocking_send_acl_data_timeout` if the queue isn't drained elsewhere. `feed` is
+    /// called on every retry.
+    pub fn blocking_send_acl_data<F: FnMut()>(
+        &mut self,
+        handle: u16,
+        pb_flag: ble::AclPbFlag,
+        data: &[u8],
+        mut feed: F,
+    ) -> Result<(), ble::AclError> {
+        nb::block!({
+            self.tx_handler();
+            self.rx_handler();
+            self.send_acl_data(handle, pb_flag, data).map_err(|e| {
+                feed();
+                e
+            })
+        })
+    }
+
+    /// Same as `blocking_send_acl_data`, but gives up with `AclError::Timeout` after `timeout`.
+    pub fn blocking_send_acl_data_timeout<T, P>(
+        &mut self,
+        handle: u16,
+        pb_flag: ble::AclPbFlag,
+        data: &[u8],
+        timer: &mut T,
+        timeout: P,
+    ) -> Result<(), ble::AclError>
+    where
+        T: crate::hal::timer::CountDown,
+        P: Into<T::Time>,
+    {
+        timer.start(timeout);
+        loop {
+            self.tx_handler();
+            self.rx_handler();
+            match self.send_acl_data(handle, pb_flag, data) {
+                Ok(()) => return Ok(()),
+                Err(nb::Error::Other(e)) => return Err(e),
+                Err(nb::Error::WouldBlock) => {}
+            }
+
+            if timer.wait().is_ok() {
+                return Err(ble::AclError::Timeout);
+            }
+        }
+    }
+
+    /// Returns current state of the BLE ACL flow control.
+    pub fn acl_flow_control(&self) -> &ble::AclFlowControl {
+        self.ble.flow_control()
+    }
+
+    /// Takes single event from the internal event queue.
+    ///
+    /// Returns `WouldBlock` if no event has been received yet.
+    pub fn read_event(&mut self) -> nb::Result<EvtBox, Void> {
+        self.dequeue_event().ok_or(nb::Error::WouldBlock)
+    }
+
+    /// Waits for an event, polling the IPCC RX handler. `feed` is called on every retry.
+    pub fn blocking_read_event<F: FnMut()>(&mut self, mut feed: F) -> EvtBox {
+        match nb::block!({
+            self.rx_handler();
+            self.read_event().map_err(|e| {
+                feed();
+                e
+            })
+        }) {
+            Ok(evt) => evt,
+            Err(void) => match void {},
+        }
+    }
+
+    /// Takes the Command Complete event of the last system command.
+    ///
+    /// Returns `WouldBlock` if CPU2 hasn't answered yet.
+    pub fn read_cc_evt(&mut self) -> nb::Result<evt::CcEvt, Void> {
+        self.pop_last_cc_evt().ok_or(nb::Error::WouldBlock)
+    }
+
+    /// Waits for the Command Complete event of the last system command, polling the IPCC TX
+    /// handler. `feed` is called on every retry.
+    pub fn blocking_read_cc_evt<F: FnMut()>(&mut self, mut feed: F) -> evt::CcEvt {
+        match nb::block!({
+            self.tx_handler();
+            self.read_cc_evt().map_err(|e| {
+                feed();
+                e
+            })
+        }) {
+            Ok(evt) => evt,
+            Err(void) => match void {},
+        }
+    }
+
+    /// Sends BLE command `opcode` and waits for its Command Complete or Command Status event,
+    /// polling the IPCC RX handler. `feed` is called on every retry.
+    ///
+    /// Other events received meanwhile are passed to `other`. Events must be delivered through
+    /// the event queue (`BleEvtDelivery::Queue`, no `evt-fanout` subscriptions).
+    pub fn blocking_ble_cmd<F: FnMut(), E: FnMut(EvtBox)>(
+        &mut self,
+        opcode: u16,
+        params: &[u8],
+        mut feed: F,
+        mut other: E,
+    ) -> Result<ble::CmdResponse, ble::CmdError> {
+        ble::send_cmd(&mut self.ipcc, opcode, params)?;
+
+        loop {
+            self.rx_handler();
+
+            while let Some(evt) = self.dequeue_event() {
+                match ble::cmd_response(&evt) {
+                    Some(rsp) if rsp.opcode == opcode => return Ok(rsp),
+                    _ => other(evt),
+                }
+            }
+
+            feed();
+        }
+    }
+
+    /// Waits for the response to the last system command, see `blocking_read_cc_evt`, and
+    /// returns its status.
+    fn blocking_sys_cmd_status<F: FnMut()>(&mut self, feed: F) -> Result<(), shci::FusError> {
+        self.blocking_read_cc_evt(feed);
+
+        let status = self.last_sys_response.as_ref().and_then(|rsp| rsp.status());
+        match status {
+            Some(0) => Ok(()),
+            Some(status) => Err(shci::FusError::Status(status)),
+            None => Err(shci::FusError::InvalidResponse),
+        }
+    }
+
+    /// Sends system command `opcode` with `payload` and waits for its Command Complete event,
+    /// see `sys::Sys::send_cmd_blocking`.
+    ///
+    /// Meant for bring-up: answers whether CPU2 responds at all without IPCC interrupts. The
+    /// response is also available with `last_sys_command_response`.
+    pub fn blocking_sys_cmd_timeout<T, P>(
+        &mut self,
+        opcode: u16,
+        payload: &[u8],
+        timer: &mut T,
+        timeout: P,
+    ) -> Result<evt::CcEvt, sys::SysError>
+    where
+        T: crate::hal::timer::CountDown,
+        P: Into<T::Time>,
+    {
+        let cc = self
+            .sys
+            .send_cmd_blocking(&mut self.ipcc, opcode, payload, timer, timeout)?;
+
+        self.last_sys_response = self.sys.cmd_response();
+        self.stats.cc_events = self.stats.cc_events.wrapping_add(1);
+
+        Ok(cc)
+    }
+
+    /// Sends `SHCI_C2_FUS_GET_STATE` and waits for the FUS state.
+    ///
+    /// If the wireless stack is running, it answers with `FusState::WirelessStackRunning` and
+    /// CPU2 reboots into FUS, see `coprocessor_mode`.
+    pub fn blocking_fus_get_state<F: FnMut()>(
+        &mut self,
+        feed: F,
+    ) -> Result<shci::FusState, shci::FusError> {
+        shci::shci_c2_fus_get_state(&mut self.ipcc);
+        self.blocking_read_cc_evt(feed);
+
+        let mode = self.coprocessor_mode;
+        self.last_sys_response
+            .as_ref()
+            .and_then(|rsp| shci::FusState::from_response(rsp.payload(), mode))
+            .ok_or(shci::FusError::InvalidResponse)
+    }
+
+    /// Sends `SHCI_C2_FUS_FW_UPGRADE` and waits for FUS to accept it, see
+    /// `shci::shci_c2_fus_fw_upgrade`.
+    pub fn blocking_fus_fw_upgrade<F: FnMut()>(
+        &mut self,
+        fw_src_add: u32,
+        fw_dest_add: u32,
+        feed: F,
+    ) -> Result<(), shci::FusError> {
+        shci::shci_c2_fus_fw_upgrade(&mut self.ipcc, fw_src_add, fw_dest_add);
+        self.blocking_sys_cmd_status(feed)
+    }
+
+    /// Sends `SHCI_C2_FUS_FW_DELETE` and waits for FUS to accept it.
+    ///
+    /// On success CPU2 reboots to delete the wireless stack, poll `blocking_fus_get_state` until
+    /// FUS is idle.
+    pub fn blocking_fus_fw_delete<F: FnMut()>(&mut self, feed: F) -> Result<(), shci::FusError> {
+        shci::shci_c2_fus_fw_delete(&mut self.ipcc);
+        self.blocking_sys_cmd_status(feed)
+    }
+
+    /// Sends `SHCI_C2_BLE_INIT` and waits for its Command Complete event.
+    ///
+    /// The BLE stack started if the event's `status` is 0.
+    pub fn blocking_shci_ble_init<F: FnMut()>(
+        &mut self,
+        param: &shci::ShciBleInitCmdParam,
+        feed: F,
+    ) -> Result<evt::CcEvt, shci::BleInitParamError> {
+        shci::shci_ble_init(&mut self.ipcc, param)?;
+        Ok(self.blocking_read_cc_evt(feed))
+    }
+
+    /// Sends `SHCI_C2_SET_FLASH_ACTIVITY_CONTROL` and waits for its status.
+    pub fn blocking_set_flash_activity_control<F: FnMut()>(
+        &mut self,
+        control: shci::FlashActivityControl,
+        feed: F,
+    ) -> Result<(), shci::ShciError> {
+        shci::shci_c2_set_flash_activity_control(&mut self.ipcc, control);
+        self.blocking_shci_status(feed)
+    }
+
+    /// Sends `SHCI_C2_FLASH_ERASE_ACTIVITY` and waits for its status, see
+    /// `shci::shci_c2_flash_erase_activity`.
+    pub fn blocking_flash_erase_activity<F: FnMut()>(
+        &mut self,
+        activity: shci::EraseActivity,
+        feed: F,
+    ) -> Result<(), shci::ShciError> {
+        shci::shci_c2_flash_erase_activity(&mut self.ipcc, activity);
+        self.blocking_shci_status(feed)
+    }
+
+    /// Sends `SHCI_C2_CONCURRENT_SET_MODE` and waits for its status.
+    pub fn blocking_concurrent_set_mode<F: FnMut()>(
+        &mut self,
+        mode: shci::ConcurrentMode,
+        feed: F,
+    ) -> Result<(), shci::ShciError> {
+        shci::shci_c2_concurrent_set_mode(&mut self.ipcc, mode);
+        self.blocking_shci_status(feed)
+    }
+
+    /// Same as `blocking_sys_cmd_status` with the status decoded for wireless stack commands.
+    fn blocking_shci_status<F: FnMut()>(&mut self, feed: F) -> Result<(), shci::ShciError> {
+        self.blocking_sys_cmd_status(feed).map_err(|err| match err {
+            shci::FusError::Status(status) => shci::ShciError::Status(status.into()),
+            shci::FusError::InvalidResponse => shci::ShciError::InvalidResponse,
+        })
+    }
+
+    /// Returns the firmware running on CPU2, decoded from the last ready event.
+    pub fn coprocessor_mode(&self) -> CoprocessorMode {
+        self.coprocessor_mode
+    }
+
+    /// Returns which firmware reported ready, once the IPCC RX handler received the CPU2 ready
+    /// event.
+    ///
+    /// Latched until the mailbox is reset; the ready event itself is queued like any other event.
+    pub fn cpu2_ready(&self) -> Option<Cpu2Ready> {
+        match self.coprocessor_mode {
+            CoprocessorMode::Unknown => None,
+            mode => Some(Cpu2Ready {
+                mode,
+                fw_info: self.wireless_fw_info(),
+            }),
+        }
+    }
+
+    /// Waits for the CPU2 ready event, polling the IPCC RX handler. `feed` is called on every
+    /// retry.
+    ///
+    /// Doesn't need the IPCC interrupts. Events received meanwhile, the ready event included,
+    /// stay in the event queue. Never returns if CPU2 has no firmware, see
+    /// `wait_for_cpu2_ready_timeout`.
+    pub fn wait_for_cpu2_ready<F: FnMut()>(&mut self, mut feed: F) -> Cpu2Ready {
+        loop {
+            if let Some(ready) = self.cpu2_ready() {
+                return ready;
+            }
+
+            self.rx_handler();
+            feed();
+        }
+    }
+
+    /// Same as `wait_for_cpu2_ready`, but gives up after `timeout`.
+    ///
+    /// On timeout, the error tells whether CPU2 has any firmware to run, see
+    /// `check_cpu2_presence`.
+    pub fn wait_for_cpu2_ready_timeout<T, P>(
+        &mut self,
+        timer: &mut T,
+        timeout: P,
+    ) -> Result<Cpu2Ready, Cpu2Timeout>
+    where
+        T: crate::hal::timer::CountDown,
+        P: Into<T::Time>,
+    {
+        timer.start(timeout);
+        loop {
+            if let Some(ready) = self.cpu2_ready() {
+                return Ok(ready);
+            }
+
+            self.rx_handler();
+            if timer.wait().is_ok() {
+                return Err(Cpu2Timeout {
+                    firmware: self.check_cpu2_presence(),
+                });
+            }
+        }
+    }
+
+    /// Checks which firmware CPU2 has, from the secure flash area in the option bytes. May be
+    /// called before CPU2 is started.
+    ///
+    /// FUS alone occupies the last 12 flash pages, a wireless stack is installed below it and
+    /// extends the secure area. Without a secure area (flash security disabled) CPU2 has nothing
+    /// to run. The versions are read from the device information table, which CPU2 fills when
+    /// it boots.
+    pub fn check_cpu2_presence(&self) -> Cpu2Firmware {
+        let secure_start_page = match crate::flash::write_protection().secure_start_page {
+            Some(page) => page as u32,
+            None => return Cpu2Firmware::None,
+        };
+        let pages = crate::flash::flash_size() / crate::flash::PAGE_SIZE;
+
+        if secure_start_page + FUS_SECURE_PAGES < pages {
+            Cpu2Firmware::WirelessStack(self.wireless_fw_info().map(|info| info.version()))
+        } else {
+            Cpu2Firmware::FusOnly(self.rss_info().map(|info| info.version().fw_version()))
+        }
+    }
+
+    /// Makes sure CPU2 runs the wireless stack, starting it from FUS if needed.
+    ///
+    /// CPU2 must be booted (`pwr::set_cpu2`). Waits for the ready event; if FUS is running,
+    /// sends `SHCI_FUS_START_WS` and waits for CPU2 to reset and report ready again, at most
+    /// `retries` times. Each wait for the ready event gives up after `timeout` counted by `timer`.
+    /// The shared tables stay valid across the CPU2 reset, only local mailbox state is reset.
+    /// Events received while waiting are dropped. `feed` is called on every poll.
+    pub fn ensure_wireless_stack_running<T, P, F>(
+        &mut self,
+        retries: u8,
+        timer: &mut T,
+        timeout: P,
+        mut feed: F,
+    ) -> Result<(), StartWirelessStackError>
+    where
+        T: crate::hal::timer::CountDown,
+        P: Into<T::Time> + Copy,
+        F: FnMut(),
+    {
+        let mut attempts = 0;
+
+        loop {
+            timer.start(timeout);
+            while self.coprocessor_mode == CoprocessorMode::Unknown {
+                self.rx_handler();
+                while self.dequeue_event().is_some() {}
+
+                feed();
+                if timer.wait().is_ok() {
+                    return Err(self.start_timeout());
+                }
+            }
+
+            match self.coprocessor_mode {
+                CoprocessorMode::WirelessStack => return Ok(()),
+                CoprocessorMode::Other(mode) => {
+                    return Err(StartWirelessStackError::UnknownMode(mode))
+                }
+                CoprocessorMode::Fus if attempts >= retries => {
+                    return Err(StartWirelessStackError::StillInFus { attempts })
+                }
+                CoprocessorMode::Fus | CoprocessorMode::Unknown => {}
+            }
+
+            attempts += 1;
+
+            while self.dequeue_event().is_some() {}
+            self.last_cc_evt = None;
+            self.coprocessor_mode = CoprocessorMode::Unknown;
+
+            shci::shci_c2_fus_start_ws(&mut self.ipcc);
+
+            // FUS only answers on failure, on success CPU2 resets and sends the ready event
+            timer.start(timeout);
+            while self.coprocessor_mode == CoprocessorMode::Unknown {
+                self.tx_handler();
+                if let Some(cc) = self.pop_last_cc_evt() {
+                    if cc.status() != 0 {
+                        return Err(StartWirelessStackError::FusRejected(cc.status()));
+                    }
+                }
+
+                self.rx_handler();
+                while self.dequeue_event().is_some() {}
+
+                feed();
+                if timer.wait().is_ok() {
+                    return Err(self.start_timeout());
+                }
+            }
+        }
+    }
+
+    fn start_timeout(&self) -> StartWirelessStackError {
+        StartWirelessStackError::Timeout(Cpu2Timeout {
+            firmware: self.check_cpu2_presence(),
+        })
+    }
+
+    /// Returns `true` while a system command is waiting for its response.
+    pub fn sys_cmd_busy(&self) -> bool {
+        self.ipcc
+            .c1_get_tx_channel(channels::cpu1::IPCC_SYSTEM_CMD_RSP_CHANNEL)
+    }
+
+    /// Returns `true` if no system command is waiting for its response and the event queue is
+    /// empty.
+    pub fn is_idle(&self) -> bool {
+        #[cfg(feature = "thread")]
+        {
+            if self.thread.cmd_pending() {
+                return false;
+            }
+        }
+
+        #[cfg(feature = "mac-802-15-4")]
+        {
+            if self.mac_802_15_4.cmd_pending() {
+                return false;
+            }
+        }
+
+        !self.sys_cmd_busy() && self.pending_events() == 0
+    }
+
+    /// Sends OpenThread command `id` with `args`, see `thread` module.
+    ///
+    /// The response is taken with `ot_response` once the IPCC TX handler received it.
+    #[cfg(feature = "thread")]
+    pub fn ot_send_cmd(&mut self, id: u32, args: &[u32]) -> Result<(), thread::OtError> {
+        self.thread.send_cmd(&mut self.ipcc, id, args)
+    }
+
+    /// Takes the response to the last OpenThread command.
+    #[cfg(feature = "thread")]
+    pub fn ot_response(&mut self) -> Option<thread::OtPacket> {
+        self.thread.take_response()
+    }
+
+    /// Sends OpenThread command `id` with `args` and waits for its response, polling the IPCC TX
+    /// handler. `feed` is called on every retry.
+    #[cfg(feature = "thread")]
+    pub fn blocking_ot_cmd<F: FnMut()>(
+        &mut self,
+        id: u32,
+        args: &[u32],
+        mut feed: F,
+    ) -> Result<thread::OtPacket, thread::OtError> {
+        self.ot_send_cmd(id, args)?;
+
+        loop {
+            self.tx_handler();
+            if let Some(rsp) = self.ot_response() {
+                return Ok(rsp);
+            }
+            feed();
+        }
+    }
+
+    /// Takes the oldest OpenThread notification.
+    #[cfg(feature = "thread")]
+    pub fn ot_dequeue_notification(&mut self) -> Option<thread::OtPacket> {
+        self.thread.dequeue_notification(&mut self.ipcc)
+    }
+
+    /// Sends MAC 802.15.4 command `cmd_code` with `params`, see `mac_802_15_4` module.
+    ///
+    /// The response is taken with `mac_response` once the IPCC TX handler received it.
+    #[cfg(feature = "mac-802-15-4")]
+    pub fn mac_send_cmd(
+        &mut self,
+        cmd_code: u16,
+        params: &[u8],
+    ) -> Result<(), mac_802_15_4::MacError> {
+        self.mac_802_15_4.send_cmd(&mut self.ipcc, cmd_code, params)
+    }
+
+    /// Takes the response to the last MAC 802.15.4 command.
+    #[cfg(feature = "mac-802-15-4")]
+    pub fn mac_response(&mut self) -> Option<mac_802_15_4::MacResponse> {
+        self.mac_802_15_4.take_response()
+    }
+
+    /// Takes the received MAC 802.15.4 notification, see `mac_802_15_4` module.
+    ///
+    /// The notification stays pending until it is given back to `mac_ack_notification`.
+    #[cfg(feature = "mac-802-15-4")]
+    pub fn mac_dequeue_notification(&mut self) -> Option<EvtBox> {
+        self.mac_802_15_4.dequeue_notification()
+    }
+
+    /// Acknowledges a notification taken with `mac_dequeue_notification`, CPU2 may then send
+    /// the next one.
+    #[cfg(feature = "mac-802-15-4")]
+    pub fn mac_ack_notification(&mut self, notification: EvtBox) {
+        self.mac_802_15_4
+            .ack_notification(&mut self.ipcc, notification)
+    }
+
+    /// Picks a single CPU2 trace packet, see `traces` module.
+    #[cfg(feature = "traces")]
+    pub fn dequeue_trace(&mut self) -> Option<traces::TraceBox> {
+        self.traces.dequeue()
+    }
+
+    /// Returns the number of trace packets dropped because the trace queue was full.
+    #[cfg(feature = "traces")]
+    pub fn dropped_traces(&self) -> u32 {
+        self.traces.dropped()
+    }
+
+    /// Passes all queued CPU2 trace packets to `sink` as length-prefixed frames, see `traces`
+    /// module. Returns the number of frames the sink took.
+    ///
+    /// `sink` returns `false` if it can't take the whole frame, which is then dropped.
+    #[cfg(feature = "traces")]
+    pub fn forward_traces<S: FnMut(&[u8]) -> bool>(&mut self, sink: S) -> usize {
+        self.traces.forward_to(sink)
+    }
+
+    /// Returns the number of trace packets dropped because the `forward_traces` sink refused
+    /// them.
+    #[cfg(feature = "traces")]
+    pub fn dropped_forwarded_traces(&self) -> u32 {
+        self.traces.sink_dropped()
+    }
+
+    /// Returns the number of events dropped because the event queue was full.
+    ///
+    /// Saturates at `u32::MAX`, not reset by `clear_stats`.
+    pub fn dropped_events(&self) -> u32 {
+        self.dropped_events
+    }
+
+    /// Returns mailbox traffic counters.
+    pub fn stats(&self) -> MboxStats {
+        self.stats.with_shared_counters()
+    }
+
+    /// Resets mailbox traffic counters.
+    pub fn clear_stats(&mut self) {
+        self.stats = MboxStats::default();
+        CMDS_SENT.store(0, Ordering::Relaxed);
+        mm::clear_released_buffers();
+    }
+
+    /// Debug facility: sends a system command with `send` and attaches `tag` to it.
+    ///
+    /// The tag is returned together with the response by `pop_tagged_cc_evt`, which allows
+    /// correlating commands and responses in stress tests. Returns `WouldBlock` while another
+    /// system command is in flight.
+    pub fn tagged_cmd<F: FnOnce(&mut crate::ipcc::Ipcc)>(
+        &mut self,
+        tag: u32,
+        send: F,
+    ) -> nb::Result<(), Void> {
+        if self.sys_cmd_busy() {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        self.pending_tag = Some(tag);
+        send(&mut self.ipcc);
+
+        Ok(())
+    }
+
+    /// Retrieves last Command Complete event together with the tag of its command, if the command
+    /// was sent with `tagged_cmd`.
+    ///
+    /// Counts a tag mismatch if the response doesn't carry `expected_tag`.
+    pub fn pop_tagged_cc_evt(&mut self, expected_tag: u32) -> Option<(Option<u32>, evt::CcEvt)> {
+        let evt = self.pop_last_cc_evt()?;
+        let tag = self.last_cc_tag.take();
+
+        if tag != Some(expected_tag) {
+            self.stats.tag_mismatches = self.stats.tag_mismatches.wrapping_add(1);
+        }
+
+        Some((tag, evt))
+    }
+
+    /// Returns the response to the last system command, including its event code, echoed
+    /// opcode and return parameters.
+    ///
+    /// Unlike `pop_last_cc_evt`, the response stays available until the next one arrives.
+    pub fn last_sys_command_response(&self) -> Option<&sys::SysCmdResponse> {
+        self.last_sys_response.as_ref()
+    }
+
+    /// Returns the first error CPU2 reported since the mailbox was initialized or reset.
+    ///
+    /// The error events are also delivered like any other event. CPU2 doesn't recover on its
+    /// own: log the error and restart CPU2, e.g. with `reset` after a CPU2 reset, rather than
+    /// waiting for responses that won't come.
+    pub fn cpu2_error(&self) -> Option<Cpu2Error> {
+        self.cpu2_error
+    }
+
+    /// Returns the last Command Status event of a BLE command.
+    ///
+    /// The event is also delivered like any other BLE event; `CsEvt::result` tells whether the
+    /// controller rejected the command. It stays available until the next one arrives.
+    pub fn last_command_status(&self) -> Option<evt::CsEvt> {
+        self.last_cs_evt
     }
 
     /// Retrieves last Command Complete event and removes it from mailbox.
@@ -376,4 +2095,621 @@ impl TlMbox {
             Some(evt)
         })
     }
+
+    /// Creates a subscription to events matching `filter`.
+    ///
+    /// While any subscription exists, events are delivered to subscriptions only and
+    /// `dequeue_event` returns `None`. Requires the `evt-fanout` feature.
+    #[cfg(feature = "evt-fanout")]
+    pub fn subscribe(
+        &mut self,
+        filter: fanout::EvtFilter,
+    ) -> Result<fanout::EvtSubscription, fanout::SubscribeError> {
+        self.fanout.subscribe(filter)
+    }
+
+    /// Removes a subscription. Events still in its queue are discarded.
+    #[cfg(feature = "evt-fanout")]
+    pub fn unsubscribe(&mut self, sub: fanout::EvtSubscription) {
+        self.fanout.unsubscribe(sub)
+    }
+
+    /// Takes the oldest event from the queue of subscription `sub`.
+    #[cfg(feature = "evt-fanout")]
+    pub fn dequeue_subscribed(
+        &mut self,
+        sub: &fanout::EvtSubscription,
+    ) -> Option<fanout::SubscribedEvt> {
+        self.fanout.dequeue(sub)
+    }
+
+    /// Returns the number of events dropped for subscription `sub` because its queue was full.
+    #[cfg(feature = "evt-fanout")]
+    pub fn subscription_dropped(&self, sub: &fanout::EvtSubscription) -> u32 {
+        self.fanout.dropped(sub)
+    }
+}
+
+impl InterruptHandler<irq::IPCC_C1_RX_IT> for TlMbox {
+    fn on_interrupt(&mut self) {
+        self.interrupt_ipcc_rx_handler();
+    }
+}
+
+impl InterruptHandler<irq::IPCC_C1_TX_IT> for TlMbox {
+    fn on_interrupt(&mut self) {
+        self.interrupt_ipcc_tx_handler();
+    }
+}
+
+/// Returns the 1-based number of an IPCC channel.
+#[cfg(feature = "thread")]
+fn channel_number(channel: crate::ipcc::IpccChannel) -> u8 {
+    (channel as u32).trailing_zeros() as u8 + 1
+}
+
+/// Reads the wireless firmware information table, shared by `TlMbox` and `split::MboxClient`.
+fn wireless_fw_info() -> Option<WirelessFwInfoTable> {
+    let info = unsafe { &(*(*TL_REF_TABLE.as_ptr()).device_info_table).wireless_fw_info_table };
+
+    // Zero version indicates that CPU2 wasn't active and didn't fill the information table
+    if info.version != 0 {
+        Some(info.clone())
+    } else {
+        None
+    }
+}
+
+/// Populates the reference table and zeroes the shared tables and buffers.
+fn init_shared_tables() {
+    // Populate reference table with pointers in the shared memory
+    unsafe {
+        #[cfg(feature = "thread")]
+        let thread_table = TL_THREAD_TABLE.as_ptr();
+        #[cfg(not(feature = "thread"))]
+        let thread_table = core::ptr::null();
+
+        #[cfg(feature = "traces")]
+        let traces_table = TL_TRACES_TABLE.as_ptr();
+        #[cfg(not(feature = "traces"))]
+        let traces_table = core::ptr::null();
+
+        #[cfg(feature = "mac-802-15-4")]
+        let mac_802_15_4_table = TL_MAC_802_15_4_TABLE.as_ptr();
+        #[cfg(not(feature = "mac-802-15-4"))]
+        let mac_802_15_4_table = core::ptr::null();
+
+        TL_REF_TABLE = MaybeUninit::new(RefTable {
+            device_info_table: TL_DEVICE_INFO_TABLE.as_ptr(),
+            ble_table: TL_BLE_TABLE.as_ptr(),
+            thread_table,
+            sys_table: TL_SYS_TABLE.as_ptr(),
+            mem_manager_table: TL_MEM_MANAGER_TABLE.as_ptr(),
+            traces_table,
+            mac_802_15_4_table,
+        });
+
+        TL_SYS_TABLE = MaybeUninit::zeroed();
+        TL_DEVICE_INFO_TABLE = MaybeUninit::zeroed();
+        TL_BLE_TABLE = MaybeUninit::zeroed();
+        #[cfg(feature = "thread")]
+        {
+            TL_THREAD_TABLE = MaybeUninit::zeroed();
+        }
+        TL_MEM_MANAGER_TABLE = MaybeUninit::zeroed();
+        #[cfg(feature = "traces")]
+        {
+            TL_TRACES_TABLE = MaybeUninit::zeroed();
+        }
+        #[cfg(feature = "mac-802-15-4")]
+        {
+            TL_MAC_802_15_4_TABLE = MaybeUninit::zeroed();
+        }
+
+        EVT_POOL = MaybeUninit::zeroed();
+        SYS_SPARE_EVT_BUF = MaybeUninit::zeroed();
+        BLE_SPARE_EVT_BUF = MaybeUninit::zeroed();
+
+        CS_BUFFER = MaybeUninit::zeroed();
+        BLE_CMD_BUFFER = MaybeUninit::zeroed();
+        HCI_ACL_DATA_BUFFER = MaybeUninit::zeroed();
+    }
+}
+
+/// Returns how many events the next drain may take from shared memory into `queue` under the
+/// `overflow` policy.
+fn drain_limit<T, Q: EvtSink<T>>(queue: &Q, overflow: EvtQueueOverflow) -> usize {
+    // Events that don't fit stay in shared memory when deferring
+    match overflow {
+        EvtQueueOverflow::Defer => queue.capacity() - queue.len(),
+        _ => usize::MAX,
+    }
+}
+
+/// IPCC action on a CPU2 event channel, see `EvtQueueOverflow::Defer`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum RxChannelAction {
+    /// Clear the channel flag, all events were taken and CPU2 may send more.
+    Clear,
+    /// Keep the flag set and mask the channel, events are left in shared memory until the event
+    /// queue has room.
+    Mask,
+    /// Unmask the channel, its flag is still set so the RX interrupt fires again right away.
+    Unmask,
+    /// Leave the channel as is.
+    Keep,
+}
+
+impl RxChannelAction {
+    /// Returns the action after a drain, `drained` if no events were left in shared memory.
+    pub(crate) fn after_drain(drained: bool) -> Self {
+        if drained {
+            RxChannelAction::Clear
+        } else {
+            RxChannelAction::Mask
+        }
+    }
+
+    /// Returns the action for a channel whose drain may have been `deferred` once the event
+    /// queue has `room` for more events.
+    pub(crate) fn resume(deferred: bool, room: usize) -> Self {
+        if deferred && room > 0 {
+            RxChannelAction::Unmask
+        } else {
+            RxChannelAction::Keep
+        }
+    }
+
+    pub(crate) fn apply(self, ipcc: &mut crate::ipcc::Ipcc, channel: crate::ipcc::IpccChannel) {
+        match self {
+            RxChannelAction::Clear => ipcc.c1_clear_flag_channel(channel),
+            RxChannelAction::Mask => ipcc.c1_set_rx_channel(channel, false),
+            RxChannelAction::Unmask => ipcc.c1_set_rx_channel(channel, true),
+            RxChannelAction::Keep => {}
+        }
+    }
+}
+
+/// Puts `evt` into `queue`, making room according to `overflow` if it's full.
+///
+/// `DropOldest` behaves like `DropNewest` if `queue` can't dequeue, i.e. is the producer of a
+/// split queue.
+fn enqueue_evt<T, Q: EvtSink<T>>(
+    queue: &mut Q,
+    evt: T,
+    overflow: EvtQueueOverflow,
+    enqueued: &mut u32,
+    dropped: &mut u32,
+) {
+    let evt = match queue.enqueue(evt) {
+        Ok(()) => {
+            *enqueued += 1;
+            return;
+        }
+        Err(evt) => evt,
+    };
+
+    *dropped += 1;
+
+    if let EvtQueueOverflow::DropOldest = overflow {
+        // Dropping the oldest event releases its shared memory buffer
+        if let Some(oldest) = queue.dequeue_oldest() {
+            drop(oldest);
+            if queue.enqueue(evt).is_ok() {
+                *enqueued += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fw_info(version: u32, memory_size: u32, info_stack: u32) -> WirelessFwInfoTable {
+        WirelessFwInfoTable {
+            version,
+            memory_size,
+            thread_info: info_stack,
+            ble_info: 0,
+        }
+    }
+
+    fn version_of(version: u32) -> (u8, u8, u8) {
+        let info = fw_info(version, 0, 0);
+        (
+            info.version_major(),
+            info.version_minor(),
+            info.subversion(),
+        )
+    }
+
+    fn sizes_of(memory_size: u32) -> (u8, u8, u8) {
+        let info = fw_info(0, memory_size, 0);
+        (info.flash_size(), info.sram2a_size(), info.sram2b_size())
+    }
+
+    #[test]
+    fn fw_info_version_fields() {
+        assert_eq!(version_of(0x0000_0000), (0, 0, 0));
+        assert_eq!(version_of(0xff00_0000), (0xff, 0, 0));
+        assert_eq!(version_of(0x00ff_0000), (0, 0xff, 0));
+        assert_eq!(version_of(0x0000_ff00), (0, 0, 0xff));
+        assert_eq!(version_of(0xffff_ffff), (0xff, 0xff, 0xff));
+
+        // Top bit of every field
+        assert_eq!(version_of(0x8000_0000), (0x80, 0, 0));
+        assert_eq!(version_of(0x0080_0000), (0, 0x80, 0));
+        assert_eq!(version_of(0x0000_8000), (0, 0, 0x80));
+
+        // Branch and build aren't part of the version
+        assert_eq!(version_of(0x0000_00ff), (0, 0, 0));
+        assert_eq!(version_of(0x010d_0250), (1, 13, 2));
+    }
+
+    #[test]
+    fn fw_info_memory_size_fields() {
+        assert_eq!(sizes_of(0x0000_00ff), (0xff, 0, 0));
+        assert_eq!(sizes_of(0xff00_0000), (0, 0xff, 0));
+        assert_eq!(sizes_of(0x00ff_0000), (0, 0, 0xff));
+        assert_eq!(sizes_of(0x8080_0080), (0x80, 0x80, 0x80));
+
+        // Reserved byte doesn't leak into the sizes
+        assert_eq!(sizes_of(0x0000_ff00), (0, 0, 0));
+        assert_eq!(sizes_of(0xffff_ffff), (0xff, 0xff, 0xff));
+    }
+
+    #[test]
+    fn fw_info_version_comparison() {
+        let info = fw_info(0x010d_0200, 0, 0);
+
+        assert_eq!(info.version(), FwVersion::from((1, 13, 2)));
+        assert!(info.meets((1, 13, 2)));
+        assert!(info.meets((1, 12, 0xff)));
+        assert!(!info.meets((1, 13, 3)));
+        assert!(!info.meets((2, 0, 0)));
+        assert!(fw_info(0xff00_0000, 0, 0).meets((0x80, 0, 0)));
+    }
+
+    #[test]
+    fn fw_info_stack_type() {
+        assert_eq!(fw_info(0, 0, 0x01).stack_type(), StackType::BleFull);
+        assert_eq!(
+            fw_info(0, 0, 0x50).stack_type(),
+            StackType::BleThreadFtdStatic
+        );
+        assert_eq!(
+            fw_info(0, 0, 0xffff_ff10).stack_type(),
+            StackType::ThreadFtd
+        );
+        assert_eq!(fw_info(0, 0, 0xff).stack_type(), StackType::Other(0xff));
+    }
+
+    fn version_info(version: u32) -> (u8, u8, u8, u8, u8) {
+        let v = VersionInfo::from(version);
+        (v.major, v.minor, v.sub, v.branch, v.build)
+    }
+
+    #[test]
+    fn version_info_fields() {
+        assert_eq!(version_info(0x0000_0000), (0, 0, 0, 0, 0));
+        assert_eq!(version_info(0xffff_ffff), (0xff, 0xff, 0xff, 0xf, 0xf));
+        assert_eq!(version_info(0xff00_0000), (0xff, 0, 0, 0, 0));
+        assert_eq!(version_info(0x00ff_0000), (0, 0xff, 0, 0, 0));
+        assert_eq!(version_info(0x0000_ff00), (0, 0, 0xff, 0, 0));
+        assert_eq!(version_info(0x0000_00f0), (0, 0, 0, 0xf, 0));
+        assert_eq!(version_info(0x0000_000f), (0, 0, 0, 0, 0xf));
+
+        // Branch and build share a byte
+        assert_eq!(version_info(0x0000_0010), (0, 0, 0, 1, 0));
+        assert_eq!(version_info(0x0000_0008), (0, 0, 0, 0, 8));
+
+        // FUS 1.2.0, released
+        assert_eq!(version_info(0x0102_000f), (1, 2, 0, 0, 15));
+        assert_eq!(
+            VersionInfo::from(0x0102_00ff).fw_version(),
+            FwVersion::from((1, 2, 0))
+        );
+    }
+
+    #[test]
+    fn safe_boot_info_version() {
+        let info = SafeBootInfoTable {
+            version: 0x0103_0a5f,
+        };
+
+        assert_eq!(
+            info.version(),
+            VersionInfo {
+                major: 1,
+                minor: 3,
+                sub: 0x0a,
+                branch: 5,
+                build: 0xf,
+            }
+        );
+    }
+
+    #[test]
+    fn rss_info_fields() {
+        let info = RssInfoTable {
+            version: 0x0102_000f,
+            memory_size: 0x0810_ff44,
+            rss_info: 0xdead_beef,
+        };
+
+        assert_eq!(info.version(), VersionInfo::from(0x0102_000f));
+        assert_eq!(info.flash_size(), 0x44);
+        assert_eq!(info.sram2a_size(), 0x08);
+        assert_eq!(info.sram2b_size(), 0x10);
+        assert_eq!(info.info(), 0xdead_beef);
+
+        // Same layout as the wireless table
+        let wireless = fw_info(0x0102_000f, 0x0810_ff44, 0);
+        assert_eq!(
+            (info.flash_size(), info.sram2a_size(), info.sram2b_size()),
+            (
+                wireless.flash_size(),
+                wireless.sram2a_size(),
+                wireless.sram2b_size()
+            )
+        );
+        assert_eq!(info.version().fw_version(), wireless.version());
+    }
+
+    type TestQueue = spsc::Queue<u32, heapless::consts::U4, u8, spsc::MultiCore>;
+
+    fn full_queue() -> TestQueue {
+        let mut queue = spsc::Queue::u8();
+        for evt in 0..4 {
+            queue.enqueue(evt).unwrap();
+        }
+        queue
+    }
+
+    fn contents(queue: &mut TestQueue) -> heapless::Vec<u32, heapless::consts::U4> {
+        let mut evts = heapless::Vec::new();
+        while let Some(evt) = queue.dequeue() {
+            evts.push(evt).unwrap();
+        }
+        evts
+    }
+
+    #[test]
+    fn drain_limit_defer_leaves_room() {
+        let mut queue = TestQueue::u8();
+        assert_eq!(drain_limit(&queue, EvtQueueOverflow::Defer), 4);
+
+        queue.enqueue(0).unwrap();
+        assert_eq!(drain_limit(&queue, EvtQueueOverflow::Defer), 3);
+
+        let queue = full_queue();
+        assert_eq!(drain_limit(&queue, EvtQueueOverflow::Defer), 0);
+        assert_eq!(
+            drain_limit(&queue, EvtQueueOverflow::DropNewest),
+            usize::MAX
+        );
+        assert_eq!(
+            drain_limit(&queue, EvtQueueOverflow::DropOldest),
+            usize::MAX
+        );
+    }
+
+    #[test]
+    fn enqueue_with_room() {
+        let mut queue = TestQueue::u8();
+        let (mut enqueued, mut dropped) = (0, 0);
+
+        for &overflow in &[
+            EvtQueueOverflow::Defer,
+            EvtQueueOverflow::DropNewest,
+            EvtQueueOverflow::DropOldest,
+        ] {
+            enqueue_evt(&mut queue, 7, overflow, &mut enqueued, &mut dropped);
+        }
+
+        assert_eq!((enqueued, dropped), (3, 0));
+        assert_eq!(&contents(&mut queue)[..], &[7, 7, 7]);
+    }
+
+    #[test]
+    fn full_queue_drop_newest() {
+        let mut queue = full_queue();
+        let (mut enqueued, mut dropped) = (0, 0);
+
+        enqueue_evt(
+            &mut queue,
+            4,
+            EvtQueueOverflow::DropNewest,
+            &mut enqueued,
+            &mut dropped,
+        );
+        enqueue_evt(
+            &mut queue,
+            5,
+            EvtQueueOverflow::DropNewest,
+            &mut enqueued,
+            &mut dropped,
+        );
+
+        assert_eq!((enqueued, dropped), (0, 2));
+        assert_eq!(&contents(&mut queue)[..], &[0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn full_queue_drop_oldest() {
+        let mut queue = full_queue();
+        let (mut enqueued, mut dropped) = (0, 0);
+
+        enqueue_evt(
+            &mut queue,
+            4,
+            EvtQueueOverflow::DropOldest,
+            &mut enqueued,
+            &mut dropped,
+        );
+        enqueue_evt(
+            &mut queue,
+            5,
+            EvtQueueOverflow::DropOldest,
+            &mut enqueued,
+            &mut dropped,
+        );
+
+        assert_eq!((enqueued, dropped), (2, 2));
+        assert_eq!(&contents(&mut queue)[..], &[2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn full_queue_defer() {
+        // Drains never hand more events than `drain_limit` to a deferring queue, so nothing is
+        // lost until the queue is read
+        let mut queue = full_queue();
+        let (mut enqueued, mut dropped) = (0, 0);
+        let mut pending = 4..8;
+
+        let limit = drain_limit(&queue, EvtQueueOverflow::Defer);
+        for evt in pending.by_ref().take(limit) {
+            enqueue_evt(
+                &mut queue,
+                evt,
+                EvtQueueOverflow::Defer,
+                &mut enqueued,
+                &mut dropped,
+            );
+        }
+        assert_eq!((enqueued, dropped), (0, 0));
+
+        // Reading two events makes room for two deferred ones
+        assert_eq!(queue.dequeue(), Some(0));
+        assert_eq!(queue.dequeue(), Some(1));
+
+        let limit = drain_limit(&queue, EvtQueueOverflow::Defer);
+        assert_eq!(limit, 2);
+        for evt in pending.by_ref().take(limit) {
+            enqueue_evt(
+                &mut queue,
+                evt,
+                EvtQueueOverflow::Defer,
+                &mut enqueued,
+                &mut dropped,
+            );
+        }
+
+        assert_eq!((enqueued, dropped), (2, 0));
+        assert_eq!(pending.start, 6);
+        assert_eq!(&contents(&mut queue)[..], &[2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn full_queue_defer_overrun_drops_newest() {
+        // Without the drain limit a deferring queue behaves like `DropNewest`
+        let mut queue = full_queue();
+        let (mut enqueued, mut dropped) = (0, 0);
+
+        enqueue_evt(
+            &mut queue,
+            4,
+            EvtQueueOverflow::Defer,
+            &mut enqueued,
+            &mut dropped,
+        );
+
+        assert_eq!((enqueued, dropped), (0, 1));
+        assert_eq!(&contents(&mut queue)[..], &[0, 1, 2, 3]);
+    }
+
+    /// Flag and mask of an event channel, set by CPU2 when it sends events.
+    struct MockChannel {
+        flag: bool,
+        unmasked: bool,
+    }
+
+    impl MockChannel {
+        fn apply(&mut self, action: RxChannelAction) {
+            match action {
+                RxChannelAction::Clear => self.flag = false,
+                RxChannelAction::Mask => self.unmasked = false,
+                RxChannelAction::Unmask => self.unmasked = true,
+                RxChannelAction::Keep => {}
+            }
+        }
+
+        fn irq_pending(&self) -> bool {
+            self.flag && self.unmasked
+        }
+    }
+
+    fn room(queue: &TestQueue) -> usize {
+        queue.capacity() as usize - queue.len()
+    }
+
+    /// Drains `shmem` into `queue` like the event handlers, returns `true` if it was drained.
+    fn defer_drain(
+        queue: &mut TestQueue,
+        shmem: &mut core::ops::Range<u32>,
+        channel: &mut MockChannel,
+    ) -> bool {
+        let (mut enqueued, mut dropped) = (0, 0);
+        let limit = drain_limit(queue, EvtQueueOverflow::Defer);
+        for evt in shmem.by_ref().take(limit) {
+            enqueue_evt(
+                queue,
+                evt,
+                EvtQueueOverflow::Defer,
+                &mut enqueued,
+                &mut dropped,
+            );
+        }
+        assert_eq!(dropped, 0);
+
+        let drained = shmem.start == shmem.end;
+        channel.apply(RxChannelAction::after_drain(drained));
+        drained
+    }
+
+    #[test]
+    fn rx_channel_actions() {
+        assert_eq!(RxChannelAction::after_drain(true), RxChannelAction::Clear);
+        assert_eq!(RxChannelAction::after_drain(false), RxChannelAction::Mask);
+        assert_eq!(RxChannelAction::resume(true, 1), RxChannelAction::Unmask);
+        assert_eq!(RxChannelAction::resume(true, 0), RxChannelAction::Keep);
+        assert_eq!(RxChannelAction::resume(false, 4), RxChannelAction::Keep);
+    }
+
+    #[test]
+    fn defer_masks_channel_until_queue_drains() {
+        let mut queue = full_queue();
+        let mut shmem = 4..7;
+        let mut channel = MockChannel {
+            flag: true,
+            unmasked: true,
+        };
+
+        // Full queue: the events stay in shared memory, the flag stays set and is masked
+        assert!(!defer_drain(&mut queue, &mut shmem, &mut channel));
+        assert!(channel.flag && !channel.unmasked);
+        assert!(!channel.irq_pending());
+
+        // Without room nothing is unmasked
+        channel.apply(RxChannelAction::resume(true, room(&queue)));
+        assert!(!channel.irq_pending());
+
+        // Reading one event unmasks the channel, the set flag raises the interrupt again
+        assert_eq!(queue.dequeue(), Some(0));
+        channel.apply(RxChannelAction::resume(true, room(&queue)));
+        assert!(channel.irq_pending());
+
+        // Only one event fits, the rest is deferred again
+        assert!(!defer_drain(&mut queue, &mut shmem, &mut channel));
+        assert!(channel.flag && !channel.unmasked);
+        assert_eq!(shmem.start, 5);
+
+        // Once the queue has room for the rest, the flag is cleared and the channel unmasked
+        assert_eq!(&contents(&mut queue)[..], &[1, 2, 3, 4]);
+        channel.apply(RxChannelAction::resume(true, room(&queue)));
+        assert!(defer_drain(&mut queue, &mut shmem, &mut channel));
+        assert!(!channel.flag && channel.unmasked);
+        assert_eq!(&contents(&mut queue)[..], &[5, 6]);
+    }
 }
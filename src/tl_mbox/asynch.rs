@@ -0,0 +1,143 @@
+//! Event and system command futures
+//!
+//! Requires the `async` feature. The IPCC handlers wake the tasks waiting in `next_event` and
+//! `send_sys_cmd`, so an async executor doesn't have to poll `dequeue_event`.
+//!
+//! The futures borrow the `SharedMbox` static the IPCC interrupts are bound to, not the mailbox
+//! itself, and only lock it while polled. The interrupt handlers can reach the mailbox in
+//! between to fill the event queue and wake the task:
+//!
+//! ```ignore
+//! static MBOX: SharedMbox = Mutex::new(RefCell::new(None));
+//!
+//! bind_interrupt!(IPCC_C1_RX_IT => MBOX);
+//! bind_interrupt!(IPCC_C1_TX_IT => MBOX);
+//!
+//! loop {
+//!     let evt = asynch::next_event(&MBOX).await;
+//!     // handle `evt`
+//! }
+//! ```
+//!
+//! No executor is required: the futures only register the waker of the task polling them. The
+//! synchronous API is unchanged.
+
+#![cfg(feature = "async")]
+
+use core::cell::RefCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+use cortex_m::interrupt::Mutex;
+
+use crate::ipcc::Ipcc;
+use crate::tl_mbox::evt::{CcEvt, EvtBox};
+use crate::tl_mbox::TlMbox;
+
+/// Mailbox shared with the IPCC interrupt handlers, see `bind_interrupt!`.
+pub type SharedMbox = Mutex<RefCell<Option<TlMbox>>>;
+
+/// Task waiting in `NextEvent`
+static EVT_WAKER: Mutex<RefCell<Option<Waker>>> = Mutex::new(RefCell::new(None));
+/// Task waiting in `SysCmdComplete`
+static CC_WAKER: Mutex<RefCell<Option<Waker>>> = Mutex::new(RefCell::new(None));
+
+/// Returns a future resolving to the next event of the event queue of `mbox`.
+///
+/// # Panics
+///
+/// When polled, if `mbox` holds no mailbox.
+pub fn next_event(mbox: &SharedMbox) -> NextEvent<'_> {
+    NextEvent { mbox }
+}
+
+/// Sends a system command with `send`, e.g. `|ipcc| shci::shci_ble_init(ipcc, param)`, and
+/// returns a future resolving to its Command Complete event.
+///
+/// The command is sent right away, not when the future is polled first. Must not be called
+/// while another system command is in flight, see `TlMbox::sys_cmd_busy`.
+///
+/// # Panics
+///
+/// If `mbox` holds no mailbox, also when the future is polled.
+pub fn send_sys_cmd<F: FnOnce(&mut Ipcc)>(mbox: &SharedMbox, send: F) -> SysCmdComplete<'_> {
+    with_mbox(mbox, |mbox| {
+        // Drop a response nobody took, it would resolve the future right away
+        mbox.last_cc_evt = None;
+        send(&mut mbox.ipcc);
+    });
+
+    SysCmdComplete { mbox }
+}
+
+/// Future returned by `next_event`.
+pub struct NextEvent<'a> {
+    mbox: &'a SharedMbox,
+}
+
+impl<'a> Future for NextEvent<'a> {
+    type Output = EvtBox;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<EvtBox> {
+        register(&EVT_WAKER, cx.waker());
+
+        // Registered before checking, an event queued in between wakes the task again
+        match with_mbox(self.mbox, |mbox| mbox.dequeue_event()) {
+            Some(evt) => Poll::Ready(evt),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Future returned by `send_sys_cmd`.
+pub struct SysCmdComplete<'a> {
+    mbox: &'a SharedMbox,
+}
+
+impl<'a> Future for SysCmdComplete<'a> {
+    type Output = CcEvt;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<CcEvt> {
+        register(&CC_WAKER, cx.waker());
+
+        match with_mbox(self.mbox, |mbox| mbox.pop_last_cc_evt()) {
+            Some(cc) => Poll::Ready(cc),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Calls `f` with the mailbox, locked against the IPCC interrupt handlers.
+fn with_mbox<R, F: FnOnce(&mut TlMbox) -> R>(mbox: &SharedMbox, f: F) -> R {
+    cortex_m::interrupt::free(|cs| {
+        let mut mbox = mbox.borrow(cs).borrow_mut();
+        f(mbox.as_mut().expect("mailbox not initialized"))
+    })
+}
+
+fn register(slot: &Mutex<RefCell<Option<Waker>>>, waker: &Waker) {
+    cortex_m::interrupt::free(|cs| {
+        let mut slot = slot.borrow(cs).borrow_mut();
+        match slot.as_ref() {
+            Some(registered) if registered.will_wake(waker) => {}
+            _ => *slot = Some(waker.clone()),
+        }
+    });
+}
+
+fn wake(slot: &Mutex<RefCell<Option<Waker>>>) {
+    if let Some(waker) = cortex_m::interrupt::free(|cs| slot.borrow(cs).borrow_mut().take()) {
+        waker.wake();
+    }
+}
+
+/// Wakes the task waiting for an event, called by the IPCC RX handler.
+pub(super) fn wake_evt() {
+    wake(&EVT_WAKER);
+}
+
+/// Wakes the task waiting for a system command response, called by the IPCC TX handler.
+pub(super) fn wake_cc() {
+    wake(&CC_WAKER);
+}
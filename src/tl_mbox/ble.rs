@@ -1,18 +1,172 @@
 use crate::ipcc::Ipcc;
 use crate::tl_mbox::channels;
-use crate::tl_mbox::cmd::{CmdPacket, CmdSerial};
+use crate::tl_mbox::cmd::{AclDataSerial, CmdPacket, CmdSerial};
 use crate::tl_mbox::consts::TlPacketType;
 use crate::tl_mbox::evt::EvtBox;
-use crate::tl_mbox::unsafe_linked_list::{
-    LST_init_head, LST_is_empty, LST_remove_head, LinkedListNode,
-};
+use crate::tl_mbox::unsafe_linked_list::{LST_init_head, ShmemList};
 use crate::tl_mbox::{
-    evt, BleTable, HeaplessEvtQueue, BLE_CMD_BUFFER, CS_BUFFER, EVT_QUEUE, HCI_ACL_DATA_BUFFER,
+    evt, BleTable, RxChannelAction, BLE_CMD_BUFFER, CS_BUFFER, EVT_QUEUE, HCI_ACL_DATA_BUFFER,
     TL_BLE_TABLE, TL_REF_TABLE,
 };
 use core::mem::MaybeUninit;
 
-pub struct Ble {}
+pub mod connection;
+
+/// Maximum number of connections tracked by `AclFlowControl`.
+pub const ACL_MAX_CONNECTIONS: usize = 8;
+
+/// Maximum ACL data payload that fits into the shared ACL data buffer.
+pub const ACL_MAX_PAYLOAD: usize = 251;
+
+// HCI event codes and opcodes used for ACL flow control
+const HCI_DISCONNECTION_COMPLETE_EVT: u8 = 0x05;
+pub(super) const HCI_COMMAND_COMPLETE_EVT: u8 = 0x0e;
+const HCI_COMMAND_STATUS_EVT: u8 = 0x0f;
+const HCI_HARDWARE_ERROR_EVT: u8 = 0x10;
+const HCI_NUMBER_OF_COMPLETED_PACKETS_EVT: u8 = 0x13;
+const HCI_LE_READ_BUFFER_SIZE_OPCODE: u16 = 0x2002;
+
+/// Packet boundary flag of an ACL data packet.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AclPbFlag {
+    /// First packet of a non-automatically-flushable L2CAP PDU
+    FirstNonFlushable = 0b00,
+    /// Continuing fragment of an L2CAP PDU
+    Continuing = 0b01,
+    /// First packet of an automatically flushable L2CAP PDU
+    FirstFlushable = 0b10,
+}
+
+/// ACL data error
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AclError {
+    /// Payload doesn't fit into the shared ACL data buffer.
+    PayloadTooLarge,
+    /// More than `ACL_MAX_CONNECTIONS` connections have packets in flight.
+    TooManyConnections,
+    /// No ACL buffer became free in time, see `TlMbox::blocking_send_acl_data_timeout`.
+    Timeout,
+}
+
+/// Host side ACL flow control.
+///
+/// The controller buffer count is learned from the `LE Read Buffer Size` command complete event,
+/// credits are returned by `Number Of Completed Packets` and `Disconnection Complete` events.
+/// Events are inspected as they pass through the mailbox and are still delivered to the
+/// application. Until the buffer count is known, sending is not limited.
+#[derive(Debug, Copy, Clone)]
+pub struct AclFlowControl {
+    total: Option<u8>,
+    /// `(connection handle, packets in flight)`, slots with zero packets are free
+    in_flight: [(u16, u16); ACL_MAX_CONNECTIONS],
+}
+
+impl AclFlowControl {
+    fn new() -> Self {
+        AclFlowControl {
+            total: None,
+            in_flight: [(0, 0); ACL_MAX_CONNECTIONS],
+        }
+    }
+
+    /// Returns total number of controller ACL buffers, if already known.
+    pub fn total_buffers(&self) -> Option<u8> {
+        self.total
+    }
+
+    /// Returns number of free controller ACL buffers, if the buffer count is already known.
+    pub fn available(&self) -> Option<u16> {
+        let used: u16 = self.in_flight.iter().map(|(_, count)| *count).sum();
+        self.total.map(|total| (total as u16).saturating_sub(used))
+    }
+
+    /// Returns number of packets sent on the connection and not yet completed by the controller.
+    pub fn in_flight(&self, handle: u16) -> u16 {
+        self.in_flight
+            .iter()
+            .find(|(h, count)| *count != 0 && *h == handle)
+            .map_or(0, |(_, count)| *count)
+    }
+
+    fn has_credit(&self) -> bool {
+        self.available().map_or(true, |available| available > 0)
+    }
+
+    fn on_sent(&mut self, handle: u16) -> Result<(), AclError> {
+        let slot = match self
+            .in_flight
+            .iter()
+            .position(|(h, count)| *count != 0 && *h == handle)
+        {
+            Some(i) => i,
+            None => self
+                .in_flight
+                .iter()
+                .position(|(_, count)| *count == 0)
+                .ok_or(AclError::TooManyConnections)?,
+        };
+
+        self.in_flight[slot] = (handle, self.in_flight[slot].1 + 1);
+        Ok(())
+    }
+
+    fn on_completed(&mut self, handle: u16, completed: u16) {
+        for (h, count) in self.in_flight.iter_mut() {
+            if *count != 0 && *h == handle {
+                *count = count.saturating_sub(completed);
+            }
+        }
+    }
+
+    fn on_event(&mut self, event: *const evt::EvtPacket) {
+        let (evt_code, payload) = unsafe {
+            if (*event).evt_serial.kind != TlPacketType::BleEvt as u8 {
+                return;
+            }
+
+            let evt = &(*event).evt_serial.evt;
+            (
+                evt.evt_code,
+                core::slice::from_raw_parts(evt.payload.as_ptr(), evt.payload_len as usize),
+            )
+        };
+
+        let u16_at = |i: usize| u16::from_le_bytes([payload[i], payload[i + 1]]);
+
+        match evt_code {
+            // num_cmd, opcode, status, ACL data length (2), total ACL packets
+            HCI_COMMAND_COMPLETE_EVT if payload.len() >= 7 => {
+                if u16_at(1) == HCI_LE_READ_BUFFER_SIZE_OPCODE && payload[3] == 0 {
+                    self.total = Some(payload[6]);
+                }
+            }
+            // num_handles, then (handle, completed) pairs
+            HCI_NUMBER_OF_COMPLETED_PACKETS_EVT if !payload.is_empty() => {
+                let num_handles = payload[0] as usize;
+                for i in 0..num_handles {
+                    let offset = 1 + i * 4;
+                    if offset + 4 > payload.len() {
+                        break;
+                    }
+
+                    self.on_completed(u16_at(offset) & 0x0fff, u16_at(offset + 2));
+                }
+            }
+            // status, handle, reason
+            HCI_DISCONNECTION_COMPLETE_EVT if payload.len() >= 3 => {
+                if payload[0] == 0 {
+                    // Controller flushes all pending packets of the connection
+                    self.on_completed(u16_at(1) & 0x0fff, u16::MAX);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub struct Ble {
+    flow_control: AclFlowControl,
+}
 
 impl Ble {
     pub(super) fn new(ipcc: &mut Ipcc) -> Self {
@@ -29,25 +183,45 @@ impl Ble {
 
         ipcc.c1_set_rx_channel(channels::cpu2::IPCC_BLE_EVENT_CHANNEL, true);
 
-        Ble {}
+        Ble {
+            flow_control: AclFlowControl::new(),
+        }
     }
 
-    pub(super) fn evt_handler(&self, ipcc: &mut Ipcc, queue: &mut HeaplessEvtQueue) {
-        unsafe {
-            let mut node_ptr: *mut LinkedListNode = core::ptr::null_mut();
-            let node_ptr_ptr: *mut *mut LinkedListNode = &mut node_ptr;
+    pub(super) fn flow_control(&self) -> &AclFlowControl {
+        &self.flow_control
+    }
 
-            while !LST_is_empty(EVT_QUEUE.as_mut_ptr()) {
-                LST_remove_head(EVT_QUEUE.as_mut_ptr(), node_ptr_ptr);
+    /// Passes at most `limit` received events to `deliver` and clears the BLE event channel.
+    ///
+    /// Returns `false` if events are left in shared memory, see `Sys::evt_handler`.
+    pub(super) fn evt_handler<F: FnMut(EvtBox)>(
+        &mut self,
+        ipcc: &mut Ipcc,
+        limit: usize,
+        mut deliver: F,
+    ) -> bool {
+        let channel = channels::cpu2::IPCC_BLE_EVENT_CHANNEL;
+        let mut queue = unsafe { ShmemList::from_head(EVT_QUEUE.as_mut_ptr()) };
 
-                let event: *mut evt::EvtPacket = node_ptr.cast();
-                let event = EvtBox::new(event);
+        for _ in 0..limit {
+            let event: *mut evt::EvtPacket = match queue.pop_head() {
+                Some(node) => node.cast(),
+                None => break,
+            };
+            self.flow_control.on_event(event);
 
-                queue.enqueue(event).unwrap();
-            }
+            deliver(EvtBox::new(event));
         }
 
-        ipcc.c1_clear_flag_channel(channels::cpu2::IPCC_BLE_EVENT_CHANNEL);
+        let drained = queue.is_empty();
+        RxChannelAction::after_drain(drained).apply(ipcc, channel);
+        drained
+    }
+
+    /// Returns the number of events left in shared memory by `evt_handler`.
+    pub(super) fn pending_events(&self) -> usize {
+        unsafe { ShmemList::from_head(EVT_QUEUE.as_mut_ptr()) }.len()
     }
 
     pub(super) fn acl_data_handler(&self, ipcc: &mut Ipcc) {
@@ -55,9 +229,180 @@ impl Ble {
 
         // TODO: ACL data ack to the user
     }
+
+    /// Sends ACL data to the connection `handle`.
+    ///
+    /// Returns `WouldBlock` while CPU2 still owns the shared ACL data buffer or while the
+    /// controller has no free ACL buffers.
+    pub(super) fn send_acl_data(
+        &mut self,
+        ipcc: &mut Ipcc,
+        handle: u16,
+        pb_flag: AclPbFlag,
+        data: &[u8],
+    ) -> nb::Result<(), AclError> {
+        if data.len() > ACL_MAX_PAYLOAD {
+            return Err(nb::Error::Other(AclError::PayloadTooLarge));
+        }
+
+        if ipcc.c1_is_active_flag(channels::cpu1::IPCC_HCI_ACL_DATA_CHANNEL)
+            || !self.flow_control.has_credit()
+        {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        self.flow_control
+            .on_sent(handle & 0x0fff)
+            .map_err(nb::Error::Other)?;
+
+        unsafe {
+            let acl_packet = (*TL_REF_TABLE.assume_init().ble_table).phci_acl_data_buffer;
+            let acl_serial: *mut AclDataSerial = &mut (*acl_packet).acl_data_serial;
+
+            (*acl_serial).ty = TlPacketType::AclData as u8;
+            (*acl_serial).handle = (handle & 0x0fff) | ((pb_flag as u16) << 12);
+            (*acl_serial).length = data.len() as u16;
+
+            let acl_data: *mut u8 = (*acl_serial).acl_data.as_mut_ptr();
+            core::ptr::copy(data.as_ptr(), acl_data, data.len());
+        }
+
+        ipcc.c1_set_flag_channel(channels::cpu1::IPCC_HCI_ACL_DATA_CHANNEL);
+        ipcc.c1_set_tx_channel(channels::cpu1::IPCC_HCI_ACL_DATA_CHANNEL, true);
+
+        Ok(())
+    }
 }
 
+/// Error of `send_cmd`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CmdError {
+    /// Parameters don't fit into the command buffer (255 bytes at most).
+    ParamsTooLong,
+}
+
+/// Sends HCI command `opcode` with `params` to the BLE stack.
+///
+/// The Command Complete or Command Status event is received through the event queue.
+pub fn send_cmd(ipcc: &mut Ipcc, opcode: u16, params: &[u8]) -> Result<(), CmdError> {
+    unsafe {
+        let pcmd_buffer: *mut CmdPacket = (&*TL_REF_TABLE.assume_init().ble_table).pcmd_buffer;
+        CmdPacket::write_into(pcmd_buffer, TlPacketType::BleCmd, opcode, params)?;
+    }
+
+    ipcc.c1_set_flag_channel(channels::cpu1::IPCC_BLE_CMD_CHANNEL);
+    super::count_cmd();
+
+    Ok(())
+}
+
+/// Maximum size of the return parameters of a command.
+pub const CMD_RSP_MAX_PARAMS: usize = 255 - 4;
+
+/// Response to a BLE command, from its Command Complete or Command Status event.
+#[derive(Copy, Clone)]
+pub struct CmdResponse {
+    /// Opcode of the command this event answers
+    pub opcode: u16,
+    /// Status, the first return parameter
+    pub status: u8,
+    len: u8,
+    params: [u8; CMD_RSP_MAX_PARAMS],
+}
+
+impl CmdResponse {
+    /// Returns the return parameters following the status. Always empty for Command Status.
+    pub fn return_params(&self) -> &[u8] {
+        &self.params[..self.len as usize]
+    }
+}
+
+impl core::fmt::Debug for CmdResponse {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("CmdResponse")
+            .field("opcode", &self.opcode)
+            .field("status", &self.status)
+            .field("return_params", &self.return_params())
+            .finish()
+    }
+}
+
+/// Decodes `evt` if it's a Command Complete or Command Status event of a BLE command.
+pub fn cmd_response(evt: &EvtBox) -> Option<CmdResponse> {
+    let packet = evt.evt();
+    if packet.kind() != TlPacketType::BleEvt as u8 {
+        return None;
+    }
+
+    let payload = evt.payload();
+    let u16_at = |i: usize| u16::from_le_bytes([payload[i], payload[i + 1]]);
+
+    let mut rsp = CmdResponse {
+        opcode: 0,
+        status: 0,
+        len: 0,
+        params: [0; CMD_RSP_MAX_PARAMS],
+    };
+
+    match packet.evt().evt_code {
+        // num_cmd, opcode, status, return parameters
+        HCI_COMMAND_COMPLETE_EVT if payload.len() >= 4 => {
+            let params = &payload[4..];
+            rsp.opcode = u16_at(1);
+            rsp.status = payload[3];
+            rsp.len = params.len() as u8;
+            rsp.params[..params.len()].copy_from_slice(params);
+        }
+        // status, num_cmd, opcode
+        HCI_COMMAND_STATUS_EVT if payload.len() >= 4 => {
+            rsp.opcode = u16_at(2);
+            rsp.status = payload[0];
+        }
+        _ => return None,
+    }
+
+    Some(rsp)
+}
+
+/// Decodes `evt` if it's a Command Status event of a BLE command.
+///
+/// Commands answered with Command Status (most LE controller commands) have no Command Complete
+/// event, their outcome is the status.
+pub fn cs_evt(evt: &EvtBox) -> Option<evt::CsEvt> {
+    if evt.kind() != TlPacketType::BleEvt as u8 {
+        return None;
+    }
+
+    // status, num_cmd, opcode
+    match (evt.evt().evt().evt_code, evt.payload()) {
+        (HCI_COMMAND_STATUS_EVT, &[status, num_cmd, lo, hi, ..]) => Some(evt::CsEvt {
+            status,
+            num_cmd,
+            cmd_code: u16::from_le_bytes([lo, hi]),
+        }),
+        _ => None,
+    }
+}
+
+/// Returns the hardware code if `evt` is an HCI Hardware Error event.
+///
+/// The BLE stack reports fatal controller errors on CPU2 with this event, the stack has to be
+/// restarted (CPU2 reset) afterwards.
+pub fn hardware_error(evt: &EvtBox) -> Option<u8> {
+    if evt.kind() != TlPacketType::BleEvt as u8 {
+        return None;
+    }
+
+    match (evt.evt().evt().evt_code, evt.payload()) {
+        (HCI_HARDWARE_ERROR_EVT, &[code, ..]) => Some(code),
+        _ => None,
+    }
+}
+
+/// Sends an already serialized HCI command (packet type, opcode, length, parameters).
 pub fn ble_send_cmd(ipcc: &mut Ipcc, buf: &[u8]) {
+    assert!(buf.len() <= core::mem::size_of::<CmdSerial>());
+
     unsafe {
         let pcmd_buffer: *mut CmdPacket = (&*TL_REF_TABLE.assume_init().ble_table).pcmd_buffer;
         let pcmd_serial: *mut CmdSerial = &mut (*pcmd_buffer).cmdserial;
@@ -70,14 +415,119 @@ pub fn ble_send_cmd(ipcc: &mut Ipcc, buf: &[u8]) {
     }
 
     ipcc.c1_set_flag_channel(channels::cpu1::IPCC_BLE_CMD_CHANNEL);
+    super::count_cmd();
 }
 
-#[allow(dead_code)] // Not used currently but reserved
-pub(super) fn ble_send_acl_data(ipcc: &mut Ipcc) {
-    let mut cmd_packet =
-        unsafe { &mut *(*TL_REF_TABLE.assume_init().ble_table).phci_acl_data_buffer };
-    cmd_packet.acl_data_serial.ty = TlPacketType::AclData as u8;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tl_mbox::evt::tests::with_evt;
+
+    const BLE_EVT: u8 = TlPacketType::BleEvt as u8;
+
+    #[test]
+    fn cmd_response_command_complete() {
+        // HCI_Read_BD_ADDR complete: num_cmd 1, opcode 0x1009, status 0, address
+        let serial = [
+            BLE_EVT, 0x0e, 0x0a, 0x01, 0x09, 0x10, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66,
+        ];
+
+        with_evt(1, &serial, |evt| {
+            let rsp = cmd_response(evt).unwrap();
+            assert_eq!((rsp.opcode, rsp.status), (0x1009, 0));
+            assert_eq!(rsp.return_params(), &[0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+            assert!(cs_evt(evt).is_none());
+        });
+
+        // HCI_Reset complete with an error, no further return parameters
+        with_evt(2, &[BLE_EVT, 0x0e, 0x04, 0x01, 0x03, 0x0c, 0x0c], |evt| {
+            let rsp = cmd_response(evt).unwrap();
+            assert_eq!((rsp.opcode, rsp.status), (0x0c03, 0x0c));
+            assert!(rsp.return_params().is_empty());
+        });
+    }
+
+    #[test]
+    fn cmd_response_command_status() {
+        // LE_Create_Connection pending: status 0, num_cmd 1, opcode 0x200d
+        let serial = [BLE_EVT, 0x0f, 0x04, 0x00, 0x01, 0x0d, 0x20];
 
-    ipcc.c1_set_flag_channel(channels::cpu1::IPCC_HCI_ACL_DATA_CHANNEL);
-    ipcc.c1_set_tx_channel(channels::cpu1::IPCC_HCI_ACL_DATA_CHANNEL, true);
+        with_evt(3, &serial, |evt| {
+            let rsp = cmd_response(evt).unwrap();
+            assert_eq!((rsp.opcode, rsp.status), (0x200d, 0));
+            assert!(rsp.return_params().is_empty());
+
+            let cs = cs_evt(evt).unwrap();
+            let (status, num_cmd, cmd_code) = (cs.status, cs.num_cmd, cs.cmd_code);
+            assert_eq!((status, num_cmd, cmd_code), (0, 1, 0x200d));
+            assert_eq!(cs.result(), Ok(()));
+        });
+
+        with_evt(0, &[BLE_EVT, 0x0f, 0x04, 0x12, 0x01, 0x0d, 0x20], |evt| {
+            assert_eq!(cs_evt(evt).unwrap().result(), Err(0x12));
+        });
+    }
+
+    #[test]
+    fn cmd_response_ignores_other_events() {
+        // Truncated Command Complete and Command Status
+        with_evt(0, &[BLE_EVT, 0x0e, 0x03, 0x01, 0x03, 0x0c], |evt| {
+            assert!(cmd_response(evt).is_none());
+        });
+        with_evt(0, &[BLE_EVT, 0x0f, 0x03, 0x00, 0x01, 0x0d], |evt| {
+            assert!(cmd_response(evt).is_none());
+            assert!(cs_evt(evt).is_none());
+        });
+
+        // Disconnection Complete
+        with_evt(0, &[BLE_EVT, 0x05, 0x04, 0x00, 0x01, 0x08, 0x13], |evt| {
+            assert!(cmd_response(evt).is_none());
+            assert!(hardware_error(evt).is_none());
+        });
+
+        // Command Complete on the system channel
+        let sys_evt = TlPacketType::SysEvt as u8;
+        with_evt(0, &[sys_evt, 0x0e, 0x04, 0x01, 0x03, 0x0c, 0x00], |evt| {
+            assert!(cmd_response(evt).is_none());
+        });
+    }
+
+    #[test]
+    fn acl_credits_returned_by_completed_packets() {
+        let mut flow = AclFlowControl::new();
+
+        // LE_Read_Buffer_Size complete: num_cmd 1, opcode 0x2002, status 0, length 251, 2 buffers
+        let serial = [
+            BLE_EVT, 0x0e, 0x07, 0x01, 0x02, 0x20, 0x00, 0xfb, 0x00, 0x02,
+        ];
+        with_evt(0, &serial, |evt| flow.on_event(evt.as_ptr()));
+        assert_eq!(flow.total_buffers(), Some(2));
+
+        flow.on_sent(0x0040).unwrap();
+        flow.on_sent(0x0040).unwrap();
+        assert_eq!(flow.available(), Some(0));
+        assert!(!flow.has_credit());
+
+        // Number Of Completed Packets: 1 handle, handle 0x0040, 2 packets
+        let serial = [BLE_EVT, 0x13, 0x05, 0x01, 0x40, 0x00, 0x02, 0x00];
+        with_evt(1, &serial, |evt| flow.on_event(evt.as_ptr()));
+        assert_eq!(flow.in_flight(0x0040), 0);
+        assert_eq!(flow.available(), Some(2));
+        assert!(flow.has_credit());
+    }
+
+    #[test]
+    fn hardware_error_code() {
+        with_evt(1, &[BLE_EVT, 0x10, 0x01, 0x2a], |evt| {
+            assert_eq!(hardware_error(evt), Some(0x2a));
+            assert!(cmd_response(evt).is_none());
+        });
+
+        with_evt(0, &[BLE_EVT, 0x10, 0x00], |evt| {
+            assert_eq!(hardware_error(evt), None);
+        });
+        with_evt(0, &[TlPacketType::SysEvt as u8, 0x10, 0x01, 0x2a], |evt| {
+            assert_eq!(hardware_error(evt), None);
+        });
+    }
 }
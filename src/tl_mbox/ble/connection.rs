@@ -0,0 +1,232 @@
+//! Connection tracking
+//!
+//! `Connections` decodes the connection related events dequeued from the mailbox and keeps the
+//! state of the open connections, so applications don't have to decode HCI events themselves:
+//!
+//! ```ignore
+//! while let Some(evt) = mbox.dequeue_event() {
+//!     if let Some(ConnectionEvent::Disconnected { .. }) = connections.process(&evt) {
+//!         // Advertising stops on connection, restart it
+//!     }
+//! }
+//! ```
+//!
+//! It also follows the availability of the ACI TX buffer pool used for notifications: once a
+//! `ACI_GATT_UPDATE_CHAR_VALUE` fails with `BLE_STATUS_INSUFFICIENT_RESOURCES`, notifications
+//! should be held back until `ConnectionEvent::TxPoolAvailable`.
+
+use super::{ACL_MAX_CONNECTIONS, HCI_DISCONNECTION_COMPLETE_EVT};
+use crate::tl_mbox::consts::TlPacketType;
+use crate::tl_mbox::evt::EvtBox;
+
+const HCI_LE_META_EVT: u8 = 0x3e;
+const HCI_VENDOR_EVT: u8 = 0xff;
+
+const HCI_LE_CONNECTION_COMPLETE_SUBEVT: u8 = 0x01;
+const HCI_LE_CONNECTION_UPDATE_COMPLETE_SUBEVT: u8 = 0x03;
+const HCI_LE_ENHANCED_CONNECTION_COMPLETE_SUBEVT: u8 = 0x0a;
+
+const ACI_GATT_TX_POOL_AVAILABLE_EVT: u16 = 0x0c16;
+
+/// ACI status: no TX buffer is available to queue the notification.
+pub const BLE_STATUS_INSUFFICIENT_RESOURCES: u8 = 0x64;
+
+/// Maximum number of tracked connections.
+pub const MAX_CONNECTIONS: usize = ACL_MAX_CONNECTIONS;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Role {
+    Master,
+    Slave,
+}
+
+/// Open connection.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Connection {
+    pub handle: u16,
+    pub role: Role,
+    pub peer_address_type: u8,
+    pub peer_address: [u8; 6],
+    /// Connection interval in 1.25 ms units
+    pub interval: u16,
+    /// Slave latency in connection events
+    pub latency: u16,
+    /// Supervision timeout in 10 ms units
+    pub supervision_timeout: u16,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ConnectionEvent {
+    /// Connection was established.
+    Connected(Connection),
+    /// Connection establishment failed with HCI status `status`.
+    ConnectFailed { status: u8 },
+    /// Connection parameters were updated.
+    Updated(Connection),
+    /// Connection was closed with HCI reason code `reason`.
+    Disconnected { handle: u16, reason: u8 },
+    /// TX buffers were released after `BLE_STATUS_INSUFFICIENT_RESOURCES`.
+    TxPoolAvailable,
+}
+
+/// Open connections, see module documentation.
+#[derive(Debug)]
+pub struct Connections {
+    connections: [Option<Connection>; MAX_CONNECTIONS],
+    tx_pool_full: bool,
+}
+
+impl Default for Connections {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Connections {
+    pub fn new() -> Self {
+        Connections {
+            connections: [None; MAX_CONNECTIONS],
+            tx_pool_full: false,
+        }
+    }
+
+    /// Updates the connections from `evt`, returns the change if it's a connection event.
+    pub fn process(&mut self, evt: &EvtBox) -> Option<ConnectionEvent> {
+        let packet = evt.evt();
+        if packet.kind() != TlPacketType::BleEvt as u8 {
+            return None;
+        }
+
+        let payload = evt.payload();
+        let u16_at = |i: usize| u16::from_le_bytes([payload[i], payload[i + 1]]);
+
+        match packet.evt().evt_code {
+            // status, handle, reason
+            HCI_DISCONNECTION_COMPLETE_EVT if payload.len() >= 4 && payload[0] == 0 => {
+                let handle = u16_at(1) & 0x0fff;
+                self.remove(handle);
+
+                Some(ConnectionEvent::Disconnected {
+                    handle,
+                    reason: payload[3],
+                })
+            }
+            HCI_LE_META_EVT if !payload.is_empty() => match payload[0] {
+                // subevent, status, handle, role, peer address type, peer address, interval,
+                // latency, supervision timeout, master clock accuracy
+                HCI_LE_CONNECTION_COMPLETE_SUBEVT if payload.len() >= 19 => {
+                    self.on_connection_complete(payload, 12)
+                }
+                // same as above with local and peer resolvable private addresses before interval
+                HCI_LE_ENHANCED_CONNECTION_COMPLETE_SUBEVT if payload.len() >= 31 => {
+                    self.on_connection_complete(payload, 24)
+                }
+                // subevent, status, handle, interval, latency, supervision timeout
+                HCI_LE_CONNECTION_UPDATE_COMPLETE_SUBEVT if payload.len() >= 10 => {
+                    if payload[1] != 0 {
+                        return None;
+                    }
+
+                    let connection = self.get_mut(u16_at(2) & 0x0fff)?;
+                    connection.interval = u16_at(4);
+                    connection.latency = u16_at(6);
+                    connection.supervision_timeout = u16_at(8);
+
+                    Some(ConnectionEvent::Updated(*connection))
+                }
+                _ => None,
+            },
+            // event code (2), connection handle, available buffers
+            HCI_VENDOR_EVT if payload.len() >= 2 && u16_at(0) == ACI_GATT_TX_POOL_AVAILABLE_EVT => {
+                self.tx_pool_full = false;
+                Some(ConnectionEvent::TxPoolAvailable)
+            }
+            _ => None,
+        }
+    }
+
+    /// Records the status of a notification or indication command.
+    ///
+    /// `BLE_STATUS_INSUFFICIENT_RESOURCES` marks the TX pool full until
+    /// `ConnectionEvent::TxPoolAvailable`.
+    pub fn on_tx_status(&mut self, status: u8) {
+        if status == BLE_STATUS_INSUFFICIENT_RESOURCES {
+            self.tx_pool_full = true;
+        }
+    }
+
+    /// Returns `true` while notifications should be held back.
+    pub fn tx_pool_full(&self) -> bool {
+        self.tx_pool_full
+    }
+
+    /// Returns the connection with handle `handle`.
+    pub fn get(&self, handle: u16) -> Option<&Connection> {
+        self.iter().find(|c| c.handle == handle)
+    }
+
+    /// Returns the open connections.
+    pub fn iter(&self) -> impl Iterator<Item = &Connection> {
+        self.connections.iter().flatten()
+    }
+
+    /// Returns number of open connections.
+    pub fn count(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Returns `true` if there is at least one open connection.
+    pub fn is_connected(&self) -> bool {
+        self.iter().next().is_some()
+    }
+
+    fn get_mut(&mut self, handle: u16) -> Option<&mut Connection> {
+        self.connections
+            .iter_mut()
+            .flatten()
+            .find(|c| c.handle == handle)
+    }
+
+    fn remove(&mut self, handle: u16) {
+        for slot in self.connections.iter_mut() {
+            if slot.map_or(false, |c| c.handle == handle) {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Decodes a (enhanced) connection complete event, connection timing starts at `timing`.
+    fn on_connection_complete(&mut self, payload: &[u8], timing: usize) -> Option<ConnectionEvent> {
+        let u16_at = |i: usize| u16::from_le_bytes([payload[i], payload[i + 1]]);
+
+        let status = payload[1];
+        if status != 0 {
+            return Some(ConnectionEvent::ConnectFailed { status });
+        }
+
+        let mut peer_address = [0; 6];
+        peer_address.copy_from_slice(&payload[6..12]);
+
+        let connection = Connection {
+            handle: u16_at(2) & 0x0fff,
+            role: if payload[4] == 0 {
+                Role::Master
+            } else {
+                Role::Slave
+            },
+            peer_address_type: payload[5],
+            peer_address,
+            interval: u16_at(timing),
+            latency: u16_at(timing + 2),
+            supervision_timeout: u16_at(timing + 4),
+        };
+
+        // A handle is reused only after its disconnection, keep the newest state anyway
+        self.remove(connection.handle);
+        if let Some(slot) = self.connections.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(connection);
+        }
+
+        Some(ConnectionEvent::Connected(connection))
+    }
+}
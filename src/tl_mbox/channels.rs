@@ -1,10 +1,28 @@
+//! IPCC channels of the mailbox
+//!
+//! `TlMbox` only ever sets, clears, masks and unmasks the channels listed here, and only those of
+//! the enabled features. The others can be used for an own protocol with custom CPU2 firmware,
+//! driven with `Ipcc::c1_set_flag_channel`, `c1_clear_flag_channel`, `c1_is_active_flag` and
+//! `c2_is_active_flag`:
+//!
+//! * channel 3, unless the `thread` or `mac-802-15-4` feature is enabled
+//! * channel 5, unless the `thread` feature is enabled
+//!
+//! `is_mbox_channel` tells them apart. Their interrupts are masked by default. The IPCC
+//! interrupts are shared with the mailbox, so a channel whose interrupt is unmasked must be
+//! handled in the application's IPCC interrupt handler in addition to the mailbox handlers.
+//! `Ipcc::reset` and `Ipcc::loopback_test` do touch all channels.
+
+use crate::ipcc::IpccChannel;
+
 pub mod cpu1 {
     use crate::ipcc::IpccChannel;
 
     pub const IPCC_BLE_CMD_CHANNEL: IpccChannel = IpccChannel::Channel1;
     pub const IPCC_SYSTEM_CMD_RSP_CHANNEL: IpccChannel = IpccChannel::Channel2;
+    #[cfg_attr(not(feature = "thread"), allow(dead_code))]
     pub const IPCC_THREAD_OT_CMD_RSP_CHANNEL: IpccChannel = IpccChannel::Channel3;
-    #[allow(dead_code)] // Not used currently but reserved
+    #[cfg_attr(not(feature = "mac-802-15-4"), allow(dead_code))]
     pub const IPCC_MAC_802_15_4_CMD_RSP_CHANNEL: IpccChannel = IpccChannel::Channel3;
     #[allow(dead_code)] // Not used currently but reserved
     pub const IPCC_THREAD_CLI_CMD_CHANNEL: IpccChannel = IpccChannel::Channel5;
@@ -17,9 +35,229 @@ pub mod cpu2 {
 
     pub const IPCC_BLE_EVENT_CHANNEL: IpccChannel = IpccChannel::Channel1;
     pub const IPCC_SYSTEM_EVENT_CHANNEL: IpccChannel = IpccChannel::Channel2;
+    #[cfg_attr(not(feature = "thread"), allow(dead_code))]
     pub const IPCC_THREAD_NOTIFICATION_ACK_CHANNEL: IpccChannel = IpccChannel::Channel3;
-    #[allow(dead_code)] // Not used currently but reserved
+    #[cfg_attr(not(feature = "mac-802-15-4"), allow(dead_code))]
     pub const IPCC_MAC_802_15_4_NOTIFICATION_ACK_CHANNEL: IpccChannel = IpccChannel::Channel3;
+    #[cfg_attr(not(feature = "traces"), allow(dead_code))]
     pub const IPCC_TRACES_CHANNEL: IpccChannel = IpccChannel::Channel4;
+    #[cfg_attr(not(feature = "thread"), allow(dead_code))]
     pub const IPCC_THREAD_CLI_NOTIFICATION_ACK_CHANNEL: IpccChannel = IpccChannel::Channel5;
 }
+
+/// Returns `true` if `TlMbox` uses `channel` in either direction with the enabled features.
+pub fn is_mbox_channel(channel: IpccChannel) -> bool {
+    match channel {
+        IpccChannel::Channel3 => cfg!(any(feature = "thread", feature = "mac-802-15-4")),
+        IpccChannel::Channel5 => cfg!(feature = "thread"),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Status registers of the IPCC: a channel is occupied from the sender setting its flag until
+    /// the receiver clears it, independently in each direction.
+    #[derive(Default)]
+    struct MockIpcc {
+        c1_to_c2: u32,
+        c2_to_c1: u32,
+    }
+
+    impl MockIpcc {
+        fn c1_set_flag_channel(&mut self, channel: IpccChannel) {
+            assert!(!self.c1_is_active_flag(channel), "{:?} occupied", channel);
+            self.c1_to_c2 |= channel.bit();
+        }
+
+        fn c1_clear_flag_channel(&mut self, channel: IpccChannel) {
+            self.c2_to_c1 &= !channel.bit();
+        }
+
+        fn c2_set_flag_channel(&mut self, channel: IpccChannel) {
+            assert!(!self.c2_is_active_flag(channel), "{:?} occupied", channel);
+            self.c2_to_c1 |= channel.bit();
+        }
+
+        fn c2_clear_flag_channel(&mut self, channel: IpccChannel) {
+            self.c1_to_c2 &= !channel.bit();
+        }
+
+        fn c1_is_active_flag(&self, channel: IpccChannel) -> bool {
+            channel.is_set_in(self.c1_to_c2)
+        }
+
+        fn c2_is_active_flag(&self, channel: IpccChannel) -> bool {
+            channel.is_set_in(self.c2_to_c1)
+        }
+    }
+
+    fn cpu1_channels() -> heapless::Vec<IpccChannel, heapless::consts::U8> {
+        let mut channels = heapless::Vec::new();
+        channels.push(cpu1::IPCC_BLE_CMD_CHANNEL).unwrap();
+        channels.push(cpu1::IPCC_SYSTEM_CMD_RSP_CHANNEL).unwrap();
+        channels.push(cpu1::IPCC_MM_RELEASE_BUFFER_CHANNEL).unwrap();
+        channels.push(cpu1::IPCC_HCI_ACL_DATA_CHANNEL).unwrap();
+        if cfg!(feature = "thread") {
+            channels.push(cpu1::IPCC_THREAD_OT_CMD_RSP_CHANNEL).unwrap();
+            channels.push(cpu1::IPCC_THREAD_CLI_CMD_CHANNEL).unwrap();
+        }
+        if cfg!(feature = "mac-802-15-4") {
+            channels
+                .push(cpu1::IPCC_MAC_802_15_4_CMD_RSP_CHANNEL)
+                .unwrap();
+        }
+        channels
+    }
+
+    fn cpu2_channels() -> heapless::Vec<IpccChannel, heapless::consts::U8> {
+        let mut channels = heapless::Vec::new();
+        channels.push(cpu2::IPCC_BLE_EVENT_CHANNEL).unwrap();
+        channels.push(cpu2::IPCC_SYSTEM_EVENT_CHANNEL).unwrap();
+        if cfg!(feature = "traces") {
+            channels.push(cpu2::IPCC_TRACES_CHANNEL).unwrap();
+        }
+        if cfg!(feature = "thread") {
+            channels
+                .push(cpu2::IPCC_THREAD_NOTIFICATION_ACK_CHANNEL)
+                .unwrap();
+            channels
+                .push(cpu2::IPCC_THREAD_CLI_NOTIFICATION_ACK_CHANNEL)
+                .unwrap();
+        }
+        if cfg!(feature = "mac-802-15-4") {
+            channels
+                .push(cpu2::IPCC_MAC_802_15_4_NOTIFICATION_ACK_CHANNEL)
+                .unwrap();
+        }
+        channels
+    }
+
+    #[test]
+    fn channel_bits_are_distinct() {
+        let mask = IpccChannel::iterator().fold(0, |mask, channel| {
+            assert_eq!(mask & channel.bit(), 0);
+            assert_eq!(channel.free_mask_bit(), channel.bit() << 16);
+            mask | channel.bit()
+        });
+
+        assert_eq!(mask, 0x3f);
+        assert_eq!(IpccChannel::iterator().count(), 6);
+    }
+
+    #[test]
+    fn assignments_are_distinct_per_direction() {
+        for channels in [cpu1_channels(), cpu2_channels()].iter() {
+            for (i, a) in channels.iter().enumerate() {
+                for b in &channels[i + 1..] {
+                    assert_ne!(a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn assignments_are_mbox_channels() {
+        for &channel in cpu1_channels().iter().chain(cpu2_channels().iter()) {
+            assert!(is_mbox_channel(channel), "{:?}", channel);
+        }
+
+        for channel in IpccChannel::iterator() {
+            let used = cpu1_channels().contains(&channel) || cpu2_channels().contains(&channel);
+            assert_eq!(is_mbox_channel(channel), used, "{:?}", channel);
+        }
+    }
+
+    #[test]
+    fn system_cmd_rsp_and_evt() {
+        let mut ipcc = MockIpcc::default();
+        let cmd_rsp = cpu1::IPCC_SYSTEM_CMD_RSP_CHANNEL;
+        let evt = cpu2::IPCC_SYSTEM_EVENT_CHANNEL;
+        let release = cpu1::IPCC_MM_RELEASE_BUFFER_CHANNEL;
+
+        // CPU1 sends a command, CPU2 reports an event before answering it
+        ipcc.c1_set_flag_channel(cmd_rsp);
+        ipcc.c2_set_flag_channel(evt);
+        assert!(ipcc.c1_is_active_flag(cmd_rsp));
+        assert!(ipcc.c2_is_active_flag(evt));
+
+        // CPU1 takes the event and hands its buffer back, the command stays pending
+        ipcc.c1_clear_flag_channel(evt);
+        ipcc.c1_set_flag_channel(release);
+        assert!(!ipcc.c2_is_active_flag(evt));
+        assert!(ipcc.c1_is_active_flag(cmd_rsp));
+
+        // CPU2 answers the command by freeing the channel, and takes the released buffer
+        ipcc.c2_clear_flag_channel(cmd_rsp);
+        ipcc.c2_clear_flag_channel(release);
+        assert_eq!((ipcc.c1_to_c2, ipcc.c2_to_c1), (0, 0));
+
+        // Next command
+        ipcc.c1_set_flag_channel(cmd_rsp);
+        ipcc.c2_clear_flag_channel(cmd_rsp);
+        assert!(!ipcc.c1_is_active_flag(cmd_rsp));
+    }
+
+    #[test]
+    fn all_channels_in_flight() {
+        let mut ipcc = MockIpcc::default();
+        let cpu1 = cpu1_channels();
+        let cpu2 = cpu2_channels();
+
+        // Every transport has a message in flight in both directions at once
+        for &channel in cpu1.iter() {
+            ipcc.c1_set_flag_channel(channel);
+        }
+        for &channel in cpu2.iter() {
+            ipcc.c2_set_flag_channel(channel);
+        }
+
+        // Each side completes its transfers in turn without touching the others
+        for (i, &channel) in cpu2.iter().enumerate() {
+            ipcc.c1_clear_flag_channel(channel);
+            for (j, &other) in cpu2.iter().enumerate() {
+                assert_eq!(ipcc.c2_is_active_flag(other), j > i, "{:?}", other);
+            }
+            for &other in cpu1.iter() {
+                assert!(ipcc.c1_is_active_flag(other));
+            }
+        }
+
+        for (i, &channel) in cpu1.iter().enumerate() {
+            ipcc.c2_clear_flag_channel(channel);
+            for (j, &other) in cpu1.iter().enumerate() {
+                assert_eq!(ipcc.c1_is_active_flag(other), j > i, "{:?}", other);
+            }
+        }
+
+        assert_eq!((ipcc.c1_to_c2, ipcc.c2_to_c1), (0, 0));
+    }
+
+    #[test]
+    fn ble_cmd_shares_channel_with_evt() {
+        // Channel 1 carries BLE commands to CPU2 and BLE events back, one per direction
+        let mut ipcc = MockIpcc::default();
+        let channel = cpu1::IPCC_BLE_CMD_CHANNEL;
+        assert_eq!(channel, cpu2::IPCC_BLE_EVENT_CHANNEL);
+
+        ipcc.c1_set_flag_channel(channel);
+        ipcc.c2_set_flag_channel(channel);
+
+        ipcc.c1_clear_flag_channel(channel);
+        assert!(ipcc.c1_is_active_flag(channel));
+        assert!(!ipcc.c2_is_active_flag(channel));
+
+        ipcc.c2_clear_flag_channel(channel);
+        assert!(!ipcc.c1_is_active_flag(channel));
+    }
+
+    #[test]
+    #[should_panic]
+    fn occupied_channel_isnt_set_again() {
+        let mut ipcc = MockIpcc::default();
+        ipcc.c1_set_flag_channel(cpu1::IPCC_HCI_ACL_DATA_CHANNEL);
+        ipcc.c1_set_flag_channel(cpu1::IPCC_HCI_ACL_DATA_CHANNEL);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::tl_mbox::ble::CmdError;
+use crate::tl_mbox::consts::TlPacketType;
 use crate::tl_mbox::evt::{EvtPacket, EvtSerial};
 use crate::tl_mbox::{PacketHeader, TL_EVT_HEADER_SIZE};
 use core::fmt::{Error, Formatter};
@@ -22,7 +24,7 @@ impl Default for Cmd {
 
 impl core::fmt::Debug for Cmd {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-        let cmd_code = self.clone().cmd_code;
+        let cmd_code = self.cmd_code;
 
         write!(
             f,
@@ -47,6 +49,35 @@ pub struct CmdPacket {
 }
 
 impl CmdPacket {
+    /// Fills packet type `ty`, `opcode`, payload length and `payload` of the command in `buf`.
+    ///
+    /// The packet header is left untouched, so the packet stays linked if it's in a list.
+    /// Returns an error if `payload` is longer than 255 bytes, `buf` isn't written then.
+    ///
+    /// # Safety
+    ///
+    /// `buf` must be valid for writes, e.g. point to a command buffer in shared memory.
+    pub unsafe fn write_into(
+        buf: *mut CmdPacket,
+        ty: TlPacketType,
+        opcode: u16,
+        payload: &[u8],
+    ) -> Result<(), CmdError> {
+        if payload.len() > 255 {
+            return Err(CmdError::ParamsTooLong);
+        }
+
+        let cmd_serial: *mut CmdSerial = &mut (*buf).cmdserial;
+        (*cmd_serial).ty = ty as u8;
+        (*cmd_serial).cmd.cmd_code = opcode;
+        (*cmd_serial).cmd.payload_len = payload.len() as u8;
+
+        let p_payload: *mut u8 = (*cmd_serial).cmd.payload.as_mut_ptr();
+        core::ptr::copy(payload.as_ptr(), p_payload, payload.len());
+
+        Ok(())
+    }
+
     /// Writes an underlying CmdPacket into the provided buffer.
     /// Returns a number of bytes that were written.
     /// Returns an error if event kind is unknown or if provided buffer size is not enough.
@@ -87,3 +118,106 @@ pub struct AclDataPacket {
     pub header: PacketHeader,
     pub acl_data_serial: AclDataSerial,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tl_mbox::TL_PACKET_HEADER_SIZE;
+
+    /// Header pointers of a packet linked into a list.
+    const NEXT: usize = 0x2003_0100;
+    const PREV: usize = 0x2003_0200;
+
+    fn linked_packet() -> CmdPacket {
+        CmdPacket {
+            header: PacketHeader {
+                next: NEXT as *mut _,
+                prev: PREV as *mut _,
+            },
+            ..CmdPacket::default()
+        }
+    }
+
+    fn bytes(packet: &CmdPacket) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(
+                (packet as *const CmdPacket).cast::<u8>(),
+                core::mem::size_of::<CmdPacket>(),
+            )
+        }
+    }
+
+    /// Writes the command and returns the serial part, `TL_CmdSerial_t` in ST's headers.
+    fn serialize(ty: TlPacketType, opcode: u16, payload: &[u8]) -> std::vec::Vec<u8> {
+        let mut packet = linked_packet();
+        unsafe { CmdPacket::write_into(&mut packet, ty, opcode, payload) }.unwrap();
+
+        let header = packet.header;
+        let (next, prev) = (header.next, header.prev);
+        assert_eq!(next as usize, NEXT, "header overwritten");
+        assert_eq!(prev as usize, PREV, "header overwritten");
+
+        let start = TL_PACKET_HEADER_SIZE;
+        bytes(&packet)[start..start + 4 + payload.len()].to_vec()
+    }
+
+    #[test]
+    fn hci_reset() {
+        assert_eq!(
+            serialize(TlPacketType::BleCmd, 0x0c03, &[]),
+            [0x01, 0x03, 0x0c, 0x00]
+        );
+    }
+
+    #[test]
+    fn hci_le_set_advertising_enable() {
+        assert_eq!(
+            serialize(TlPacketType::BleCmd, 0x200a, &[0x01]),
+            [0x01, 0x0a, 0x20, 0x01, 0x01]
+        );
+    }
+
+    #[test]
+    fn hci_le_set_advertising_parameters() {
+        let params = [
+            0xa0, 0x00, 0xa0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x07,
+            0x00,
+        ];
+        assert_eq!(
+            serialize(TlPacketType::BleCmd, 0x2006, &params),
+            [
+                0x01, 0x06, 0x20, 0x0f, 0xa0, 0x00, 0xa0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x07, 0x00,
+            ]
+        );
+    }
+
+    #[test]
+    fn shci_fus_get_state() {
+        assert_eq!(
+            serialize(TlPacketType::SysCmd, 0xfc52, &[]),
+            [0x10, 0x52, 0xfc, 0x00]
+        );
+    }
+
+    #[test]
+    fn longest_payload() {
+        let payload = [0x5a; 255];
+        let serial = serialize(TlPacketType::BleCmd, 0xfc01, &payload);
+
+        assert_eq!(serial[..4], [0x01, 0x01, 0xfc, 0xff]);
+        assert!(serial[4..].iter().all(|&b| b == 0x5a));
+    }
+
+    #[test]
+    fn too_long_payload_is_rejected() {
+        let mut packet = linked_packet();
+        let before = bytes(&packet).to_vec();
+
+        let result =
+            unsafe { CmdPacket::write_into(&mut packet, TlPacketType::BleCmd, 0x0c03, &[0; 256]) };
+
+        assert_eq!(result, Err(CmdError::ParamsTooLong));
+        assert_eq!(bytes(&packet), &before[..]);
+    }
+}
@@ -1,9 +1,20 @@
+use crate::tl_mbox::ble::HCI_COMMAND_COMPLETE_EVT;
 use crate::tl_mbox::cmd::{AclDataPacket, AclDataSerial};
 use crate::tl_mbox::consts::TlPacketType;
-use crate::tl_mbox::{PacketHeader, TL_EVT_HEADER_SIZE};
+use crate::tl_mbox::shci::{
+    SHCI_EVT_CODE_VENDOR, SHCI_SUB_EVT_CODE_READY, SHCI_SUB_EVT_ERROR_NOTIF,
+};
+use crate::tl_mbox::{PacketHeader, WirelessFwInfoTable, TL_EVT_HEADER_SIZE, TL_REF_TABLE};
 use core::convert::TryFrom;
 use core::mem::MaybeUninit;
 
+/// Size of an event pool block following the packet header.
+const EVT_FRAME_SIZE: usize = TL_EVT_HEADER_SIZE + 255;
+
+/// Maximum data length of an ACL data packet in an event pool block (type, handle and length
+/// take 5 bytes).
+const ACL_DATA_MAX_LEN: usize = EVT_FRAME_SIZE - 5;
+
 /**
  * The payload of `Evt` for a command status event
  */
@@ -15,6 +26,16 @@ pub struct CsEvt {
     pub cmd_code: u16,
 }
 
+impl CsEvt {
+    /// Returns `Err` with the status if the controller rejected the command.
+    pub fn result(&self) -> Result<(), u8> {
+        match self.status {
+            0 => Ok(()),
+            status => Err(status),
+        }
+    }
+}
+
 /**
  * The payload of `Evt` for a command complete event
  */
@@ -27,6 +48,11 @@ pub struct CcEvt {
 }
 
 impl CcEvt {
+    /// Returns the status, the first return parameter of the command.
+    pub fn status(&self) -> u8 {
+        self.payload[0]
+    }
+
     pub fn write(&self, buf: &mut [u8]) {
         unsafe {
             let len = core::mem::size_of::<CcEvt>();
@@ -87,21 +113,161 @@ impl EvtPacket {
     }
 }
 
+/// Read-only view of an event in shared memory, see `EvtBox::view`.
+///
+/// Borrows the `EvtBox`, so the buffer can't be released while the view is alive.
+#[derive(Debug, Copy, Clone)]
+pub struct EvtView<'a> {
+    packet: &'a EvtPacket,
+    payload: &'a [u8],
+}
+
+impl<'a> EvtView<'a> {
+    /// Returns the packet type, see `TlPacketType`.
+    pub fn kind(&self) -> u8 {
+        self.packet.evt_serial.kind
+    }
+
+    /// Returns the event code. Meaningless for ACL data.
+    pub fn evt_code(&self) -> u8 {
+        self.packet.evt_serial.evt.evt_code
+    }
+
+    /// Returns the sub event code of a vendor specific event, i.e. of system and BLE ACI events.
+    ///
+    /// Returns `None` for other events and for ACL data.
+    pub fn sub_evt_code(&self) -> Option<u16> {
+        if self.kind() == TlPacketType::AclData as u8 || self.evt_code() != SHCI_EVT_CODE_VENDOR {
+            return None;
+        }
+
+        match self.payload {
+            [lo, hi, ..] => Some(u16::from_le_bytes([*lo, *hi])),
+            _ => None,
+        }
+    }
+
+    /// Returns the event parameters, see `EvtBox::payload`.
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    /// Returns the parameters of an HCI Command Complete event.
+    ///
+    /// Returns `None` for other events and if the parameters don't include a status.
+    pub fn cc(&self) -> Option<&'a CcEvt> {
+        if self.kind() == TlPacketType::AclData as u8
+            || self.evt_code() != HCI_COMMAND_COMPLETE_EVT
+            || self.payload.len() < core::mem::size_of::<CcEvt>()
+        {
+            return None;
+        }
+
+        // `CcEvt` is packed, so any address is aligned for it
+        Some(unsafe { &*self.payload.as_ptr().cast() })
+    }
+}
+
+/// System event decoded by `EvtBox::try_into_shci_event`.
+#[derive(Debug, Copy, Clone)]
+pub enum ShciEvent<'a> {
+    /// CPU2 is ready to receive system commands. Holds the wireless firmware information,
+    /// which CPU2 fills before sending the event.
+    C2Ready(WirelessFwInfoTable),
+    /// CPU2 reported an error.
+    Error(ShciErrorCode),
+    /// Other system event with its sub event code and parameters.
+    Unknown { code: u16, payload: &'a [u8] },
+}
+
+/// Error reported by CPU2 with `ShciEvent::Error`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ShciErrorCode {
+    /// BLE stack initialization failed.
+    BleInit,
+    /// Thread low level driver fatal error.
+    ThreadLldFatalError,
+    /// Thread command isn't known to the stack.
+    ThreadUnknownCommand,
+    /// Zigbee command isn't known to the stack.
+    ZigbeeUnknownCommand,
+    Other(u8),
+}
+
+impl From<u8> for ShciErrorCode {
+    fn from(code: u8) -> Self {
+        match code {
+            0x00 => ShciErrorCode::BleInit,
+            0x7d => ShciErrorCode::ThreadLldFatalError,
+            0x7e => ShciErrorCode::ThreadUnknownCommand,
+            0xc8 => ShciErrorCode::ZigbeeUnknownCommand,
+            other => ShciErrorCode::Other(other),
+        }
+    }
+}
+
+/// System event parsing error.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ParseError {
+    /// Packet type isn't `TlPacketType::SysEvt`.
+    NotSysEvt(u8),
+    /// Event code isn't the vendor specific code used by system events.
+    InvalidEvtCode(u8),
+    /// Payload is shorter than its sub event code requires.
+    Truncated,
+}
+
 /// Smart pointer to the `EvtPacket` that will dispose underlying EvtPacket buffer automatically
 /// on `Drop`.
+///
+/// `EvtBox` is `Send`, so events can be passed to another context, e.g. as the message of an
+/// RTIC software task:
+///
+/// ```ignore
+/// #[task(binds = IPCC_C1_RX_IT, resources = [mbox], spawn = [handle_evt])]
+/// fn ipcc_rx(cx: ipcc_rx::Context) {
+///     cx.resources.mbox.interrupt_ipcc_rx_handler();
+///     while let Some(evt) = cx.resources.mbox.dequeue_event() {
+///         cx.spawn.handle_evt(evt).ok();
+///     }
+/// }
+///
+/// #[task(capacity = 8)]
+/// fn handle_evt(_: handle_evt::Context, evt: EvtBox) {
+///     // `evt` is released when it goes out of scope
+/// }
+/// ```
 #[derive(Debug)]
 pub struct EvtBox {
     ptr: *mut EvtPacket,
+    /// `false` for a fixed transport buffer, which is acknowledged instead of released
+    pooled: bool,
 }
 
+// An `EvtBox` is the only owner of its buffer: it's created once per node taken off a CPU2 event
+// queue and isn't `Clone`, so moving it to another context moves the ownership along. The buffer
+// is released on `Drop` by `mm::evt_drop`, which runs in a critical section and may be called
+// from any context. `EvtBox` stays `!Sync`, reading the buffer isn't synchronized.
 unsafe impl Send for EvtBox {}
 
 impl EvtBox {
     pub(super) fn new(ptr: *mut EvtPacket) -> Self {
-        Self { ptr }
+        Self { ptr, pooled: true }
+    }
+
+    /// Wraps a fixed transport buffer, e.g. the MAC 802.15.4 notification buffer. It isn't given
+    /// back to the memory manager on `Drop`, the transport acknowledges it instead.
+    pub(super) fn fixed(ptr: *mut EvtPacket) -> Self {
+        Self { ptr, pooled: false }
+    }
+
+    pub(super) fn as_ptr(&self) -> *const EvtPacket {
+        self.ptr
     }
 
     /// Copies event data from inner pointer and returns an event structure.
+    ///
+    /// Use `view` to read the event in place.
     pub fn evt(&self) -> EvtPacket {
         let mut evt = MaybeUninit::uninit();
         unsafe {
@@ -110,6 +276,89 @@ impl EvtBox {
         }
     }
 
+    /// Returns a view of the event, reading it from shared memory without copying.
+    pub fn view(&self) -> EvtView<'_> {
+        EvtView {
+            packet: unsafe { &*self.ptr },
+            payload: self.payload(),
+        }
+    }
+
+    /// Returns the packet type, see `TlPacketType`.
+    pub fn kind(&self) -> u8 {
+        unsafe { (*self.ptr).evt_serial.kind }
+    }
+
+    /// Returns the sub event code of a vendor specific event, i.e. of system and BLE ACI events.
+    ///
+    /// Returns `None` for other events and for ACL data.
+    pub fn sub_event_code(&self) -> Option<u16> {
+        self.view().sub_evt_code()
+    }
+
+    /// Returns the event parameters following the event code and length, or the data of an ACL
+    /// data packet.
+    ///
+    /// The length is taken from the packet. An ACL data length beyond the pool block is
+    /// truncated to the block; an event length always fits.
+    pub fn payload(&self) -> &[u8] {
+        unsafe {
+            if (*self.ptr).evt_serial.kind == TlPacketType::AclData as u8 {
+                let acl_data: *const AclDataPacket = self.ptr.cast();
+                let acl_serial = &(*acl_data).acl_data_serial;
+                let len = (acl_serial.length as usize).min(ACL_DATA_MAX_LEN);
+                core::slice::from_raw_parts(acl_serial.acl_data.as_ptr(), len)
+            } else {
+                let evt = &(*self.ptr).evt_serial.evt;
+                core::slice::from_raw_parts(evt.payload.as_ptr(), evt.payload_len as usize)
+            }
+        }
+    }
+
+    /// Decodes a system event.
+    ///
+    /// Payload: sub event code (2 bytes, little endian), then the sub event parameters.
+    pub fn try_into_shci_event(&self) -> Result<ShciEvent<'_>, ParseError> {
+        let kind = self.kind();
+        if kind != TlPacketType::SysEvt as u8 {
+            return Err(ParseError::NotSysEvt(kind));
+        }
+
+        let evt_code = self.evt_code();
+        if evt_code != SHCI_EVT_CODE_VENDOR {
+            return Err(ParseError::InvalidEvtCode(evt_code));
+        }
+
+        let payload = self.payload();
+        if payload.len() < 2 {
+            return Err(ParseError::Truncated);
+        }
+
+        let (code, params) = payload.split_at(2);
+        match u16::from_le_bytes([code[0], code[1]]) {
+            SHCI_SUB_EVT_CODE_READY => {
+                let info = unsafe {
+                    (*(*TL_REF_TABLE.as_ptr()).device_info_table)
+                        .wireless_fw_info_table
+                        .clone()
+                };
+                Ok(ShciEvent::C2Ready(info))
+            }
+            SHCI_SUB_EVT_ERROR_NOTIF => match params.first() {
+                Some(&code) => Ok(ShciEvent::Error(code.into())),
+                None => Err(ParseError::Truncated),
+            },
+            code => Ok(ShciEvent::Unknown {
+                code,
+                payload: params,
+            }),
+        }
+    }
+
+    fn evt_code(&self) -> u8 {
+        unsafe { (*self.ptr).evt_serial.evt.evt_code }
+    }
+
     /// Writes an underlying EvtPacket into the provided buffer.
     /// Returns a number of bytes that were written.
     /// Returns an error if event kind is unknown or if provided buffer size is not enough.
@@ -171,9 +420,192 @@ impl Drop for EvtBox {
     fn drop(&mut self) {
         use crate::ipcc::IpccExt;
 
+        if !self.pooled {
+            return;
+        }
+
         let mut ipcc = unsafe { stm32wb_pac::Peripherals::steal() }
             .IPCC
             .constrain();
         super::mm::evt_drop(self.ptr, &mut ipcc);
     }
 }
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::tl_mbox::TL_PACKET_HEADER_SIZE;
+    use core::mem::ManuallyDrop;
+
+    /// Offsets of the packet from a word boundary, CPU2 doesn't align events.
+    const OFFSETS: [usize; 4] = [0, 1, 2, 3];
+
+    #[repr(align(4))]
+    struct Block([u8; TL_PACKET_HEADER_SIZE + EVT_FRAME_SIZE + 3]);
+
+    /// Places a packet with `serial` after the header at `offset` and passes it to `f`.
+    pub(in crate::tl_mbox) fn with_evt(offset: usize, serial: &[u8], f: impl FnOnce(&EvtBox)) {
+        let mut block = Block([0xa5; TL_PACKET_HEADER_SIZE + EVT_FRAME_SIZE + 3]);
+        let start = offset + TL_PACKET_HEADER_SIZE;
+        block.0[start..start + serial.len()].copy_from_slice(serial);
+
+        // Dropping the box would release the buffer to CPU2
+        let ptr = unsafe { block.0.as_mut_ptr().add(offset) };
+        let evt = ManuallyDrop::new(EvtBox::new(ptr.cast()));
+        f(&evt);
+    }
+
+    fn written(evt: &EvtBox) -> ([u8; 300], usize) {
+        let mut buf = [0; 300];
+        let len = evt.write(&mut buf).unwrap();
+        assert_eq!(evt.size(), Ok(len));
+        (buf, len)
+    }
+
+    #[test]
+    fn command_complete_at_odd_offsets() {
+        // HCI_Reset complete: num_cmd 1, opcode 0x0c03, status 0
+        let serial = [0x04, 0x0e, 0x04, 0x01, 0x03, 0x0c, 0x00];
+
+        for &offset in &OFFSETS {
+            with_evt(offset, &serial, |evt| {
+                assert_eq!(evt.kind(), TlPacketType::BleEvt as u8);
+                assert_eq!(evt.payload(), &[0x01, 0x03, 0x0c, 0x00]);
+                assert_eq!(evt.sub_event_code(), None);
+
+                let view = evt.view();
+                assert_eq!(view.evt_code(), HCI_COMMAND_COMPLETE_EVT);
+                let cc = *view.cc().unwrap();
+                let (num_cmd, cmd_code) = (cc.num_cmd, cc.cmd_code);
+                assert_eq!((num_cmd, cmd_code, cc.status()), (1, 0x0c03, 0));
+
+                let packet = evt.evt();
+                let serial_copy = packet.evt_serial;
+                assert_eq!(serial_copy.evt.payload_len, 4);
+
+                let (buf, len) = written(evt);
+                assert_eq!(&buf[..len], &serial[..]);
+            });
+        }
+    }
+
+    #[test]
+    fn command_complete_without_status() {
+        let serial = [0x04, 0x0e, 0x03, 0x01, 0x03, 0x0c];
+
+        for &offset in &OFFSETS {
+            with_evt(offset, &serial, |evt| assert!(evt.view().cc().is_none()));
+        }
+    }
+
+    #[test]
+    fn vendor_event_at_odd_offsets() {
+        let serial = [0x04, 0xff, 0x04, 0x0c, 0x04, 0xaa, 0xbb];
+
+        for &offset in &OFFSETS {
+            with_evt(offset, &serial, |evt| {
+                assert_eq!(evt.sub_event_code(), Some(0x040c));
+                assert!(evt.view().cc().is_none());
+                assert_eq!(evt.payload(), &[0x0c, 0x04, 0xaa, 0xbb]);
+            });
+        }
+    }
+
+    #[test]
+    fn shci_events_at_odd_offsets() {
+        let sys_evt = TlPacketType::SysEvt as u8;
+
+        for &offset in &OFFSETS {
+            with_evt(
+                offset,
+                &[sys_evt, 0xff, 0x03, 0x01, 0x92, 0x7d],
+                |evt| match evt.try_into_shci_event() {
+                    Ok(ShciEvent::Error(code)) => {
+                        assert_eq!(code, ShciErrorCode::ThreadLldFatalError)
+                    }
+                    other => panic!("{:?}", other),
+                },
+            );
+
+            with_evt(
+                offset,
+                &[sys_evt, 0xff, 0x03, 0x34, 0x12, 0x55],
+                |evt| match evt.try_into_shci_event() {
+                    Ok(ShciEvent::Unknown { code, payload }) => {
+                        assert_eq!(code, 0x1234);
+                        assert_eq!(payload, &[0x55]);
+                    }
+                    other => panic!("{:?}", other),
+                },
+            );
+
+            with_evt(offset, &[sys_evt, 0xff, 0x02, 0x01, 0x92], |evt| {
+                assert_eq!(
+                    evt.try_into_shci_event().unwrap_err(),
+                    ParseError::Truncated
+                );
+            });
+
+            with_evt(offset, &[sys_evt, 0xff, 0x01, 0x01], |evt| {
+                assert_eq!(
+                    evt.try_into_shci_event().unwrap_err(),
+                    ParseError::Truncated
+                );
+            });
+
+            with_evt(offset, &[sys_evt, 0x0e, 0x00], |evt| {
+                assert_eq!(
+                    evt.try_into_shci_event().unwrap_err(),
+                    ParseError::InvalidEvtCode(0x0e)
+                );
+            });
+
+            with_evt(offset, &[0x04, 0xff, 0x00], |evt| {
+                assert_eq!(
+                    evt.try_into_shci_event().unwrap_err(),
+                    ParseError::NotSysEvt(0x04)
+                );
+            });
+        }
+    }
+
+    #[test]
+    fn acl_data_at_odd_offsets() {
+        // Handle 0x0801, 3 bytes of data
+        let serial = [0x02, 0x01, 0x08, 0x03, 0x00, 0x01, 0x02, 0x03];
+
+        for &offset in &OFFSETS {
+            with_evt(offset, &serial, |evt| {
+                assert_eq!(evt.kind(), TlPacketType::AclData as u8);
+                assert_eq!(evt.payload(), &[0x01, 0x02, 0x03]);
+                assert_eq!(evt.sub_event_code(), None);
+                assert!(evt.view().cc().is_none());
+
+                let (buf, len) = written(evt);
+                assert_eq!(&buf[..len], &serial[..]);
+            });
+        }
+    }
+
+    #[test]
+    fn acl_data_length_is_truncated_to_block() {
+        let serial = [0x02, 0x01, 0x08, 0xff, 0xff];
+
+        for &offset in &OFFSETS {
+            with_evt(offset, &serial, |evt| {
+                assert_eq!(evt.payload().len(), ACL_DATA_MAX_LEN);
+            });
+        }
+    }
+
+    #[test]
+    fn unknown_kind_isnt_written() {
+        for &offset in &OFFSETS {
+            with_evt(offset, &[0x00, 0x0e, 0x00], |evt| {
+                let mut buf = [0; 8];
+                assert_eq!(evt.write(&mut buf), Err(()));
+                assert_eq!(evt.size(), Err(()));
+            });
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! Mailbox event fan-out to multiple consumers
+//!
+//! Requires the `evt-fanout` feature. Once a subscription is created with `TlMbox::subscribe`,
+//! events are no longer put into the single consumer event queue. Instead, the IPCC RX handler
+//! copies every event into the queues of the matching subscriptions and releases the shared
+//! memory buffer right away.
+
+#![cfg(feature = "evt-fanout")]
+
+use heapless::consts::U4;
+use heapless::spsc;
+
+use crate::tl_mbox::consts::TlPacketType;
+use crate::tl_mbox::evt::{EvtBox, EvtPacket};
+use crate::tl_mbox::TL_BLE_EVENT_FRAME_SIZE;
+
+/// Maximum number of subscriptions.
+pub const MAX_SUBSCRIPTIONS: usize = 4;
+
+/// Size of the event copy kept for subscriptions created `with_payload`.
+pub const EVT_COPY_SIZE: usize = TL_BLE_EVENT_FRAME_SIZE;
+
+/// Selects events delivered to a subscription.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct EvtFilter {
+    kind: Option<u8>,
+    evt_code: Option<u8>,
+    payload: bool,
+}
+
+impl EvtFilter {
+    /// Matches all events, without copying their payload.
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Only matches events of packet type `kind`.
+    pub fn kind(mut self, kind: TlPacketType) -> Self {
+        self.kind = Some(kind as u8);
+        self
+    }
+
+    /// Only matches events with event code `evt_code`.
+    pub fn evt_code(mut self, evt_code: u8) -> Self {
+        self.evt_code = Some(evt_code);
+        self
+    }
+
+    /// Copies the whole event (as written by `EvtBox::write`) into the subscription queue.
+    pub fn with_payload(mut self) -> Self {
+        self.payload = true;
+        self
+    }
+
+    fn matches(&self, evt: &EvtPacket) -> bool {
+        self.kind.map_or(true, |kind| kind == evt.kind())
+            && self
+                .evt_code
+                .map_or(true, |evt_code| evt_code == evt.evt().evt_code)
+    }
+}
+
+/// Event metadata delivered to every matching subscription.
+#[derive(Debug, Copy, Clone)]
+pub struct EvtMeta {
+    pub kind: u8,
+    pub evt_code: u8,
+    pub payload_len: u8,
+}
+
+/// Event delivered to a subscription.
+#[derive(Clone)]
+pub struct SubscribedEvt {
+    pub meta: EvtMeta,
+    len: usize,
+    data: [u8; EVT_COPY_SIZE],
+}
+
+impl SubscribedEvt {
+    /// Returns event copy if the subscription was created `with_payload`, empty slice otherwise.
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+/// Handle of a subscription created by `TlMbox::subscribe`.
+#[derive(Debug)]
+pub struct EvtSubscription {
+    id: u8,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SubscribeError {
+    /// All `MAX_SUBSCRIPTIONS` subscriptions are in use.
+    TooManySubscriptions,
+}
+
+struct Subscription {
+    filter: EvtFilter,
+    queue: spsc::Queue<SubscribedEvt, U4>,
+    dropped: u32,
+}
+
+pub(super) struct EvtFanout {
+    subs: [Option<Subscription>; MAX_SUBSCRIPTIONS],
+}
+
+impl EvtFanout {
+    pub(super) fn new() -> Self {
+        Self {
+            subs: [None, None, None, None],
+        }
+    }
+
+    /// Returns `true` if there is at least one subscription.
+    pub(super) fn is_active(&self) -> bool {
+        self.subs.iter().any(Option::is_some)
+    }
+
+    pub(super) fn subscribe(
+        &mut self,
+        filter: EvtFilter,
+    ) -> Result<EvtSubscription, SubscribeError> {
+        let (id, slot) = self
+            .subs
+            .iter_mut()
+            .enumerate()
+            .find(|(_, slot)| slot.is_none())
+            .ok_or(SubscribeError::TooManySubscriptions)?;
+
+        *slot = Some(Subscription {
+            filter,
+            queue: spsc::Queue::new(),
+            dropped: 0,
+        });
+
+        Ok(EvtSubscription { id: id as u8 })
+    }
+
+    pub(super) fn unsubscribe(&mut self, sub: EvtSubscription) {
+        self.subs[sub.id as usize] = None;
+    }
+
+    pub(super) fn dequeue(&mut self, sub: &EvtSubscription) -> Option<SubscribedEvt> {
+        self.subs[sub.id as usize].as_mut()?.queue.dequeue()
+    }
+
+    pub(super) fn dropped(&self, sub: &EvtSubscription) -> u32 {
+        self.subs[sub.id as usize]
+            .as_ref()
+            .map_or(0, |sub| sub.dropped)
+    }
+
+    /// Copies `evt` into the queues of the matching subscriptions.
+    ///
+    /// Events that don't fit into a subscription queue are counted as dropped for it.
+    pub(super) fn dispatch(&mut self, evt: &EvtBox) {
+        let packet = evt.evt();
+        let meta = EvtMeta {
+            kind: packet.kind(),
+            evt_code: packet.evt().evt_code,
+            payload_len: packet.evt().payload_len,
+        };
+
+        for sub in self.subs.iter_mut().flatten() {
+            if !sub.filter.matches(&packet) {
+                continue;
+            }
+
+            let mut copy = SubscribedEvt {
+                meta,
+                len: 0,
+                data: [0; EVT_COPY_SIZE],
+            };
+
+            if sub.filter.payload {
+                match evt.write(&mut copy.data) {
+                    Ok(len) => copy.len = len,
+                    Err(()) => {
+                        sub.dropped = sub.dropped.wrapping_add(1);
+                        continue;
+                    }
+                }
+            }
+
+            if sub.queue.enqueue(copy).is_err() {
+                sub.dropped = sub.dropped.wrapping_add(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! `bluetooth-hci` controller on top of the mailbox
+//!
+//! Requires the `bluetooth-hci` feature. `HciController` implements `bluetooth_hci::Controller`,
+//! so the host side of `bluetooth-hci` (`bluetooth_hci::host::uart::Hci`) and vendor crates
+//! built on it drive the BLE stack on CPU2 directly:
+//!
+//! ```ignore
+//! use bluetooth_hci::host::{uart::Hci as UartHci, Hci};
+//!
+//! let mut hci: HciController<Stm32Wb5xTypes> = HciController::new(&mut mbox);
+//! block!(hci.reset()).unwrap();
+//! let evt = block!(hci.read()).unwrap();
+//! ```
+//!
+//! Commands and ACL data are written into the BLE command and ACL data buffers. Events are read
+//! from the event queue, packet by packet with the UART framing (packet type first) expected by
+//! `bluetooth_hci::host::uart`. An event's buffer is released once it has been read completely.
+//! Events must be delivered through the event queue (`BleEvtDelivery::Queue`) and received by the
+//! IPCC RX handler (or `HciController::poll_rx`); reads return `WouldBlock` until then.
+
+#![cfg(feature = "bluetooth-hci")]
+
+use core::marker::PhantomData;
+
+use crate::tl_mbox::ble::{self, AclError, AclPbFlag, CmdError};
+use crate::tl_mbox::consts::TlPacketType;
+use crate::tl_mbox::evt::EvtBox;
+use crate::tl_mbox::{TlMbox, TL_EVT_HEADER_SIZE};
+
+/// Largest packet read from the event queue: event header and 255 bytes of parameters.
+const HCI_MAX_PACKET_SIZE: usize = TL_EVT_HEADER_SIZE + 255;
+
+/// `HciController` error
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum HciError {
+    Cmd(CmdError),
+    Acl(AclError),
+    /// Written packet type isn't a command or ACL data.
+    UnsupportedPacketType(u8),
+    /// Written header is too short for its packet type.
+    InvalidHeader,
+    /// Read goes past the end of the current packet.
+    ReadPastPacket,
+    /// Queued event has an unknown packet type or doesn't fit the read buffer.
+    InvalidEvent,
+}
+
+/// `bluetooth_hci::Controller` over the mailbox, see module documentation.
+///
+/// `V` selects the vendor-specific commands and events, e.g. `stm32wb55::Stm32Wb5xTypes`.
+pub struct HciController<'a, V> {
+    mbox: &'a mut TlMbox,
+
+    /// Event being read, released once `pos` reaches `len`
+    evt: Option<EvtBox>,
+    buf: [u8; HCI_MAX_PACKET_SIZE],
+    len: usize,
+    pos: usize,
+
+    _vendor: PhantomData<V>,
+}
+
+impl<'a, V> HciController<'a, V> {
+    pub fn new(mbox: &'a mut TlMbox) -> Self {
+        HciController {
+            mbox,
+            evt: None,
+            buf: [0; HCI_MAX_PACKET_SIZE],
+            len: 0,
+            pos: 0,
+            _vendor: PhantomData,
+        }
+    }
+
+    /// Services the mailbox with `TlMbox::poll`, for applications that poll the mailbox instead
+    /// of handling the IPCC interrupts.
+    pub fn poll_rx(&mut self) {
+        self.mbox.poll();
+    }
+
+    /// Takes the next event from the queue unless one is being read.
+    fn fill(&mut self) -> nb::Result<(), HciError> {
+        if self.evt.is_some() {
+            return Ok(());
+        }
+
+        let evt = self.mbox.dequeue_event().ok_or(nb::Error::WouldBlock)?;
+        self.len = evt
+            .write(&mut self.buf)
+            .map_err(|_| nb::Error::Other(HciError::InvalidEvent))?;
+        self.pos = 0;
+        self.evt = Some(evt);
+
+        Ok(())
+    }
+
+    fn remaining(&self) -> usize {
+        self.len - self.pos
+    }
+}
+
+impl<'a, V: bluetooth_hci::Vendor> bluetooth_hci::Controller for HciController<'a, V> {
+    type Error = HciError;
+    type Header = bluetooth_hci::host::uart::CommandHeader;
+    type Vendor = V;
+
+    fn write(&mut self, header: &[u8], payload: &[u8]) -> nb::Result<(), Self::Error> {
+        let ty = *header
+            .first()
+            .ok_or(nb::Error::Other(HciError::InvalidHeader))?;
+
+        match ty {
+            // Packet type, opcode, parameter length
+            t if t == TlPacketType::BleCmd as u8 && header.len() >= 4 => {
+                let opcode = u16::from_le_bytes([header[1], header[2]]);
+                ble::send_cmd(self.mbox.ipcc(), opcode, payload)
+                    .map_err(|e| nb::Error::Other(HciError::Cmd(e)))
+            }
+            // Packet type, handle and flags, data length
+            t if t == TlPacketType::AclData as u8 && header.len() >= 5 => {
+                let handle = u16::from_le_bytes([header[1], header[2]]);
+                let pb_flag = match (handle >> 12) & 0b11 {
+                    0b00 => AclPbFlag::FirstNonFlushable,
+                    0b01 => AclPbFlag::Continuing,
+                    _ => AclPbFlag::FirstFlushable,
+                };
+
+                self.mbox
+                    .send_acl_data(handle & 0x0fff, pb_flag, payload)
+                    .map_err(|e| match e {
+                        nb::Error::WouldBlock => nb::Error::WouldBlock,
+                        nb::Error::Other(e) => nb::Error::Other(HciError::Acl(e)),
+                    })
+            }
+            t if t == TlPacketType::BleCmd as u8 || t == TlPacketType::AclData as u8 => {
+                Err(nb::Error::Other(HciError::InvalidHeader))
+            }
+            other => Err(nb::Error::Other(HciError::UnsupportedPacketType(other))),
+        }
+    }
+
+    fn read_into(&mut self, buffer: &mut [u8]) -> nb::Result<(), Self::Error> {
+        self.fill()?;
+
+        if buffer.len() > self.remaining() {
+            return Err(nb::Error::Other(HciError::ReadPastPacket));
+        }
+
+        buffer.copy_from_slice(&self.buf[self.pos..self.pos + buffer.len()]);
+        self.pos += buffer.len();
+
+        if self.remaining() == 0 {
+            // Releases the shared memory buffer
+            self.evt = None;
+        }
+
+        Ok(())
+    }
+
+    fn peek(&mut self, n: usize) -> nb::Result<u8, Self::Error> {
+        self.fill()?;
+
+        if n >= self.remaining() {
+            return Err(nb::Error::Other(HciError::ReadPastPacket));
+        }
+
+        Ok(self.buf[self.pos + n])
+    }
+}
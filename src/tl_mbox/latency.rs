@@ -0,0 +1,116 @@
+//! Mailbox event latency measurement
+//!
+//! Requires the `mbox-latency` feature. Timestamps are taken from the DWT cycle counter, which
+//! must be enabled by the application (`DCB::enable_trace` and `DWT::enable_cycle_counter`).
+
+#![cfg(feature = "mbox-latency")]
+
+use cortex_m::peripheral::DWT;
+
+/// Number of latency samples kept.
+pub const NUM_SAMPLES: usize = 64;
+
+const NUM_PENDING: usize = 32;
+
+/// Timestamps of a single event in DWT cycles.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct LatencySample {
+    /// IPCC RX handler entry
+    pub irq_entry: u32,
+    /// Event put into the event queue
+    pub enqueue: u32,
+    /// Event taken from the event queue by the application
+    pub dequeue: u32,
+}
+
+impl LatencySample {
+    /// Cycles between IPCC RX handler entry and application dequeue.
+    pub fn total(&self) -> u32 {
+        self.dequeue.wrapping_sub(self.irq_entry)
+    }
+
+    /// Cycles the event spent in the event queue.
+    pub fn queued(&self) -> u32 {
+        self.dequeue.wrapping_sub(self.enqueue)
+    }
+}
+
+pub(super) struct LatencyTracker {
+    /// Timestamps of events that are still in the event queue, in queue order
+    pending: [(u32, u32); NUM_PENDING],
+    pending_head: usize,
+    pending_len: usize,
+
+    samples: [LatencySample; NUM_SAMPLES],
+    next_sample: usize,
+    num_samples: usize,
+}
+
+impl LatencyTracker {
+    pub(super) fn new() -> Self {
+        LatencyTracker {
+            pending: [(0, 0); NUM_PENDING],
+            pending_head: 0,
+            pending_len: 0,
+            samples: [LatencySample::default(); NUM_SAMPLES],
+            next_sample: 0,
+            num_samples: 0,
+        }
+    }
+
+    pub(super) fn timestamp() -> u32 {
+        DWT::get_cycle_count()
+    }
+
+    /// Records `count` events enqueued by the handler that was entered at `irq_entry`.
+    pub(super) fn on_enqueue(&mut self, irq_entry: u32, count: usize) {
+        let enqueue = Self::timestamp();
+
+        for _ in 0..count {
+            if self.pending_len == NUM_PENDING {
+                break;
+            }
+
+            let i = (self.pending_head + self.pending_len) % NUM_PENDING;
+            self.pending[i] = (irq_entry, enqueue);
+            self.pending_len += 1;
+        }
+    }
+
+    pub(super) fn on_dequeue(&mut self) {
+        if self.pending_len == 0 {
+            return;
+        }
+
+        let (irq_entry, enqueue) = self.pending[self.pending_head];
+        self.pending_head = (self.pending_head + 1) % NUM_PENDING;
+        self.pending_len -= 1;
+
+        self.samples[self.next_sample] = LatencySample {
+            irq_entry,
+            enqueue,
+            dequeue: Self::timestamp(),
+        };
+        self.next_sample = (self.next_sample + 1) % NUM_SAMPLES;
+        self.num_samples = core::cmp::min(self.num_samples + 1, NUM_SAMPLES);
+    }
+
+    /// Forgets the oldest queued event, which was dropped without being dequeued.
+    pub(super) fn on_drop(&mut self) {
+        if self.pending_len == 0 {
+            return;
+        }
+
+        self.pending_head = (self.pending_head + 1) % NUM_PENDING;
+        self.pending_len -= 1;
+    }
+
+    pub(super) fn samples(&self) -> &[LatencySample] {
+        &self.samples[..self.num_samples]
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.next_sample = 0;
+        self.num_samples = 0;
+    }
+}
@@ -0,0 +1,112 @@
+//! Compile-time checks of the structures shared with CPU2.
+//!
+//! Expected sizes are taken from ST's `tl.h`, `mbox_def.h` and `shci.h`. Field offsets of the
+//! packed structures follow from the field sizes and are listed next to each check. Addresses
+//! of the tables in shared RAM are fixed by the `memory_x*.x` linker scripts and must be updated
+//! together with these checks.
+
+use super::cmd::{AclDataPacket, AclDataSerial, Cmd, CmdPacket, CmdSerial};
+use super::evt::{AsynchEvt, CcEvt, CsEvt, Evt, EvtPacket, EvtSerial};
+use super::shci::ShciBleInitCmdParam;
+use super::{
+    BleTable, DeviceInfoTable, Mac802154Table, MemManagerTable, PacketHeader, RefTable,
+    RssInfoTable, SafeBootInfoTable, SysTable, ThreadTable, TracesTable, WirelessFwInfoTable,
+};
+
+macro_rules! assert_size {
+    ($ty:ty, $size:expr) => {
+        const _: [(); $size] = [(); core::mem::size_of::<$ty>()];
+    };
+}
+
+macro_rules! assert_align {
+    ($ty:ty, $align:expr) => {
+        const _: [(); $align] = [(); core::mem::align_of::<$ty>()];
+    };
+}
+
+// MB_RefTable_t, placed at the start of SRAM2a
+// 0x00 p_device_info_table
+// 0x04 p_ble_table
+// 0x08 p_thread_table
+// 0x0c p_sys_table
+// 0x10 p_mem_manager_table
+// 0x14 p_traces_table
+// 0x18 p_mac_802_15_4_table
+assert_size!(RefTable, 28);
+assert_align!(RefTable, 4);
+
+// MB_SafeBootInfoTable_t: 0x00 version
+assert_size!(SafeBootInfoTable, 4);
+
+// MB_RssInfoTable_t: 0x00 version, 0x04 memory_size, 0x08 rss_info
+assert_size!(RssInfoTable, 12);
+
+// MB_WirelessFwInfoTable_t: 0x00 version, 0x04 memory_size, 0x08 thread_info, 0x0c ble_info
+assert_size!(WirelessFwInfoTable, 16);
+
+// MB_DeviceInfoTable_t: 0x00 safe boot, 0x04 RSS, 0x10 wireless firmware
+assert_size!(DeviceInfoTable, 32);
+assert_align!(DeviceInfoTable, 4);
+
+// MB_BleTable_t: 0x00 pcmd_buffer, 0x04 pcs_buffer, 0x08 pevt_queue, 0x0c phci_acl_data_buffer
+assert_size!(BleTable, 16);
+assert_align!(BleTable, 4);
+
+// MB_ThreadTable_t: 0x00 notack_buffer, 0x04 clicmdrsp_buffer, 0x08 otcmdrsp_buffer
+assert_size!(ThreadTable, 12);
+assert_align!(ThreadTable, 4);
+
+// MB_SysTable_t: 0x00 pcmd_buffer, 0x04 sys_queue
+assert_size!(SysTable, 8);
+assert_align!(SysTable, 4);
+
+// MB_MemManagerTable_t
+// 0x00 spare_ble_buffer
+// 0x04 spare_sys_buffer
+// 0x08 blepool
+// 0x0c blepoolsize
+// 0x10 pevt_free_buffer_queue
+// 0x14 traces_evt_pool
+// 0x18 tracespoolsize
+assert_size!(MemManagerTable, 28);
+assert_align!(MemManagerTable, 4);
+
+// MB_TracesTable_t: 0x00 traces_queue
+assert_size!(TracesTable, 4);
+
+// MB_Mac_802_15_4_t: 0x00 p_cmdrsp_buffer, 0x04 p_notack_buffer, 0x08 evt_queue
+assert_size!(Mac802154Table, 12);
+
+// tListNode: 0x00 next, 0x04 prev
+assert_size!(PacketHeader, 8);
+assert_align!(PacketHeader, 4);
+
+// TL_Cmd_t: 0x00 cmdcode, 0x02 plen, 0x03 payload[255]
+assert_size!(Cmd, 258);
+// TL_CmdSerial_t: 0x00 type, 0x01 cmd
+assert_size!(CmdSerial, 259);
+// TL_CmdPacket_t: 0x00 header, 0x08 cmdserial
+assert_size!(CmdPacket, 267);
+
+// TL_AclDataSerial_t: 0x00 type, 0x01 handle, 0x03 length, 0x05 acl_data
+assert_size!(AclDataSerial, 6);
+// TL_AclDataPacket_t: 0x00 header, 0x08 AclDataSerial
+assert_size!(AclDataPacket, 14);
+
+// TL_Evt_t: 0x00 evtcode, 0x01 plen, 0x02 payload
+assert_size!(Evt, 3);
+// TL_EvtSerial_t: 0x00 type, 0x01 evt
+assert_size!(EvtSerial, 4);
+// TL_EvtPacket_t: 0x00 header, 0x08 evtserial
+assert_size!(EvtPacket, 12);
+
+// TL_CsEvt_t: 0x00 status, 0x01 numcmd, 0x02 cmdcode
+assert_size!(CsEvt, 4);
+// TL_CcEvt_t: 0x00 numcmd, 0x01 cmdcode, 0x03 payload
+assert_size!(CcEvt, 4);
+// TL_AsynchEvt_t: 0x00 subevtcode, 0x02 payload
+assert_size!(AsynchEvt, 3);
+
+// SHCI_C2_Ble_Init_Cmd_Param_t
+assert_size!(ShciBleInitCmdParam, 33);
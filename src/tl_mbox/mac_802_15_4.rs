@@ -0,0 +1,217 @@
+//! MAC 802.15.4 transport
+//!
+//! Requires the `mac-802-15-4` feature and a CPU2 firmware with the 802.15.4 MAC stack. The MAC
+//! transport uses the same IPCC channels as Thread, so it can't be combined with `thread`.
+//!
+//! `mac802154` is an alias of the feature.
+//!
+//! A MAC command is written into the command buffer and CPU2 answers in the same buffer when it
+//! frees the MAC command channel. Notifications are received one at a time in the notification
+//! buffer and put into a dedicated queue, from which `TlMbox::mac_dequeue_notification` returns
+//! them as `EvtBox` values. CPU2 sends the next one only after the `EvtBox` is given back to
+//! `TlMbox::mac_ack_notification`, as the next notification overwrites the buffer:
+//!
+//! ```ignore
+//! if let Some(notification) = mbox.mac_dequeue_notification() {
+//!     // handle `notification.payload()`
+//!     mbox.mac_ack_notification(notification);
+//! }
+//! ```
+//!
+//! Dropping the `EvtBox` instead leaves the notification unacknowledged, which stalls the MAC
+//! stack.
+
+#![cfg(feature = "mac-802-15-4")]
+
+#[cfg(feature = "thread")]
+compile_error!("`mac-802-15-4` and `thread` features share IPCC channels, enable only one");
+
+use core::mem::MaybeUninit;
+
+use heapless::spsc;
+
+use crate::ipcc::Ipcc;
+use crate::tl_mbox::channels;
+use crate::tl_mbox::cmd::CmdPacket;
+use crate::tl_mbox::consts::TlPacketType;
+use crate::tl_mbox::evt::{EvtBox, EvtPacket};
+use crate::tl_mbox::{
+    Mac802154Table, TL_EVT_HEADER_SIZE, TL_MAC_802_15_4_TABLE, TL_PACKET_HEADER_SIZE,
+};
+
+/// Maximum payload of a command, response or notification.
+pub const MAC_MAX_PAYLOAD: usize = 255;
+
+#[link_section = "MAC_802_15_4_CMD_BUFFER"]
+static mut MAC_802_15_4_CMD_BUFFER: MaybeUninit<CmdPacket> = MaybeUninit::uninit();
+
+#[link_section = "MAC_802_15_4_NOTIF_BUFFER"]
+static mut MAC_802_15_4_NOTIF_BUFFER: MaybeUninit<
+    [u8; TL_PACKET_HEADER_SIZE + TL_EVT_HEADER_SIZE + MAC_MAX_PAYLOAD],
+> = MaybeUninit::uninit();
+
+/// CPU2 sends one notification at a time
+type NotificationQueue = spsc::Queue<EvtBox, heapless::consts::U1, u8, spsc::SingleCore>;
+
+/// MAC command error
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MacError {
+    /// Previous command wasn't answered yet.
+    Busy,
+    /// Parameters are longer than `MAC_MAX_PAYLOAD`.
+    PayloadTooLarge,
+}
+
+/// Response to a MAC command.
+#[derive(Copy, Clone)]
+pub struct MacResponse {
+    len: u8,
+    payload: [u8; MAC_MAX_PAYLOAD],
+}
+
+impl MacResponse {
+    /// Returns the response parameters.
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.len as usize]
+    }
+}
+
+impl core::fmt::Debug for MacResponse {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("MacResponse")
+            .field("payload", &self.payload())
+            .finish()
+    }
+}
+
+pub struct Mac802154 {
+    cmd_pending: bool,
+    response: Option<MacResponse>,
+    notifications: NotificationQueue,
+    /// Notification received and not acknowledged yet
+    notification_pending: bool,
+}
+
+impl Mac802154 {
+    pub(super) fn new(ipcc: &mut Ipcc) -> Self {
+        unsafe {
+            TL_MAC_802_15_4_TABLE = MaybeUninit::new(Mac802154Table {
+                p_cmdrsp_buffer: MAC_802_15_4_CMD_BUFFER.as_ptr().cast(),
+                p_notack_buffer: MAC_802_15_4_NOTIF_BUFFER.as_ptr().cast(),
+                evt_queue: core::ptr::null(),
+            });
+        }
+
+        ipcc.c1_set_rx_channel(
+            channels::cpu2::IPCC_MAC_802_15_4_NOTIFICATION_ACK_CHANNEL,
+            true,
+        );
+
+        Mac802154 {
+            cmd_pending: false,
+            response: None,
+            notifications: unsafe { spsc::Queue::u8_sc() },
+            notification_pending: false,
+        }
+    }
+
+    /// Writes MAC command `cmd_code` with `params` and notifies CPU2.
+    pub(super) fn send_cmd(
+        &mut self,
+        ipcc: &mut Ipcc,
+        cmd_code: u16,
+        params: &[u8],
+    ) -> Result<(), MacError> {
+        if self.cmd_pending {
+            return Err(MacError::Busy);
+        }
+        unsafe {
+            let buf = MAC_802_15_4_CMD_BUFFER.as_mut_ptr();
+            CmdPacket::write_into(buf, TlPacketType::OtCmd, cmd_code, params)
+                .map_err(|_| MacError::PayloadTooLarge)?;
+        }
+
+        self.cmd_pending = true;
+        self.response = None;
+
+        ipcc.c1_set_flag_channel(channels::cpu1::IPCC_MAC_802_15_4_CMD_RSP_CHANNEL);
+        ipcc.c1_set_tx_channel(channels::cpu1::IPCC_MAC_802_15_4_CMD_RSP_CHANNEL, true);
+        super::count_cmd();
+
+        Ok(())
+    }
+
+    /// MAC command channel freed: CPU2 wrote the response into the command buffer.
+    pub(super) fn cmd_rsp_handler(&mut self, ipcc: &mut Ipcc) {
+        ipcc.c1_set_tx_channel(channels::cpu1::IPCC_MAC_802_15_4_CMD_RSP_CHANNEL, false);
+
+        let payload = unsafe { evt_payload(MAC_802_15_4_CMD_BUFFER.as_ptr().cast()) };
+        let mut response = MacResponse {
+            len: payload.len() as u8,
+            payload: [0; MAC_MAX_PAYLOAD],
+        };
+        response.payload[..payload.len()].copy_from_slice(payload);
+
+        self.response = Some(response);
+        self.cmd_pending = false;
+    }
+
+    pub(super) fn take_response(&mut self) -> Option<MacResponse> {
+        self.response.take()
+    }
+
+    pub(super) fn cmd_pending(&self) -> bool {
+        self.cmd_pending
+    }
+
+    /// Notification channel occupied: queues the notification and masks the channel until it is
+    /// acknowledged.
+    pub(super) fn notification_handler(&mut self, ipcc: &mut Ipcc) {
+        ipcc.c1_set_rx_channel(
+            channels::cpu2::IPCC_MAC_802_15_4_NOTIFICATION_ACK_CHANNEL,
+            false,
+        );
+        if self.notification_pending {
+            return;
+        }
+
+        let evt = EvtBox::fixed(unsafe { MAC_802_15_4_NOTIF_BUFFER.as_mut_ptr().cast() });
+        // Can't be full, the previous notification was acknowledged
+        let _ = self.notifications.enqueue(evt);
+        self.notification_pending = true;
+    }
+
+    pub(super) fn dequeue_notification(&mut self) -> Option<EvtBox> {
+        self.notifications.dequeue()
+    }
+
+    /// Acknowledges `notification`, CPU2 may then overwrite its buffer with the next one.
+    pub(super) fn ack_notification(&mut self, ipcc: &mut Ipcc, notification: EvtBox) {
+        let notif_buffer: *const EvtPacket = unsafe { MAC_802_15_4_NOTIF_BUFFER.as_ptr().cast() };
+        debug_assert!(
+            notification.as_ptr() == notif_buffer,
+            "not a MAC 802.15.4 notification"
+        );
+        drop(notification);
+
+        if !self.notification_pending {
+            return;
+        }
+
+        unsafe {
+            let ack: *mut CmdPacket = MAC_802_15_4_NOTIF_BUFFER.as_mut_ptr().cast();
+            (*ack).cmdserial.ty = TlPacketType::OtAck as u8;
+        }
+        self.notification_pending = false;
+
+        let channel = channels::cpu2::IPCC_MAC_802_15_4_NOTIFICATION_ACK_CHANNEL;
+        ipcc.c1_clear_flag_channel(channel);
+        ipcc.c1_set_rx_channel(channel, true);
+    }
+}
+
+/// Returns the parameters of the event in `evt`.
+unsafe fn evt_payload<'a>(evt: *const EvtPacket) -> &'a [u8] {
+    let evt = &(*evt).evt_serial.evt;
+    core::slice::from_raw_parts(evt.payload.as_ptr(), evt.payload_len as usize)
+}
@@ -1,26 +1,39 @@
 //! MemoryManager routines.
 
 use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU32, Ordering};
 
 use super::channels::cpu1::IPCC_MM_RELEASE_BUFFER_CHANNEL;
-use super::unsafe_linked_list::{
-    LST_init_head, LST_insert_tail, LST_is_empty, LST_remove_head, LinkedListNode,
-};
+use super::unsafe_linked_list::{LST_init_head, LST_insert_tail, ShmemList};
 use super::{
-    MemManagerTable, BLE_SPARE_EVT_BUF, EVT_POOL, FREE_BUF_QUEUE, LOCAL_FREE_BUF_QUEUE, POOL_SIZE,
-    SYS_SPARE_EVT_BUF, TL_MEM_MANAGER_TABLE,
+    MemManagerTable, BLE_SPARE_EVT_BUF, EVT_POOL, FREE_BUF_QUEUE, LOCAL_FREE_BUF_QUEUE, SRAM2_END,
+    SRAM2_START, SYS_SPARE_EVT_BUF, TL_MEM_MANAGER_TABLE,
 };
 
+use crate::ipcc::token::IpccTxToken;
 use crate::ipcc::Ipcc;
 use crate::tl_mbox::evt::EvtPacket;
 use crate::tl_mbox::TL_REF_TABLE;
 
+/// Buffers are released from `EvtBox::drop`, without access to the mailbox.
+static RELEASED_BUFFERS: AtomicU32 = AtomicU32::new(0);
+
 pub(super) struct MemoryManager {}
 
 impl MemoryManager {
-    pub fn new() -> Self {
+    /// Gives `blepoolsize` bytes of the BLE event pool to CPU2.
+    pub fn new(blepoolsize: u32) -> Self {
         // Configure MemManager
         unsafe {
+            #[cfg(feature = "traces")]
+            let (traces_evt_pool, tracespoolsize) = (
+                super::traces::TRACES_EVT_POOL.as_ptr().cast(),
+                super::traces::TRACES_POOL_SIZE as u32,
+            );
+            // Without traces CPU2 doesn't need a dedicated pool, like in ST's applications
+            #[cfg(not(feature = "traces"))]
+            let (traces_evt_pool, tracespoolsize) = (core::ptr::null(), 0);
+
             LST_init_head(FREE_BUF_QUEUE.as_mut_ptr());
             LST_init_head(LOCAL_FREE_BUF_QUEUE.as_mut_ptr());
 
@@ -28,54 +41,94 @@ impl MemoryManager {
                 spare_ble_buffer: BLE_SPARE_EVT_BUF.as_ptr().cast(),
                 spare_sys_buffer: SYS_SPARE_EVT_BUF.as_ptr().cast(),
                 blepool: EVT_POOL.as_ptr().cast(),
-                blepoolsize: POOL_SIZE as u32,
+                blepoolsize,
                 pevt_free_buffer_queue: FREE_BUF_QUEUE.as_mut_ptr(),
-                traces_evt_pool: core::ptr::null(),
-                tracespoolsize: 0,
+                traces_evt_pool,
+                tracespoolsize,
             });
+
+            let table = &*TL_MEM_MANAGER_TABLE.as_ptr();
+            debug_assert_shared(table.spare_ble_buffer as usize);
+            debug_assert_shared(table.spare_sys_buffer as usize);
+            debug_assert_shared(table.blepool as usize);
+            debug_assert_shared(table.pevt_free_buffer_queue as usize);
+            debug_assert_shared(TL_MEM_MANAGER_TABLE.as_ptr() as usize);
         }
 
         MemoryManager {}
     }
 }
 
+const RELEASE_BUFFER: IpccTxToken = IpccTxToken::new(IPCC_MM_RELEASE_BUFFER_CHANNEL);
+
+/// Checks in debug builds that CPU2 can use the buffer at `addr`.
+fn debug_assert_shared(addr: usize) {
+    debug_assert!(addr % 4 == 0, "shared buffer not 4-byte aligned");
+    debug_assert!(
+        (SRAM2_START..SRAM2_END).contains(&addr),
+        "shared buffer outside of SRAM2"
+    );
+}
+
+/// Gives the buffer of a dropped event back to CPU2.
+///
+/// The buffer is moved to the free buffer queue right away if the release channel is idle,
+/// otherwise the IPCC TX handler (`free_buf_handler`) moves it once CPU2 has consumed the
+/// previous release.
 pub fn evt_drop(evt: *mut EvtPacket, ipcc: &mut Ipcc) {
-    unsafe {
+    // Events may be dropped from any context: the channel state must not change between the
+    // check and the release, and the TX handler must not drain the local queue meanwhile.
+    cortex_m::interrupt::free(|_| unsafe {
         let list_node: *mut _ = evt.cast();
 
         LST_insert_tail(LOCAL_FREE_BUF_QUEUE.as_mut_ptr(), list_node);
 
-        let channel_is_busy = ipcc.c1_is_active_flag(IPCC_MM_RELEASE_BUFFER_CHANNEL);
-
         // Postpone event buffer freeing to IPCC interrupt handler
-        if channel_is_busy {
-            ipcc.c1_set_tx_channel(IPCC_MM_RELEASE_BUFFER_CHANNEL, true);
-        } else {
-            send_free_buf();
-            ipcc.c1_set_flag_channel(IPCC_MM_RELEASE_BUFFER_CHANNEL);
+        if RELEASE_BUFFER.is_busy(ipcc) {
+            RELEASE_BUFFER.enable_ack_irq(ipcc);
+        } else if send_free_buf().is_ok() {
+            RELEASE_BUFFER.notify_simplex(ipcc);
         }
-    }
+    });
 }
 
 /// Gives free event buffers back to the CPU2 from local buffer queue.
-pub fn send_free_buf() {
+///
+/// Returns `Err` and keeps the buffers if the memory manager table isn't set up.
+pub fn send_free_buf() -> Result<(), ()> {
     unsafe {
-        let mut node_ptr: *mut LinkedListNode = core::ptr::null_mut();
-        let node_ptr_ptr: *mut *mut LinkedListNode = &mut node_ptr;
-
-        while !LST_is_empty(LOCAL_FREE_BUF_QUEUE.as_mut_ptr()) {
-            LST_remove_head(LOCAL_FREE_BUF_QUEUE.as_mut_ptr(), node_ptr_ptr);
-            LST_insert_tail(
-                (&*(*TL_REF_TABLE.as_ptr()).mem_manager_table).pevt_free_buffer_queue,
-                node_ptr,
-            );
+        let mem_manager_table = (*TL_REF_TABLE.as_ptr()).mem_manager_table;
+        if mem_manager_table.is_null() {
+            return Err(());
+        }
+
+        let mut local = ShmemList::from_head(LOCAL_FREE_BUF_QUEUE.as_mut_ptr());
+        let mut free = ShmemList::from_head((*mem_manager_table).pevt_free_buffer_queue);
+        while let Some(node) = local.pop_head() {
+            free.push_tail(node);
+            RELEASED_BUFFERS.fetch_add(1, Ordering::Relaxed);
         }
     }
+
+    Ok(())
+}
+
+/// Returns the number of buffers given back to CPU2, see `MboxStats::buffers_released`.
+pub(super) fn released_buffers() -> u32 {
+    RELEASED_BUFFERS.load(Ordering::Relaxed)
+}
+
+pub(super) fn clear_released_buffers() {
+    RELEASED_BUFFERS.store(0, Ordering::Relaxed);
 }
 
 /// Free buffer channel interrupt handler.
-pub fn free_buf_handler(ipcc: &mut Ipcc) {
-    ipcc.c1_set_tx_channel(IPCC_MM_RELEASE_BUFFER_CHANNEL, false);
-    send_free_buf();
-    ipcc.c1_set_flag_channel(IPCC_MM_RELEASE_BUFFER_CHANNEL);
+pub fn free_buf_handler(ipcc: &mut Ipcc) -> Result<(), ()> {
+    // May be preempted by `evt_drop`, see there
+    cortex_m::interrupt::free(|_| {
+        RELEASE_BUFFER.disable_ack_irq(ipcc);
+        send_free_buf()?;
+        RELEASE_BUFFER.notify_simplex(ipcc);
+        Ok(())
+    })
 }
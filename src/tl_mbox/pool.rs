@@ -0,0 +1,78 @@
+//! BLE event pool
+//!
+//! CPU2 allocates BLE events from a pool in shared memory. The pool is a static buffer, so it's
+//! sized at build time:
+//!
+//! * `ble-evt-pool-2`, `ble-evt-pool-6` or `ble-evt-pool-10` select the number of events it
+//!   holds, 5 if none is selected (ST's default)
+//! * `ble-evt-payload-27` sizes the events for 27 byte payloads instead of 255 bytes
+//!
+//! A beacon gets by with `ble-evt-pool-2` and `ble-evt-payload-27`, a scanner wants
+//! `ble-evt-pool-6`. The pool must fit into the `EVT_POOL` section of the linker scripts, which
+//! is checked at compile time, e.g. `ble-evt-pool-10` requires `ble-evt-payload-27`.
+//!
+//! `TlMboxConfig::ble_evt_pool_len` hands a smaller part of the pool to CPU2.
+
+use super::{divc, TL_EVT_HEADER_SIZE, TL_PACKET_HEADER_SIZE};
+
+#[cfg(any(
+    all(feature = "ble-evt-pool-2", feature = "ble-evt-pool-6"),
+    all(feature = "ble-evt-pool-2", feature = "ble-evt-pool-10"),
+    all(feature = "ble-evt-pool-6", feature = "ble-evt-pool-10"),
+))]
+compile_error!(
+    "Select at most one of the `ble-evt-pool-2`, `ble-evt-pool-6` and `ble-evt-pool-10` features"
+);
+
+/**
+ * Queue length of BLE Event
+ * This parameter defines the number of asynchronous events that can be stored in the HCI layer before
+ * being reported to the application. When a command is sent to the BLE core coprocessor, the HCI layer
+ * is waiting for the event with the Num_HCI_Command_Packets set to 1. The receive queue shall be large
+ * enough to store all asynchronous events received in between.
+ * When CFG_TLBLE_MOST_EVENT_PAYLOAD_SIZE is set to 27, this allow to store three 255 bytes long asynchronous events
+ * between the HCI command and its event.
+ * This parameter depends on the value given to CFG_TLBLE_MOST_EVENT_PAYLOAD_SIZE. When the queue size is to small,
+ * the system may hang if the queue is full with asynchronous events and the HCI layer is still waiting
+ * for a CC/CS event, In that case, the notification TL_BLE_HCI_ToNot() is called to indicate
+ * to the application a HCI command did not receive its command event within 30s (Default HCI Timeout).
+ */
+#[cfg(feature = "ble-evt-pool-2")]
+pub const BLE_EVT_POOL_LEN: usize = 2;
+#[cfg(feature = "ble-evt-pool-6")]
+pub const BLE_EVT_POOL_LEN: usize = 6;
+#[cfg(feature = "ble-evt-pool-10")]
+pub const BLE_EVT_POOL_LEN: usize = 10;
+#[cfg(not(any(
+    feature = "ble-evt-pool-2",
+    feature = "ble-evt-pool-6",
+    feature = "ble-evt-pool-10"
+)))]
+pub const BLE_EVT_POOL_LEN: usize = 5;
+
+/// Event payload size the pool is sized for, `CFG_TLBLE_MOST_EVENT_PAYLOAD_SIZE`.
+#[cfg(feature = "ble-evt-payload-27")]
+pub const BLE_EVT_MOST_PAYLOAD_SIZE: usize = 27;
+#[cfg(not(feature = "ble-evt-payload-27"))]
+pub const BLE_EVT_MOST_PAYLOAD_SIZE: usize = 255;
+
+const BLE_EVT_FRAME_SIZE: usize =
+    TL_PACKET_HEADER_SIZE + TL_EVT_HEADER_SIZE + BLE_EVT_MOST_PAYLOAD_SIZE;
+
+/// Size of one event in the pool, with packet header, rounded up to 4 bytes.
+pub const BLE_EVT_BLOCK_SIZE: usize = 4 * divc(BLE_EVT_FRAME_SIZE, 4);
+
+/// Size of the BLE event pool in bytes.
+pub const POOL_SIZE: usize = BLE_EVT_POOL_LEN * BLE_EVT_BLOCK_SIZE;
+
+/// Space for the pool in the linker scripts, from `EVT_POOL` to `EVT_QUEUE`.
+const EVT_POOL_SECTION_SIZE: usize = 0x2003_0b10 - 0x2003_03c0;
+
+// The pool must fit into its section and hold whole events
+const _: [(); 0] = [(); (POOL_SIZE > EVT_POOL_SECTION_SIZE) as usize];
+const _: [(); 0] = [(); POOL_SIZE % BLE_EVT_BLOCK_SIZE];
+
+/// Returns the size in bytes of a pool of `len` events, at most `BLE_EVT_POOL_LEN`.
+pub(super) fn pool_size(len: usize) -> u32 {
+    (len.min(BLE_EVT_POOL_LEN) * BLE_EVT_BLOCK_SIZE) as u32
+}
@@ -1,10 +1,32 @@
+//! Typed system (SHCI) commands
+//!
+//! Every command is written into the system command buffer and answered by a Command Complete
+//! event, received in the IPCC TX handler.
+
 use crate::ipcc::Ipcc;
 use crate::tl_mbox::cmd::CmdPacket;
 use crate::tl_mbox::consts::TlPacketType;
 use crate::tl_mbox::sys;
+use crate::tl_mbox::CoprocessorMode;
 use crate::tl_mbox::{TL_CS_EVT_SIZE, TL_EVT_HEADER_SIZE, TL_PACKET_HEADER_SIZE, TL_SYS_TABLE};
 
 pub const SHCI_OPCODE_BLE_INIT: u16 = 0xfc66;
+pub const SHCI_OPCODE_C2_FUS_GET_STATE: u16 = 0xfc52;
+pub const SHCI_OPCODE_C2_FUS_FW_UPGRADE: u16 = 0xfc54;
+pub const SHCI_OPCODE_C2_FUS_FW_DELETE: u16 = 0xfc55;
+pub const SHCI_OPCODE_C2_FUS_START_WS: u16 = 0xfc5a;
+pub const SHCI_OPCODE_C2_FLASH_ERASE_ACTIVITY: u16 = 0xfc69;
+pub const SHCI_OPCODE_C2_CONCURRENT_SET_MODE: u16 = 0xfc6a;
+pub const SHCI_OPCODE_C2_SET_FLASH_ACTIVITY_CONTROL: u16 = 0xfc73;
+
+pub const SHCI_EVT_CODE_VENDOR: u8 = 0xff;
+pub const SHCI_SUB_EVT_CODE_READY: u16 = 0x9200;
+pub const SHCI_SUB_EVT_ERROR_NOTIF: u16 = 0x9201;
+
+/// Ready event payload: the wireless stack is running
+pub const SHCI_WIRELESS_FW_RUNNING: u8 = 0x00;
+/// Ready event payload: FUS is running
+pub const SHCI_FUS_FW_RUNNING: u8 = 0x01;
 
 #[derive(Debug, Copy, Clone)]
 #[repr(C, packed)]
@@ -34,42 +56,389 @@ pub struct ShciBleInitCmdParam {
     pub hw_version: u8,
 }
 
-#[derive(Debug, Copy, Clone, Default)]
-#[repr(C, packed)]
-pub struct ShciHeader {
-    meta_data: [u32; 3],
+pub const TL_BLEEVT_CS_PACKET_SIZE: usize = TL_EVT_HEADER_SIZE + TL_CS_EVT_SIZE;
+#[allow(dead_code)] // Not used currently but reserved
+const TL_BLEEVT_CS_BUFFER_SIZE: usize = TL_PACKET_HEADER_SIZE + TL_BLEEVT_CS_PACKET_SIZE;
+
+/// Invalid `ShciBleInitCmdParam` field.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BleInitParamError {
+    /// `num_attr_record` is less than the 9 records of the GAP and GATT services.
+    NumAttrRecord,
+    /// `num_attr_serv` is less than 2, the GAP and GATT services.
+    NumAttrServ,
+    /// `attr_value_arr_size` is not a multiple of 4.
+    AttrValueArrSize,
+    /// `num_of_links` is not in 1..=8.
+    NumOfLinks,
+    /// `att_mtu` is not in 23..=512.
+    AttMtu,
+    /// `slave_sca` is above 500 ppm or `master_sca` above 7.
+    SleepClockAccuracy,
+    /// `ls_source` is not 0 or 1.
+    LsSource,
+    /// A boolean field is not 0 or 1.
+    Flag,
 }
 
-#[derive(Debug, Copy, Clone)]
-#[repr(C, packed)]
-pub struct ShciBleInitCmdPacket {
-    header: ShciHeader,
-    param: ShciBleInitCmdParam,
+impl Default for ShciBleInitCmdParam {
+    /// Defaults of ST's BLE applications.
+    fn default() -> Self {
+        ShciBleInitCmdParam {
+            p_ble_buffer_address: 0,
+            ble_buffer_size: 0,
+            num_attr_record: 68,
+            num_attr_serv: 8,
+            attr_value_arr_size: 1344,
+            num_of_links: 8,
+            extended_packet_length_enable: 1,
+            pr_write_list_size: 0x3a,
+            mb_lock_count: 0x79,
+            att_mtu: 156,
+            slave_sca: 500,
+            master_sca: 0,
+            ls_source: 1,
+            max_conn_event_length: 0xffff_ffff,
+            hs_startup_time: 0x148,
+            viterbi_enable: 1,
+            ll_only: 0,
+            hw_version: 0,
+        }
+    }
 }
 
-pub const TL_BLEEVT_CS_PACKET_SIZE: usize = TL_EVT_HEADER_SIZE + TL_CS_EVT_SIZE;
-#[allow(dead_code)] // Not used currently but reserved
-const TL_BLEEVT_CS_BUFFER_SIZE: usize = TL_PACKET_HEADER_SIZE + TL_BLEEVT_CS_PACKET_SIZE;
+impl ShciBleInitCmdParam {
+    /// Sets GATT database size: attribute records, services and attribute value storage.
+    pub fn with_gatt_db(
+        mut self,
+        num_attr_record: u16,
+        num_attr_serv: u16,
+        attr_value_arr_size: u16,
+    ) -> Self {
+        self.num_attr_record = num_attr_record;
+        self.num_attr_serv = num_attr_serv;
+        self.attr_value_arr_size = attr_value_arr_size;
+        self
+    }
+
+    /// Sets maximum number of simultaneous connections.
+    pub fn with_num_of_links(mut self, num_of_links: u8) -> Self {
+        self.num_of_links = num_of_links;
+        self
+    }
+
+    /// Sets maximum supported ATT_MTU.
+    pub fn with_att_mtu(mut self, att_mtu: u16) -> Self {
+        self.att_mtu = att_mtu;
+        self
+    }
+
+    /// Sets the number of prepare write request buffers, see `BLE_PREP_WRITE_X_ATT` in ST's
+    /// `ble_defs.h` for the size required by an ATT_MTU.
+    pub fn with_prepare_write_list_size(mut self, pr_write_list_size: u8) -> Self {
+        self.pr_write_list_size = pr_write_list_size;
+        self
+    }
+
+    /// Enables the data length extension (packets up to 251 bytes).
+    pub fn with_extended_packet_length(mut self, enable: bool) -> Self {
+        self.extended_packet_length_enable = enable as u8;
+        self
+    }
+
+    /// Sets sleep clock accuracy, in ppm when slave and as SCA range when master.
+    pub fn with_sca(mut self, slave_sca: u16, master_sca: u8) -> Self {
+        self.slave_sca = slave_sca;
+        self.master_sca = master_sca;
+        self
+    }
+
+    /// Selects the low speed clock: `false` for LSE, `true` for the internal RO.
+    pub fn with_ls_source_ro(mut self, ro: bool) -> Self {
+        self.ls_source = ro as u8;
+        self
+    }
+
+    /// Runs the stack as link layer only.
+    pub fn with_ll_only(mut self, ll_only: bool) -> Self {
+        self.ll_only = ll_only as u8;
+        self
+    }
+
+    /// Checks the parameters against the ranges accepted by the wireless stack.
+    pub fn validate(&self) -> Result<(), BleInitParamError> {
+        let Self {
+            num_attr_record,
+            num_attr_serv,
+            attr_value_arr_size,
+            num_of_links,
+            att_mtu,
+            slave_sca,
+            master_sca,
+            ls_source,
+            extended_packet_length_enable,
+            viterbi_enable,
+            ll_only,
+            ..
+        } = *self;
+
+        if num_attr_record < 9 {
+            Err(BleInitParamError::NumAttrRecord)
+        } else if num_attr_serv < 2 {
+            Err(BleInitParamError::NumAttrServ)
+        } else if attr_value_arr_size % 4 != 0 {
+            Err(BleInitParamError::AttrValueArrSize)
+        } else if num_of_links < 1 || num_of_links > 8 {
+            Err(BleInitParamError::NumOfLinks)
+        } else if att_mtu < 23 || att_mtu > 512 {
+            Err(BleInitParamError::AttMtu)
+        } else if slave_sca > 500 || master_sca > 7 {
+            Err(BleInitParamError::SleepClockAccuracy)
+        } else if ls_source > 1 {
+            Err(BleInitParamError::LsSource)
+        } else if extended_packet_length_enable > 1 || viterbi_enable > 1 || ll_only > 1 {
+            Err(BleInitParamError::Flag)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        // NOTE(unsafe) packed struct of integers, no padding
+        unsafe {
+            core::slice::from_raw_parts(
+                (self as *const Self).cast::<u8>(),
+                core::mem::size_of::<Self>(),
+            )
+        }
+    }
+}
+
+/// FUS state, decoded from the `SHCI_C2_FUS_GET_STATE` response.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FusState {
+    /// FUS is idle, ready for a command.
+    Idle,
+    /// Wireless firmware upgrade or deletion ongoing, with the raw state (0x10..=0x1f).
+    FwUpgradeOngoing(u8),
+    /// FUS upgrade ongoing, with the raw state (0x20..=0x2f).
+    FusUpgradeOngoing(u8),
+    /// Other FUS service ongoing, with the raw state (0x30..=0x3f).
+    ServiceOngoing(u8),
+    /// FUS is in error state.
+    Error(FusErrorCode),
+    /// The wireless stack answered instead of FUS. CPU2 then reboots into FUS and sends a new
+    /// ready event.
+    WirelessStackRunning,
+    /// Undocumented state.
+    Other(u8),
+}
+
+impl FusState {
+    /// Decodes the `SHCI_C2_FUS_GET_STATE` return parameters: state, then error code.
+    ///
+    /// FUS and the wireless stack both answer, the latter always with the error state, so
+    /// `mode` (from the last ready event) tells the two apart.
+    pub fn from_response(payload: &[u8], mode: CoprocessorMode) -> Option<FusState> {
+        let state = *payload.first()?;
+        let error = payload.get(1).copied().unwrap_or(0xff);
 
-pub fn shci_ble_init(ipcc: &mut Ipcc, param: ShciBleInitCmdParam) {
-    let mut packet = ShciBleInitCmdPacket {
-        header: ShciHeader::default(),
-        param,
+        Some(match state {
+            0xff if mode == CoprocessorMode::WirelessStack => FusState::WirelessStackRunning,
+            0xff => FusState::Error(error.into()),
+            0x00 => FusState::Idle,
+            0x10..=0x1f => FusState::FwUpgradeOngoing(state),
+            0x20..=0x2f => FusState::FusUpgradeOngoing(state),
+            0x30..=0x3f => FusState::ServiceOngoing(state),
+            other => FusState::Other(other),
+        })
+    }
+}
+
+/// Error code of `FusState::Error`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FusErrorCode {
+    NoError,
+    /// No firmware image found in the user flash.
+    ImageNotFound,
+    ImageCorrupt,
+    /// Image signature check failed.
+    ImageNotAuthentic,
+    /// Not enough flash for the image.
+    NotEnoughSpace,
+    UserAbort,
+    EraseError,
+    WriteError,
+    Unknown(u8),
+}
+
+impl From<u8> for FusErrorCode {
+    fn from(code: u8) -> Self {
+        match code {
+            0x00 => FusErrorCode::NoError,
+            0x01 => FusErrorCode::ImageNotFound,
+            0x02 => FusErrorCode::ImageCorrupt,
+            0x03 => FusErrorCode::ImageNotAuthentic,
+            0x04 => FusErrorCode::NotEnoughSpace,
+            0x05 => FusErrorCode::UserAbort,
+            0x06 => FusErrorCode::EraseError,
+            0x07 => FusErrorCode::WriteError,
+            other => FusErrorCode::Unknown(other),
+        }
+    }
+}
+
+/// FUS command failure.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FusError {
+    /// FUS rejected the command with status.
+    Status(u8),
+    /// Response without status.
+    InvalidResponse,
+}
+
+/// CPU2 flash activity control, see `shci_c2_set_flash_activity_control`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u8)]
+pub enum FlashActivityControl {
+    /// CPU2 suspends CPU1 flash operations with PESD (default).
+    Pes = 0x00,
+    /// CPU2 holds `flash::CPU2_BLOCK_FLASH_SEMAPHORE` while flash must not be accessed.
+    Sem7 = 0x01,
+}
+
+/// Flash erase activity of CPU1, see `shci_c2_flash_erase_activity`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u8)]
+pub enum EraseActivity {
+    /// No flash erase ongoing.
+    Off = 0x00,
+    /// CPU1 erases flash pages, CPU2 keeps its radio activity compatible with the erase.
+    On = 0x01,
+}
+
+/// Stack started by `shci_c2_concurrent_set_mode` in static concurrent mode.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u8)]
+pub enum ConcurrentMode {
+    Ble = 0x00,
+    Thread = 0x01,
+    Zigbee = 0x02,
+    Mac802_15_4 = 0x03,
+}
+
+/// Status of a system command, the first return parameter of its Command Complete event.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ShciStatus {
+    Success,
+    UnknownCommand,
+    MemoryCapacityExceeded,
+    UnsupportedFeature,
+    InvalidHciCommandParams,
+    InvalidParams,
+    /// The command isn't supported by FUS.
+    FusCommandNotSupported,
+    Other(u8),
+}
+
+impl From<u8> for ShciStatus {
+    fn from(status: u8) -> Self {
+        match status {
+            0x00 => ShciStatus::Success,
+            0x01 => ShciStatus::UnknownCommand,
+            0x07 => ShciStatus::MemoryCapacityExceeded,
+            0x11 => ShciStatus::UnsupportedFeature,
+            0x12 => ShciStatus::InvalidHciCommandParams,
+            0x42 => ShciStatus::InvalidParams,
+            0xff => ShciStatus::FusCommandNotSupported,
+            other => ShciStatus::Other(other),
+        }
+    }
+}
+
+/// System command failure.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ShciError {
+    /// CPU2 rejected the command with status.
+    Status(ShciStatus),
+    /// Response without status.
+    InvalidResponse,
+}
+
+/// Sends `SHCI_C2_BLE_INIT`, which starts the BLE stack, if `param` is valid.
+pub fn shci_ble_init(
+    ipcc: &mut Ipcc,
+    param: &ShciBleInitCmdParam,
+) -> Result<(), BleInitParamError> {
+    param.validate()?;
+    shci_send_cmd(ipcc, SHCI_OPCODE_BLE_INIT, param.as_bytes());
+    Ok(())
+}
+
+/// Sends `SHCI_C2_FUS_GET_STATE`.
+pub fn shci_c2_fus_get_state(ipcc: &mut Ipcc) {
+    shci_send_cmd(ipcc, SHCI_OPCODE_C2_FUS_GET_STATE, &[]);
+}
+
+/// Sends `SHCI_C2_FUS_FW_UPGRADE`, which makes FUS install the image stored in the user flash.
+///
+/// `fw_src_add` and `fw_dest_add` are optional, 0 lets FUS look for the image and place it.
+/// On success CPU2 reboots to install the image, poll `SHCI_C2_FUS_GET_STATE` until FUS is idle.
+pub fn shci_c2_fus_fw_upgrade(ipcc: &mut Ipcc, fw_src_add: u32, fw_dest_add: u32) {
+    let mut payload = [0; 8];
+    payload[..4].copy_from_slice(&fw_src_add.to_le_bytes());
+    payload[4..].copy_from_slice(&fw_dest_add.to_le_bytes());
+
+    let len = match (fw_src_add, fw_dest_add) {
+        (0, 0) => 0,
+        (_, 0) => 4,
+        _ => 8,
     };
+    shci_send_cmd(ipcc, SHCI_OPCODE_C2_FUS_FW_UPGRADE, &payload[..len]);
+}
 
-    let packet_ptr: *mut _ = &mut packet;
+/// Sends `SHCI_C2_FUS_FW_DELETE`, which makes FUS delete the installed wireless stack.
+pub fn shci_c2_fus_fw_delete(ipcc: &mut Ipcc) {
+    shci_send_cmd(ipcc, SHCI_OPCODE_C2_FUS_FW_DELETE, &[]);
+}
 
-    unsafe {
-        let cmd_ptr: *mut CmdPacket = packet_ptr.cast();
+/// Sends `SHCI_C2_FUS_START_WS`, which makes FUS start the wireless stack.
+pub fn shci_c2_fus_start_ws(ipcc: &mut Ipcc) {
+    shci_send_cmd(ipcc, SHCI_OPCODE_C2_FUS_START_WS, &[]);
+}
 
-        (*cmd_ptr).cmdserial.cmd.cmd_code = SHCI_OPCODE_BLE_INIT;
-        (*cmd_ptr).cmdserial.cmd.payload_len = core::mem::size_of::<ShciBleInitCmdParam>() as u8;
+/// Sends `SHCI_C2_SET_FLASH_ACTIVITY_CONTROL`.
+pub fn shci_c2_set_flash_activity_control(ipcc: &mut Ipcc, control: FlashActivityControl) {
+    shci_send_cmd(
+        ipcc,
+        SHCI_OPCODE_C2_SET_FLASH_ACTIVITY_CONTROL,
+        &[control as u8],
+    );
+}
 
-        let mut p_cmd_buffer = &mut *(*TL_SYS_TABLE.as_mut_ptr()).pcmd_buffer;
-        core::ptr::write(p_cmd_buffer, *cmd_ptr);
+/// Sends `SHCI_C2_FLASH_ERASE_ACTIVITY`.
+///
+/// Signals CPU2 before (`On`) and after (`Off`) erasing flash pages, so that CPU2 schedules its
+/// radio activity around the erase.
+pub fn shci_c2_flash_erase_activity(ipcc: &mut Ipcc, activity: EraseActivity) {
+    shci_send_cmd(ipcc, SHCI_OPCODE_C2_FLASH_ERASE_ACTIVITY, &[activity as u8]);
+}
 
-        (*p_cmd_buffer).cmdserial.ty = TlPacketType::SysCmd as u8;
+/// Sends `SHCI_C2_CONCURRENT_SET_MODE`, which switches the running stack of a static concurrent
+/// wireless firmware (e.g. BLE and Thread).
+pub fn shci_c2_concurrent_set_mode(ipcc: &mut Ipcc, mode: ConcurrentMode) {
+    shci_send_cmd(ipcc, SHCI_OPCODE_C2_CONCURRENT_SET_MODE, &[mode as u8]);
+}
 
-        sys::send_cmd(ipcc);
+/// Sends system command `opcode` with `payload` to CPU2.
+///
+/// The Command Complete event is received in the IPCC TX handler.
+pub fn shci_send_cmd(ipcc: &mut Ipcc, opcode: u16, payload: &[u8]) {
+    unsafe {
+        let p_cmd_buffer = (*TL_SYS_TABLE.as_mut_ptr()).pcmd_buffer;
+        CmdPacket::write_into(p_cmd_buffer, TlPacketType::SysCmd, opcode, payload)
+            .expect("system command payload longer than 255 bytes");
     }
+
+    sys::send_cmd(ipcc);
 }
@@ -0,0 +1,334 @@
+//! Mailbox split into an interrupt half and a task half
+//!
+//! `TlMbox::split` hands out the event queue producer and the `Ipcc` with the IPCC handlers
+//! (`MboxIrq`) and the consumer with the command and event API (`MboxClient`). Both are `Send`,
+//! so under RTIC they are separate resources: the IPCC tasks own `MboxIrq`, any other task owns
+//! `MboxClient` and dequeues events without locking the mailbox.
+//!
+//! ```ignore
+//! let (irq, client) = TlMbox::tl_init(&mut rcc, dp.IPCC.constrain()).split();
+//!
+//! // IPCC C1 RX task, resources = [irq]
+//! irq.interrupt_ipcc_rx_handler();
+//!
+//! // Application task, resources = [client]
+//! while let Some(evt) = client.dequeue_event() {
+//!     // handle `evt`
+//! }
+//! ```
+//!
+//! `MboxIrq` runs the same IPCC handlers as `TlMbox`. The client doesn't own the `Ipcc`: it
+//! accesses the few IPCC registers it needs (sending a command, resuming a deferred drain) within
+//! a critical section, like `EvtBox` does to release a buffer. The state the handlers record (last
+//! Command Complete and Command Status events, system command response, coprocessor mode, CPU2
+//! error, deferred drains) is shared through statics.
+//!
+//! Requires the `split` feature, which can't be combined with the `thread`, `mac-802-15-4`,
+//! `traces`, `evt-fanout` and `mbox-latency` features, whose state is used by both halves. ACL
+//! data is sent through the unsplit `TlMbox` only, as the flow control credits are returned by the
+//! IRQ side. `EvtQueueOverflow::DropOldest` behaves like `DropNewest`, the producer can't take
+//! events out of the queue.
+
+#![cfg(feature = "split")]
+
+#[cfg(any(
+    feature = "thread",
+    feature = "mac-802-15-4",
+    feature = "traces",
+    feature = "evt-fanout",
+    feature = "mbox-latency"
+))]
+compile_error!(
+    "`split` can't be combined with `thread`, `mac-802-15-4`, `traces`, `evt-fanout` or \
+     `mbox-latency`"
+);
+
+use core::cell::Cell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use cortex_m::interrupt::Mutex;
+use heapless::spsc;
+use void::Void;
+
+use crate::ipcc::{Ipcc, IpccExt};
+use crate::tl_mbox::evt::{self, EvtBox};
+use crate::tl_mbox::{
+    ble, channels, sys, CoprocessorMode, Cpu2Error, EvtQueueLen, EvtSink, HeaplessEvtQueue,
+    MboxStats, TlMbox, WirelessFwInfoTable,
+};
+
+type EvtProducer = spsc::Producer<'static, EvtBox, EvtQueueLen, u8, spsc::MultiCore>;
+type EvtConsumer = spsc::Consumer<'static, EvtBox, EvtQueueLen, u8, spsc::MultiCore>;
+
+static mut SPLIT_EVT_QUEUE: MaybeUninit<HeaplessEvtQueue> = MaybeUninit::uninit();
+static SPLIT_TAKEN: AtomicBool = AtomicBool::new(false);
+
+static LAST_CC_EVT: Mutex<Cell<Option<evt::CcEvt>>> = Mutex::new(Cell::new(None));
+static LAST_CS_EVT: Mutex<Cell<Option<evt::CsEvt>>> = Mutex::new(Cell::new(None));
+static LAST_SYS_RESPONSE: Mutex<Cell<Option<sys::SysCmdResponse>>> = Mutex::new(Cell::new(None));
+static CPU2_ERROR: Mutex<Cell<Option<Cpu2Error>>> = Mutex::new(Cell::new(None));
+static COPROCESSOR_MODE: Mutex<Cell<CoprocessorMode>> =
+    Mutex::new(Cell::new(CoprocessorMode::Unknown));
+static SYS_EVT_DEFERRED: AtomicBool = AtomicBool::new(false);
+static BLE_EVT_DEFERRED: AtomicBool = AtomicBool::new(false);
+
+impl EvtSink<EvtBox> for EvtProducer {
+    fn len(&self) -> usize {
+        spsc::Producer::len(self)
+    }
+
+    fn capacity(&self) -> usize {
+        spsc::Producer::capacity(self) as usize
+    }
+
+    fn enqueue(&mut self, item: EvtBox) -> Result<(), EvtBox> {
+        spsc::Producer::enqueue(self, item)
+    }
+
+    fn dequeue_oldest(&mut self) -> Option<EvtBox> {
+        None
+    }
+}
+
+impl TlMbox {
+    /// Splits the mailbox into its interrupt and task halves, see `split` module.
+    ///
+    /// Queued events are kept. Panics if called a second time, the event queue is static.
+    pub fn split(self) -> (MboxIrq, MboxClient) {
+        assert!(
+            !SPLIT_TAKEN.swap(true, Ordering::AcqRel),
+            "TlMbox can only be split once"
+        );
+
+        let TlMbox {
+            ipcc,
+            sys,
+            ble,
+            _mm,
+            evt_queue,
+            last_cc_evt,
+            last_cs_evt,
+            cpu2_error,
+            last_sys_response,
+            pending_tag,
+            last_cc_tag,
+            stats,
+            dropped_events,
+            sys_evt_deferred,
+            ble_evt_deferred,
+            config,
+            coprocessor_mode,
+            polled,
+        } = self;
+
+        // Only taken once, see `SPLIT_TAKEN`
+        let queue: &'static mut HeaplessEvtQueue = unsafe {
+            SPLIT_EVT_QUEUE = MaybeUninit::new(evt_queue);
+            &mut *SPLIT_EVT_QUEUE.as_mut_ptr()
+        };
+        let (producer, consumer) = queue.split();
+
+        let mut irq = MboxIrq {
+            mbox: TlMbox {
+                ipcc,
+                sys,
+                ble,
+                _mm,
+                evt_queue: producer,
+                last_cc_evt,
+                last_cs_evt,
+                cpu2_error,
+                last_sys_response,
+                pending_tag,
+                last_cc_tag,
+                stats,
+                dropped_events,
+                sys_evt_deferred,
+                ble_evt_deferred,
+                config,
+                coprocessor_mode,
+                polled,
+            },
+        };
+        irq.publish();
+
+        (irq, MboxClient { consumer })
+    }
+}
+
+/// Interrupt half of the mailbox: runs the IPCC handlers and fills the event queue.
+pub struct MboxIrq {
+    mbox: TlMbox<EvtProducer>,
+}
+
+impl MboxIrq {
+    /// Same as `TlMbox::interrupt_ipcc_rx_handler`.
+    pub fn interrupt_ipcc_rx_handler(&mut self) {
+        self.mbox.rx_handler();
+        self.publish();
+    }
+
+    /// Same as `TlMbox::interrupt_ipcc_tx_handler`.
+    pub fn interrupt_ipcc_tx_handler(&mut self) {
+        self.mbox.tx_handler();
+        self.publish();
+    }
+
+    /// Returns mailbox traffic counters. Events taken by `MboxClient` aren't counted.
+    pub fn stats(&self) -> MboxStats {
+        self.mbox.stats.with_shared_counters()
+    }
+
+    /// Returns the number of events dropped because the event queue was full.
+    pub fn dropped_events(&self) -> u32 {
+        self.mbox.dropped_events
+    }
+
+    /// Moves the state recorded by the handlers to the statics `MboxClient` reads.
+    fn publish(&mut self) {
+        let mbox = &mut self.mbox;
+        cortex_m::interrupt::free(|cs| {
+            if let Some(cc) = mbox.last_cc_evt.take() {
+                LAST_CC_EVT.borrow(cs).set(Some(cc));
+                LAST_SYS_RESPONSE
+                    .borrow(cs)
+                    .set(mbox.last_sys_response.take());
+            }
+            if let Some(cs_evt) = mbox.last_cs_evt.take() {
+                LAST_CS_EVT.borrow(cs).set(Some(cs_evt));
+            }
+            if mbox.cpu2_error.is_some() {
+                CPU2_ERROR.borrow(cs).set(mbox.cpu2_error);
+            }
+            COPROCESSOR_MODE.borrow(cs).set(mbox.coprocessor_mode);
+        });
+
+        if mbox.sys_evt_deferred {
+            SYS_EVT_DEFERRED.store(true, Ordering::Release);
+            mbox.sys_evt_deferred = false;
+        }
+        if mbox.ble_evt_deferred {
+            BLE_EVT_DEFERRED.store(true, Ordering::Release);
+            mbox.ble_evt_deferred = false;
+        }
+    }
+}
+
+// Both halves are `Send`, so they can be separate RTIC resources
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<MboxIrq>();
+    assert_send::<MboxClient>();
+};
+
+/// Task half of the mailbox: takes events and sends commands.
+pub struct MboxClient {
+    consumer: EvtConsumer,
+}
+
+impl MboxClient {
+    /// Picks single `EvtBox` from the event queue filled by `MboxIrq`.
+    pub fn dequeue_event(&mut self) -> Option<EvtBox> {
+        let evt = self.consumer.dequeue()?;
+
+        // The queue has room again, see `TlMbox::resume_deferred`
+        let sys = SYS_EVT_DEFERRED.swap(false, Ordering::AcqRel);
+        let ble = BLE_EVT_DEFERRED.swap(false, Ordering::AcqRel);
+        if sys || ble {
+            with_ipcc(|ipcc| {
+                if sys {
+                    ipcc.c1_set_rx_channel(channels::cpu2::IPCC_SYSTEM_EVENT_CHANNEL, true);
+                }
+                if ble {
+                    ipcc.c1_set_rx_channel(channels::cpu2::IPCC_BLE_EVENT_CHANNEL, true);
+                }
+            });
+        }
+
+        Some(evt)
+    }
+
+    /// Takes single event from the event queue.
+    ///
+    /// Returns `WouldBlock` if no event has been received yet.
+    pub fn read_event(&mut self) -> nb::Result<EvtBox, Void> {
+        self.dequeue_event().ok_or(nb::Error::WouldBlock)
+    }
+
+    /// Returns the number of events in the event queue.
+    pub fn pending_events(&self) -> usize {
+        self.consumer.len()
+    }
+
+    /// Retrieves last Command Complete event and removes it from mailbox.
+    pub fn pop_last_cc_evt(&mut self) -> Option<evt::CcEvt> {
+        cortex_m::interrupt::free(|cs| LAST_CC_EVT.borrow(cs).take())
+    }
+
+    /// Takes the Command Complete event of the last system command.
+    ///
+    /// Returns `WouldBlock` if CPU2 hasn't answered yet.
+    pub fn read_cc_evt(&mut self) -> nb::Result<evt::CcEvt, Void> {
+        self.pop_last_cc_evt().ok_or(nb::Error::WouldBlock)
+    }
+
+    /// Returns `true` while a system command is waiting for its response.
+    pub fn sys_cmd_busy(&self) -> bool {
+        with_ipcc(|ipcc| ipcc.c1_get_tx_channel(channels::cpu1::IPCC_SYSTEM_CMD_RSP_CHANNEL))
+    }
+
+    /// Sends a system command with `send`, e.g. `|ipcc| shci::shci_ble_init(ipcc, param)`.
+    ///
+    /// Returns `WouldBlock` while another system command is in flight. The response is taken
+    /// with `read_cc_evt` once the IPCC TX handler of `MboxIrq` received it.
+    pub fn send_sys_cmd<F: FnOnce(&mut Ipcc)>(&mut self, send: F) -> nb::Result<(), Void> {
+        if self.sys_cmd_busy() {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        with_ipcc(send);
+
+        Ok(())
+    }
+
+    /// Sends BLE HCI command `opcode` with `params`, see `ble::send_cmd`.
+    pub fn send_ble_cmd(&mut self, opcode: u16, params: &[u8]) -> Result<(), ble::CmdError> {
+        with_ipcc(|ipcc| ble::send_cmd(ipcc, opcode, params))
+    }
+
+    /// Returns the firmware running on CPU2 as reported by its last ready event.
+    pub fn coprocessor_mode(&self) -> CoprocessorMode {
+        cortex_m::interrupt::free(|cs| COPROCESSOR_MODE.borrow(cs).get())
+    }
+
+    /// Same as `TlMbox::last_command_status`.
+    pub fn last_command_status(&self) -> Option<evt::CsEvt> {
+        cortex_m::interrupt::free(|cs| LAST_CS_EVT.borrow(cs).get())
+    }
+
+    /// Same as `TlMbox::last_sys_command_response`, returned by value.
+    pub fn last_sys_command_response(&self) -> Option<sys::SysCmdResponse> {
+        cortex_m::interrupt::free(|cs| LAST_SYS_RESPONSE.borrow(cs).get())
+    }
+
+    /// Same as `TlMbox::cpu2_error`.
+    pub fn cpu2_error(&self) -> Option<Cpu2Error> {
+        cortex_m::interrupt::free(|cs| CPU2_ERROR.borrow(cs).get())
+    }
+
+    /// Returns CPU2 wireless firmware information (if present).
+    pub fn wireless_fw_info(&self) -> Option<WirelessFwInfoTable> {
+        crate::tl_mbox::wireless_fw_info()
+    }
+}
+
+/// Runs `f` on the IPCC within a critical section, the IPCC itself is owned by the IRQ side.
+fn with_ipcc<R, F: FnOnce(&mut Ipcc) -> R>(f: F) -> R {
+    cortex_m::interrupt::free(|_| {
+        let mut ipcc = unsafe { stm32wb_pac::Peripherals::steal() }
+            .IPCC
+            .constrain();
+        f(&mut ipcc)
+    })
+}
@@ -2,18 +2,73 @@
 use core::mem::MaybeUninit;
 
 use super::channels;
+use crate::hal::timer::CountDown;
+use crate::ipcc::token::{IpccRxToken, IpccTxToken};
 use crate::ipcc::Ipcc;
 use crate::tl_mbox::cmd::{CmdPacket, CmdSerial};
 use crate::tl_mbox::evt::{CcEvt, EvtBox, EvtSerial};
-use crate::tl_mbox::unsafe_linked_list::{
-    LST_init_head, LST_is_empty, LST_remove_head, LinkedListNode,
+use crate::tl_mbox::shci::{
+    shci_send_cmd, SHCI_EVT_CODE_VENDOR, SHCI_FUS_FW_RUNNING, SHCI_SUB_EVT_CODE_READY,
+    SHCI_WIRELESS_FW_RUNNING,
 };
+use crate::tl_mbox::unsafe_linked_list::{LST_init_head, ShmemList};
 use crate::tl_mbox::{
-    evt, HeaplessEvtQueue, SysTable, SYSTEM_EVT_QUEUE, SYS_CMD_BUF, TL_SYS_TABLE,
+    CoprocessorMode, RxChannelAction, SysTable, SYSTEM_EVT_QUEUE, SYS_CMD_BUF, TL_SYS_TABLE,
 };
 
 pub type SysCallback = fn();
 
+const CMD_RSP: IpccTxToken = IpccTxToken::new(channels::cpu1::IPCC_SYSTEM_CMD_RSP_CHANNEL);
+const EVENT: IpccRxToken = IpccRxToken::new(channels::cpu2::IPCC_SYSTEM_EVENT_CHANNEL);
+
+/// Maximum payload of a system command response: event payload minus the Command Complete
+/// header (`num_cmd` and `cmd_code`).
+pub const SYS_RSP_MAX_PAYLOAD: usize = 255 - 3;
+
+/// Response to a system command, decoded from the Command Complete event.
+#[derive(Copy, Clone)]
+pub struct SysCmdResponse {
+    /// Event code, Command Complete (0x0e)
+    pub evt_code: u8,
+    pub num_cmd: u8,
+    /// Opcode of the command this event answers
+    pub cmd_code: u16,
+    len: u8,
+    payload: [u8; SYS_RSP_MAX_PAYLOAD],
+}
+
+impl SysCmdResponse {
+    /// Returns the return parameters of the command.
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.len as usize]
+    }
+
+    /// Returns the status code, the first return parameter of every system command.
+    pub fn status(&self) -> Option<u8> {
+        self.payload().first().copied()
+    }
+}
+
+impl core::fmt::Debug for SysCmdResponse {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("SysCmdResponse")
+            .field("evt_code", &self.evt_code)
+            .field("num_cmd", &self.num_cmd)
+            .field("cmd_code", &self.cmd_code)
+            .field("payload", &self.payload())
+            .finish()
+    }
+}
+
+/// Blocking system command error
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SysError {
+    /// Previous system command wasn't answered yet.
+    Busy,
+    /// CPU2 didn't answer in time, e.g. because it isn't booted or runs an unexpected firmware.
+    Timeout,
+}
+
 pub struct Sys {}
 
 impl Sys {
@@ -27,13 +82,13 @@ impl Sys {
             });
         }
 
-        ipcc.c1_set_rx_channel(channels::cpu2::IPCC_SYSTEM_EVENT_CHANNEL, true);
+        EVENT.enable(ipcc);
 
         Sys {}
     }
 
     pub fn cmd_evt_handler(&self, ipcc: &mut Ipcc) -> CcEvt {
-        ipcc.c1_set_tx_channel(channels::cpu1::IPCC_SYSTEM_CMD_RSP_CHANNEL, false);
+        CMD_RSP.disable_ack_irq(ipcc);
 
         // ST's command response data structure is really convoluted.
         //
@@ -43,35 +98,173 @@ impl Sys {
         // 2. Access CmdPacket's cmdserial field and interpret its content as EvtSerial
         // 3. Access EvtSerial's evt field (as Evt) and interpret its payload as CcEvt type.
         // 4. CcEvt type is the actual SHCI response.
-        unsafe {
+        let cc = unsafe {
             let pcmd: *const CmdPacket = (&*TL_SYS_TABLE.as_ptr()).pcmd_buffer;
             let cmd_serial: *const CmdSerial = &(*pcmd).cmdserial;
             let evt_serial: *const EvtSerial = cmd_serial.cast();
             let cc: *const CcEvt = (*evt_serial).evt.payload.as_ptr().cast();
             *cc
-        }
+        };
+
+        trace!("SYS command complete: opcode {:#06x}", { cc.cmd_code });
+
+        cc
     }
 
-    pub fn evt_handler(&self, ipcc: &mut Ipcc, queue: &mut HeaplessEvtQueue) {
+    /// Decodes the whole Command Complete event left in the command buffer by CPU2.
+    ///
+    /// Must be called after `cmd_evt_handler` and before the next command is sent. Returns `None`
+    /// if the event is too short to be a Command Complete event.
+    pub fn cmd_response(&self) -> Option<SysCmdResponse> {
         unsafe {
-            let mut node_ptr: *mut LinkedListNode = core::ptr::null_mut();
-            let node_ptr_ptr: *mut *mut LinkedListNode = &mut node_ptr;
+            let pcmd: *const CmdPacket = (&*TL_SYS_TABLE.as_ptr()).pcmd_buffer;
+            let evt_serial: *const EvtSerial = (&(*pcmd).cmdserial as *const CmdSerial).cast();
+            let evt = &(*evt_serial).evt;
+            let cc: *const CcEvt = evt.payload.as_ptr().cast();
 
-            while !LST_is_empty(SYSTEM_EVT_QUEUE.as_mut_ptr()) {
-                LST_remove_head(SYSTEM_EVT_QUEUE.as_mut_ptr(), node_ptr_ptr);
+            let len = (evt.payload_len as usize).checked_sub(3)?;
+            let mut payload = [0; SYS_RSP_MAX_PAYLOAD];
+            core::ptr::copy_nonoverlapping((*cc).payload.as_ptr(), payload.as_mut_ptr(), len);
 
-                let event: *mut evt::EvtPacket = node_ptr.cast();
-                let event = EvtBox::new(event);
+            Some(SysCmdResponse {
+                evt_code: evt.evt_code,
+                num_cmd: (*cc).num_cmd,
+                cmd_code: (*cc).cmd_code,
+                len: len as u8,
+                payload,
+            })
+        }
+    }
+
+    /// Passes at most `limit` received events to `deliver` and clears the system event channel.
+    ///
+    /// Returns `false` if events are left in shared memory: the channel is then masked and its
+    /// flag stays set, so CPU2 holds back further events until it's unmasked again.
+    pub fn evt_handler<F: FnMut(EvtBox)>(
+        &self,
+        ipcc: &mut Ipcc,
+        limit: usize,
+        mut deliver: F,
+    ) -> bool {
+        let mut queue = unsafe { ShmemList::from_head(SYSTEM_EVT_QUEUE.as_mut_ptr()) };
 
-                queue.enqueue(event).unwrap();
+        for _ in 0..limit {
+            match queue.pop_head() {
+                Some(node) => deliver(EvtBox::new(node.cast())),
+                None => break,
             }
         }
 
-        ipcc.c1_clear_flag_channel(channels::cpu2::IPCC_SYSTEM_EVENT_CHANNEL);
+        let drained = queue.is_empty();
+        RxChannelAction::after_drain(drained).apply(ipcc, EVENT.channel());
+        drained
+    }
+
+    /// Returns the number of events left in shared memory by `evt_handler`.
+    pub fn pending_events(&self) -> usize {
+        unsafe { ShmemList::from_head(SYSTEM_EVT_QUEUE.as_mut_ptr()) }.len()
+    }
+
+    /// Sends system command `opcode` with `payload` and polls for its Command Complete event.
+    ///
+    /// Doesn't need the IPCC interrupts: the channel flag, which CPU2 clears once it wrote the
+    /// response, is polled until `timer` started with `timeout` expires.
+    pub fn send_cmd_blocking<T, P>(
+        &mut self,
+        ipcc: &mut Ipcc,
+        opcode: u16,
+        payload: &[u8],
+        timer: &mut T,
+        timeout: P,
+    ) -> Result<CcEvt, SysError>
+    where
+        T: CountDown,
+        P: Into<T::Time>,
+    {
+        if CMD_RSP.is_busy(ipcc) {
+            return Err(SysError::Busy);
+        }
+
+        shci_send_cmd(ipcc, opcode, payload);
+
+        timer.start(timeout);
+        while CMD_RSP.is_busy(ipcc) {
+            if timer.wait().is_ok() {
+                return Err(SysError::Timeout);
+            }
+        }
+
+        Ok(self.cmd_evt_handler(ipcc))
     }
 }
 
 pub fn send_cmd(ipcc: &mut Ipcc) {
-    ipcc.c1_set_flag_channel(channels::cpu1::IPCC_SYSTEM_CMD_RSP_CHANNEL);
-    ipcc.c1_set_tx_channel(channels::cpu1::IPCC_SYSTEM_CMD_RSP_CHANNEL, true);
+    CMD_RSP.notify(ipcc);
+    super::count_cmd();
+}
+
+/// Decodes the CPU2 mode if `evt` is the ready event.
+///
+/// Ready event payload: sub event code (2 bytes, little endian), then the running firmware.
+pub(super) fn ready_evt_mode(evt: &EvtBox) -> Option<CoprocessorMode> {
+    let mut buf = [0u8; 8];
+    let len = evt.write(&mut buf).ok()?;
+
+    // Event serial: kind, event code, payload length, payload
+    let (header, payload) = buf[..len].split_at(3);
+    if header[1] != SHCI_EVT_CODE_VENDOR || payload.len() < 3 {
+        return None;
+    }
+
+    if u16::from_le_bytes([payload[0], payload[1]]) != SHCI_SUB_EVT_CODE_READY {
+        return None;
+    }
+
+    Some(match payload[2] {
+        SHCI_WIRELESS_FW_RUNNING => CoprocessorMode::WirelessStack,
+        SHCI_FUS_FW_RUNNING => CoprocessorMode::Fus,
+        other => CoprocessorMode::Other(other),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tl_mbox::consts::TlPacketType;
+    use crate::tl_mbox::evt::tests::with_evt;
+
+    const SYS_EVT: u8 = TlPacketType::SysEvt as u8;
+
+    #[test]
+    fn ready_evt_modes() {
+        let table = [
+            (SHCI_WIRELESS_FW_RUNNING, CoprocessorMode::WirelessStack),
+            (SHCI_FUS_FW_RUNNING, CoprocessorMode::Fus),
+            (0x7f, CoprocessorMode::Other(0x7f)),
+        ];
+
+        for &(running, mode) in table.iter() {
+            with_evt(1, &[SYS_EVT, 0xff, 0x03, 0x00, 0x92, running], |evt| {
+                assert_eq!(ready_evt_mode(evt), Some(mode));
+            });
+        }
+    }
+
+    #[test]
+    fn ready_evt_mode_ignores_other_events() {
+        // Error notification
+        with_evt(0, &[SYS_EVT, 0xff, 0x03, 0x01, 0x92, 0x00], |evt| {
+            assert_eq!(ready_evt_mode(evt), None);
+        });
+
+        // Ready event without the running firmware
+        with_evt(0, &[SYS_EVT, 0xff, 0x02, 0x00, 0x92], |evt| {
+            assert_eq!(ready_evt_mode(evt), None);
+        });
+
+        // Not a vendor specific event
+        with_evt(0, &[SYS_EVT, 0x0e, 0x03, 0x00, 0x92, 0x00], |evt| {
+            assert_eq!(ready_evt_mode(evt), None);
+        });
+    }
 }
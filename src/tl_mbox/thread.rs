@@ -0,0 +1,225 @@
+//! OpenThread transport
+//!
+//! Requires the `thread` feature and a CPU2 firmware with the Thread stack, e.g. the dynamic
+//! concurrent BLE/Thread firmware.
+//!
+//! An OpenThread command is written into the OT command buffer and CPU2 answers in the same
+//! buffer when it frees the OT command channel, so only one command can be in flight. Commands
+//! and responses carry the OpenThread API function ID, the number of arguments and the arguments
+//! as 32-bit words, see `OtPacket`.
+//!
+//! Notifications are received in the notification buffer. The notification is copied into a
+//! queue and acknowledged right away; while the queue is full, the notification channel stays
+//! occupied, which holds back CPU2 until `TlMbox::ot_dequeue_notification` makes room.
+
+#![cfg(feature = "thread")]
+
+use core::mem::MaybeUninit;
+
+use heapless::spsc;
+
+use crate::ipcc::Ipcc;
+use crate::tl_mbox::channels;
+use crate::tl_mbox::cmd::CmdPacket;
+use crate::tl_mbox::consts::TlPacketType;
+use crate::tl_mbox::evt::EvtPacket;
+use crate::tl_mbox::{ThreadTable, TL_EVT_HEADER_SIZE, TL_PACKET_HEADER_SIZE, TL_THREAD_TABLE};
+
+/// Maximum payload of a command, response or notification.
+pub const OT_MAX_PAYLOAD: usize = 255;
+
+/// Maximum number of 32-bit arguments of a command (ID and argument count take 8 bytes).
+pub const OT_MAX_ARGS: usize = (OT_MAX_PAYLOAD - 8) / 4;
+
+#[link_section = "OT_CMD_BUFFER"]
+static mut OT_CMD_BUFFER: MaybeUninit<CmdPacket> = MaybeUninit::uninit();
+
+#[link_section = "CLI_CMD_BUFFER"]
+static mut CLI_CMD_BUFFER: MaybeUninit<CmdPacket> = MaybeUninit::uninit();
+
+#[link_section = "OT_NOTIF_BUFFER"]
+static mut OT_NOTIF_BUFFER: MaybeUninit<
+    [u8; TL_PACKET_HEADER_SIZE + TL_EVT_HEADER_SIZE + OT_MAX_PAYLOAD],
+> = MaybeUninit::uninit();
+
+type NotificationQueue = spsc::Queue<OtPacket, heapless::consts::U4, u8, spsc::SingleCore>;
+
+/// OpenThread command error
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OtError {
+    /// Previous command wasn't answered yet.
+    Busy,
+    /// More than `OT_MAX_ARGS` arguments.
+    TooManyArgs,
+}
+
+/// OpenThread response or notification.
+///
+/// Payload: API function ID (4 bytes), number of arguments (4 bytes), then the arguments as
+/// 32-bit words, all little endian.
+#[derive(Copy, Clone)]
+pub struct OtPacket {
+    len: u8,
+    payload: [u8; OT_MAX_PAYLOAD],
+}
+
+impl OtPacket {
+    /// Copies the event in `evt`.
+    unsafe fn copy_from(evt: *const EvtPacket) -> Self {
+        let evt = &(*evt).evt_serial.evt;
+        let len = evt.payload_len;
+
+        let mut payload = [0; OT_MAX_PAYLOAD];
+        core::ptr::copy_nonoverlapping(evt.payload.as_ptr(), payload.as_mut_ptr(), len as usize);
+
+        OtPacket { len, payload }
+    }
+
+    /// Returns the raw payload.
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.len as usize]
+    }
+
+    /// Returns the OpenThread API function ID.
+    pub fn id(&self) -> Option<u32> {
+        self.word(0)
+    }
+
+    /// Returns argument `i`, `None` if the packet has fewer arguments.
+    pub fn arg(&self, i: usize) -> Option<u32> {
+        if i >= self.word(1)? as usize {
+            return None;
+        }
+        self.word(2 + i)
+    }
+
+    fn word(&self, i: usize) -> Option<u32> {
+        let bytes = self.payload().get(i * 4..i * 4 + 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+impl core::fmt::Debug for OtPacket {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("OtPacket")
+            .field("payload", &self.payload())
+            .finish()
+    }
+}
+
+pub struct Thread {
+    cmd_pending: bool,
+    response: Option<OtPacket>,
+    notifications: NotificationQueue,
+}
+
+impl Thread {
+    pub(super) fn new(ipcc: &mut Ipcc) -> Self {
+        unsafe {
+            TL_THREAD_TABLE = MaybeUninit::new(ThreadTable {
+                nostack_buffer: OT_NOTIF_BUFFER.as_ptr().cast(),
+                clicmdrsp_buffer: CLI_CMD_BUFFER.as_ptr().cast(),
+                otcmdrsp_buffer: OT_CMD_BUFFER.as_ptr().cast(),
+            });
+        }
+
+        ipcc.c1_set_rx_channel(channels::cpu2::IPCC_THREAD_NOTIFICATION_ACK_CHANNEL, true);
+
+        Thread {
+            cmd_pending: false,
+            response: None,
+            notifications: unsafe { spsc::Queue::u8_sc() },
+        }
+    }
+
+    /// Writes OpenThread command `id` with `args` and notifies CPU2.
+    pub(super) fn send_cmd(
+        &mut self,
+        ipcc: &mut Ipcc,
+        id: u32,
+        args: &[u32],
+    ) -> Result<(), OtError> {
+        if self.cmd_pending {
+            return Err(OtError::Busy);
+        }
+        if args.len() > OT_MAX_ARGS {
+            return Err(OtError::TooManyArgs);
+        }
+
+        unsafe {
+            let cmd_serial = &mut (*OT_CMD_BUFFER.as_mut_ptr()).cmdserial;
+            cmd_serial.ty = TlPacketType::OtCmd as u8;
+            cmd_serial.cmd.cmd_code = 0;
+            cmd_serial.cmd.payload_len = (8 + args.len() * 4) as u8;
+
+            let payload = &mut cmd_serial.cmd.payload;
+            payload[0..4].copy_from_slice(&id.to_le_bytes());
+            payload[4..8].copy_from_slice(&(args.len() as u32).to_le_bytes());
+            for (i, arg) in args.iter().enumerate() {
+                payload[8 + i * 4..12 + i * 4].copy_from_slice(&arg.to_le_bytes());
+            }
+        }
+
+        self.cmd_pending = true;
+        self.response = None;
+
+        ipcc.c1_set_flag_channel(channels::cpu1::IPCC_THREAD_OT_CMD_RSP_CHANNEL);
+        ipcc.c1_set_tx_channel(channels::cpu1::IPCC_THREAD_OT_CMD_RSP_CHANNEL, true);
+        super::count_cmd();
+
+        Ok(())
+    }
+
+    /// OT command channel freed: CPU2 wrote the response into the command buffer.
+    pub(super) fn cmd_rsp_handler(&mut self, ipcc: &mut Ipcc) {
+        ipcc.c1_set_tx_channel(channels::cpu1::IPCC_THREAD_OT_CMD_RSP_CHANNEL, false);
+
+        self.response = Some(unsafe { OtPacket::copy_from(OT_CMD_BUFFER.as_ptr().cast()) });
+        self.cmd_pending = false;
+    }
+
+    pub(super) fn take_response(&mut self) -> Option<OtPacket> {
+        self.response.take()
+    }
+
+    pub(super) fn cmd_pending(&self) -> bool {
+        self.cmd_pending
+    }
+
+    /// Notification channel occupied: queues and acknowledges the notification.
+    ///
+    /// Returns `false` if the queue is full. The channel is masked and stays occupied until
+    /// `dequeue_notification` makes room.
+    pub(super) fn notification_handler(&mut self, ipcc: &mut Ipcc) -> bool {
+        let channel = channels::cpu2::IPCC_THREAD_NOTIFICATION_ACK_CHANNEL;
+
+        let notification = unsafe { OtPacket::copy_from(OT_NOTIF_BUFFER.as_ptr().cast()) };
+        if self.notifications.enqueue(notification).is_err() {
+            ipcc.c1_set_rx_channel(channel, false);
+            return false;
+        }
+
+        unsafe {
+            let ack: *mut CmdPacket = OT_NOTIF_BUFFER.as_mut_ptr().cast();
+            (*ack).cmdserial.ty = TlPacketType::OtAck as u8;
+        }
+
+        ipcc.c1_clear_flag_channel(channel);
+        ipcc.c1_set_rx_channel(channel, true);
+
+        true
+    }
+
+    pub(super) fn dequeue_notification(&mut self, ipcc: &mut Ipcc) -> Option<OtPacket> {
+        let notification = self.notifications.dequeue()?;
+
+        // Take the notification held back while the queue was full
+        if ipcc.c2_is_active_flag(channels::cpu2::IPCC_THREAD_NOTIFICATION_ACK_CHANNEL)
+            && !ipcc.c1_get_rx_channel(channels::cpu2::IPCC_THREAD_NOTIFICATION_ACK_CHANNEL)
+        {
+            self.notification_handler(ipcc);
+        }
+
+        Some(notification)
+    }
+}
@@ -0,0 +1,220 @@
+//! CPU2 trace transport
+//!
+//! Requires the `traces` feature and a CPU2 firmware built with traces enabled. Without the
+//! feature, neither the traces table nor the trace buffers take space in SRAM2.
+//!
+//! CPU2 allocates trace packets from a dedicated pool, links them into the traces queue and
+//! signals the traces channel. The IPCC RX handler moves them into a local queue, from which
+//! `TlMbox::dequeue_trace` returns them as `TraceBox` values, e.g. to forward the bytes to a
+//! UART. Dropping a `TraceBox` gives its buffer back to CPU2.
+//!
+//! `TlMbox::forward_traces` does the forwarding: each packet is passed to a sink as one frame,
+//! the length of the trace bytes (`u16`, little-endian) followed by the bytes, so the host can
+//! find the packet boundaries in a byte stream (USB CDC, UART):
+//!
+//! ```ignore
+//! mbox.forward_traces(|frame| tx_ring.push_slice(frame).is_ok());
+//! ```
+//!
+//! The sink takes a frame whole or not at all. A frame it refuses is dropped and counted in
+//! `TlMbox::dropped_forwarded_traces`, frames are never split. The buffers go back to CPU2 in
+//! both cases, a slow sink must not starve the trace pool.
+
+#![cfg(feature = "traces")]
+
+use core::mem::MaybeUninit;
+
+use heapless::spsc;
+
+use crate::ipcc::Ipcc;
+use crate::tl_mbox::channels;
+use crate::tl_mbox::evt::{EvtBox, EvtPacket};
+use crate::tl_mbox::unsafe_linked_list::{LST_init_head, LinkedListNode, ShmemList};
+use crate::tl_mbox::{
+    divc, TracesTable, TL_EVT_HEADER_SIZE, TL_PACKET_HEADER_SIZE, TL_TRACES_TABLE,
+};
+
+/// Number of trace packets CPU2 can have in flight.
+const CFG_TRACES_BUFFER_COUNT: usize = 2;
+const CFG_TRACES_MOST_PAYLOAD_SIZE: usize = 255;
+
+pub(super) const TRACES_POOL_SIZE: usize = CFG_TRACES_BUFFER_COUNT
+    * 4
+    * divc(
+        TL_PACKET_HEADER_SIZE + TL_EVT_HEADER_SIZE + CFG_TRACES_MOST_PAYLOAD_SIZE,
+        4,
+    );
+
+#[link_section = "TRACES_EVT_QUEUE"]
+static mut TRACES_EVT_QUEUE: MaybeUninit<LinkedListNode> = MaybeUninit::uninit();
+
+#[link_section = "TRACES_EVT_POOL"]
+pub(super) static mut TRACES_EVT_POOL: MaybeUninit<[u8; TRACES_POOL_SIZE]> = MaybeUninit::uninit();
+
+type TraceQueue = spsc::Queue<TraceBox, heapless::consts::U8, u8, spsc::SingleCore>;
+
+/// Size of the length prefix of a forwarded trace frame, see `TlMbox::forward_traces`.
+pub const FRAME_HEADER_SIZE: usize = 2;
+
+/// Largest forwarded trace frame.
+pub const FRAME_MAX_SIZE: usize = FRAME_HEADER_SIZE + CFG_TRACES_MOST_PAYLOAD_SIZE;
+
+/// Trace packet received from CPU2.
+///
+/// Gives the buffer back to CPU2 on `Drop`.
+#[derive(Debug)]
+pub struct TraceBox {
+    evt: EvtBox,
+}
+
+impl TraceBox {
+    /// Returns the raw trace bytes.
+    pub fn as_slice(&self) -> &[u8] {
+        self.evt.payload()
+    }
+}
+
+pub struct Traces {
+    queue: TraceQueue,
+    dropped: u32,
+    /// Packets refused by the `forward_to` sink
+    sink_dropped: u32,
+}
+
+impl Traces {
+    pub(super) fn new(ipcc: &mut Ipcc) -> Self {
+        unsafe {
+            LST_init_head(TRACES_EVT_QUEUE.as_mut_ptr());
+
+            TL_TRACES_TABLE = MaybeUninit::new(TracesTable {
+                traces_queue: TRACES_EVT_QUEUE.as_ptr().cast(),
+            });
+        }
+
+        ipcc.c1_set_rx_channel(channels::cpu2::IPCC_TRACES_CHANNEL, true);
+
+        Traces {
+            queue: unsafe { spsc::Queue::u8_sc() },
+            dropped: 0,
+            sink_dropped: 0,
+        }
+    }
+
+    /// Traces channel occupied: moves the received packets into the local queue.
+    ///
+    /// Packets that don't fit are dropped, which releases their buffers right away.
+    pub(super) fn evt_handler(&mut self, ipcc: &mut Ipcc) {
+        let mut queue = unsafe { ShmemList::from_head(TRACES_EVT_QUEUE.as_mut_ptr()) };
+        while let Some(node) = queue.pop_head() {
+            let evt: *mut EvtPacket = node.cast();
+            let trace = TraceBox {
+                evt: EvtBox::new(evt),
+            };
+            if self.queue.enqueue(trace).is_err() {
+                self.dropped = self.dropped.saturating_add(1);
+            }
+        }
+
+        ipcc.c1_clear_flag_channel(channels::cpu2::IPCC_TRACES_CHANNEL);
+    }
+
+    pub(super) fn dequeue(&mut self) -> Option<TraceBox> {
+        self.queue.dequeue()
+    }
+
+    pub(super) fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Passes all queued packets to `sink` as frames, returns the number of frames it took.
+    pub(super) fn forward_to<S: FnMut(&[u8]) -> bool>(&mut self, mut sink: S) -> usize {
+        let mut buf = [0; FRAME_MAX_SIZE];
+        let mut forwarded = 0;
+
+        // Dropping `trace` releases its buffer, whether the sink took the frame or not
+        while let Some(trace) = self.queue.dequeue() {
+            if forward(trace.as_slice(), &mut buf, &mut sink) {
+                forwarded += 1;
+            } else {
+                self.sink_dropped = self.sink_dropped.saturating_add(1);
+            }
+        }
+
+        forwarded
+    }
+
+    pub(super) fn sink_dropped(&self) -> u32 {
+        self.sink_dropped
+    }
+}
+
+/// Frames `trace` in `buf` and passes the frame to `sink`. Returns `false` if the sink refused
+/// it or the trace doesn't fit a frame.
+fn forward<S: FnMut(&[u8]) -> bool>(
+    trace: &[u8],
+    buf: &mut [u8; FRAME_MAX_SIZE],
+    sink: &mut S,
+) -> bool {
+    let len = FRAME_HEADER_SIZE + trace.len();
+    if len > FRAME_MAX_SIZE {
+        return false;
+    }
+
+    buf[..FRAME_HEADER_SIZE].copy_from_slice(&(trace.len() as u16).to_le_bytes());
+    buf[FRAME_HEADER_SIZE..len].copy_from_slice(trace);
+    sink(&buf[..len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_is_length_prefixed() {
+        let mut buf = [0; FRAME_MAX_SIZE];
+        let mut frames = 0;
+        let taken = forward(&[0xaa, 0xbb, 0xcc], &mut buf, &mut |frame: &[u8]| {
+            assert_eq!(frame, &[3, 0, 0xaa, 0xbb, 0xcc]);
+            frames += 1;
+            true
+        });
+
+        assert!(taken);
+        assert_eq!(frames, 1);
+    }
+
+    #[test]
+    fn empty_trace_is_header_only() {
+        let mut buf = [0xff; FRAME_MAX_SIZE];
+        assert!(forward(&[], &mut buf, &mut |frame: &[u8]| frame == [0, 0]));
+    }
+
+    #[test]
+    fn largest_trace_fits() {
+        let trace = [0x5a; CFG_TRACES_MOST_PAYLOAD_SIZE];
+        let mut buf = [0; FRAME_MAX_SIZE];
+        assert!(forward(&trace, &mut buf, &mut |frame: &[u8]| {
+            frame.len() == FRAME_MAX_SIZE
+                && frame[..FRAME_HEADER_SIZE] == [0xff, 0]
+                && frame[FRAME_HEADER_SIZE..] == trace[..]
+        }));
+    }
+
+    #[test]
+    fn oversized_trace_is_not_split() {
+        let trace = [0; CFG_TRACES_MOST_PAYLOAD_SIZE + 1];
+        let mut buf = [0; FRAME_MAX_SIZE];
+        let mut called = false;
+        assert!(!forward(&trace, &mut buf, &mut |_: &[u8]| {
+            called = true;
+            true
+        }));
+        assert!(!called);
+    }
+
+    #[test]
+    fn refused_frame_is_reported() {
+        let mut buf = [0; FRAME_MAX_SIZE];
+        assert!(!forward(&[1, 2], &mut buf, &mut |_: &[u8]| false));
+    }
+}
@@ -3,8 +3,22 @@
 
 #![allow(dead_code, mutable_transmutes, non_camel_case_types, non_snake_case, non_upper_case_globals, unused_assignments, unused_mut)]
 
+#[cfg(not(test))]
 use cortex_m::interrupt;
 
+/// Host tests have no interrupts to mask, `cortex_m::interrupt::free` only runs on the target.
+#[cfg(test)]
+mod interrupt {
+    use cortex_m::interrupt::CriticalSection;
+
+    pub fn free<F, R>(f: F) -> R
+    where
+        F: FnOnce(&CriticalSection) -> R,
+    {
+        f(unsafe { &CriticalSection::new() })
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 #[repr(C, packed(4))]
 pub struct LinkedListNode {
@@ -131,3 +145,269 @@ pub unsafe fn LST_get_prev_node(
         *node = (*ref_node).prev;
     });
 }
+
+/// Handle to a list whose head node lives in a static, e.g. in shared memory.
+///
+/// Wraps the `LST_*` functions for the queue operations of the mailbox, with debug assertions
+/// on the node pointers.
+pub struct ShmemList {
+    head: *mut LinkedListNode,
+}
+
+impl ShmemList {
+    /// Initializes the list at `head` as empty.
+    ///
+    /// # Safety
+    ///
+    /// `head` must be valid for the whole program and nothing may use the list meanwhile.
+    pub unsafe fn init(head: *mut LinkedListNode) -> Self {
+        debug_assert_node(head);
+        LST_init_head(head);
+        ShmemList { head }
+    }
+
+    /// Creates a handle to the list at `head`, initialized with `init` before.
+    ///
+    /// # Safety
+    ///
+    /// `head` must be valid for the whole program and hold an initialized list.
+    pub unsafe fn from_head(head: *mut LinkedListNode) -> Self {
+        debug_assert_node(head);
+        ShmemList { head }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        unsafe { LST_is_empty(self.head) }
+    }
+
+    /// Returns the number of nodes.
+    pub fn len(&self) -> usize {
+        unsafe { LST_get_size(self.head) }
+    }
+
+    /// Appends `node`.
+    ///
+    /// # Safety
+    ///
+    /// `node` must be valid until it's removed and must not be in a list.
+    pub unsafe fn push_tail(&mut self, node: *mut LinkedListNode) {
+        debug_assert_node(node);
+        LST_insert_tail(self.head, node);
+    }
+
+    /// Removes the first node.
+    pub fn pop_head(&mut self) -> Option<*mut LinkedListNode> {
+        interrupt::free(|_| unsafe {
+            if LST_is_empty(self.head) {
+                return None;
+            }
+
+            let mut node: *mut LinkedListNode = core::ptr::null_mut();
+            LST_remove_head(self.head, &mut node);
+            debug_assert_node(node);
+            Some(node)
+        })
+    }
+}
+
+/// Checks in debug builds that `node` can be a list node.
+fn debug_assert_node(node: *mut LinkedListNode) {
+    debug_assert!(!node.is_null(), "null list node");
+    debug_assert!(node as usize % 4 == 0, "list node not 4-byte aligned");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NODES: usize = 8;
+
+    /// List head and nodes on the host stack instead of SRAM2.
+    struct Fixture {
+        head: LinkedListNode,
+        nodes: [LinkedListNode; NODES],
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            Fixture {
+                head: LinkedListNode::default(),
+                nodes: [LinkedListNode::default(); NODES],
+            }
+        }
+
+        fn head(&mut self) -> *mut LinkedListNode {
+            &mut self.head
+        }
+
+        fn node(&mut self, i: usize) -> *mut LinkedListNode {
+            &mut self.nodes[i]
+        }
+
+        /// Returns the node indices from head to tail, checking the links in both directions.
+        fn order(&mut self) -> Vec<usize> {
+            let head = self.head();
+            let base = self.node(0) as usize;
+            let index = |node: *mut LinkedListNode| {
+                (node as usize - base) / core::mem::size_of::<LinkedListNode>()
+            };
+
+            let mut order = Vec::new();
+            unsafe {
+                let mut prev = head;
+                let mut node = (*head).next;
+                while node != head {
+                    assert_eq!((*node).prev, prev, "broken prev link");
+                    order.push(index(node));
+                    prev = node;
+                    node = (*node).next;
+                }
+                assert_eq!((*head).prev, prev, "broken tail link");
+                assert_eq!(LST_get_size(head), order.len());
+            }
+
+            order
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let mut f = Fixture::new();
+        let head = f.head();
+
+        unsafe {
+            LST_init_head(head);
+            assert!(LST_is_empty(head));
+            assert_eq!(LST_get_size(head), 0);
+        }
+        assert!(f.order().is_empty());
+    }
+
+    #[test]
+    fn one_node() {
+        let mut f = Fixture::new();
+        let head = f.head();
+        let node = f.node(0);
+
+        unsafe {
+            LST_init_head(head);
+            LST_insert_tail(head, node);
+            assert!(!LST_is_empty(head));
+            assert_eq!(f.order(), [0]);
+
+            let mut removed = core::ptr::null_mut();
+            LST_remove_head(head, &mut removed);
+            assert_eq!(removed, node);
+            assert!(LST_is_empty(head));
+        }
+    }
+
+    #[test]
+    fn many_nodes() {
+        let mut f = Fixture::new();
+        let head = f.head();
+
+        unsafe {
+            LST_init_head(head);
+            for i in 0..NODES {
+                LST_insert_tail(head, f.node(i));
+            }
+        }
+        assert_eq!(f.order(), (0..NODES).collect::<Vec<_>>());
+
+        unsafe {
+            let mut removed = core::ptr::null_mut();
+            LST_remove_tail(head, &mut removed);
+            assert_eq!(removed, f.node(NODES - 1));
+            LST_remove_head(head, &mut removed);
+            assert_eq!(removed, f.node(0));
+        }
+        assert_eq!(f.order(), (1..NODES - 1).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn insert_head_and_relative() {
+        let mut f = Fixture::new();
+        let head = f.head();
+
+        unsafe {
+            LST_init_head(head);
+            LST_insert_head(head, f.node(1));
+            LST_insert_head(head, f.node(0));
+            LST_insert_node_after(f.node(3), f.node(1));
+            LST_insert_node_before(f.node(2), f.node(3));
+        }
+        assert_eq!(f.order(), [0, 1, 2, 3]);
+
+        unsafe {
+            let mut next = core::ptr::null_mut();
+            LST_get_next_node(f.node(1), &mut next);
+            assert_eq!(next, f.node(2));
+
+            let mut prev = core::ptr::null_mut();
+            LST_get_prev_node(f.node(0), &mut prev);
+            assert_eq!(prev, head);
+        }
+    }
+
+    #[test]
+    fn interleaved_insert_remove() {
+        let mut f = Fixture::new();
+        let head = f.head();
+
+        unsafe {
+            LST_init_head(head);
+            LST_insert_tail(head, f.node(0));
+            LST_insert_tail(head, f.node(1));
+            LST_insert_tail(head, f.node(2));
+            LST_remove_node(f.node(1));
+            LST_insert_head(head, f.node(3));
+            LST_insert_tail(head, f.node(1));
+            LST_remove_node(f.node(0));
+        }
+        assert_eq!(f.order(), [3, 2, 1]);
+
+        unsafe {
+            LST_remove_node(f.node(3));
+            LST_remove_node(f.node(1));
+            LST_remove_node(f.node(2));
+            assert!(LST_is_empty(head));
+        }
+    }
+
+    #[test]
+    fn shmem_list_fifo() {
+        let mut f = Fixture::new();
+        let mut list = unsafe { ShmemList::init(f.head()) };
+
+        assert!(list.is_empty());
+        assert_eq!(list.pop_head(), None);
+
+        unsafe {
+            list.push_tail(f.node(0));
+            list.push_tail(f.node(1));
+        }
+        assert_eq!(list.len(), 2);
+        assert_eq!(list.pop_head(), Some(f.node(0)));
+
+        unsafe { list.push_tail(f.node(2)) };
+        assert_eq!(list.pop_head(), Some(f.node(1)));
+        assert_eq!(list.pop_head(), Some(f.node(2)));
+        assert_eq!(list.pop_head(), None);
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn shmem_list_from_head_shares_list() {
+        let mut f = Fixture::new();
+        let head = f.head();
+
+        let mut producer = unsafe { ShmemList::init(head) };
+        unsafe { producer.push_tail(f.node(4)) };
+
+        let mut consumer = unsafe { ShmemList::from_head(head) };
+        assert_eq!(consumer.len(), 1);
+        assert_eq!(consumer.pop_head(), Some(f.node(4)));
+        assert!(producer.is_empty());
+    }
+}
@@ -3,9 +3,21 @@
 //! Requires the `stm32-usbd` feature.
 //!
 //! See `examples` directory for usage examples.
+//!
+//! `stm32_usbd::UsbBus` only enables the interrupts usb-device needs (`CTRM`, `RESETM`, `SUSPM`,
+//! `WKUPM`). The start-of-frame interrupts are enabled separately with `enable_sof_interrupt` and
+//! `enable_esof_interrupt`, and the `USB_LP` handler must then call `on_frame_interrupt`, which
+//! counts and clears them; usb-device ignores these flags, so they would fire again right away.
+//!
+//! A single `UsbDevice::poll` services the transfers of all endpoints: the bus collects the
+//! `CTR_RX`/`CTR_TX` bits of every endpoint in one pass. `ISTR.CTR` stays set, and the interrupt
+//! pending, as long as an endpoint has a transfer that wasn't read yet, so the handler can poll
+//! until `ctr_pending` returns `false` to drain them without waiting for the next interrupt.
 
 #![cfg(feature = "stm32-usbd")]
 
+use core::sync::atomic::{AtomicU32, Ordering};
+
 use crate::stm32::{RCC, USB};
 use stm32_usbd::UsbPeripheral;
 
@@ -46,3 +58,102 @@ unsafe impl UsbPeripheral for Peripheral {
 }
 
 pub type UsbBusType = UsbBus<Peripheral>;
+
+/// `ISTR.CTR`: an endpoint completed a transfer
+const ISTR_CTR: u32 = 1 << 15;
+/// `CNTR.SOFM`, `ISTR.SOF`: start of frame
+const SOF: u32 = 1 << 9;
+/// `CNTR.ESOFM`, `ISTR.ESOF`: expected start of frame missed
+const ESOF: u32 = 1 << 8;
+
+static SOF_COUNT: AtomicU32 = AtomicU32::new(0);
+static ESOF_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Start-of-frame interrupts handled by `on_frame_interrupt`.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct FrameEvents {
+    /// A SOF packet was received, once per millisecond while the bus is active.
+    pub sof: bool,
+    /// An expected SOF packet was missed, e.g. while the bus is suspended.
+    pub esof: bool,
+}
+
+/// Returns the 11-bit number of the last received start-of-frame (SOF) packet.
+///
+/// The host sends SOF every 1 ms while the bus is active, so the difference between two readings
+/// can be used as a millisecond tick without enabling the SOF interrupt.
+pub fn frame_number() -> u16 {
+    // NOTE(unsafe) atomic read with no side effects
+    let fnr = unsafe { (*USB::ptr()).fnr.read().bits() };
+    (fnr & 0x7ff) as u16
+}
+
+/// Enables or disables the start-of-frame (SOF) interrupt, see module documentation.
+///
+/// Must be called after `UsbBus::new`, which resets the peripheral.
+pub fn enable_sof_interrupt(enabled: bool) {
+    set_cntr_mask(SOF, enabled);
+}
+
+/// Enables or disables the expected start-of-frame (ESOF) interrupt, see
+/// `enable_sof_interrupt`.
+pub fn enable_esof_interrupt(enabled: bool) {
+    set_cntr_mask(ESOF, enabled);
+}
+
+fn set_cntr_mask(bit: u32, enabled: bool) {
+    cortex_m::interrupt::free(|_| {
+        // NOTE(unsafe) only the mask bit changes, within a critical section like `UsbBus` does
+        unsafe { &*USB::ptr() }.cntr.modify(|r, w| unsafe {
+            w.bits(if enabled {
+                r.bits() | bit
+            } else {
+                r.bits() & !bit
+            })
+        });
+    });
+}
+
+/// Counts and clears the pending SOF and ESOF interrupts. Must be called from the `USB_LP`
+/// handler once they are enabled.
+pub fn on_frame_interrupt() -> FrameEvents {
+    // NOTE(unsafe) the flags are `rc_w0`: only the written zeros are cleared, so the other
+    // flags, which `UsbBus` handles, are left alone
+    let usb = unsafe { &*USB::ptr() };
+    let istr = usb.istr.read().bits();
+
+    let events = FrameEvents {
+        sof: istr & SOF != 0,
+        esof: istr & ESOF != 0,
+    };
+    let mut clear = 0;
+    if events.sof {
+        SOF_COUNT.fetch_add(1, Ordering::Relaxed);
+        clear |= SOF;
+    }
+    if events.esof {
+        ESOF_COUNT.fetch_add(1, Ordering::Relaxed);
+        clear |= ESOF;
+    }
+    if clear != 0 {
+        usb.istr.write(|w| unsafe { w.bits(0xffff & !clear) });
+    }
+
+    events
+}
+
+/// Returns the number of SOF interrupts handled by `on_frame_interrupt`, wrapping.
+pub fn sof_count() -> u32 {
+    SOF_COUNT.load(Ordering::Relaxed)
+}
+
+/// Returns the number of ESOF interrupts handled by `on_frame_interrupt`, wrapping.
+pub fn esof_count() -> u32 {
+    ESOF_COUNT.load(Ordering::Relaxed)
+}
+
+/// Returns `true` if an endpoint has a completed transfer that wasn't serviced yet.
+pub fn ctr_pending() -> bool {
+    // NOTE(unsafe) atomic read with no side effects
+    unsafe { (*USB::ptr()).istr.read().bits() & ISTR_CTR != 0 }
+}
@@ -0,0 +1,148 @@
+//! Wireless stack bring-up
+//!
+//! Starting the radio requires a fixed sequence: clocks (including the RF wake-up clock), IPCC,
+//! mailbox initialization, CPU2 boot, waiting for the CPU2 ready event and finally sending
+//! `SHCI_C2_BLE_INIT`. `setup` performs the whole sequence in the right order.
+
+pub mod low_power;
+
+use cortex_m::peripheral::NVIC;
+
+use crate::flash::ACR;
+use crate::hal::timer::CountDown;
+use crate::ipcc::IpccExt;
+use crate::rcc::{ClockConfigError, Config, Rcc, RfWakeupClock};
+use crate::stm32::{interrupt, IPCC};
+use crate::tl_mbox::shci::{shci_ble_init, BleInitParamError, ShciBleInitCmdParam};
+use crate::tl_mbox::{CoprocessorMode, TlMbox, WirelessFwInfoTable};
+
+/// Wireless stack configuration.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct WirelessConfig {
+    /// Parameters of the `SHCI_C2_BLE_INIT` command.
+    pub ble_init: ShciBleInitCmdParam,
+    /// Unmasks `IPCC_C1_RX_IT` and `IPCC_C1_TX_IT` in the NVIC once the stack is up.
+    ///
+    /// Off by default: the lines stay masked, so the mailbox can be serviced with `TlMbox::poll`,
+    /// or the application unmasks them itself once its handlers are ready.
+    pub unmask_interrupts: bool,
+}
+
+/// Bring-up step that failed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SetupError {
+    /// Clock configuration was rejected.
+    ClockConfig(ClockConfigError),
+    /// Clock configuration doesn't select an RF wake-up clock source.
+    NoRfWakeupClock,
+    /// CPU2 didn't report the ready event in time.
+    Cpu2ReadyTimeout,
+    /// CPU2 reported ready with another firmware than the wireless stack, e.g. FUS. See
+    /// `TlMbox::ensure_wireless_stack_running` to start the wireless stack from FUS.
+    NotWirelessStack(CoprocessorMode),
+    /// CPU2 reported ready, but didn't fill the wireless firmware information table.
+    NoWirelessFwInfo,
+    /// `SHCI_C2_BLE_INIT` parameters are out of range.
+    InvalidBleInitParam(BleInitParamError),
+    /// CPU2 didn't answer `SHCI_C2_BLE_INIT` in time.
+    BleInitTimeout,
+    /// `SHCI_C2_BLE_INIT` was answered with a non-zero status.
+    BleInitFailed(u8),
+}
+
+/// Peripherals and mailbox returned by a successful `setup`.
+pub struct Wireless {
+    pub rcc: Rcc,
+    pub mbox: TlMbox,
+    pub fw_info: WirelessFwInfoTable,
+}
+
+/// Brings up the wireless stack on CPU2.
+///
+/// Applies `clock_config` (checked with `Config::check_rf_clocks`), initializes IPCC and the
+/// mailbox, boots CPU2, waits for the CPU2 ready event of the wireless stack and initializes the
+/// BLE stack. Each wait is bounded by `timeout` counted by `timer`.
+///
+/// The mailbox is polled during the setup with `IPCC_C1_RX_IT` and `IPCC_C1_TX_IT` masked in the
+/// NVIC. They are unmasked on success only with `WirelessConfig::unmask_interrupts`.
+pub fn setup<T, P>(
+    mut rcc: Rcc,
+    clock_config: Config,
+    acr: &mut ACR,
+    ipcc: IPCC,
+    config: WirelessConfig,
+    timer: &mut T,
+    timeout: P,
+) -> Result<Wireless, SetupError>
+where
+    T: CountDown,
+    P: Into<T::Time> + Copy,
+{
+    if let RfWakeupClock::None = clock_config.rf_wkp_src {
+        return Err(SetupError::NoRfWakeupClock);
+    }
+
+    rcc.apply_clock_config(clock_config.with_rf(), acr)
+        .map_err(SetupError::ClockConfig)?;
+
+    let mut mbox = TlMbox::tl_init(&mut rcc, ipcc.constrain());
+
+    // Events are polled until the stack is up
+    NVIC::mask(interrupt::IPCC_C1_RX_IT);
+    NVIC::mask(interrupt::IPCC_C1_TX_IT);
+
+    crate::pwr::set_cpu2(true);
+
+    // Wait for the CPU2 ready event
+    timer.start(timeout);
+    let ready = loop {
+        mbox.interrupt_ipcc_rx_handler();
+
+        if let Some(ready) = mbox.cpu2_ready() {
+            break ready;
+        }
+
+        if timer.wait().is_ok() {
+            return Err(SetupError::Cpu2ReadyTimeout);
+        }
+    };
+
+    // `SHCI_C2_BLE_INIT` is only understood by the wireless stack
+    if ready.mode != CoprocessorMode::WirelessStack {
+        return Err(SetupError::NotWirelessStack(ready.mode));
+    }
+
+    let fw_info = mbox
+        .wireless_fw_info()
+        .ok_or(SetupError::NoWirelessFwInfo)?;
+
+    // Initialize BLE stack and wait for its response
+    shci_ble_init(mbox.ipcc(), &config.ble_init).map_err(SetupError::InvalidBleInitParam)?;
+
+    timer.start(timeout);
+    let cc = loop {
+        mbox.interrupt_ipcc_tx_handler();
+
+        if let Some(cc) = mbox.pop_last_cc_evt() {
+            break cc;
+        }
+
+        if timer.wait().is_ok() {
+            return Err(SetupError::BleInitTimeout);
+        }
+    };
+
+    let status = cc.payload[0];
+    if status != 0 {
+        return Err(SetupError::BleInitFailed(status));
+    }
+
+    if config.unmask_interrupts {
+        unsafe {
+            NVIC::unmask(interrupt::IPCC_C1_RX_IT);
+            NVIC::unmask(interrupt::IPCC_C1_TX_IT);
+        }
+    }
+
+    Ok(Wireless { rcc, mbox, fw_info })
+}
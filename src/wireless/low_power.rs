@@ -0,0 +1,126 @@
+//! Stop2 between radio events
+//!
+//! CPU2 runs the radio on its own and wakes CPU1 through the IPCC only when it has something to
+//! report, so a low duty cycle application (e.g. an advertiser) should keep CPU1 in Stop2
+//! whenever the mailbox is idle:
+//!
+//! ```ignore
+//! loop {
+//!     while let Some(evt) = mbox.dequeue_event() {
+//!         low_power::event_dequeued();
+//!         // handle `evt`
+//!     }
+//!
+//!     let _ = low_power::enter_stop2(&mut rcc, &mbox, &mut scb);
+//! }
+//! ```
+//!
+//! `enter_stop2` enters Stop2 with interrupts masked. After wake-up, the clocks are restored with
+//! `Rcc::restore_after_stop` before interrupts are unmasked, so the IPCC handlers never run on
+//! the Stop wake-up clock.
+//!
+//! With RTIC, the mailbox is a resource shared with the IPCC tasks and locking it in `idle`
+//! raises BASEPRI, which keeps the IPCC interrupt from ending Stop2. Check `TlMbox::is_idle`
+//! within the lock and call `enter_stop2_unchecked` after releasing it. An event that arrives in
+//! between is handled by the IPCC task before Stop2 is entered, so dequeue events in the IPCC
+//! tasks rather than in `idle`. `Rcc` must be owned by `idle`.
+//!
+//! The time from wake-up to the first dequeued event is measured with the DWT cycle counter,
+//! which must be enabled by the application (`DCB::enable_trace` and
+//! `DWT::enable_cycle_counter`). Compare `wakeup_latency` with the connection interval to check
+//! that events are handled in time. The counter is stopped in Stop2, so the regulator wake-up
+//! time isn't included.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use cortex_m::peripheral::{DWT, SCB};
+
+use crate::rcc::Rcc;
+use crate::tl_mbox::TlMbox;
+
+static WAKEUP_PENDING: AtomicBool = AtomicBool::new(false);
+static WAKEUP_TIMESTAMP: AtomicU32 = AtomicU32::new(0);
+static LAST_LATENCY: AtomicU32 = AtomicU32::new(0);
+static MAX_LATENCY: AtomicU32 = AtomicU32::new(0);
+
+/// Stop2 wasn't entered because the mailbox has pending work.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MboxBusy;
+
+/// Wake-up to event dequeued time in DWT cycles.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WakeupLatency {
+    pub last: u32,
+    pub max: u32,
+}
+
+/// Enters Stop2 if the mailbox is idle, see module documentation.
+///
+/// Returns after wake-up with the clocks restored.
+pub fn enter_stop2(rcc: &mut Rcc, mbox: &TlMbox, scb: &mut SCB) -> Result<(), MboxBusy> {
+    cortex_m::interrupt::free(|_| {
+        // Checked with interrupts masked, a later event ends Stop2 right away
+        if !mbox.is_idle() {
+            return Err(MboxBusy);
+        }
+
+        stop2(rcc, scb);
+        Ok(())
+    })
+}
+
+/// Enters Stop2 without checking the mailbox.
+///
+/// Returns after wake-up with the clocks restored.
+pub fn enter_stop2_unchecked(rcc: &mut Rcc, scb: &mut SCB) {
+    cortex_m::interrupt::free(|_| stop2(rcc, scb));
+}
+
+/// Stops the wake-up stopwatch, call it after `TlMbox::dequeue_event` returned an event.
+///
+/// Only the first event after a wake-up is measured.
+pub fn event_dequeued() {
+    if !WAKEUP_PENDING.swap(false, Ordering::Relaxed) {
+        return;
+    }
+
+    let latency = DWT::get_cycle_count().wrapping_sub(WAKEUP_TIMESTAMP.load(Ordering::Relaxed));
+    LAST_LATENCY.store(latency, Ordering::Relaxed);
+    if latency > MAX_LATENCY.load(Ordering::Relaxed) {
+        MAX_LATENCY.store(latency, Ordering::Relaxed);
+    }
+}
+
+/// Returns the measured wake-up latency, `None` until an event was dequeued after a wake-up.
+pub fn wakeup_latency() -> Option<WakeupLatency> {
+    let max = MAX_LATENCY.load(Ordering::Relaxed);
+    if max == 0 {
+        return None;
+    }
+
+    Some(WakeupLatency {
+        last: LAST_LATENCY.load(Ordering::Relaxed),
+        max,
+    })
+}
+
+/// Clears the measured wake-up latency.
+pub fn reset_wakeup_latency() {
+    cortex_m::interrupt::free(|_| {
+        LAST_LATENCY.store(0, Ordering::Relaxed);
+        MAX_LATENCY.store(0, Ordering::Relaxed);
+    });
+}
+
+/// Must be called with interrupts masked.
+fn stop2(rcc: &mut Rcc, scb: &mut SCB) {
+    // IPCC interrupts wake up CPU1 through an EXTI direct line
+    crate::ipcc::set_exti_wakeup(true);
+
+    crate::pwr::stop2(scb);
+
+    WAKEUP_TIMESTAMP.store(DWT::get_cycle_count(), Ordering::Relaxed);
+    WAKEUP_PENDING.store(true, Ordering::Relaxed);
+
+    rcc.restore_after_stop();
+}